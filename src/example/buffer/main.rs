@@ -7,9 +7,11 @@ use winit::window::Window;
 use wgpu::{BackendBit, RequestAdapterOptions, PowerPreference, DeviceDescriptor, Features, TextureUsage, TextureFormat, PresentMode, CommandBufferDescriptor, CommandEncoderDescriptor, RenderPassDescriptor, ShaderModuleDescriptor, ShaderFlags, PipelineLayoutDescriptor, RenderPipelineDescriptor, VertexState, FragmentState, ColorTargetState, BlendState, BlendComponent, PrimitiveState, PrimitiveTopology, FrontFace, Face, PolygonMode, MultisampleState, ShaderModule, SwapChainDescriptor, BufferUsage, VertexBufferLayout, InputStepMode, IndexFormat};
 use wgpu::util::{DeviceExt, BufferInitDescriptor};
 use std::mem::size_of;
+use utils::bytes::AsBytes;
+use utils::buffer::GpuBuffer;
 
 #[repr(C)]
-#[derive(Debug,Copy, Clone,bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug,Copy,Clone,AsBytes)]
 struct Vertex{
     position: [f32;3],
     color: [f32;4]
@@ -38,8 +40,8 @@ struct State{
     size : winit::dpi::PhysicalSize<u32>,
     clear_color : wgpu::Color,
     pipeline : wgpu::RenderPipeline,
-    vertices : wgpu::Buffer,
-    indices : wgpu::Buffer
+    vertices : GpuBuffer<Vertex>,
+    indices : GpuBuffer<u32>
 }
 
 impl State{
@@ -78,17 +80,9 @@ impl State{
             flags: ShaderFlags::all()
         });
 
-        let vertices = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Vertices"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: BufferUsage::VERTEX
-        });
+        let vertices = GpuBuffer::new(&device,Some("Vertices"),BufferUsage::VERTEX,VERTICES);
 
-        let indices = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Indices"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: BufferUsage::INDEX
-        });
+        let indices = GpuBuffer::new(&device,Some("Indices"),BufferUsage::INDEX,INDICES);
 
         let pipeline = Self::create_pipeline(&device,&shader,&sc_desc);
 
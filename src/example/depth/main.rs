@@ -13,10 +13,17 @@ use cgmath::{Matrix4, SquareMatrix, Vector3, Zero, Rad, Quaternion, Rotation3, V
 use winit::dpi::{Pixel, PhysicalPosition};
 use std::ops::Range;
 use wgpu::LoadOp::Clear;
-use utils::{from_raw_parts, from_raw_parts_ex};
+use utils::bytes::AsBytes;
+use utils::buffer::GpuBuffer;
+use utils::uniform::{UniformLayout, AddressSpace};
+use utils::vertex::{VertexLayout, VertexLayoutDesc};
+use utils::reflect::{check_vertex_layouts, check_bind_group_layouts};
+use utils::pipeline::{PipelineCache, PipelineDesc, DepthState};
+use utils::material::CompareDesc;
+use std::rc::Rc;
 
 #[repr(C)]
-#[derive(Debug,Copy, Clone,bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug,Copy,Clone,VertexLayout,AsBytes)]
 struct Vertex{
     position: [f32;3],
     color: [f32;4],
@@ -62,14 +69,12 @@ const DEPTH_VERTICES: &[Vertex] = &[
 
 const DEPTH_INDICES: &[u32] = &[0, 1, 2, 0, 2, 3];
 
-#[repr(C)]
-#[derive(Debug,Copy, Clone)]
+#[derive(Debug,Copy, Clone,UniformLayout)]
 struct Uniform {
     projection: Matrix4<f32>,
     view: Matrix4<f32>,
 }
-#[repr(C)]
-#[derive(Debug,Copy, Clone)]
+#[derive(Debug,Copy, Clone,UniformLayout)]
 struct Uniform2 {
     projection: Matrix4<f32>,
     view: Matrix4<f32>,
@@ -80,6 +85,14 @@ struct Instance{
     quaternion: Quaternion<f32>,
 }
 
+#[repr(C)]
+#[derive(Debug,Copy,Clone,VertexLayout,AsBytes)]
+#[step(instance)]
+struct InstanceRaw{
+    #[location(5)]
+    model: Matrix4<f32>,
+}
+
 struct State{
     surface : wgpu::Surface,
     device : wgpu::Device,
@@ -88,24 +101,24 @@ struct State{
     swap_chain : wgpu::SwapChain,
     size : winit::dpi::PhysicalSize<u32>,
     clear_color : wgpu::Color,
-    pipeline : wgpu::RenderPipeline,
-    vertices : wgpu::Buffer,
-    indices : wgpu::Buffer,
+    pipeline : Rc<RenderPipeline>,
+    vertices : GpuBuffer<Vertex>,
+    indices : GpuBuffer<u32>,
     img1: (Texture,TextureView,Sampler),
     bind_groups : Vec<BindGroup>,
     uniform : Uniform,
     uniform_buf : Buffer,
     instances: Vec<Instance>,
-    instance_buf: Vec<Matrix4<f32>>,
     rotate : Vector3<f32>,
-    instance_buffer: Buffer,
+    instance_buffer: GpuBuffer<InstanceRaw>,
     left_btn_down: bool,
     last_cursor_pos : Vector2<f32>,
     depth_stencil : (Texture,TextureView,Sampler),
-    mesh_quad: (Buffer,Buffer),
-    depth_pipeline:RenderPipeline,
+    mesh_quad: (GpuBuffer<Vertex>,GpuBuffer<u32>),
+    depth_pipeline:Rc<RenderPipeline>,
     depth_uniform:(Uniform2,Buffer),
-    depth_bind_group_layout:BindGroupLayout,
+    depth_bind_group_layout:Rc<BindGroupLayout>,
+    pipelines: PipelineCache,
 }
 
 impl State{
@@ -138,69 +151,50 @@ impl State{
 
         let clear_color = wgpu::Color::BLACK;
 
-        let shader = device.create_shader_module(&ShaderModuleDescriptor{
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-            flags: ShaderFlags::all()
-        });
-        let shader_depth = device.create_shader_module(&ShaderModuleDescriptor{
-            label: Some("Shader Depth"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader_depth.wgsl").into()),
-            flags: ShaderFlags::all()
-        });
+        if let Err(e) = check_vertex_layouts(include_str!("shader.wgsl"),"main",&[Vertex::layout(),InstanceRaw::layout()]) {
+            panic!("shader.wgsl: {}",e);
+        }
+        if let Err(e) = check_vertex_layouts(include_str!("shader_depth.wgsl"),"main",&[Vertex::layout()]) {
+            panic!("shader_depth.wgsl: {}",e);
+        }
 
-        let vertices = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Vertices"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: BufferUsage::VERTEX
-        });
+        let vertices = GpuBuffer::new(&device,Some("Vertices"),BufferUsage::VERTEX,VERTICES);
 
-        let indices = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Indices"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: BufferUsage::INDEX
-        });
+        let indices = GpuBuffer::new(&device,Some("Indices"),BufferUsage::INDEX,INDICES);
         
-        let depth_vertices = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Depth Vertices"),
-            contents: bytemuck::cast_slice(DEPTH_VERTICES),
-            usage: BufferUsage::VERTEX
-        });
+        let depth_vertices = GpuBuffer::new(&device,Some("Depth Vertices"),BufferUsage::VERTEX,DEPTH_VERTICES);
 
-        let depth_indices = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Depth Indices"),
-            contents: bytemuck::cast_slice(DEPTH_INDICES),
-            usage: BufferUsage::INDEX
-        });
+        let depth_indices = GpuBuffer::new(&device,Some("Depth Indices"),BufferUsage::INDEX,DEPTH_INDICES);
         
         
         let img_data = include_bytes!("../textures/happy-tree.png");
         let (texture,texture_view,sampler) = Self::load_texture(&device,&queue,img_data).unwrap();
 
-        let bind_group_layout =  device.create_bind_group_layout(&BindGroupLayoutDescriptor{
-            label: Some("Bind Group Layout"),
-            entries: &[
-                BindGroupLayoutEntry{
-                    binding: 0,
-                    visibility: ShaderStage::FRAGMENT,
-                    ty: BindingType::Texture {
-                        sample_type: TextureSampleType::Float{ filterable: true },
-                        view_dimension: TextureViewDimension::D2,
-                        multisampled: false
-                    },
-                    count: None
+        let texture_entries = [
+            BindGroupLayoutEntry{
+                binding: 0,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float{ filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false
                 },
-                BindGroupLayoutEntry{
-                    binding: 1,
-                    visibility: ShaderStage::FRAGMENT,
-                    ty: BindingType::Sampler{
-                        filtering: true,
-                        comparison: false
-                    },
-                    count: None
-                }
-            ]
-        });
+                count: None
+            },
+            BindGroupLayoutEntry{
+                binding: 1,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Sampler{
+                    filtering: true,
+                    comparison: false
+                },
+                count: None
+            }
+        ];
+        let bind_group_layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Bind Group Layout"),
+            entries: &texture_entries
+        }));
 
         let bind_group = device.create_bind_group(&BindGroupDescriptor{
             label: Some("Depth Bind Group"),
@@ -213,30 +207,31 @@ impl State{
 
         let depth_stencil = Self::create_depth_stencil(&device,&sc_desc);
 
-        let depth_bind_group_layout =  device.create_bind_group_layout(&BindGroupLayoutDescriptor{
-            label: Some("Depth Bind Group Layout"),
-            entries: &[
-                BindGroupLayoutEntry{
-                    binding: 0,
-                    visibility: ShaderStage::FRAGMENT,
-                    ty: BindingType::Texture {
-                        sample_type: TextureSampleType::Depth,
-                        view_dimension: TextureViewDimension::D2,
-                        multisampled: false
-                    },
-                    count: None
+        let depth_entries = [
+            BindGroupLayoutEntry{
+                binding: 0,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false
                 },
-                BindGroupLayoutEntry{
-                    binding: 1,
-                    visibility: ShaderStage::FRAGMENT,
-                    ty: BindingType::Sampler{
-                        filtering: true,
-                        comparison: true
-                    },
-                    count: None
-                }
-            ]
-        });
+                count: None
+            },
+            BindGroupLayoutEntry{
+                binding: 1,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Sampler{
+                    filtering: true,
+                    comparison: true
+                },
+                count: None
+            }
+        ];
+        let depth_bind_group_layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Depth Bind Group Layout"),
+            entries: &depth_entries
+        }));
 
         let depth_bind_group = device.create_bind_group(&BindGroupDescriptor{
             label: Some("Depth Bind Group"),
@@ -252,43 +247,46 @@ impl State{
 
         let uniform_buf = device.create_buffer_init(&BufferInitDescriptor{
             label: Some("Uniform Buffer"),
-            contents: unsafe { from_raw_parts(&uniform) },
+            contents: uniform.to_bytes().as_slice(),
             usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST
         });
 
         let uniform2_buf = device.create_buffer_init(&BufferInitDescriptor{
             label: Some("Depth Uniform Buffer"),
-            contents: unsafe { from_raw_parts(&uniform2) },
+            contents: uniform2.to_bytes().as_slice(),
             usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST
         });
 
         let instances = Instance::gen_instances(81,9,Vector3::new(0.0,0.03,0.0),1.0);
         let instance_buf:Vec<_> = instances.iter().map(|it|{
-            it.to_matrix()
-
+            InstanceRaw{ model: it.to_matrix() }
         }).collect();
 
-        let instance_buffer = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Instance Buffer"),
-            contents: unsafe { from_raw_parts_ex(instance_buf.as_slice()) },
-            usage: BufferUsage::VERTEX
-        });
+        let instance_buffer = GpuBuffer::new(&device,Some("Instance Buffer"),BufferUsage::VERTEX,instance_buf.as_slice());
 
-        let vertex_binding_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+        let vertex_entries = [
+            wgpu::BindGroupLayoutEntry{
+                binding: 0,
+                visibility: ShaderStage::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None
+                },
+                count: None
+            }
+        ];
+        let vertex_binding_group_layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
             label: Some("Vertex Binding Group"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry{
-                    binding: 0,
-                    visibility: ShaderStage::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None
-                    },
-                    count: None
-                }
-            ]
-        });
+            entries: &vertex_entries
+        }));
+
+        if let Err(e) = check_bind_group_layouts(include_str!("shader.wgsl"),&[&texture_entries,&vertex_entries]) {
+            panic!("shader.wgsl: {}",e);
+        }
+        if let Err(e) = check_bind_group_layouts(include_str!("shader_depth.wgsl"),&[&depth_entries,&vertex_entries]) {
+            panic!("shader_depth.wgsl: {}",e);
+        }
 
         let vertex_binding_group = device.create_bind_group(&BindGroupDescriptor{
             label: Some("Vertex binding Gropu"),
@@ -312,16 +310,16 @@ impl State{
             ]
         });
 
-        let pipeline = Self::create_pipeline(&device,&shader,&sc_desc,&[&bind_group_layout,
-            &vertex_binding_group_layout],&[VertexBufferLayout{
-            array_stride: size_of::<Matrix4<f32>>() as _,
-            step_mode: InputStepMode::Instance,
-            attributes: &wgpu::vertex_attr_array![ 5 => Float32x4,6 => Float32x4,7 => Float32x4,8 => Float32x4 ]
-        }],true);
-
-        let depth_pipeline = Self::create_pipeline(&device,&shader_depth,&sc_desc,&[
-            &depth_bind_group_layout,&vertex_binding_group_layout
-        ],&[],false);
+        let mut pipelines = PipelineCache::new(sc_desc.format);
+        let shader = pipelines.shader(&device,"shader.wgsl",include_str!("shader.wgsl"));
+        let shader_depth = pipelines.shader(&device,"shader_depth.wgsl",include_str!("shader_depth.wgsl"));
+        let layouts = vec![pipelines.layout(&bind_group_layout),pipelines.layout(&vertex_binding_group_layout)];
+        let pipeline = pipelines.get(&device,&PipelineDesc{
+            depth: Some(DepthState{ format: TextureFormat::Depth32Float, write: true, compare: CompareDesc::Less }),
+            ..PipelineDesc::new(shader,layouts,vec![Vertex::layout(),InstanceRaw::layout()])
+        });
+        let depth_layouts = vec![pipelines.layout(&depth_bind_group_layout),pipelines.layout(&vertex_binding_group_layout)];
+        let depth_pipeline = pipelines.get(&device,&PipelineDesc::new(shader_depth,depth_layouts,vec![Vertex::layout()]));
 
         State{
             surface,
@@ -340,7 +338,6 @@ impl State{
             uniform_buf,
             rotate: Vector3::zero(),
             instances,
-            instance_buf,
             instance_buffer,
             left_btn_down :false,
             last_cursor_pos: Vector2::zero(),
@@ -348,7 +345,8 @@ impl State{
             mesh_quad: (depth_vertices,depth_indices),
             depth_pipeline,
             depth_uniform : (uniform2,uniform2_buf),
-            depth_bind_group_layout
+            depth_bind_group_layout,
+            pipelines
         }
     }
 
@@ -385,70 +383,6 @@ impl State{
         (tex,tex_view,sampler)
     }
 
-    fn create_pipeline(
-        device:& wgpu::Device,shader:&ShaderModule,
-        sc_desc:&SwapChainDescriptor,
-        bind_group_layouts:&'_[&'_ BindGroupLayout],
-        added_vertex_buffer:&[VertexBufferLayout],
-        has_depth_stencil:bool) -> wgpu::RenderPipeline
-    {
-        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor{
-            label: Some("Render Pipeline Layout"),
-            bind_group_layouts,
-            push_constant_ranges: &[]
-        });
-
-        let attributes = wgpu::vertex_attr_array![ 0 => Float32x3, 1=> Float32x4, 2=> Float32x2 ];
-        let mut vertex_buffers = vec![ VertexBufferLayout{
-            array_stride: size_of::<Vertex>() as _,
-            step_mode: InputStepMode::Vertex,
-            attributes: &attributes
-        }];
-        if added_vertex_buffer.len() > 0{
-            vertex_buffers.extend_from_slice(added_vertex_buffer);
-        }
-
-        device.create_render_pipeline(&RenderPipelineDescriptor{
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: VertexState{
-                module: &shader,
-                entry_point: "main",
-                buffers: vertex_buffers.as_slice()
-            },
-            fragment: Some(FragmentState{
-                module: &shader,
-                entry_point: "main",
-                targets: &[ColorTargetState{
-                    format: sc_desc.format,
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrite::all()
-                }]
-            }),
-            primitive: PrimitiveState{
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: FrontFace::Ccw,
-                cull_mode: None,
-                clamp_depth: false,
-                polygon_mode: PolygonMode::Fill,
-                conservative: false
-            },
-            depth_stencil: if has_depth_stencil { Some(DepthStencilState{
-                format: TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Less,
-                stencil: Default::default(),
-                bias: Default::default()
-            }) }else{None},
-            multisample: MultisampleState{
-                count: 1,
-                mask: u64::MAX,
-                alpha_to_coverage_enabled: false
-            }
-        })
-    }
-
     fn resize(&mut self,size:winit::dpi::PhysicalSize<u32>)
     {
         if size.width > 0 && size.height > 0
@@ -507,9 +441,9 @@ impl State{
 
     fn update(&mut self) {
         self.uniform.set_rotate(self.rotate);
-        unsafe { self.queue.write_buffer(&self.uniform_buf, 0, from_raw_parts(&self.uniform)) }
+        self.queue.write_buffer(&self.uniform_buf, 0, self.uniform.to_bytes().as_slice());
         //self.depth_uniform.0.set_rotate(self.rotate);
-        //unsafe { self.queue.write_buffer(&self.depth_uniform.1, 0, from_raw_parts(&self.depth_uniform.0)) }
+        //self.queue.write_buffer(&self.depth_uniform.1, 0, self.depth_uniform.0.to_bytes().as_slice());
     }
     fn render(&mut self) -> Result<(),wgpu::SwapChainError>
     {
@@ -545,7 +479,7 @@ impl State{
             render_pass.set_vertex_buffer(1,self.instance_buffer.slice(..));
             render_pass.set_index_buffer(self.indices.slice(..),IndexFormat::Uint32);
             //render_pass.draw(0..VERTICES.len() as u32,0..1);
-            render_pass.draw_indexed(0..INDICES.len() as u32,0,0..self.instance_buf.len() as _);
+            render_pass.draw_indexed(0..INDICES.len() as u32,0,0..self.instance_buffer.len() as _);
         }
         {
             let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor{
@@ -13,10 +13,23 @@ use cgmath::{Matrix4, SquareMatrix, Vector3, Zero, Rad, Quaternion, Rotation3, V
 use winit::dpi::{Pixel, PhysicalPosition};
 use std::ops::Range;
 use wgpu::LoadOp::Clear;
-use utils::{from_raw_parts, from_raw_parts_ex};
+use utils::bytes::AsBytes;
+use utils::buffer::GpuBuffer;
+use utils::uniform::{UniformLayout, AddressSpace};
+use utils::vertex::{VertexLayout, VertexLayoutDesc};
+use utils::reflect::{check_vertex_layouts, check_bind_group_layouts, reflect_bind_group_layouts};
+use utils::pipeline::{PipelineCache, PipelineDesc, DepthState};
+use utils::material::CompareDesc;
+use utils::render_graph::{RenderGraph, RenderNode, GraphResources, AttachmentDesc, AttachmentSize, PassDesc, Target, GraphBinding, BindGroupId};
+use wgpu::RenderPass;
+use std::rc::Rc;
+use std::cell::Cell;
+use utils::bounds::{Aabb, Frustum};
+use utils::camera::OPENGL_TO_WGPU_MATRIX;
+use utils::debug_view::{DebugView, DebugSource};
 
 #[repr(C)]
-#[derive(Debug,Copy, Clone,bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug,Copy,Clone,VertexLayout,AsBytes)]
 struct Vertex{
     position: [f32;3],
     color: [f32;4],
@@ -37,49 +50,27 @@ const INDICES: &[u32] = &[
     2, 3, 4,
 ];
 
-const DEPTH_VERTICES: &[Vertex] = &[
-    Vertex {
-        position: [-0.9, -0.9, 0.0],
-        tex_coords: [0.0, 1.0],
-        color : [1.0,1.0,1.0,1.0]
-    },
-    Vertex {
-        position: [0.9, -0.9, 0.0],
-        tex_coords: [1.0, 1.0],
-        color : [1.0,1.0,1.0,1.0]
-    },
-    Vertex {
-        position: [0.9, 0.9, 0.0],
-        tex_coords: [1.0, 0.0],
-        color : [1.0,1.0,1.0,1.0]
-    },
-    Vertex {
-        position: [-0.9, 0.9, 0.0],
-        tex_coords: [0.0, 0.0],
-        color : [1.0,1.0,1.0,1.0]
-    },
-];
-
-const DEPTH_INDICES: &[u32] = &[0, 1, 2, 0, 2, 3];
+const NEAR: f32 = 0.1;
+const FAR: f32 = 100.0;
 
-#[repr(C)]
-#[derive(Debug,Copy, Clone)]
+#[derive(Debug,Copy, Clone,UniformLayout)]
 struct Uniform {
     projection: Matrix4<f32>,
     view: Matrix4<f32>,
 }
-#[repr(C)]
-#[derive(Debug,Copy, Clone)]
-struct Uniform2 {
-    projection: Matrix4<f32>,
-    view: Matrix4<f32>,
-    model: Matrix4<f32>,
-}
 struct Instance{
     pos: Vector3<f32>,
     quaternion: Quaternion<f32>,
 }
 
+#[repr(C)]
+#[derive(Debug,Copy,Clone,VertexLayout,AsBytes)]
+#[step(instance)]
+struct InstanceRaw{
+    #[location(5)]
+    model: Matrix4<f32>,
+}
+
 struct State{
     surface : wgpu::Surface,
     device : wgpu::Device,
@@ -88,24 +79,45 @@ struct State{
     swap_chain : wgpu::SwapChain,
     size : winit::dpi::PhysicalSize<u32>,
     clear_color : wgpu::Color,
-    pipeline : wgpu::RenderPipeline,
-    vertices : wgpu::Buffer,
-    indices : wgpu::Buffer,
     img1: (Texture,TextureView,Sampler),
-    bind_groups : Vec<BindGroup>,
     uniform : Uniform,
     uniform_buf : Buffer,
     instances: Vec<Instance>,
-    instance_buf: Vec<Matrix4<f32>>,
     rotate : Vector3<f32>,
-    instance_buffer: Buffer,
     left_btn_down: bool,
     last_cursor_pos : Vector2<f32>,
-    depth_stencil : (Texture,TextureView,Sampler),
-    mesh_quad: (Buffer,Buffer),
-    depth_pipeline:RenderPipeline,
-    depth_uniform:(Uniform2,Buffer),
-    depth_bind_group_layout: BindGroupLayout,
+    graph: RenderGraph,
+    pipelines: PipelineCache,
+    mesh_bounds: Aabb,
+    /// Shared with the `SceneNode`, only the instances in the view are written to it.
+    instance_buffer: Rc<GpuBuffer<InstanceRaw>>,
+    visible: Rc<Cell<u32>>,
+    culling: bool,
+    /// Shows the depth attachment, V switches the layout.
+    debug_view: DebugView,
+}
+
+/// The instanced scene, it renders the depth attachment.
+struct SceneNode{
+    pipeline : Rc<RenderPipeline>,
+    vertices : GpuBuffer<Vertex>,
+    indices : GpuBuffer<u32>,
+    bind_groups : Vec<BindGroup>,
+    instance_buffer: Rc<GpuBuffer<InstanceRaw>>,
+    visible: Rc<Cell<u32>>,
+}
+
+impl RenderNode for SceneNode {
+    fn record<'a>(&'a self,render_pass:&mut RenderPass<'a>,res:&'a GraphResources)
+    {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0,&self.bind_groups[0],&[]);
+        render_pass.set_bind_group(1,&self.bind_groups[1],&[]);
+        render_pass.set_vertex_buffer(0,self.vertices.slice(..));
+        render_pass.set_vertex_buffer(1,self.instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.indices.slice(..),IndexFormat::Uint32);
+        render_pass.draw_indexed(0..INDICES.len() as u32,0,0..self.visible.get());
+    }
 }
 
 impl State{
@@ -138,69 +150,42 @@ impl State{
 
         let clear_color = wgpu::Color::BLACK;
 
-        let shader = device.create_shader_module(&ShaderModuleDescriptor{
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-            flags: ShaderFlags::all()
-        });
-        let shader_depth = device.create_shader_module(&ShaderModuleDescriptor{
-            label: Some("Shader Depth"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader_depth.wgsl").into()),
-            flags: ShaderFlags::all()
-        });
+        if let Err(e) = check_vertex_layouts(include_str!("shader.wgsl"),"main",&[Vertex::layout(),InstanceRaw::layout()]) {
+            panic!("shader.wgsl: {}",e);
+        }
 
-        let vertices = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Vertices"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: BufferUsage::VERTEX
-        });
+        let vertices = GpuBuffer::new(&device,Some("Vertices"),BufferUsage::VERTEX,VERTICES);
 
-        let indices = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Indices"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: BufferUsage::INDEX
-        });
-        
-        let depth_vertices = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Depth Vertices"),
-            contents: bytemuck::cast_slice(DEPTH_VERTICES),
-            usage: BufferUsage::VERTEX
-        });
+        let indices = GpuBuffer::new(&device,Some("Indices"),BufferUsage::INDEX,INDICES);
 
-        let depth_indices = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Depth Indices"),
-            contents: bytemuck::cast_slice(DEPTH_INDICES),
-            usage: BufferUsage::INDEX
-        });
-        
-        
         let img_data = include_bytes!("../textures/happy-tree.png");
         let (texture,texture_view,sampler) = Self::load_texture(&device,&queue,img_data).unwrap();
 
-        let bind_group_layout =  device.create_bind_group_layout(&BindGroupLayoutDescriptor{
-            label: Some("Bind Group Layout"),
-            entries: &[
-                BindGroupLayoutEntry{
-                    binding: 0,
-                    visibility: ShaderStage::FRAGMENT,
-                    ty: BindingType::Texture {
-                        sample_type: TextureSampleType::Float{ filterable: true },
-                        view_dimension: TextureViewDimension::D2,
-                        multisampled: false
-                    },
-                    count: None
+        let texture_entries = [
+            BindGroupLayoutEntry{
+                binding: 0,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float{ filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false
                 },
-                BindGroupLayoutEntry{
-                    binding: 1,
-                    visibility: ShaderStage::FRAGMENT,
-                    ty: BindingType::Sampler{
-                        filtering: true,
-                        comparison: false
-                    },
-                    count: None
-                }
-            ]
-        });
+                count: None
+            },
+            BindGroupLayoutEntry{
+                binding: 1,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Sampler{
+                    filtering: true,
+                    comparison: false
+                },
+                count: None
+            }
+        ];
+        let bind_group_layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Bind Group Layout"),
+            entries: &texture_entries
+        }));
 
         let bind_group = device.create_bind_group(&BindGroupDescriptor{
             label: Some("Depth Bind Group"),
@@ -211,84 +196,42 @@ impl State{
             ]
         });
 
-        let depth_stencil = Self::create_depth_stencil(&device,&sc_desc);
-
-        let depth_bind_group_layout =  device.create_bind_group_layout(&BindGroupLayoutDescriptor{
-            label: Some("Depth Bind Group Layout"),
-            entries: &[
-                BindGroupLayoutEntry{
-                    binding: 0,
-                    visibility: ShaderStage::FRAGMENT,
-                    ty: BindingType::Texture {
-                        sample_type: TextureSampleType::Depth,
-                        view_dimension: TextureViewDimension::D2,
-                        multisampled: false
-                    },
-                    count: None
-                },
-                BindGroupLayoutEntry{
-                    binding: 1,
-                    visibility: ShaderStage::FRAGMENT,
-                    ty: BindingType::Sampler{
-                        filtering: true,
-                        comparison: false
-                    },
-                    count: None
-                }
-            ]
-        });
-
-        let depth_bind_group = device.create_bind_group(&BindGroupDescriptor{
-            label: Some("Depth Bind Group"),
-            layout: &depth_bind_group_layout,
-            entries: &[
-                BindGroupEntry{ binding: 0, resource: BindingResource::TextureView(&depth_stencil.1) },
-                BindGroupEntry{ binding: 1, resource: BindingResource::Sampler(&depth_stencil.2) }
-            ]
-        });
-
-        let uniform = Uniform::new(60.0,size.width as f32 / 2.0f32 / size.height as f32 );
-        let uniform2 = Uniform2::new(60.0,size.width as f32 / 2.0f32 / size.height as f32 );
+        let uniform = Uniform::new(60.0,size.width as f32 / size.height as f32);
 
         let uniform_buf = device.create_buffer_init(&BufferInitDescriptor{
             label: Some("Uniform Buffer"),
-            contents: unsafe { from_raw_parts(&uniform) },
-            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST
-        });
-
-        let uniform2_buf = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Depth Uniform Buffer"),
-            contents: unsafe { from_raw_parts(&uniform2) },
+            contents: uniform.to_bytes().as_slice(),
             usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST
         });
 
         let instances = Instance::gen_instances(255,15,Vector3::new(0.0,0.03,0.0),1.0);
         let instance_buf:Vec<_> = instances.iter().map(|it|{
-            it.to_matrix()
-
+            InstanceRaw{ model: it.to_matrix() }
         }).collect();
 
-        let instance_buffer = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Instance Buffer"),
-            contents: unsafe { from_raw_parts_ex(instance_buf.as_slice()) },
-            usage: BufferUsage::VERTEX
-        });
-
-        let vertex_binding_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+        let instance_buffer = Rc::new(GpuBuffer::new(&device,Some("Instance Buffer"),BufferUsage::VERTEX,instance_buf.as_slice()));
+        let visible = Rc::new(Cell::new(instance_buf.len() as u32));
+
+        let vertex_entries = [
+            wgpu::BindGroupLayoutEntry{
+                binding: 0,
+                visibility: ShaderStage::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None
+                },
+                count: None
+            }
+        ];
+        let vertex_binding_group_layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
             label: Some("Vertex Binding Group"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry{
-                    binding: 0,
-                    visibility: ShaderStage::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None
-                    },
-                    count: None
-                }
-            ]
-        });
+            entries: &vertex_entries
+        }));
+
+        if let Err(e) = check_bind_group_layouts(include_str!("shader.wgsl"),&[&texture_entries,&vertex_entries]) {
+            panic!("shader.wgsl: {}",e);
+        }
 
         let vertex_binding_group = device.create_bind_group(&BindGroupDescriptor{
             label: Some("Vertex binding Gropu"),
@@ -301,27 +244,41 @@ impl State{
             ]
         });
 
-        let depth_vertex_binding_group = device.create_bind_group(&BindGroupDescriptor{
-            label: Some("Vertex binding Gropu"),
-            layout: &vertex_binding_group_layout,
-            entries: &[
-                BindGroupEntry{
-                    binding: 0,
-                    resource: uniform2_buf.as_entire_binding()
-                }
-            ]
+        let mut pipelines = PipelineCache::new(sc_desc.format);
+        let shader = pipelines.shader(&device,"shader.wgsl",include_str!("shader.wgsl"));
+        let layouts = vec![pipelines.layout(&bind_group_layout),pipelines.layout(&vertex_binding_group_layout)];
+        let pipeline = pipelines.get(&device,&PipelineDesc{
+            depth: Some(DepthState{ format: TextureFormat::Depth32Float, write: true, compare: CompareDesc::Less }),
+            ..PipelineDesc::new(shader,layouts,vec![Vertex::layout(),InstanceRaw::layout()])
         });
 
-        let pipeline = Self::create_pipeline(&device,&shader,&sc_desc,&[&bind_group_layout,
-            &vertex_binding_group_layout],&[VertexBufferLayout{
-            array_stride: size_of::<Matrix4<f32>>() as _,
-            step_mode: InputStepMode::Instance,
-            attributes: &wgpu::vertex_attr_array![ 5 => Float32x4,6 => Float32x4,7 => Float32x4,8 => Float32x4 ]
-        }],true);
-
-        let depth_pipeline = Self::create_pipeline(&device,&shader_depth,&sc_desc,&[
-            &depth_bind_group_layout,&vertex_binding_group_layout
-        ],&[],false);
+        let mut graph = RenderGraph::new(&sc_desc);
+        let depth = graph.add_attachment(AttachmentDesc{
+            label: "Depth Stencil Tex".to_string(),
+            format: TextureFormat::Depth32Float,
+            size: AttachmentSize::Relative(1.0),
+            usage: TextureUsage::empty(),
+            multisampled: false
+        });
+        graph.add_pass(PassDesc{
+            label: "Render Pass".to_string(),
+            colors: vec![(Target::SwapChain,LoadOp::Clear(clear_color))],
+            depth: Some((depth,LoadOp::Clear(1f32))),
+            reads: vec![]
+        },Box::new(SceneNode{
+            pipeline,
+            vertices,
+            indices,
+            bind_groups: vec![bind_group,vertex_binding_group],
+            instance_buffer: instance_buffer.clone(),
+            visible: visible.clone()
+        }));
+        let debug_view = DebugView::new(&device,&mut graph,&mut pipelines,vec![
+            ("Depth",depth,DebugSource::LinearDepth{ near: NEAR, far: FAR })
+        ]);
+        if let Err(e) = graph.build(&device) {
+            panic!("render graph: {}",e);
+        }
 
         State{
             surface,
@@ -331,124 +288,23 @@ impl State{
             swap_chain,
             size,
             clear_color,
-            pipeline,
-            vertices,
-            indices,
             img1: (texture,texture_view,sampler),
-            bind_groups: vec![bind_group,vertex_binding_group,depth_bind_group,depth_vertex_binding_group],
             uniform,
             uniform_buf,
             rotate: Vector3::zero(),
             instances,
-            instance_buf,
-            instance_buffer,
             left_btn_down :false,
             last_cursor_pos: Vector2::zero(),
-            depth_stencil,
-            mesh_quad: (depth_vertices,depth_indices),
-            depth_pipeline,
-            depth_uniform : (uniform2,uniform2_buf),
-            depth_bind_group_layout
+            graph,
+            pipelines,
+            mesh_bounds: Aabb::from_points(VERTICES.iter().map(|v| v.position)).unwrap(),
+            instance_buffer,
+            visible,
+            culling: true,
+            debug_view
         }
     }
 
-    fn create_depth_stencil(device:&Device,sc_desc:&SwapChainDescriptor)-> (Texture,TextureView,Sampler)
-    {
-        let tex = device.create_texture(&TextureDescriptor{
-            label: Some("Depth Stencil Tex"),
-            size: Extent3d{
-                width: sc_desc.width,
-                height: sc_desc.height,
-                depth_or_array_layers: 1
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Depth32Float,
-            usage: TextureUsage::RENDER_ATTACHMENT | TextureUsage::SAMPLED
-        });
-        let tex_view = tex.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&SamplerDescriptor{
-            label: Some("Depth Stencil Sampler"),
-            address_mode_u: AddressMode::ClampToEdge,
-            address_mode_v: AddressMode::ClampToEdge,
-            address_mode_w: AddressMode::ClampToEdge,
-            mag_filter: FilterMode::Linear,
-            min_filter: FilterMode::Linear,
-            mipmap_filter: FilterMode::Nearest,
-            lod_min_clamp: -100 as _,
-            lod_max_clamp: 100 as _,
-            compare: None,
-            anisotropy_clamp: None,
-            border_color: None
-        });
-        (tex,tex_view,sampler)
-    }
-
-    fn create_pipeline(
-        device:& wgpu::Device,shader:&ShaderModule,
-        sc_desc:&SwapChainDescriptor,
-        bind_group_layouts:&'_[&'_ BindGroupLayout],
-        added_vertex_buffer:&[VertexBufferLayout],
-        has_depth_stencil:bool) -> wgpu::RenderPipeline
-    {
-        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor{
-            label: Some("Render Pipeline Layout"),
-            bind_group_layouts,
-            push_constant_ranges: &[]
-        });
-
-        let attributes = wgpu::vertex_attr_array![ 0 => Float32x3, 1=> Float32x4, 2=> Float32x2 ];
-        let mut vertex_buffers = vec![ VertexBufferLayout{
-            array_stride: size_of::<Vertex>() as _,
-            step_mode: InputStepMode::Vertex,
-            attributes: &attributes
-        }];
-        if added_vertex_buffer.len() > 0{
-            vertex_buffers.extend_from_slice(added_vertex_buffer);
-        }
-
-        device.create_render_pipeline(&RenderPipelineDescriptor{
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: VertexState{
-                module: &shader,
-                entry_point: "main",
-                buffers: vertex_buffers.as_slice()
-            },
-            fragment: Some(FragmentState{
-                module: &shader,
-                entry_point: "main",
-                targets: &[ColorTargetState{
-                    format: sc_desc.format,
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrite::all()
-                }]
-            }),
-            primitive: PrimitiveState{
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: FrontFace::Ccw,
-                cull_mode: None,
-                clamp_depth: false,
-                polygon_mode: PolygonMode::Fill,
-                conservative: false
-            },
-            depth_stencil: if has_depth_stencil { Some(DepthStencilState{
-                format: TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Less,
-                stencil: Default::default(),
-                bias: Default::default()
-            }) }else{None},
-            multisample: MultisampleState{
-                count: 1,
-                mask: u64::MAX,
-                alpha_to_coverage_enabled: false
-            }
-        })
-    }
-
     fn resize(&mut self,size:winit::dpi::PhysicalSize<u32>)
     {
         if size.width > 0 && size.height > 0
@@ -457,16 +313,7 @@ impl State{
             self.sc_desc.width = size.width;
             self.sc_desc.height = size.height;
             self.swap_chain = self.device.create_swap_chain(&self.surface,&self.sc_desc);
-            self.depth_stencil = Self::create_depth_stencil(&self.device,&self.sc_desc);
-
-            self.bind_groups[2] = self.device.create_bind_group(&BindGroupDescriptor{
-                label: Some("Depth Bind Group"),
-                layout: &self.depth_bind_group_layout,
-                entries: &[
-                    BindGroupEntry{ binding: 0, resource: BindingResource::TextureView(&self.depth_stencil.1) },
-                    BindGroupEntry{ binding: 1, resource: BindingResource::Sampler(&self.depth_stencil.2) }
-                ]
-            });
+            self.graph.resize(&self.device,&self.sc_desc);
         }
     }
 
@@ -476,6 +323,15 @@ impl State{
             &WindowEvent::KeyboardInput{ input:KeyboardInput{
                 virtual_keycode:Some(VirtualKeyCode::Space),state:ElementState::Released,..
             },.. } => {
+                self.culling = !self.culling;
+                window.set_title(self.title().as_str());
+                true
+            }
+            &WindowEvent::KeyboardInput{ input:KeyboardInput{
+                virtual_keycode:Some(VirtualKeyCode::V),state:ElementState::Released,..
+            },.. } => {
+                self.debug_view.next_layout();
+                window.set_title(self.title().as_str());
                 true
             }
             &WindowEvent::MouseInput {
@@ -505,11 +361,24 @@ impl State{
         }
     }
 
+    fn title(&self) -> String
+    {
+        format!("swap chain{} - {}: {}",if self.culling { " (culling)" } else { "" },
+            self.debug_view.selected_name(),self.debug_view.layout().name())
+    }
+
     fn update(&mut self) {
         self.uniform.set_rotate(self.rotate);
-        unsafe { self.queue.write_buffer(&self.uniform_buf, 0, from_raw_parts(&self.uniform)) }
-        //self.depth_uniform.0.set_rotate(self.rotate);
-        //unsafe { self.queue.write_buffer(&self.depth_uniform.1, 0, from_raw_parts(&self.depth_uniform.0)) }
+        self.queue.write_buffer(&self.uniform_buf, 0, self.uniform.to_bytes().as_slice());
+        // only the instances whose box touches the view volume are drawn
+        let frustum = Frustum::from_matrix(&(self.uniform.projection * self.uniform.view));
+        let visible:Vec<_> = self.instances.iter().map(|it| InstanceRaw{ model: it.to_matrix() })
+            .filter(|raw| !self.culling || frustum.intersects_aabb(&self.mesh_bounds.transform(&raw.model)))
+            .collect();
+        if !visible.is_empty() {
+            self.instance_buffer.write(&self.queue,0,visible.as_slice());
+        }
+        self.visible.set(visible.len() as u32);
     }
     fn render(&mut self) -> Result<(),wgpu::SwapChainError>
     {
@@ -518,59 +387,7 @@ impl State{
         let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor{
             label: Some("Render Encoder")
         });
-        {
-            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor{
-                label: Some("Render Pass"),
-                color_attachments: &[
-                    wgpu::RenderPassColorAttachment{
-                        view: &frame.view,
-                        resolve_target: None,
-                        ops: wgpu::Operations{
-                            load: wgpu::LoadOp::Clear(self.clear_color),
-                            store: true
-                        }
-                    }
-                ],
-                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment{
-                    view: &self.depth_stencil.1,
-                    depth_ops: Some(Operations::<f32>{ load: LoadOp::Clear(1f32), store: true }),
-                    stencil_ops: None
-                })
-            });
-            render_pass.set_viewport(0.0,0.0,self.sc_desc.width as f32 / 2f32,self.sc_desc.height as f32,0.0,1.0);
-            render_pass.set_pipeline(&self.pipeline);
-            render_pass.set_bind_group(0,&self.bind_groups[0],&[]);
-            render_pass.set_bind_group(1,&self.bind_groups[1],&[]);
-            render_pass.set_vertex_buffer(0,self.vertices.slice(..));
-            render_pass.set_vertex_buffer(1,self.instance_buffer.slice(..));
-            render_pass.set_index_buffer(self.indices.slice(..),IndexFormat::Uint32);
-            //render_pass.draw(0..VERTICES.len() as u32,0..1);
-            render_pass.draw_indexed(0..INDICES.len() as u32,0,0..self.instance_buf.len() as _);
-        }
-        {
-            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor{
-                label: Some("Render Pass depth"),
-                color_attachments: &[
-                    wgpu::RenderPassColorAttachment{
-                        view: &frame.view,
-                        resolve_target: None,
-                        ops: wgpu::Operations{
-                            load: wgpu::LoadOp::Load,
-                            store: true
-                        }
-                    }
-                ],
-                depth_stencil_attachment: None
-            });
-            render_pass.set_viewport(self.sc_desc.width as f32 / 2f32,0.0,self.sc_desc.width as f32 / 2f32,self.sc_desc.height as f32,0.0,1.0);
-            render_pass.set_pipeline(&self.depth_pipeline);
-            render_pass.set_bind_group(0,&self.bind_groups[2],&[]);
-            render_pass.set_bind_group(1,&self.bind_groups[3],&[]);
-            render_pass.set_vertex_buffer(0,self.mesh_quad.0.slice(..));
-            render_pass.set_index_buffer(self.mesh_quad.1.slice(..),IndexFormat::Uint32);
-            //render_pass.draw(0..VERTICES.len() as u32,0..1);
-            render_pass.draw_indexed(0..DEPTH_INDICES.len() as u32,0,0..1);
-        }
+        self.graph.execute(&mut encoder,&frame.view);
         self.queue.submit(std::iter::once(encoder.finish()));
 
         Ok(())
@@ -637,6 +454,7 @@ fn main() {
         .build(&event_loop).unwrap();
 
     let mut state = pollster::block_on(State::new(&window));
+    window.set_title(state.title().as_str());
 
     event_loop.run(move |e,_,control_flow|{
         match e {
@@ -690,7 +508,7 @@ fn main() {
 impl Uniform{
     fn new(fovy:f32,aspect:f32) -> Uniform
     {
-        let proj = cgmath::perspective(cgmath::Deg(fovy),aspect,0.1,100f32);
+        let proj = OPENGL_TO_WGPU_MATRIX * cgmath::perspective(cgmath::Deg(fovy),aspect,NEAR,FAR);
         Uniform{
             projection : proj,
             view : cgmath::Matrix4::from_translation(Vector3::new(0.0,0.0,-9f32)),
@@ -707,29 +525,6 @@ impl Uniform{
         self.view = mat;
     }
 }
-impl Uniform2{
-    fn new(fovy:f32,aspect:f32) -> Uniform2
-    {
-        let proj = cgmath::perspective(cgmath::Deg(fovy),aspect,0.1,100f32);
-        Uniform2{
-            projection : proj,
-            view : cgmath::Matrix4::from_translation(Vector3::new(0.0,0.0,-2f32)),
-            model : cgmath::Matrix4::identity()
-        }
-    }
-
-    fn set_rotate(&mut self,r:Vector3<f32>)
-    {
-        let mat =
-            cgmath::Matrix4::from_translation(Vector3::new(0.0,0.0,-2f32)) *
-                Matrix4::from_angle_x(Rad(r.x)) *
-                Matrix4::from_angle_y(Rad(r.y)) *
-                Matrix4::from_angle_z(Rad(r.z));
-        self.view = mat;
-    }
-}
-
-
 impl Instance {
     fn to_matrix(&self) -> Matrix4<f32>
     {
@@ -13,10 +13,15 @@ use cgmath::{Matrix4, SquareMatrix, Vector3, Zero, Rad, Quaternion, Rotation3, V
 use winit::dpi::{Pixel, PhysicalPosition};
 use std::ops::Range;
 use wgpu::LoadOp::Clear;
-use utils::{from_raw_parts,from_raw_parts_ex};
+use utils::bytes::AsBytes;
+use utils::buffer::{GpuBuffer, GpuVec};
+use utils::uniform::{UniformLayout, AddressSpace};
+use utils::vertex::VertexLayout;
+use utils::reflect::check_vertex_layouts;
+use utils::msaa::{MsaaTargets, check_sample_count};
 
 #[repr(C)]
-#[derive(Debug,Copy, Clone,bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug,Copy,Clone,VertexLayout,AsBytes)]
 struct Vertex{
     position: [f32;3],
     color: [f32;4],
@@ -37,8 +42,10 @@ const INDICES: &[u32] = &[
     2, 3, 4,
 ];
 
-#[repr(C)]
-#[derive(Debug,Copy, Clone)]
+/// Falls back to no MSAA when the count is not one every adapter supports.
+const SAMPLE_COUNT: u32 = 4;
+
+#[derive(Debug,Copy, Clone,UniformLayout)]
 struct Uniform {
     projection: Matrix4<f32>,
     view: Matrix4<f32>,
@@ -48,6 +55,14 @@ struct Instance{
     quaternion: Quaternion<f32>,
 }
 
+#[repr(C)]
+#[derive(Debug,Copy,Clone,VertexLayout,AsBytes)]
+#[step(instance)]
+struct InstanceRaw{
+    #[location(5)]
+    model: Matrix4<f32>,
+}
+
 struct State{
     surface : wgpu::Surface,
     device : wgpu::Device,
@@ -57,20 +72,19 @@ struct State{
     size : winit::dpi::PhysicalSize<u32>,
     clear_color : wgpu::Color,
     pipeline : wgpu::RenderPipeline,
-    vertices : wgpu::Buffer,
-    indices : wgpu::Buffer,
+    vertices : GpuBuffer<Vertex>,
+    indices : GpuBuffer<u32>,
     img1: (Texture,TextureView,Sampler),
     bind_group_layouts: Vec<BindGroupLayout>,
     bind_groups : Vec<BindGroup>,
     uniform : Uniform,
     uniform_buf : Buffer,
     instances: Vec<Instance>,
-    instance_buf: Vec<Matrix4<f32>>,
     rotate : Vector3<f32>,
-    instance_buffer: Buffer,
+    instance_buffer: GpuVec<InstanceRaw>,
     left_btn_down: bool,
     last_cursor_pos : Vector2<f32>,
-    depth_stencil : (Texture,TextureView,Sampler)
+    msaa : MsaaTargets
 }
 
 impl State{
@@ -100,6 +114,10 @@ impl State{
         };
 
         let swap_chain = device.create_swap_chain(&surface,&sc_desc);
+        let sample_count = check_sample_count(SAMPLE_COUNT).unwrap_or_else(|e|{
+            eprintln!("{}, rendering without MSAA",e);
+            1
+        });
 
         let clear_color = wgpu::Color::BLACK;
 
@@ -108,18 +126,13 @@ impl State{
             source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
             flags: ShaderFlags::all()
         });
+        if let Err(e) = check_vertex_layouts(include_str!("shader.wgsl"),"main",&[Vertex::layout(),InstanceRaw::layout()]) {
+            panic!("shader.wgsl: {}",e);
+        }
 
-        let vertices = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Vertices"),
-            contents: bytemuck::cast_slice(VERTICES),
-            usage: BufferUsage::VERTEX
-        });
+        let vertices = GpuBuffer::new(&device,Some("Vertices"),BufferUsage::VERTEX,VERTICES);
 
-        let indices = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Indices"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: BufferUsage::INDEX
-        });
+        let indices = GpuBuffer::new(&device,Some("Indices"),BufferUsage::INDEX,INDICES);
         let img_data = include_bytes!("../textures/happy-tree.png");
         let (texture,texture_view,sampler) = Self::load_texture(&device,&queue,img_data).unwrap();
 
@@ -160,20 +173,16 @@ impl State{
 
         let uniform_buf = device.create_buffer_init(&BufferInitDescriptor{
             label: Some("Uniform Buffer"),
-            contents: unsafe { from_raw_parts(&uniform) },
+            contents: uniform.to_bytes().as_slice(),
             usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST
         });
 
         let instances = Instance::gen_instances(81,9,Vector3::new(0.0,0.03,0.0),1.0);
         let instance_buf:Vec<_> = instances.iter().map(|it|{
-            it.to_matrix()
+            InstanceRaw{ model: it.to_matrix() }
         }).collect();
 
-        let instance_buffer = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Instance Buffer"),
-            contents: unsafe { from_raw_parts_ex(instance_buf.as_slice()) },
-            usage: BufferUsage::VERTEX
-        });
+        let instance_buffer = GpuVec::from_vec(&device,Some("Instance Buffer"),BufferUsage::VERTEX,instance_buf);
 
         let vertex_binding_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor{
             label: Some("Vertex Binding Group"),
@@ -203,8 +212,8 @@ impl State{
         });
 
         let pipeline = Self::create_pipeline(&device,&shader,&sc_desc,&[&bind_group_layout,
-            &vertex_binding_group_layout]);
-        let depth_stencil = Self::create_depth_stencil(&device,&sc_desc);
+            &vertex_binding_group_layout],sample_count);
+        let msaa = MsaaTargets::new(&device,&sc_desc,sample_count,Some(TextureFormat::Depth32Float));
         State{
             surface,
             device,
@@ -223,50 +232,17 @@ impl State{
             uniform_buf,
             rotate: Vector3::zero(),
             instances,
-            instance_buf,
             instance_buffer,
             left_btn_down :false,
             last_cursor_pos: Vector2::zero(),
-            depth_stencil
+            msaa
         }
     }
 
-    fn create_depth_stencil(device:&Device,sc_desc:&SwapChainDescriptor)-> (Texture,TextureView,Sampler)
-    {
-        let tex = device.create_texture(&TextureDescriptor{
-            label: Some("Depth Stencil Tex"),
-            size: Extent3d{
-                width: sc_desc.width,
-                height: sc_desc.height,
-                depth_or_array_layers: 1
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Depth32Float,
-            usage: TextureUsage::RENDER_ATTACHMENT | TextureUsage::SAMPLED
-        });
-        let tex_view = tex.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&SamplerDescriptor{
-            label: Some("Depth Stencil Sampler"),
-            address_mode_u: AddressMode::ClampToEdge,
-            address_mode_v: AddressMode::ClampToEdge,
-            address_mode_w: AddressMode::ClampToEdge,
-            mag_filter: FilterMode::Linear,
-            min_filter: FilterMode::Linear,
-            mipmap_filter: FilterMode::Nearest,
-            lod_min_clamp: -100 as _,
-            lod_max_clamp: 100 as _,
-            compare: Some(CompareFunction::LessEqual),
-            anisotropy_clamp: None,
-            border_color: None
-        });
-        (tex,tex_view,sampler)
-    }
-
     fn create_pipeline(device:& wgpu::Device,shader:&ShaderModule,
                        sc_desc:&SwapChainDescriptor,
-                       bind_group_layouts:&'_[&'_ BindGroupLayout]) -> wgpu::RenderPipeline
+                       bind_group_layouts:&'_[&'_ BindGroupLayout],
+                       sample_count:u32) -> wgpu::RenderPipeline
     {
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor{
             label: Some("Render Pipeline Layout"),
@@ -274,23 +250,15 @@ impl State{
             push_constant_ranges: &[]
         });
 
+        let vertex_layouts = [Vertex::layout(),InstanceRaw::layout()];
+        let vertex_buffers:Vec<_> = vertex_layouts.iter().map(|l| l.as_layout()).collect();
         device.create_render_pipeline(&RenderPipelineDescriptor{
             label: Some("Render Pipeline"),
             layout: Some(&pipeline_layout),
             vertex: VertexState{
                 module: &shader,
                 entry_point: "main",
-                buffers: &[ VertexBufferLayout{
-                    array_stride: size_of::<Vertex>() as _,
-                    step_mode: InputStepMode::Vertex,
-                    attributes: &wgpu::vertex_attr_array![ 0 => Float32x3, 1=> Float32x4, 2=> Float32x2 ]
-                },
-                    VertexBufferLayout{
-                        array_stride: size_of::<Matrix4<f32>>() as _,
-                        step_mode: InputStepMode::Instance,
-                        attributes: &wgpu::vertex_attr_array![ 5 => Float32x4,6 => Float32x4,7 => Float32x4,8 => Float32x4 ]
-                    }
-                ]
+                buffers: vertex_buffers.as_slice()
             },
             fragment: Some(FragmentState{
                 module: &shader,
@@ -318,7 +286,7 @@ impl State{
                 bias: Default::default()
             }),
             multisample: MultisampleState{
-                count: 1,
+                count: sample_count,
                 mask: u64::MAX,
                 alpha_to_coverage_enabled: false
             }
@@ -333,7 +301,7 @@ impl State{
             self.sc_desc.width = size.width;
             self.sc_desc.height = size.height;
             self.swap_chain = self.device.create_swap_chain(&self.surface,&self.sc_desc);
-            self.depth_stencil = Self::create_depth_stencil(&self.device,&self.sc_desc);
+            self.msaa.resize(&self.device,&self.sc_desc);
         }
     }
 
@@ -343,6 +311,12 @@ impl State{
             &WindowEvent::KeyboardInput{ input:KeyboardInput{
                 virtual_keycode:Some(VirtualKeyCode::Space),state:ElementState::Released,..
             },.. } => {
+                // one more row, the instance buffer grows when it is full
+                let all = Instance::gen_instances(self.instances.len() + 9,9,Vector3::new(0.0,0.03,0.0),1.0);
+                for it in all.into_iter().skip(self.instances.len()) {
+                    self.instance_buffer.push(InstanceRaw{ model: it.to_matrix() });
+                    self.instances.push(it);
+                }
                 true
             }
             &WindowEvent::MouseInput {
@@ -374,7 +348,8 @@ impl State{
 
     fn update(&mut self) {
         self.uniform.set_rotate(self.rotate);
-        unsafe { self.queue.write_buffer(&self.uniform_buf, 0, from_raw_parts(&self.uniform)) }
+        self.queue.write_buffer(&self.uniform_buf, 0, self.uniform.to_bytes().as_slice());
+        self.instance_buffer.sync(&self.device,&self.queue);
     }
     fn render(&mut self) -> Result<(),wgpu::SwapChainError>
     {
@@ -386,21 +361,8 @@ impl State{
         {
             let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor{
                 label: Some("Render Pass"),
-                color_attachments: &[
-                    wgpu::RenderPassColorAttachment{
-                        view: &frame.view,
-                        resolve_target: None,
-                        ops: wgpu::Operations{
-                            load: wgpu::LoadOp::Clear(self.clear_color),
-                            store: true
-                        }
-                    }
-                ],
-                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment{
-                    view: &self.depth_stencil.1,
-                    depth_ops: Some(Operations::<f32>{ load: LoadOp::Clear(1f32), store: true }),
-                    stencil_ops: None
-                })
+                color_attachments: &[self.msaa.color_attachment(&frame.view,LoadOp::Clear(self.clear_color))],
+                depth_stencil_attachment: self.msaa.depth_attachment(LoadOp::Clear(1f32))
             });
             render_pass.set_pipeline(&self.pipeline);
             render_pass.set_bind_group(0,&self.bind_groups[0],&[]);
@@ -409,7 +371,7 @@ impl State{
             render_pass.set_vertex_buffer(1,self.instance_buffer.slice(..));
             render_pass.set_index_buffer(self.indices.slice(..),IndexFormat::Uint32);
             //render_pass.draw(0..VERTICES.len() as u32,0..1);
-            render_pass.draw_indexed(0..INDICES.len() as u32,0,0..self.instance_buf.len() as _);
+            render_pass.draw_indexed(0..INDICES.len() as u32,0,0..self.instance_buffer.len() as _);
         }
         self.queue.submit(std::iter::once(encoder.finish()));
         Ok(())
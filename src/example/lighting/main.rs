@@ -0,0 +1,551 @@
+use winit::{
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+use winit::window::Window;
+use wgpu::{BackendBit, RequestAdapterOptions, PowerPreference, DeviceDescriptor, Features, TextureUsage, TextureFormat, PresentMode, CommandEncoderDescriptor, BufferUsage, BindGroupDescriptor, BindGroupEntry, BindGroup, Buffer, LoadOp, RenderPipeline, RenderPass, Color};
+use wgpu::util::{DeviceExt, BufferInitDescriptor};
+use cgmath::{Matrix4, Vector3, Vector4, Vector2, Zero, Deg, Rad, Point3, SquareMatrix, EuclideanSpace};
+use winit::dpi::PhysicalPosition;
+use std::rc::Rc;
+use std::pin::Pin;
+use std::time::Instant;
+use utils::bytes::AsBytes;
+use utils::buffer::GpuBuffer;
+use utils::uniform::{UniformLayout, AddressSpace};
+use utils::vertex::VertexLayout;
+use utils::mesh::{MeshData, MeshVertex, GpuMesh};
+use utils::reflect::{check_vertex_layouts, check_bind_group_layouts, reflect_bind_group_layouts};
+use utils::pipeline::{PipelineCache, PipelineDesc, DepthState, ColorFormat};
+use utils::material::CompareDesc;
+use utils::lighting::{LightBuffer, collect_lights, lighting_wgsl};
+use utils::cubemap::{GpuCubemap, EnvironmentMap, environment_wgsl, direction_to_equirect, load_hdr};
+use utils::skybox::Skybox;
+use utils::texture::{HdrImage, HdrImageRes};
+use utils::resource_manager::ResourceMgr;
+use utils::render_graph::{RenderGraph, RenderNode, GraphResources, AttachmentDesc, AttachmentSize, PassDesc, Target};
+use utils::post::{PostStack, PostEffect, Tonemapper, HDR_FORMAT};
+use utils::components::{Transform, DirectionalLight, PointLight, SpotLight};
+use utils::object::Object;
+use utils::AsAny;
+use utils::camera::OPENGL_TO_WGPU_MATRIX;
+
+const EXPOSURE: usize = 0;
+const TONEMAP: usize = 2;
+
+/// Bloom picks up the sun, FXAA replaces MSAA as the scene renders into a single sampled
+/// HDR target.
+const EFFECTS: [PostEffect;6] = [
+    PostEffect::Exposure(0.0),
+    PostEffect::Bloom{ threshold: 1.0, intensity: 0.6 },
+    PostEffect::Tonemap(Tonemapper::Aces),
+    PostEffect::Vignette{ radius: 0.6, strength: 0.4 },
+    PostEffect::Gamma(2.2),
+    PostEffect::Fxaa{ span: 8.0 },
+];
+
+#[repr(C)]
+#[derive(Debug,Copy,Clone,VertexLayout,AsBytes)]
+#[step(instance)]
+struct InstanceRaw{
+    #[location(5)]
+    model: Matrix4<f32>,
+    albedo: Vector4<f32>,
+    /// metallic, roughness, specular, shininess
+    material: Vector4<f32>,
+}
+
+#[derive(Debug,Copy,Clone,UniformLayout)]
+struct Camera{
+    view_proj: Matrix4<f32>,
+    eye: Vector4<f32>,
+    /// x: 0 Blinn-Phong, 1 PBR, y: ambient of Blinn-Phong
+    params: Vector4<f32>,
+}
+
+struct Model{
+    mesh: GpuMesh,
+    instances: GpuBuffer<InstanceRaw>,
+}
+
+/// The lit models and the sky behind them, rendered into the HDR target of the post stack.
+struct SceneNode{
+    pipeline: Rc<RenderPipeline>,
+    camera_bind_group: BindGroup,
+    light_buffer: Rc<LightBuffer>,
+    environment: EnvironmentMap,
+    skybox: Rc<Skybox>,
+    models: Vec<Model>,
+}
+
+impl RenderNode for SceneNode {
+    fn record<'a>(&'a self,render_pass:&mut RenderPass<'a>,_res:&'a GraphResources)
+    {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0,&self.camera_bind_group,&[]);
+        render_pass.set_bind_group(1,self.light_buffer.bind_group(),&[]);
+        render_pass.set_bind_group(2,self.environment.bind_group(),&[]);
+        for m in self.models.iter() {
+            m.mesh.bind(render_pass,0);
+            render_pass.set_vertex_buffer(1,m.instances.slice(..));
+            m.mesh.draw(render_pass,0..m.instances.len() as u32);
+        }
+        self.skybox.execute(render_pass);
+    }
+}
+
+struct State{
+    surface : wgpu::Surface,
+    device : wgpu::Device,
+    queue : wgpu::Queue,
+    sc_desc : wgpu::SwapChainDescriptor,
+    swap_chain : wgpu::SwapChain,
+    size : winit::dpi::PhysicalSize<u32>,
+    camera : Camera,
+    camera_buf : Buffer,
+    lights : Vec<Pin<Box<Object>>>,
+    light_buffer : Rc<LightBuffer>,
+    skybox : Rc<Skybox>,
+    graph : RenderGraph,
+    post : PostStack,
+    exposure : f32,
+    rotate : Vector2<f32>,
+    left_btn_down: bool,
+    last_cursor_pos : Vector2<f32>,
+    start : Instant,
+    pipelines : PipelineCache,
+}
+
+impl State{
+    async fn new(window: &Window) -> State
+    {
+        let size = window.inner_size();
+        let ins = wgpu::Instance::new(BackendBit::PRIMARY);
+        let surface = unsafe{ ins.create_surface(window) };
+
+        let adapter = ins.request_adapter(&RequestAdapterOptions{
+            power_preference: PowerPreference::HighPerformance,
+            compatible_surface: Some(&surface)
+        }).await.unwrap();
+
+        let (device,queue) = adapter.request_device(&DeviceDescriptor{
+            label: None,
+            features: Features::empty(),
+            limits: Default::default()
+        },None).await.unwrap();
+
+        let sc_desc = wgpu::SwapChainDescriptor{
+            usage: TextureUsage::RENDER_ATTACHMENT,
+            format: adapter.get_swap_chain_preferred_format(&surface).unwrap(),
+            width: size.width,
+            height: size.height,
+            present_mode: PresentMode::Fifo
+        };
+
+        let swap_chain = device.create_swap_chain(&surface,&sc_desc);
+
+        let shader_src = format!("{}\n{}\n{}",lighting_wgsl(1),environment_wgsl(2),include_str!("shader.wgsl"));
+        if let Err(e) = check_vertex_layouts(shader_src.as_str(),"main",&[MeshVertex::layout(),InstanceRaw::layout()]) {
+            panic!("shader.wgsl: {}",e);
+        }
+        let layouts = match reflect_bind_group_layouts(shader_src.as_str()) {
+            Ok(l) => l,
+            Err(e) => panic!("shader.wgsl: {}",e)
+        };
+        if let Err(e) = check_bind_group_layouts(shader_src.as_str(),&[layouts[0].entries.as_slice(),LightBuffer::layout_entries().as_slice(),EnvironmentMap::layout_entries().as_slice()]) {
+            panic!("shader.wgsl: {}",e);
+        }
+
+        let camera = Camera{
+            view_proj: Matrix4::identity(),
+            eye: Vector4::zero(),
+            params: Vector4::new(1.0,0.03,0.0,0.0)
+        };
+        let camera_buf = device.create_buffer_init(&BufferInitDescriptor{
+            label: Some("Camera Buffer"),
+            contents: camera.to_bytes().as_slice(),
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST
+        });
+        let camera_layout = Rc::new(device.create_bind_group_layout(&layouts[0].descriptor(Some("Camera Bind Group Layout"))));
+        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Camera Bind Group"),
+            layout: &camera_layout,
+            entries: &[
+                BindGroupEntry{ binding: 0, resource: camera_buf.as_entire_binding() }
+            ]
+        });
+        let lights = create_lights();
+        let light_buffer = Rc::new(LightBuffer::new(&device,lights.len()));
+
+        let mut pipelines = PipelineCache::new(sc_desc.format);
+        let mut graph = RenderGraph::new(&sc_desc);
+        let post = PostStack::new(&device,&mut graph,&mut pipelines,EFFECTS.to_vec());
+        let depth = graph.add_attachment(AttachmentDesc{
+            label: "Depth Stencil Tex".to_string(),
+            format: TextureFormat::Depth32Float,
+            size: AttachmentSize::Relative(1.0),
+            usage: TextureUsage::empty(),
+            multisampled: false
+        });
+
+        // an equirectangular .hdr given on the command line, a generated sky otherwise
+        let mut res_mgr = ResourceMgr::new(concat!(env!("CARGO_MANIFEST_DIR"),"/src/example").to_string());
+        res_mgr.add_process(Box::new(HdrImageRes::new()));
+        let sky = std::env::args().nth(1)
+            .and_then(|p| load_hdr(&mut res_mgr,p.as_str()).or_else(|| { eprintln!("failed to load {}, using the generated sky",p); None }))
+            .unwrap_or_else(|| Rc::new(sky_image(512,256)));
+        let cubemap = Rc::new(GpuCubemap::from_equirect(&device,&queue,&mut pipelines,&sky,256));
+        let skybox = Rc::new(Skybox::new(&device,&mut pipelines,&cubemap,ColorFormat::Format(HDR_FORMAT),Some(TextureFormat::Depth32Float),1));
+        let environment = EnvironmentMap::new(&device,cubemap,1.0);
+
+        let shader = pipelines.shader(&device,"shader.wgsl",shader_src.as_str());
+        let pipeline_layouts = vec![pipelines.layout(&camera_layout),pipelines.layout(light_buffer.layout()),pipelines.layout(environment.layout())];
+        let pipeline = pipelines.get(&device,&PipelineDesc{
+            color: Some(ColorFormat::Format(HDR_FORMAT)),
+            depth: Some(DepthState{ format: TextureFormat::Depth32Float, write: true, compare: CompareDesc::Less }),
+            ..PipelineDesc::new(shader,pipeline_layouts,vec![MeshVertex::layout(),InstanceRaw::layout()])
+        });
+
+        let models = vec![
+            Model{
+                mesh: GpuMesh::new(&device,&sphere(32,16)),
+                instances: GpuBuffer::new(&device,Some("Sphere Instances"),BufferUsage::VERTEX,sphere_instances(7,2.5).as_slice())
+            },
+            Model{
+                mesh: GpuMesh::new(&device,&ground()),
+                instances: GpuBuffer::new(&device,Some("Ground Instances"),BufferUsage::VERTEX,&[InstanceRaw{
+                    model: Matrix4::from_translation(Vector3::new(0.0,-1.0,0.0)) * Matrix4::from_scale(20.0),
+                    albedo: Vector4::new(0.5,0.5,0.5,1.0),
+                    material: material(0.0,0.8)
+                }])
+            }
+        ];
+
+        graph.add_pass(PassDesc{
+            label: "Render Pass".to_string(),
+            colors: vec![(Target::Attachment(post.target()),LoadOp::Clear(Color::BLACK))],
+            depth: Some((depth,LoadOp::Clear(1f32))),
+            reads: vec![]
+        },Box::new(SceneNode{
+            pipeline,
+            camera_bind_group,
+            light_buffer: light_buffer.clone(),
+            environment,
+            skybox: skybox.clone(),
+            models
+        }));
+        if let Err(e) = graph.build(&device) {
+            panic!("render graph: {}",e);
+        }
+
+        State{
+            surface,
+            device,
+            queue,
+            sc_desc,
+            swap_chain,
+            size,
+            camera,
+            camera_buf,
+            lights,
+            light_buffer,
+            skybox,
+            graph,
+            post,
+            exposure: 0.0,
+            rotate: Vector2::new(0.4,0.0),
+            left_btn_down: false,
+            last_cursor_pos: Vector2::zero(),
+            start: Instant::now(),
+            pipelines
+        }
+    }
+
+    fn resize(&mut self,size:winit::dpi::PhysicalSize<u32>)
+    {
+        if size.width > 0 && size.height > 0
+        {
+            self.size = size;
+            self.sc_desc.width = size.width;
+            self.sc_desc.height = size.height;
+            self.swap_chain = self.device.create_swap_chain(&self.surface,&self.sc_desc);
+            self.graph.resize(&self.device,&self.sc_desc);
+            self.post.set_format(&self.queue,self.sc_desc.format);
+        }
+    }
+
+    fn input(&mut self,event:&WindowEvent,_window:&Window) -> bool
+    {
+        match event{
+            &WindowEvent::KeyboardInput{ input:KeyboardInput{
+                virtual_keycode:Some(VirtualKeyCode::Space),state:ElementState::Released,..
+            },.. } => {
+                // switch between Blinn-Phong and PBR
+                self.camera.params.x = 1.0 - self.camera.params.x;
+                true
+            }
+            &WindowEvent::KeyboardInput{ input:KeyboardInput{
+                virtual_keycode:Some(VirtualKeyCode::T),state:ElementState::Released,..
+            },.. } => {
+                let tonemapper = match self.post.effects()[TONEMAP] {
+                    PostEffect::Tonemap(Tonemapper::Aces) => Tonemapper::Reinhard,
+                    _ => Tonemapper::Aces
+                };
+                self.post.set(&self.queue,TONEMAP,PostEffect::Tonemap(tonemapper));
+                true
+            }
+            &WindowEvent::KeyboardInput{ input:KeyboardInput{
+                virtual_keycode:Some(key @ (VirtualKeyCode::Up | VirtualKeyCode::Down)),state:ElementState::Pressed,..
+            },.. } => {
+                // half a stop per press
+                self.exposure += if key == VirtualKeyCode::Up { 0.5 } else { -0.5 };
+                self.post.set(&self.queue,EXPOSURE,PostEffect::Exposure(self.exposure));
+                true
+            }
+            &WindowEvent::MouseInput {
+                button:MouseButton::Left,
+                state,..
+            } => {
+                self.left_btn_down = match state{
+                    ElementState::Pressed => {true}
+                    ElementState::Released => {false}
+                };
+                true
+            }
+            &WindowEvent::CursorMoved{position:PhysicalPosition::<f64> {x,y},..} =>
+            {
+                if self.left_btn_down{
+                    let offset = Vector2::new(x as f32,y as f32) - self.last_cursor_pos;
+                    self.rotate.y += offset.x * 0.005;
+                    self.rotate.x = (self.rotate.x + offset.y * 0.005).max(0.05).min(1.5);
+                }
+                self.last_cursor_pos = Vector2::new(x as f32,y as f32);
+                true
+            }
+            _ => { false }
+        }
+    }
+
+    fn update(&mut self) {
+        // the point lights circle over the spheres
+        let t = self.start.elapsed().as_secs_f32();
+        for (i,obj) in self.lights.iter_mut().enumerate() {
+            if let Some(trans) = obj.as_mut().pin_get().get_comp_mut::<Transform>()
+                .and_then(|c| c.as_mut_any().downcast_mut::<Transform>())
+            {
+                let a = t * 0.7 + i as f32 * std::f32::consts::PI;
+                trans.position = Vector3::new(a.cos() * 6.0,2.0,a.sin() * 6.0);
+            }
+        }
+        let lights = collect_lights(self.lights.iter().map(|o| &**o));
+        if !self.light_buffer.update(&self.queue,lights.as_slice()) {
+            // the graph node keeps the bind group, so the buffer can not grow, light the first ones
+            let fit = &lights[..self.light_buffer.capacity()];
+            assert!(self.light_buffer.update(&self.queue,fit));
+        }
+
+        let eye = Point3::new(
+            18.0 * self.rotate.x.cos() * self.rotate.y.sin(),
+            18.0 * self.rotate.x.sin(),
+            18.0 * self.rotate.x.cos() * self.rotate.y.cos()
+        );
+        let view = Matrix4::look_at_rh(eye,Point3::origin(),Vector3::unit_y());
+        let projection = OPENGL_TO_WGPU_MATRIX * cgmath::perspective(Deg(45f32),self.size.width as f32 / self.size.height as f32,0.1,100.0);
+        self.camera.view_proj = projection * view;
+        self.camera.eye = eye.to_vec().extend(1.0);
+        self.queue.write_buffer(&self.camera_buf,0,self.camera.to_bytes().as_slice());
+        self.skybox.update(&self.queue,&view,&projection);
+    }
+
+    fn render(&mut self) -> Result<(),wgpu::SwapChainError>
+    {
+        let frame = self.swap_chain.get_current_frame()?.output;
+
+        let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor{
+            label: Some("Render Encoder")
+        });
+        self.graph.execute(&mut encoder,&frame.view);
+        self.queue.submit(std::iter::once(encoder.finish()));
+        Ok(())
+    }
+}
+
+/// Blinn-Phong takes the shininess the roughness roughly corresponds to.
+fn material(metallic:f32,roughness:f32) -> Vector4<f32>
+{
+    let a = roughness * roughness;
+    let shininess = (2.0 / (a * a).max(1e-4) - 2.0).max(1.0);
+    Vector4::new(metallic,roughness,1.0 - roughness * 0.8,shininess)
+}
+
+/// A dim sun, two point lights moved by their `Transform`s and a spot light from above.
+fn create_lights() -> Vec<Pin<Box<Object>>>
+{
+    let mut res = Vec::new();
+
+    let mut sun = Object::new();
+    let mut light = DirectionalLight::new(Vector3::new(-0.3,-1.0,-0.5));
+    light.intensity = 0.8;
+    light.cast_shadows = false;
+    sun.as_mut().pin_get().add_comp(Box::new(light));
+    res.push(sun);
+
+    for color in [Vector3::new(1.0,0.4,0.2),Vector3::new(0.2,0.5,1.0)].iter() {
+        let mut obj = Object::new();
+        obj.as_mut().pin_get().add_comp(Box::new(Transform::new()));
+        let mut light = PointLight::new(Vector3::zero(),15.0);
+        light.color = *color;
+        light.intensity = 40.0;
+        obj.as_mut().pin_get().add_comp(Box::new(light));
+        res.push(obj);
+    }
+
+    let mut spot = Object::new();
+    let mut light = SpotLight::new(Vector3::new(0.0,10.0,0.0),Vector3::new(0.0,-1.0,0.0),20.0);
+    light.intensity = 80.0;
+    light.inner_angle = Rad::from(Deg(10f32));
+    light.outer_angle = Rad::from(Deg(18f32));
+    spot.as_mut().pin_get().add_comp(Box::new(light));
+    res.push(spot);
+    res
+}
+
+/// An equirectangular sky: blue overhead, bright at the horizon, a dark ground and a small,
+/// very bright sun in the direction of the `DirectionalLight`.
+fn sky_image(width:u32,height:u32) -> HdrImage
+{
+    let sun = direction_to_equirect(Vector3::new(0.3,1.0,0.5));
+    let mut data = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        let v = (y as f32 + 0.5) / height as f32;
+        // 1 straight up, 0 at the horizon, -1 straight down
+        let up = (v * std::f32::consts::PI).cos();
+        for x in 0..width {
+            let u = (x as f32 + 0.5) / width as f32;
+            let color = if up >= 0.0 {
+                let t = up.powf(0.5);
+                Vector3::new(0.9,0.85,0.8) * (1.0 - t) + Vector3::new(0.2,0.4,0.9) * t
+            }else{
+                let t = (-up).powf(0.3);
+                Vector3::new(0.5,0.45,0.4) * (1.0 - t) + Vector3::new(0.15,0.12,0.1) * t
+            };
+            let du = (u - sun.0).abs().min(1.0 - (u - sun.0).abs()) * 2.0;
+            let d = (du * du + (v - sun.1) * (v - sun.1)).sqrt();
+            let color = if d < 0.02 { Vector3::new(40.0,36.0,30.0) } else { color };
+            data.push([color.x,color.y,color.z,1.0]);
+        }
+    }
+    HdrImage{ width, height, data }
+}
+
+fn sphere(segments:u32,rings:u32) -> MeshData
+{
+    let mut data = MeshData::default();
+    for r in 0..=rings {
+        let v = r as f32 / rings as f32;
+        let theta = v * std::f32::consts::PI;
+        for s in 0..=segments {
+            let u = s as f32 / segments as f32;
+            let phi = u * std::f32::consts::PI * 2.0;
+            let p = [theta.sin() * phi.cos(),theta.cos(),theta.sin() * phi.sin()];
+            data.positions.push(p);
+            data.normals.push(p);
+            data.uvs.push([u,v]);
+        }
+    }
+    for r in 0..rings {
+        for s in 0..segments {
+            let a = r * (segments + 1) + s;
+            let b = a + segments + 1;
+            data.indices.extend_from_slice(&[a,a + 1,b,a + 1,b + 1,b]);
+        }
+    }
+    data.fill_missing();
+    data
+}
+
+fn ground() -> MeshData
+{
+    let mut data = MeshData{
+        positions: vec![[-1.0,0.0,-1.0],[-1.0,0.0,1.0],[1.0,0.0,1.0],[1.0,0.0,-1.0]],
+        indices: vec![0,1,2,0,2,3],
+        ..Default::default()
+    };
+    data.fill_missing();
+    data
+}
+
+/// Metallic grows along x and roughness along z.
+fn sphere_instances(row:usize,space:f32) -> Vec<InstanceRaw>
+{
+    let offset = (row - 1) as f32 * space / 2f32;
+    let mut res = Vec::new();
+    for z in 0..row {
+        for x in 0..row {
+            let metallic = x as f32 / (row - 1) as f32;
+            let roughness = (z as f32 / (row - 1) as f32).max(0.05);
+            res.push(InstanceRaw{
+                model: Matrix4::from_translation(Vector3::new(x as f32 * space - offset,0.0,z as f32 * space - offset)),
+                albedo: Vector4::new(0.9,0.6,0.2,1.0),
+                material: material(metallic,roughness)
+            });
+        }
+    }
+    res
+}
+
+fn main() {
+    env_logger::init();
+    let event_loop = EventLoop::new();
+
+    let window = WindowBuilder::new()
+        .with_title("lighting")
+        .build(&event_loop).unwrap();
+
+    let mut state = pollster::block_on(State::new(&window));
+
+    event_loop.run(move |e,_,control_flow|{
+        match e {
+            Event::WindowEvent { window_id,event} => {
+                if window_id == window.id() {
+                    if !state.input(&event,&window) {
+                        match event {
+                            WindowEvent::CloseRequested | WindowEvent::KeyboardInput {
+                                input: KeyboardInput {
+                                    state: ElementState::Released,
+                                    virtual_keycode: Some(VirtualKeyCode::Escape), ..
+                                }, ..
+                            } => {
+                                *control_flow = ControlFlow::Exit;
+                            }
+                            WindowEvent::Resized(size) => {
+                                state.resize(size);
+                            }
+                            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                                state.resize(*new_inner_size);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            Event::RedrawRequested(_) => {
+                state.update();
+                match state.render() {
+                    Ok(_) => {}
+                    // Recreate the swap_chain if lost
+                    Err(wgpu::SwapChainError::Lost) => state.resize(state.size),
+                    // The system is out of memory, we should probably quit
+                    Err(wgpu::SwapChainError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                    // All other errors (Outdated, Timeout) should be resolved by the next frame
+                    Err(e) => eprintln!("{:?}", e),
+                }
+            }
+            Event::MainEventsCleared => {
+                window.request_redraw();
+            }
+            _=>{}
+        }
+    });
+}
@@ -0,0 +1,71 @@
+// blinn_phong, pbr and the light buffer (group 1) come from utils::lighting::lighting_wgsl,
+// environment_light and the environment map (group 2) from utils::cubemap::environment_wgsl
+
+struct VertexOutput{
+    [[builtin(position)]] clip_position : vec4<f32>;
+    [[location(0)]] world_pos : vec3<f32>;
+    [[location(1)]] normal : vec3<f32>;
+    [[location(2)]] albedo : vec4<f32>;
+    [[location(3)]] material : vec4<f32>;
+};
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(3)]] normal: vec3<f32>;
+};
+
+struct InstanceInput {
+    [[location(5)]] model_matrix_0: vec4<f32>;
+    [[location(6)]] model_matrix_1: vec4<f32>;
+    [[location(7)]] model_matrix_2: vec4<f32>;
+    [[location(8)]] model_matrix_3: vec4<f32>;
+    [[location(9)]] albedo: vec4<f32>;
+    // metallic, roughness, specular, shininess
+    [[location(10)]] material: vec4<f32>;
+};
+
+[[block]]
+struct Camera {
+    view_proj: mat4x4<f32>;
+    eye: vec4<f32>;
+    // x: 0 Blinn-Phong, 1 PBR; y: ambient of Blinn-Phong
+    params: vec4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+
+[[stage(vertex)]]
+fn main(in : VertexInput,instance: InstanceInput) -> VertexOutput
+{
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world = model_matrix * vec4<f32>(in.position,1.0);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world;
+    out.world_pos = world.xyz;
+    out.normal = (model_matrix * vec4<f32>(in.normal,0.0)).xyz;
+    out.albedo = instance.albedo;
+    out.material = instance.material;
+    return out;
+}
+
+[[stage(fragment)]]
+fn main(v:VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    let n = normalize(v.normal);
+    let view = normalize(camera.eye.xyz - v.world_pos);
+    var color: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
+    if (camera.params.x > 0.5) {
+        color = pbr(n,view,v.world_pos,v.albedo.rgb,v.material.x,v.material.y)
+            + environment_light(n,view,v.albedo.rgb,v.material.x,v.material.y);
+    } else {
+        color = blinn_phong(n,view,v.world_pos,v.albedo.rgb,v.material.z,v.material.w)
+            + v.albedo.rgb * camera.params.y;
+    }
+    return vec4<f32>(color,v.albedo.a);
+}
@@ -0,0 +1,254 @@
+use winit::{
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+use winit::window::Window;
+use wgpu::{BackendBit, RequestAdapterOptions, PowerPreference, DeviceDescriptor, Features, TextureUsage, TextureFormat, PresentMode, CommandEncoderDescriptor, RenderPassDescriptor, BufferUsage, IndexFormat};
+use wgpu::util::{DeviceExt, BufferInitDescriptor};
+use std::rc::Rc;
+use utils::resource_manager::{ResourceMgr, TextRes};
+use utils::texture::{ImageRes, GpuTextureRes};
+use utils::material::{Material, MaterialDescRes, MaterialRes, MaterialEnv, load_material};
+use utils::vertex::VertexLayout;
+
+#[repr(C)]
+#[derive(Debug,Copy, Clone,bytemuck::Pod, bytemuck::Zeroable,VertexLayout)]
+struct Vertex{
+    position: [f32;3],
+    color: [f32;4],
+    tex_coords: [f32;2]
+}
+
+const VERTICES: &[Vertex] = &[
+    Vertex { position: [-0.0868241, 0.49240386, 0.0], color:  [0.5, 0.0, 0.5,1.0],tex_coords: [0.4131759, 0.00759614]   ,}, // A
+    Vertex { position: [-0.49513406, 0.06958647, 0.0], color: [0.5, 1.0, 0.5,1.0],tex_coords: [0.0048659444, 0.43041354],}, // B
+    Vertex { position: [-0.21918549, -0.44939706, 0.0], color:[0.0, 0.5, 0.5,1.0],tex_coords: [0.28081453, 0.949397]    ,}, // C
+    Vertex { position: [0.35966998, -0.3473291, 0.0], color:  [0.0, 0.5, 1.0,1.0],tex_coords: [0.85967, 0.84732914]     ,}, // D
+    Vertex { position: [0.44147372, 0.2347359, 0.0], color:   [0.5, 1.0, 0.5,1.0],tex_coords: [0.9414737, 0.2652641]    , }, // E
+];
+
+const INDICES: &[u32] = &[
+    0, 1, 4,
+    1, 2, 4,
+    2, 3, 4,
+];
+
+const MATERIAL: &str = "material/happy.ron";
+
+struct State{
+    surface : wgpu::Surface,
+    device : Rc<wgpu::Device>,
+    queue : Rc<wgpu::Queue>,
+    sc_desc : wgpu::SwapChainDescriptor,
+    swap_chain : wgpu::SwapChain,
+    size : winit::dpi::PhysicalSize<u32>,
+    clear_color : wgpu::Color,
+    vertices : wgpu::Buffer,
+    indices : wgpu::Buffer,
+    res_mgr : ResourceMgr,
+    material : Rc<Material>,
+    use2 : bool
+}
+
+impl State{
+    async fn new(window: &Window) -> State
+    {
+        let size = window.inner_size();
+        let ins = wgpu::Instance::new(BackendBit::PRIMARY);
+        let surface = unsafe{ ins.create_surface(window) };
+
+        let adapter = ins.request_adapter(&RequestAdapterOptions{
+            power_preference: PowerPreference::HighPerformance,
+            compatible_surface: Some(&surface)
+        }).await.unwrap();
+
+        let (device,queue) = adapter.request_device(&DeviceDescriptor{
+            label: None,
+            features: Features::empty(),
+            limits: Default::default()
+        },None).await.unwrap();
+        let device = Rc::new(device);
+        let queue = Rc::new(queue);
+
+        let sc_desc = wgpu::SwapChainDescriptor{
+            usage: TextureUsage::RENDER_ATTACHMENT,
+            format: adapter.get_swap_chain_preferred_format(&surface).unwrap(),
+            width: size.width,
+            height: size.height,
+            present_mode: PresentMode::Fifo
+        };
+
+        let swap_chain = device.create_swap_chain(&surface,&sc_desc);
+
+        let clear_color = wgpu::Color::BLACK;
+
+        let vertices = device.create_buffer_init(&BufferInitDescriptor{
+            label: Some("Vertices"),
+            contents: bytemuck::cast_slice(VERTICES),
+            usage: BufferUsage::VERTEX
+        });
+
+        let indices = device.create_buffer_init(&BufferInitDescriptor{
+            label: Some("Indices"),
+            contents: bytemuck::cast_slice(INDICES),
+            usage: BufferUsage::INDEX
+        });
+
+        let mut res_mgr = ResourceMgr::new(concat!(env!("CARGO_MANIFEST_DIR"),"/src/example").to_string());
+        res_mgr.add_process(Box::new(TextRes::new()));
+        res_mgr.add_process(Box::new(ImageRes::new()));
+        res_mgr.add_process(Box::new(GpuTextureRes::new(device.clone(),queue.clone())));
+        res_mgr.add_process(Box::new(MaterialDescRes::new()));
+        res_mgr.add_process(Box::new(MaterialRes::new(device.clone(),queue.clone(),MaterialEnv{
+            color_format: sc_desc.format,
+            depth_format: TextureFormat::Depth32Float,
+            sample_count: 1,
+            vertex_layouts: vec![Vertex::layout()],
+            shared_layouts: vec![]
+        })));
+        let material = load_material(&mut res_mgr,MATERIAL).unwrap();
+
+        State{
+            surface,
+            device,
+            queue,
+            sc_desc,
+            swap_chain,
+            size,
+            clear_color,
+            vertices,
+            indices,
+            res_mgr,
+            material,
+            use2:false
+        }
+    }
+
+    fn resize(&mut self,size:winit::dpi::PhysicalSize<u32>)
+    {
+        if size.width > 0 && size.height > 0
+        {
+            self.size = size;
+            self.sc_desc.width = size.width;
+            self.sc_desc.height = size.height;
+            self.swap_chain = self.device.create_swap_chain(&self.surface,&self.sc_desc);
+        }
+    }
+
+    fn input(&mut self,event:&WindowEvent) -> bool
+    {
+        match event{
+            &WindowEvent::KeyboardInput{ input:KeyboardInput{
+                virtual_keycode:Some(VirtualKeyCode::Space),state:ElementState::Released,..
+            },.. } => {
+                self.use2 = !self.use2;
+                self.material.set_param(&self.queue,"mix",[if self.use2 { 1.0 } else { 0.0 },0.0,0.0,0.0]);
+                true
+            }
+            &WindowEvent::KeyboardInput{ input:KeyboardInput{
+                virtual_keycode:Some(VirtualKeyCode::R),state:ElementState::Released,..
+            },.. } => {
+                // hot reload: edited shader/texture/material files rebuild the material
+                if !self.res_mgr.check_modified().is_empty() {
+                    match load_material(&mut self.res_mgr,MATERIAL) {
+                        Ok(m) => {
+                            self.material = m;
+                            self.material.set_param(&self.queue,"mix",[if self.use2 { 1.0 } else { 0.0 },0.0,0.0,0.0]);
+                        }
+                        Err(e) => eprintln!("{}",e)
+                    }
+                }
+                true
+            }
+            _ => { false }
+        }
+    }
+
+    fn update(&mut self) {}
+    fn render(&mut self) -> Result<(),wgpu::SwapChainError>
+    {
+        let frame = self.swap_chain.get_current_frame().unwrap().output;
+
+        let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor{
+            label: Some("Render Encoder")
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor{
+                label: Some("Render Pass"),
+                color_attachments: &[
+                    wgpu::RenderPassColorAttachment{
+                        view: &frame.view,
+                        resolve_target: None,
+                        ops: wgpu::Operations{
+                            load: wgpu::LoadOp::Clear(self.clear_color),
+                            store: true
+                        }
+                    }
+                ],
+                depth_stencil_attachment: None
+            });
+            self.material.bind(&mut render_pass);
+            render_pass.set_vertex_buffer(0,self.vertices.slice(..));
+            render_pass.set_index_buffer(self.indices.slice(..),IndexFormat::Uint32);
+            render_pass.draw_indexed(0..INDICES.len() as u32,0,0..1);
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+        Ok(())
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("material")
+        .build(&event_loop).unwrap();
+
+    let mut state = pollster::block_on(State::new(&window));
+
+    event_loop.run(move |e,_,control_flow|{
+        match e {
+            Event::WindowEvent { window_id,event} => {
+                if window_id == window.id() {
+                    if !state.input(&event) {
+                        match event {
+                            WindowEvent::CloseRequested | WindowEvent::KeyboardInput {
+                                input: KeyboardInput {
+                                    state: ElementState::Released,
+                                    virtual_keycode: Some(VirtualKeyCode::Escape), ..
+                                }, ..
+                            } => {
+                                *control_flow = ControlFlow::Exit;
+                            }
+                            WindowEvent::Resized(size) => {
+                                state.resize(size);
+                            }
+                            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                                // new_inner_size is &mut so w have to dereference it twice
+                                state.resize(*new_inner_size);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            Event::RedrawRequested(_) => {
+                state.update();
+                match state.render() {
+                    Ok(_) => {}
+                    // Recreate the swap_chain if lost
+                    Err(wgpu::SwapChainError::Lost) => state.resize(state.size),
+                    // The system is out of memory, we should probably quit
+                    Err(wgpu::SwapChainError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                    // All other errors (Outdated, Timeout) should be resolved by the next frame
+                    Err(e) => eprintln!("{:?}", e),
+                }
+            }
+            Event::MainEventsCleared => {
+                window.request_redraw();
+            }
+            _=>{}
+        }
+    });
+}
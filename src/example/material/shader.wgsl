@@ -10,36 +10,37 @@ struct VertexInput {
     [[location(2)]] uv: vec2<f32>;
 };
 
-[[block]] // 1.
-struct Uniforms {
-    projection: mat4x4<f32>;
-    view: mat4x4<f32>;
-    model: mat4x4<f32>;
-};
-[[group(1), binding(0)]] // 2.
-var<uniform> uniforms: Uniforms;
-
 [[stage(vertex)]]
 fn main(in : VertexInput) -> VertexOutput
 {
     var out: VertexOutput;
-    out.clip_position = uniforms.projection * uniforms.view * uniforms.model * vec4<f32>(in.position,1.0);
+    out.clip_position = vec4<f32>(in.position,1.0);
     out.color = in.color;
     out.uv = in.uv;
     return out;
 }
 
-
 [[group(0), binding(0)]]
-var t_shadow: texture_depth_2d;
+var t_diffuse: texture_2d<f32>;
 [[group(0), binding(1)]]
-var s_shadow: sampler;
+var s_diffuse: sampler;
+[[group(0), binding(2)]]
+var t_cartoon: texture_2d<f32>;
+[[group(0), binding(3)]]
+var s_cartoon: sampler;
+
+[[block]]
+struct Params {
+    tint: vec4<f32>;
+    mix: vec4<f32>;
+};
+[[group(1), binding(0)]]
+var<uniform> params: Params;
 
 [[stage(fragment)]]
 fn main(v:VertexOutput) -> [[location(0)]] vec4<f32>
 {
-    let depth = textureSample(t_shadow, s_shadow, v.uv);
-    return vec4<f32>(depth);
+    let a = textureSample(t_diffuse,s_diffuse,v.uv);
+    let b = textureSample(t_cartoon,s_cartoon,v.uv);
+    return mix(a,b,vec4<f32>(params.mix.x)) * params.tint;
 }
-
-
@@ -0,0 +1,430 @@
+use winit::{
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+use winit::window::Window;
+use wgpu::{BackendBit, RequestAdapterOptions, PowerPreference, DeviceDescriptor, Features, TextureUsage, TextureFormat, PresentMode, CommandEncoderDescriptor, RenderPassDescriptor, BufferUsage, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType, ShaderStage, BindGroup, LoadOp};
+use cgmath::{Matrix4, Vector2, Vector3, Vector4, Point3, Deg, EuclideanSpace};
+use std::rc::Rc;
+use std::pin::Pin;
+use std::time::Instant;
+use utils::buffer::GpuBuffer;
+use utils::vertex::VertexLayout;
+use utils::mesh::{MeshData, MeshVertex, GpuMesh};
+use utils::msaa::{MsaaTargets, check_sample_count};
+use utils::resource_manager::{ResourceMgr, TextRes};
+use utils::texture::{ImageRes, GpuTextureRes};
+use utils::material::{MaterialDescRes, MaterialRes, MaterialEnv, load_material};
+use utils::draw::{DrawList, ModelInstance};
+use utils::bounds::Frustum;
+use utils::debug_draw::{DebugDraw, DebugRenderer};
+use utils::pipeline::{PipelineCache, ColorFormat};
+use utils::overlay::{Overlay, OverlayRenderer};
+use utils::sprite::SpriteBatch;
+use utils::component::RenderNecessary;
+use utils::components::{Transform, MeshRenderer};
+use utils::object::Object;
+use utils::AsAny;
+use utils::camera::OPENGL_TO_WGPU_MATRIX;
+
+struct State{
+    surface : wgpu::Surface,
+    device : Rc<wgpu::Device>,
+    queue : Rc<wgpu::Queue>,
+    sc_desc : wgpu::SwapChainDescriptor,
+    swap_chain : wgpu::SwapChain,
+    size : winit::dpi::PhysicalSize<u32>,
+    clear_color : wgpu::Color,
+    camera_buf : GpuBuffer<Matrix4<f32>>,
+    camera_bind_group : BindGroup,
+    targets : MsaaTargets,
+    objects : Vec<Pin<Box<Object>>>,
+    draw_list : DrawList,
+    sprites : SpriteBatch,
+    debug : DebugDraw,
+    debug_renderer : DebugRenderer,
+    show_debug : bool,
+    overlay : Overlay,
+    overlay_renderer : OverlayRenderer,
+    adapter_info : String,
+    last_frame : Instant,
+    frame_time : f32,
+    _res_mgr : ResourceMgr,
+    angle : f32,
+}
+
+impl State{
+    async fn new(window: &Window) -> State
+    {
+        let size = window.inner_size();
+        let ins = wgpu::Instance::new(BackendBit::PRIMARY);
+        let surface = unsafe{ ins.create_surface(window) };
+
+        let adapter = ins.request_adapter(&RequestAdapterOptions{
+            power_preference: PowerPreference::HighPerformance,
+            compatible_surface: Some(&surface)
+        }).await.unwrap();
+
+        let (device,queue) = adapter.request_device(&DeviceDescriptor{
+            label: None,
+            features: Features::empty(),
+            limits: Default::default()
+        },None).await.unwrap();
+        let device = Rc::new(device);
+        let queue = Rc::new(queue);
+
+        let sc_desc = wgpu::SwapChainDescriptor{
+            usage: TextureUsage::RENDER_ATTACHMENT,
+            format: adapter.get_swap_chain_preferred_format(&surface).unwrap(),
+            width: size.width,
+            height: size.height,
+            present_mode: PresentMode::Fifo
+        };
+
+        let swap_chain = device.create_swap_chain(&surface,&sc_desc);
+        // alpha tested materials get alpha to coverage with MSAA
+        let sample_count = check_sample_count(4).unwrap_or(1);
+
+        let camera_buf = GpuBuffer::from_value(&device,Some("Camera Buffer"),BufferUsage::UNIFORM | BufferUsage::COPY_DST,&camera(size.width,size.height,0.0).0);
+        let camera_layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Camera Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry{
+                    binding: 0,
+                    visibility: ShaderStage::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                }
+            ]
+        }));
+        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Camera Bind Group"),
+            layout: &camera_layout,
+            entries: &[
+                BindGroupEntry{ binding: 0, resource: camera_buf.as_entire_binding() }
+            ]
+        });
+
+        let mut res_mgr = ResourceMgr::new(concat!(env!("CARGO_MANIFEST_DIR"),"/src/example").to_string());
+        res_mgr.add_process(Box::new(TextRes::new()));
+        res_mgr.add_process(Box::new(ImageRes::new()));
+        res_mgr.add_process(Box::new(GpuTextureRes::new(device.clone(),queue.clone())));
+        res_mgr.add_process(Box::new(MaterialDescRes::new()));
+        res_mgr.add_process(Box::new(MaterialRes::new(device.clone(),queue.clone(),MaterialEnv{
+            color_format: sc_desc.format,
+            depth_format: TextureFormat::Depth32Float,
+            sample_count,
+            vertex_layouts: vec![MeshVertex::layout(),ModelInstance::layout()],
+            shared_layouts: vec![camera_layout]
+        })));
+        let happy = load_material(&mut res_mgr,"scene/happy.ron").unwrap();
+        let cartoon = load_material(&mut res_mgr,"scene/cartoon.ron").unwrap();
+        let tree = load_material(&mut res_mgr,"scene/tree.ron").unwrap();
+        let glass = load_material(&mut res_mgr,"scene/glass.ron").unwrap();
+
+        let cube = Rc::new(GpuMesh::new(&device,&cube()));
+        let ground = Rc::new(GpuMesh::new(&device,&ground()));
+        let quad = Rc::new(GpuMesh::new(&device,&quad()));
+
+        // the scene is nothing but objects, the render code below does not know about them,
+        // the 25 opaque cubes end up as two instanced draws, one per material
+        let mut objects = Vec::new();
+        let mut obj = Object::new();
+        let mut trans = Transform::new();
+        trans.scale = Vector3::new(8.0,1.0,8.0);
+        obj.as_mut().pin_get().add_comp(Box::new(trans));
+        obj.as_mut().pin_get().add_comp(Box::new(MeshRenderer::new(ground,happy.clone())));
+        objects.push(obj);
+        for z in 0..5 {
+            for x in 0..5 {
+                let mut obj = Object::new();
+                let mut trans = Transform::new();
+                trans.position = Vector3::new(x as f32 * 2.5 - 5.0,0.6,z as f32 * 2.5 - 5.0);
+                trans.rotation.y = (x + z) as f32 * 0.3;
+                obj.as_mut().pin_get().add_comp(Box::new(trans));
+                let material = if (x + z) % 2 == 0 { happy.clone() } else { cartoon.clone() };
+                let mut renderer = MeshRenderer::new(cube.clone(),material);
+                renderer.color = Vector4::new(0.6 + 0.1 * x as f32,0.8,1.0 - 0.1 * z as f32,1.0);
+                obj.as_mut().pin_get().add_comp(Box::new(renderer));
+                objects.push(obj);
+            }
+        }
+        // alpha tested trees behind the cubes and glass boxes in front, sorted back to front
+        for i in 0..5 {
+            let mut obj = Object::new();
+            let mut trans = Transform::new();
+            trans.position = Vector3::new(i as f32 * 2.5 - 5.0,1.5,-7.5);
+            trans.scale = Vector3::new(3.0,3.0,3.0);
+            obj.as_mut().pin_get().add_comp(Box::new(trans));
+            obj.as_mut().pin_get().add_comp(Box::new(MeshRenderer::new(quad.clone(),tree.clone())));
+            objects.push(obj);
+
+            let mut obj = Object::new();
+            let mut trans = Transform::new();
+            trans.position = Vector3::new(i as f32 * 2.5 - 5.0,1.0,7.0);
+            trans.scale = Vector3::new(1.5,2.0,1.5);
+            obj.as_mut().pin_get().add_comp(Box::new(trans));
+            let mut renderer = MeshRenderer::new(cube.clone(),glass.clone());
+            renderer.color = Vector4::new(0.2 * i as f32,0.6,1.0 - 0.2 * i as f32,1.0);
+            obj.as_mut().pin_get().add_comp(Box::new(renderer));
+            objects.push(obj);
+        }
+
+        let mut pipelines = PipelineCache::new(sc_desc.format);
+        let debug_renderer = DebugRenderer::new(&device,&mut pipelines,ColorFormat::SwapChain,Some(TextureFormat::Depth32Float),sample_count);
+        let overlay = Overlay::new();
+        let overlay_renderer = OverlayRenderer::new(&device,&queue,&mut pipelines,ColorFormat::SwapChain,overlay.font());
+        let info = adapter.get_info();
+        let adapter_info = format!("{} ({:?}, {:?})",info.name,info.backend,info.device_type);
+
+        State{
+            targets: MsaaTargets::new(&device,&sc_desc,sample_count,Some(TextureFormat::Depth32Float)),
+            draw_list: DrawList::new(&device),
+            sprites: SpriteBatch::new(),
+            debug: DebugDraw::new(),
+            debug_renderer,
+            show_debug: true,
+            overlay,
+            overlay_renderer,
+            adapter_info,
+            last_frame: Instant::now(),
+            frame_time: 1.0 / 60.0,
+            surface,
+            device,
+            queue,
+            sc_desc,
+            swap_chain,
+            size,
+            clear_color: wgpu::Color{ r: 0.1, g: 0.2, b: 0.3, a: 1.0 },
+            camera_buf,
+            camera_bind_group,
+            objects,
+            _res_mgr: res_mgr,
+            angle: 0.0
+        }
+    }
+
+    fn resize(&mut self,size:winit::dpi::PhysicalSize<u32>)
+    {
+        if size.width > 0 && size.height > 0
+        {
+            self.size = size;
+            self.sc_desc.width = size.width;
+            self.sc_desc.height = size.height;
+            self.swap_chain = self.device.create_swap_chain(&self.surface,&self.sc_desc);
+            self.targets.resize(&self.device,&self.sc_desc);
+        }
+    }
+
+    fn input(&mut self,event:&WindowEvent) -> bool
+    {
+        match event{
+            &WindowEvent::KeyboardInput{ input:KeyboardInput{
+                virtual_keycode:Some(VirtualKeyCode::D),state:ElementState::Released,..
+            },.. } => {
+                self.show_debug = !self.show_debug;
+                true
+            }
+            _ => false
+        }
+    }
+
+    fn update(&mut self) {
+        self.angle += 0.002;
+        // smoothed, so the fps text stays readable
+        let now = Instant::now();
+        self.frame_time = self.frame_time * 0.95 + (now - self.last_frame).as_secs_f32() * 0.05;
+        self.last_frame = now;
+        for obj in self.objects.iter_mut().skip(1).take(25) {
+            if let Some(trans) = obj.as_mut().pin_get().get_comp_mut::<Transform>()
+                .and_then(|c| c.as_mut_any().downcast_mut::<Transform>())
+            {
+                trans.rotation.y += 0.01;
+            }
+        }
+        self.camera_buf.set(&self.queue,&camera(self.size.width,self.size.height,self.angle).0);
+    }
+
+    fn render(&mut self) -> Result<(),wgpu::SwapChainError>
+    {
+        let frame = self.swap_chain.get_current_frame()?.output;
+
+        self.draw_list.clear();
+        for obj in self.objects.iter_mut() {
+            obj.as_mut().pin_get().render(RenderNecessary{
+                swap_chain_desc: &self.sc_desc,
+                draw_list: &mut self.draw_list,
+                sprites: &mut self.sprites
+            });
+        }
+        let (view_proj,eye) = camera(self.size.width,self.size.height,self.angle);
+        let culled = self.draw_list.cull(&Frustum::from_matrix(&view_proj));
+        self.draw_list.prepare(&self.device,&self.queue,eye);
+
+        // D toggles the bounds of everything that survived culling, the object axes and a grid
+        if self.show_debug {
+            self.debug.set_depth_test(true);
+            self.debug.grid(Vector3::new(0.0,0.001,0.0),1.0,16,Vector4::new(0.4,0.4,0.4,1.0));
+            for c in self.draw_list.commands().iter() {
+                if let Some(b) = c.mesh.aabb() {
+                    self.debug.aabb(&b.transform(&c.world),Vector4::new(1.0,1.0,0.0,1.0));
+                }
+            }
+            self.debug.set_depth_test(false);
+            for obj in self.objects.iter_mut() {
+                if let Some(trans) = obj.as_mut().pin_get().get_comp::<Transform>()
+                    .and_then(|c| c.as_any().downcast_ref::<Transform>())
+                {
+                    self.debug.axis(trans,0.5);
+                }
+            }
+        }
+        self.debug_renderer.prepare(&self.device,&self.queue,&mut self.debug,&view_proj);
+
+        let text = format!("{:.1} fps ({:.2} ms)\n{}\n{} draws in {} batches, {} culled\nD: debug lines {}",
+            1.0 / self.frame_time,self.frame_time * 1000.0,self.adapter_info,
+            self.draw_list.len(),self.draw_list.batches().len(),culled,if self.show_debug { "on" } else { "off" });
+        let size = Overlay::text_size(14.0,text.as_str());
+        self.overlay.draw_rect(Vector2::new(4.0,4.0),size + Vector2::new(8.0,6.0),Vector4::new(0.0,0.0,0.0,0.5));
+        self.overlay.draw_text(Vector2::new(8.0,8.0),14.0,Vector4::new(1.0,1.0,1.0,1.0),text.as_str());
+        self.overlay_renderer.prepare(&self.device,&self.queue,&mut self.overlay,self.size.width,self.size.height);
+
+        let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor{
+            label: Some("Render Encoder")
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor{
+                label: Some("Render Pass"),
+                color_attachments: &[self.targets.color_attachment(&frame.view,LoadOp::Clear(self.clear_color))],
+                depth_stencil_attachment: self.targets.depth_attachment(LoadOp::Clear(1f32))
+            });
+            render_pass.set_bind_group(1,&self.camera_bind_group,&[]);
+            self.draw_list.execute(&mut render_pass);
+            self.debug_renderer.execute(&mut render_pass);
+        }
+        // after the msaa resolve, straight into the frame
+        self.overlay_renderer.render(&mut encoder,&frame.view);
+        self.queue.submit(std::iter::once(encoder.finish()));
+        Ok(())
+    }
+}
+
+/// The view-projection matrix and the eye position.
+fn camera(width:u32,height:u32,angle:f32) -> (Matrix4<f32>,Vector3<f32>)
+{
+    let eye = Point3::new(14.0 * angle.sin(),9.0,14.0 * angle.cos());
+    let view = Matrix4::look_at_rh(eye,Point3::new(0.0,0.0,0.0),Vector3::unit_y());
+    let projection = cgmath::perspective(Deg(45f32),width as f32 / height.max(1) as f32,0.1,100.0);
+    (OPENGL_TO_WGPU_MATRIX * projection * view,eye.to_vec())
+}
+
+/// 24 vertices so every face has its own normal and a full uv square.
+fn cube() -> MeshData
+{
+    let faces:[([f32;3],[f32;3],[f32;3]);6] = [
+        // normal, u axis, v axis
+        ([1.0,0.0,0.0],[0.0,0.0,-1.0],[0.0,1.0,0.0]),
+        ([-1.0,0.0,0.0],[0.0,0.0,1.0],[0.0,1.0,0.0]),
+        ([0.0,1.0,0.0],[1.0,0.0,0.0],[0.0,0.0,-1.0]),
+        ([0.0,-1.0,0.0],[1.0,0.0,0.0],[0.0,0.0,1.0]),
+        ([0.0,0.0,1.0],[1.0,0.0,0.0],[0.0,1.0,0.0]),
+        ([0.0,0.0,-1.0],[-1.0,0.0,0.0],[0.0,1.0,0.0]),
+    ];
+    let mut data = MeshData::default();
+    for (n,u,v) in faces.iter() {
+        let base = data.positions.len() as u32;
+        for (su,sv) in [(-1.0,-1.0),(1.0,-1.0),(1.0,1.0),(-1.0,1.0)].iter() {
+            let p = |i:usize| 0.5 * (n[i] + su * u[i] + sv * v[i]);
+            data.positions.push([p(0),p(1),p(2)]);
+            data.normals.push(*n);
+            data.uvs.push([(su + 1.0) / 2.0,(1.0 - sv) / 2.0]);
+        }
+        data.indices.extend_from_slice(&[base,base + 1,base + 2,base,base + 2,base + 3]);
+    }
+    data.fill_missing();
+    data
+}
+
+fn ground() -> MeshData
+{
+    let mut data = MeshData{
+        positions: vec![[-1.0,0.0,-1.0],[-1.0,0.0,1.0],[1.0,0.0,1.0],[1.0,0.0,-1.0]],
+        uvs: vec![[0.0,0.0],[0.0,1.0],[1.0,1.0],[1.0,0.0]],
+        indices: vec![0,1,2,0,2,3],
+        ..Default::default()
+    };
+    data.fill_missing();
+    data
+}
+
+/// Upright unit quad facing +z, for the trees.
+fn quad() -> MeshData
+{
+    let mut data = MeshData{
+        positions: vec![[-0.5,-0.5,0.0],[0.5,-0.5,0.0],[0.5,0.5,0.0],[-0.5,0.5,0.0]],
+        uvs: vec![[0.0,1.0],[1.0,1.0],[1.0,0.0],[0.0,0.0]],
+        indices: vec![0,1,2,0,2,3],
+        ..Default::default()
+    };
+    data.fill_missing();
+    data
+}
+
+fn main() {
+    env_logger::init();
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("scene")
+        .build(&event_loop).unwrap();
+
+    let mut state = pollster::block_on(State::new(&window));
+
+    event_loop.run(move |e,_,control_flow|{
+        match e {
+            Event::WindowEvent { window_id,event} => {
+                if window_id == window.id() {
+                    if !state.input(&event) {
+                        match event {
+                            WindowEvent::CloseRequested | WindowEvent::KeyboardInput {
+                                input: KeyboardInput {
+                                    state: ElementState::Released,
+                                    virtual_keycode: Some(VirtualKeyCode::Escape), ..
+                                }, ..
+                            } => {
+                                *control_flow = ControlFlow::Exit;
+                            }
+                            WindowEvent::Resized(size) => {
+                                state.resize(size);
+                            }
+                            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                                state.resize(*new_inner_size);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            Event::RedrawRequested(_) => {
+                state.update();
+                match state.render() {
+                    Ok(_) => {}
+                    // Recreate the swap_chain if lost
+                    Err(wgpu::SwapChainError::Lost) => state.resize(state.size),
+                    // The system is out of memory, we should probably quit
+                    Err(wgpu::SwapChainError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                    // All other errors (Outdated, Timeout) should be resolved by the next frame
+                    Err(e) => eprintln!("{:?}", e),
+                }
+            }
+            Event::MainEventsCleared => {
+                window.request_redraw();
+            }
+            _=>{}
+        }
+    });
+}
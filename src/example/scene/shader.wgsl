@@ -0,0 +1,66 @@
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(2)]] uv: vec2<f32>;
+    [[location(3)]] normal: vec3<f32>;
+};
+
+struct InstanceInput {
+    [[location(5)]] model_0: vec4<f32>;
+    [[location(6)]] model_1: vec4<f32>;
+    [[location(7)]] model_2: vec4<f32>;
+    [[location(8)]] model_3: vec4<f32>;
+    [[location(9)]] color: vec4<f32>;
+};
+
+struct VertexOutput{
+    [[builtin(position)]] clip_position : vec4<f32>;
+    [[location(0)]] uv : vec2<f32>;
+    [[location(1)]] normal : vec3<f32>;
+    [[location(2)]] color : vec4<f32>;
+};
+
+[[block]]
+struct Camera {
+    view_proj: mat4x4<f32>;
+};
+[[group(1), binding(0)]]
+var<uniform> camera: Camera;
+
+[[stage(vertex)]]
+fn main(in : VertexInput,instance : InstanceInput) -> VertexOutput
+{
+    let model = mat4x4<f32>(instance.model_0,instance.model_1,instance.model_2,instance.model_3);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model * vec4<f32>(in.position,1.0);
+    out.uv = in.uv;
+    out.color = instance.color;
+    // fine as long as the scale is uniform
+    out.normal = (model * vec4<f32>(in.normal,0.0)).xyz;
+    return out;
+}
+
+[[group(0), binding(0)]]
+var t_diffuse: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_diffuse: sampler;
+
+[[block]]
+struct Params {
+    tint: vec4<f32>;
+    // x: alpha cutoff, 0 keeps everything
+    cutoff: vec4<f32>;
+};
+[[group(2), binding(0)]]
+var<uniform> params: Params;
+
+[[stage(fragment)]]
+fn main(v:VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    let light = normalize(vec3<f32>(0.4,1.0,0.6));
+    let diffuse = max(dot(normalize(v.normal),light),0.0) * 0.8 + 0.2;
+    let color = textureSample(t_diffuse,s_diffuse,v.uv) * params.tint * v.color;
+    if (color.a < params.cutoff.x) {
+        discard;
+    }
+    return vec4<f32>(color.rgb * diffuse,color.a);
+}
@@ -0,0 +1,469 @@
+use winit::{
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+use winit::window::Window;
+use wgpu::{BackendBit, RequestAdapterOptions, PowerPreference, DeviceDescriptor, Features, TextureUsage, TextureFormat, PresentMode, CommandEncoderDescriptor, BufferUsage, IndexFormat, BindGroupDescriptor, BindGroupEntry, BindGroup, Buffer, LoadOp, RenderPipeline, RenderPass};
+use wgpu::util::{DeviceExt, BufferInitDescriptor};
+use cgmath::{Matrix4, Vector3, Vector4, Vector2, Zero, Rad, Deg, Point3, SquareMatrix};
+use winit::dpi::PhysicalPosition;
+use std::rc::Rc;
+use std::pin::Pin;
+use utils::bytes::AsBytes;
+use utils::buffer::GpuBuffer;
+use utils::uniform::{UniformLayout, AddressSpace};
+use utils::vertex::VertexLayout;
+use utils::reflect::{check_vertex_layouts, check_bind_group_layouts, reflect_bind_group_layouts};
+use utils::pipeline::{PipelineCache, PipelineDesc, DepthState};
+use utils::material::CompareDesc;
+use utils::render_graph::{RenderGraph, RenderNode, GraphResources, AttachmentDesc, AttachmentSize, PassDesc, Target, BindGroupId};
+use utils::shadow::{ShadowMap, ShadowNode, ShadowCaster, CameraFrustum, shadow_wgsl};
+use utils::camera::OPENGL_TO_WGPU_MATRIX;
+use utils::components::{DirectionalLight, Transform};
+use utils::lighting::LightRaw;
+use utils::debug_view::{DebugView, DebugSource};
+use utils::object::Object;
+use utils::AsAny;
+
+const SHADOW_MAP_SIZE: u32 = 2048;
+const CASCADES: usize = 3;
+
+#[repr(C)]
+#[derive(Debug,Copy,Clone,VertexLayout,AsBytes)]
+struct Vertex{
+    position: [f32;3],
+    normal: [f32;3],
+}
+
+#[repr(C)]
+#[derive(Debug,Copy,Clone,VertexLayout,AsBytes)]
+#[step(instance)]
+struct InstanceRaw{
+    #[location(5)]
+    model: Matrix4<f32>,
+    color: Vector4<f32>,
+}
+
+#[derive(Debug,Copy,Clone,UniformLayout)]
+struct Camera{
+    view_proj: Matrix4<f32>,
+    view: Matrix4<f32>,
+    light_dir: Vector4<f32>,
+    light_color: Vector4<f32>,
+}
+
+/// A cube drawn once per instance, the ground is a flat one.
+struct Mesh{
+    vertices: GpuBuffer<Vertex>,
+    indices: GpuBuffer<u32>,
+    instances: GpuBuffer<InstanceRaw>,
+}
+
+impl Mesh {
+    fn draw<'a>(&'a self,pass:&mut RenderPass<'a>)
+    {
+        pass.set_vertex_buffer(0,self.vertices.slice(..));
+        pass.set_vertex_buffer(1,self.instances.slice(..));
+        pass.set_index_buffer(self.indices.slice(..),IndexFormat::Uint32);
+        pass.draw_indexed(0..self.indices.len() as u32,0,0..self.instances.len() as u32);
+    }
+}
+
+impl ShadowCaster for Mesh {
+    fn draw_depth<'a>(&'a self,pass:&mut RenderPass<'a>)
+    {
+        self.draw(pass);
+    }
+}
+
+/// Lit scene sampling the shadow map.
+struct SceneNode{
+    pipeline: Rc<RenderPipeline>,
+    mesh: Rc<Mesh>,
+    camera_bind_group: BindGroup,
+    shadow_bind_group: BindGroupId,
+}
+
+impl RenderNode for SceneNode {
+    fn record<'a>(&'a self,render_pass:&mut RenderPass<'a>,res:&'a GraphResources)
+    {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0,&self.camera_bind_group,&[]);
+        render_pass.set_bind_group(1,res.bind_group(self.shadow_bind_group),&[]);
+        self.mesh.draw(render_pass);
+    }
+}
+
+struct State{
+    surface : wgpu::Surface,
+    device : wgpu::Device,
+    queue : wgpu::Queue,
+    sc_desc : wgpu::SwapChainDescriptor,
+    swap_chain : wgpu::SwapChain,
+    size : winit::dpi::PhysicalSize<u32>,
+    light : Pin<Box<Object>>,
+    shadow : Rc<ShadowMap>,
+    camera : Camera,
+    camera_buf : Buffer,
+    rotate : Vector2<f32>,
+    left_btn_down: bool,
+    last_cursor_pos : Vector2<f32>,
+    graph: RenderGraph,
+    pipelines: PipelineCache,
+    /// V switches the layout, Tab the shown attachment.
+    debug_view: DebugView,
+}
+
+impl State{
+    async fn new(window: &Window) -> State
+    {
+        let size = window.inner_size();
+        let ins = wgpu::Instance::new(BackendBit::PRIMARY);
+        let surface = unsafe{ ins.create_surface(window) };
+
+        let adapter = ins.request_adapter(&RequestAdapterOptions{
+            power_preference: PowerPreference::HighPerformance,
+            compatible_surface: Some(&surface)
+        }).await.unwrap();
+
+        let (device,queue) = adapter.request_device(&DeviceDescriptor{
+            label: None,
+            features: Features::empty(),
+            limits: Default::default()
+        },None).await.unwrap();
+
+        let sc_desc = wgpu::SwapChainDescriptor{
+            usage: TextureUsage::RENDER_ATTACHMENT,
+            format: adapter.get_swap_chain_preferred_format(&surface).unwrap(),
+            width: size.width,
+            height: size.height,
+            present_mode: PresentMode::Fifo
+        };
+
+        let swap_chain = device.create_swap_chain(&surface,&sc_desc);
+
+        let shader_src = format!("{}\n{}",shadow_wgsl(1),include_str!("shader.wgsl"));
+        if let Err(e) = check_vertex_layouts(shader_src.as_str(),"main",&[Vertex::layout(),InstanceRaw::layout()]) {
+            panic!("shader.wgsl: {}",e);
+        }
+        if let Err(e) = check_vertex_layouts(include_str!("shadow_depth.wgsl"),"main",&[Vertex::layout(),InstanceRaw::layout()]) {
+            panic!("shadow_depth.wgsl: {}",e);
+        }
+        let layouts = match reflect_bind_group_layouts(shader_src.as_str()) {
+            Ok(l) => l,
+            Err(e) => panic!("shader.wgsl: {}",e)
+        };
+        if let Err(e) = check_bind_group_layouts(shader_src.as_str(),&[layouts[0].entries.as_slice(),ShadowMap::layout_entries().as_slice()]) {
+            panic!("shader.wgsl: {}",e);
+        }
+        if let Err(e) = check_bind_group_layouts(include_str!("shadow_depth.wgsl"),&[ShadowMap::cascade_entries().as_slice()]) {
+            panic!("shadow_depth.wgsl: {}",e);
+        }
+
+        let mut light = Object::new();
+        let mut sun = DirectionalLight::new(Vector3::new(-0.4,-1.0,-0.6));
+        sun.color = Vector3::new(1.0,0.95,0.85);
+        light.as_mut().pin_get().add_comp(Box::new(sun));
+
+        let camera = Camera{
+            view_proj: Matrix4::identity(),
+            view: Matrix4::identity(),
+            light_dir: Vector4::zero(),
+            light_color: Vector4::zero()
+        };
+        let camera_buf = device.create_buffer_init(&BufferInitDescriptor{
+            label: Some("Camera Buffer"),
+            contents: camera.to_bytes().as_slice(),
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST
+        });
+        let camera_layout = Rc::new(device.create_bind_group_layout(&layouts[0].descriptor(Some("Camera Bind Group Layout"))));
+        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Camera Bind Group"),
+            layout: &camera_layout,
+            entries: &[
+                BindGroupEntry{ binding: 0, resource: camera_buf.as_entire_binding() }
+            ]
+        });
+
+        let (vertices,indices) = cube();
+        let mesh = Rc::new(Mesh{
+            vertices: GpuBuffer::new(&device,Some("Vertices"),BufferUsage::VERTEX,vertices.as_slice()),
+            indices: GpuBuffer::new(&device,Some("Indices"),BufferUsage::INDEX,indices.as_slice()),
+            instances: GpuBuffer::new(&device,Some("Instance Buffer"),BufferUsage::VERTEX,gen_instances(12,2.5).as_slice())
+        });
+
+        let mut graph = RenderGraph::new(&sc_desc);
+        let shadow = Rc::new(ShadowMap::new(&device,&mut graph,SHADOW_MAP_SIZE,CASCADES));
+        let depth = graph.add_attachment(AttachmentDesc{
+            label: "Depth Stencil Tex".to_string(),
+            format: TextureFormat::Depth32Float,
+            size: AttachmentSize::Relative(1.0),
+            usage: TextureUsage::empty(),
+            multisampled: false
+        });
+
+        let mut pipelines = PipelineCache::new(sc_desc.format);
+        let shader = pipelines.shader(&device,"shader.wgsl",shader_src.as_str());
+        let shader_depth = pipelines.shader(&device,"shadow_depth.wgsl",include_str!("shadow_depth.wgsl"));
+        let scene_layouts = vec![pipelines.layout(&camera_layout),pipelines.layout(shadow.layout())];
+        let pipeline = pipelines.get(&device,&PipelineDesc{
+            depth: Some(DepthState{ format: TextureFormat::Depth32Float, write: true, compare: CompareDesc::Less }),
+            ..PipelineDesc::new(shader,scene_layouts,vec![Vertex::layout(),InstanceRaw::layout()])
+        });
+        let shadow_layouts = vec![pipelines.layout(shadow.cascade_layout())];
+        let shadow_pipeline = pipelines.get(&device,&PipelineDesc{
+            color: None,
+            depth: Some(ShadowMap::depth_state()),
+            ..PipelineDesc::new(shader_depth,shadow_layouts,vec![Vertex::layout(),InstanceRaw::layout()])
+        });
+
+        graph.add_pass(shadow.pass(),Box::new(ShadowNode{
+            map: shadow.clone(),
+            pipeline: shadow_pipeline,
+            casters: vec![Box::new(mesh.clone())]
+        }));
+        graph.add_pass(PassDesc{
+            label: "Render Pass".to_string(),
+            colors: vec![(Target::SwapChain,LoadOp::Clear(wgpu::Color{ r: 0.4, g: 0.6, b: 0.9, a: 1.0 }))],
+            depth: Some((depth,LoadOp::Clear(1f32))),
+            reads: vec![shadow.attachment()]
+        },Box::new(SceneNode{
+            pipeline,
+            mesh,
+            camera_bind_group,
+            shadow_bind_group: shadow.bind_group()
+        }));
+        let debug_view = DebugView::new(&device,&mut graph,&mut pipelines,vec![
+            ("Depth",depth,DebugSource::LinearDepth{ near: 0.1, far: 100.0 }),
+            ("Shadow Map",shadow.attachment(),DebugSource::Depth)
+        ]);
+        if let Err(e) = graph.build(&device) {
+            panic!("render graph: {}",e);
+        }
+
+        State{
+            surface,
+            device,
+            queue,
+            sc_desc,
+            swap_chain,
+            size,
+            light,
+            shadow,
+            camera,
+            camera_buf,
+            rotate: Vector2::new(0.5,0.0),
+            left_btn_down: false,
+            last_cursor_pos: Vector2::zero(),
+            graph,
+            pipelines,
+            debug_view
+        }
+    }
+
+    fn resize(&mut self,size:winit::dpi::PhysicalSize<u32>)
+    {
+        if size.width > 0 && size.height > 0
+        {
+            self.size = size;
+            self.sc_desc.width = size.width;
+            self.sc_desc.height = size.height;
+            self.swap_chain = self.device.create_swap_chain(&self.surface,&self.sc_desc);
+            self.graph.resize(&self.device,&self.sc_desc);
+        }
+    }
+
+    fn input(&mut self,event:&WindowEvent,window:&Window) -> bool
+    {
+        match event{
+            &WindowEvent::KeyboardInput{ input:KeyboardInput{
+                virtual_keycode:Some(key @ (VirtualKeyCode::V | VirtualKeyCode::Tab)),state:ElementState::Released,..
+            },.. } => {
+                if key == VirtualKeyCode::V {
+                    self.debug_view.next_layout();
+                }else{
+                    self.debug_view.next_source();
+                }
+                window.set_title(self.title().as_str());
+                true
+            }
+            &WindowEvent::MouseInput {
+                button:MouseButton::Left,
+                state,..
+            } => {
+                self.left_btn_down = match state{
+                    ElementState::Pressed => {true}
+                    ElementState::Released => {false}
+                };
+                true
+            }
+            &WindowEvent::CursorMoved{position:PhysicalPosition::<f64> {x,y},..} =>
+            {
+                if self.left_btn_down{
+                    let offset = Vector2::new(x as f32,y as f32) - self.last_cursor_pos;
+                    self.rotate.y += offset.x * 0.005;
+                    self.rotate.x = (self.rotate.x + offset.y * 0.005).max(0.05).min(1.5);
+                }
+                self.last_cursor_pos = Vector2::new(x as f32,y as f32);
+                true
+            }
+            _ => { false }
+        }
+    }
+
+    fn title(&self) -> String
+    {
+        format!("shadow - {}: {}",self.debug_view.selected_name(),self.debug_view.layout().name())
+    }
+
+    fn camera_frustum(&self) -> CameraFrustum
+    {
+        let eye = Point3::new(
+            30.0 * self.rotate.x.cos() * self.rotate.y.sin(),
+            30.0 * self.rotate.x.sin(),
+            30.0 * self.rotate.x.cos() * self.rotate.y.cos()
+        );
+        CameraFrustum{
+            view: Matrix4::look_at_rh(eye,Point3::new(0.0,0.0,0.0),Vector3::unit_y()),
+            fovy: Rad::from(Deg(45f32)),
+            aspect: self.size.width as f32 / self.size.height as f32,
+            near: 0.1,
+            far: 100.0
+        }
+    }
+
+    fn update(&mut self) {
+        let frustum = self.camera_frustum();
+        let light = self.light.get_comp::<DirectionalLight>()
+            .and_then(|c| c.as_any().downcast_ref::<DirectionalLight>())
+            .unwrap();
+        let world = self.light.get_comp::<Transform>()
+            .and_then(|c| c.as_any().downcast_ref::<Transform>())
+            .map_or_else(Matrix4::identity,|t| t.get_world_matrix());
+        let projection = OPENGL_TO_WGPU_MATRIX * cgmath::perspective(frustum.fovy,frustum.aspect,frustum.near,frustum.far);
+        self.camera.view_proj = projection * frustum.view;
+        self.camera.view = frustum.view;
+        self.camera.light_dir = LightRaw::directional(light,&world).direction.extend(0.0);
+        self.camera.light_color = (light.color * light.intensity).extend(1.0);
+        self.queue.write_buffer(&self.camera_buf,0,self.camera.to_bytes().as_slice());
+        self.shadow.update(&self.queue,light,&world,&frustum);
+    }
+
+    fn render(&mut self) -> Result<(),wgpu::SwapChainError>
+    {
+        let frame = self.swap_chain.get_current_frame()?.output;
+
+        let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor{
+            label: Some("Render Encoder")
+        });
+        self.graph.execute(&mut encoder,&frame.view);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        Ok(())
+    }
+}
+
+/// 24 vertices so every face has its own normal.
+fn cube() -> (Vec<Vertex>,Vec<u32>)
+{
+    let faces:[([f32;3],[f32;3],[f32;3]);6] = [
+        // normal, u axis, v axis
+        ([1.0,0.0,0.0],[0.0,0.0,-1.0],[0.0,1.0,0.0]),
+        ([-1.0,0.0,0.0],[0.0,0.0,1.0],[0.0,1.0,0.0]),
+        ([0.0,1.0,0.0],[1.0,0.0,0.0],[0.0,0.0,-1.0]),
+        ([0.0,-1.0,0.0],[1.0,0.0,0.0],[0.0,0.0,1.0]),
+        ([0.0,0.0,1.0],[1.0,0.0,0.0],[0.0,1.0,0.0]),
+        ([0.0,0.0,-1.0],[-1.0,0.0,0.0],[0.0,1.0,0.0]),
+    ];
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for (n,u,v) in faces.iter() {
+        let base = vertices.len() as u32;
+        for (su,sv) in [(-1.0,-1.0),(1.0,-1.0),(1.0,1.0),(-1.0,1.0)].iter() {
+            let p = |i:usize| 0.5 * (n[i] + su * u[i] + sv * v[i]);
+            vertices.push(Vertex{ position: [p(0),p(1),p(2)], normal: *n });
+        }
+        indices.extend_from_slice(&[base,base + 1,base + 2,base,base + 2,base + 3]);
+    }
+    (vertices,indices)
+}
+
+/// The ground and a `row` by `row` grid of boxes of different heights on it.
+fn gen_instances(row:usize,space:f32) -> Vec<InstanceRaw>
+{
+    let extent = row as f32 * space;
+    let mut res = vec![InstanceRaw{
+        model: Matrix4::from_translation(Vector3::new(0.0,-0.05,0.0)) * Matrix4::from_nonuniform_scale(extent * 2.0,0.1,extent * 2.0),
+        color: Vector4::new(0.8,0.8,0.8,1.0)
+    }];
+    let offset = (row - 1) as f32 * space / 2f32;
+    for z in 0..row {
+        for x in 0..row {
+            let height = 1.0 + ((x * 7 + z * 13) % 5) as f32;
+            res.push(InstanceRaw{
+                model: Matrix4::from_translation(Vector3::new(x as f32 * space - offset,height / 2f32,z as f32 * space - offset)) *
+                    Matrix4::from_angle_y(Rad((x + z) as f32 * 0.3)) *
+                    Matrix4::from_nonuniform_scale(1.0,height,1.0),
+                color: Vector4::new(0.3 + 0.05 * x as f32,0.5,0.9 - 0.05 * z as f32,1.0)
+            });
+        }
+    }
+    res
+}
+
+fn main() {
+    env_logger::init();
+    let event_loop = EventLoop::new();
+
+    let window = WindowBuilder::new()
+        .with_title("shadow")
+        .build(&event_loop).unwrap();
+
+    let mut state = pollster::block_on(State::new(&window));
+    window.set_title(state.title().as_str());
+
+    event_loop.run(move |e,_,control_flow|{
+        match e {
+            Event::WindowEvent { window_id,event} => {
+                if window_id == window.id() {
+                    if !state.input(&event,&window) {
+                        match event {
+                            WindowEvent::CloseRequested | WindowEvent::KeyboardInput {
+                                input: KeyboardInput {
+                                    state: ElementState::Released,
+                                    virtual_keycode: Some(VirtualKeyCode::Escape), ..
+                                }, ..
+                            } => {
+                                *control_flow = ControlFlow::Exit;
+                            }
+                            WindowEvent::Resized(size) => {
+                                state.resize(size);
+                            }
+                            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                                state.resize(*new_inner_size);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            Event::RedrawRequested(_) => {
+                state.update();
+                match state.render() {
+                    Ok(_) => {}
+                    // Recreate the swap_chain if lost
+                    Err(wgpu::SwapChainError::Lost) => state.resize(state.size),
+                    // The system is out of memory, we should probably quit
+                    Err(wgpu::SwapChainError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                    // All other errors (Outdated, Timeout) should be resolved by the next frame
+                    Err(e) => eprintln!("{:?}", e),
+                }
+            }
+            Event::MainEventsCleared => {
+                window.request_redraw();
+            }
+            _=>{}
+        }
+    });
+}
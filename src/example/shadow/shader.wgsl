@@ -0,0 +1,62 @@
+// shadow_factor and the shadow bindings (group 1) come from utils::shadow::shadow_wgsl
+
+struct VertexOutput{
+    [[builtin(position)]] clip_position : vec4<f32>;
+    [[location(0)]] world_pos : vec3<f32>;
+    [[location(1)]] normal : vec3<f32>;
+    [[location(2)]] color : vec4<f32>;
+    [[location(3)]] view_depth : f32;
+};
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] normal: vec3<f32>;
+};
+
+struct InstanceInput {
+    [[location(5)]] model_matrix_0: vec4<f32>;
+    [[location(6)]] model_matrix_1: vec4<f32>;
+    [[location(7)]] model_matrix_2: vec4<f32>;
+    [[location(8)]] model_matrix_3: vec4<f32>;
+    [[location(9)]] color: vec4<f32>;
+};
+
+[[block]]
+struct Camera {
+    view_proj: mat4x4<f32>;
+    view: mat4x4<f32>;
+    light_dir: vec4<f32>;
+    light_color: vec4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+
+[[stage(vertex)]]
+fn main(in : VertexInput,instance: InstanceInput) -> VertexOutput
+{
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world = model_matrix * vec4<f32>(in.position,1.0);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world;
+    out.world_pos = world.xyz;
+    out.normal = (model_matrix * vec4<f32>(in.normal,0.0)).xyz;
+    out.color = instance.color;
+    out.view_depth = -(camera.view * world).z;
+    return out;
+}
+
+[[stage(fragment)]]
+fn main(v:VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    let n = normalize(v.normal);
+    let l = normalize(-camera.light_dir.xyz);
+    let diffuse = max(dot(n,l),0.0) * shadow_factor(v.world_pos,v.view_depth);
+    let light = camera.light_color.rgb * (0.15 + diffuse);
+    return vec4<f32>(v.color.rgb * light,v.color.a);
+}
@@ -0,0 +1,268 @@
+use winit::{
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+use winit::window::Window;
+use wgpu::{BackendBit, RequestAdapterOptions, PowerPreference, DeviceDescriptor, Features, TextureUsage, PresentMode, CommandEncoderDescriptor, RenderPassDescriptor, RenderPassColorAttachment, Operations, LoadOp};
+use cgmath::{Vector2, Vector3, Vector4};
+use std::rc::Rc;
+use std::pin::Pin;
+use std::time::Instant;
+use utils::resource_manager::ResourceMgr;
+use utils::resource_manager::pack::RgbaMips;
+use utils::texture::{ImageRes, SamplerDesc};
+use utils::atlas::load_atlas;
+use utils::sprite::{SpriteBatch, SpriteRenderer};
+use utils::overlay::{Overlay, OverlayRenderer, pixel_projection};
+use utils::pipeline::{PipelineCache, ColorFormat};
+use utils::draw::DrawList;
+use utils::component::RenderNecessary;
+use utils::components::{Transform, Sprite};
+use utils::object::Object;
+use utils::AsAny;
+
+const ATLAS_IMAGES: [&str;4] = ["textures/happy-tree.png","load_jpeg/maomaotou.jpg","load_jpeg/btn_list_arrow.jpg","load_jpeg/red.jpg"];
+
+struct State{
+    surface : wgpu::Surface,
+    device : wgpu::Device,
+    queue : wgpu::Queue,
+    sc_desc : wgpu::SwapChainDescriptor,
+    swap_chain : wgpu::SwapChain,
+    size : winit::dpi::PhysicalSize<u32>,
+    clear_color : wgpu::Color,
+    objects : Vec<Pin<Box<Object>>>,
+    draw_list : DrawList,
+    sprites : SpriteBatch,
+    sprite_renderer : SpriteRenderer,
+    overlay : Overlay,
+    overlay_renderer : OverlayRenderer,
+    atlas_size : (u32,u32),
+    last_frame : Instant,
+    frame_time : f32,
+    time : f32,
+}
+
+impl State{
+    async fn new(window: &Window) -> State
+    {
+        let size = window.inner_size();
+        let ins = wgpu::Instance::new(BackendBit::PRIMARY);
+        let surface = unsafe{ ins.create_surface(window) };
+
+        let adapter = ins.request_adapter(&RequestAdapterOptions{
+            power_preference: PowerPreference::HighPerformance,
+            compatible_surface: Some(&surface)
+        }).await.unwrap();
+
+        let (device,queue) = adapter.request_device(&DeviceDescriptor{
+            label: None,
+            features: Features::empty(),
+            limits: Default::default()
+        },None).await.unwrap();
+
+        let sc_desc = wgpu::SwapChainDescriptor{
+            usage: TextureUsage::RENDER_ATTACHMENT,
+            format: adapter.get_swap_chain_preferred_format(&surface).unwrap(),
+            width: size.width,
+            height: size.height,
+            present_mode: PresentMode::Fifo
+        };
+
+        let swap_chain = device.create_swap_chain(&surface,&sc_desc);
+
+        let mut pipelines = PipelineCache::new(sc_desc.format);
+        let sprite_renderer = SpriteRenderer::new(&device,&mut pipelines,ColorFormat::SwapChain,1);
+        let overlay = Overlay::new();
+        let overlay_renderer = OverlayRenderer::new(&device,&queue,&mut pipelines,ColorFormat::SwapChain,overlay.font());
+
+        let mut res_mgr = ResourceMgr::new(concat!(env!("CARGO_MANIFEST_DIR"),"/src/example").to_string());
+        res_mgr.add_process(Box::new(ImageRes::new()));
+        // four images in one texture, the cartoon tree on its own to have a second texture to batch by
+        let atlas = load_atlas(&mut res_mgr,&ATLAS_IMAGES,2048).unwrap();
+        let sampler = SamplerDesc::default().to_wgpu();
+        let atlas_texture = sprite_renderer.create_texture(&device,&queue,&atlas.image,&sampler);
+        let cartoon = load_image(&mut res_mgr,"textures/happy-tree-cartoon.png").unwrap();
+        let cartoon_texture = sprite_renderer.create_texture(&device,&queue,&cartoon,&sampler);
+
+        let mut objects = Vec::new();
+        for i in 0..400u32 {
+            // a cheap hash, so the layout is the same every run
+            let h = i.wrapping_mul(2654435761);
+            let mut obj = Object::new();
+            let mut trans = Transform::new();
+            trans.position = Vector3::new((h % 1280) as f32,((h >> 11) % 720) as f32,0.0);
+            trans.rotation.z = (h % 360) as f32 / 57.3;
+            obj.as_mut().pin_get().add_comp(Box::new(trans));
+
+            let mut sprite = if i % 5 == 0 {
+                Sprite::new(cartoon_texture.clone(),[0.0,0.0,1.0,1.0],Vector2::new(64.0,64.0))
+            }else{
+                let name = ATLAS_IMAGES[(i % 4) as usize];
+                let r = atlas.region(name).unwrap();
+                let scale = if r.width < 32 { 4.0 } else if r.width > 128 { 0.25 } else { 1.0 };
+                Sprite::new(atlas_texture.clone(),atlas.uv(name).unwrap(),Vector2::new(r.width as f32,r.height as f32) * scale)
+            };
+            sprite.z = (i % 3) as i32;
+            sprite.flip_x = h & 1 == 1;
+            sprite.flip_y = i % 7 == 0;
+            sprite.tint = Vector4::new(1.0,0.6 + 0.4 * ((h >> 3) % 2) as f32,1.0,0.9);
+            obj.as_mut().pin_get().add_comp(Box::new(sprite));
+            objects.push(obj);
+        }
+
+        State{
+            draw_list: DrawList::new(&device),
+            sprites: SpriteBatch::new(),
+            atlas_size: (atlas.image.width,atlas.image.height),
+            surface,
+            device,
+            queue,
+            sc_desc,
+            swap_chain,
+            size,
+            clear_color: wgpu::Color{ r: 0.1, g: 0.2, b: 0.3, a: 1.0 },
+            objects,
+            sprite_renderer,
+            overlay,
+            overlay_renderer,
+            last_frame: Instant::now(),
+            frame_time: 1.0 / 60.0,
+            time: 0.0
+        }
+    }
+
+    fn resize(&mut self,size:winit::dpi::PhysicalSize<u32>)
+    {
+        if size.width > 0 && size.height > 0
+        {
+            self.size = size;
+            self.sc_desc.width = size.width;
+            self.sc_desc.height = size.height;
+            self.swap_chain = self.device.create_swap_chain(&self.surface,&self.sc_desc);
+        }
+    }
+
+    fn input(&mut self,_event:&WindowEvent) -> bool
+    {
+        false
+    }
+
+    fn update(&mut self) {
+        let now = Instant::now();
+        self.frame_time = self.frame_time * 0.95 + (now - self.last_frame).as_secs_f32() * 0.05;
+        self.last_frame = now;
+        self.time += 0.016;
+        for (i,obj) in self.objects.iter_mut().enumerate() {
+            if let Some(trans) = obj.as_mut().pin_get().get_comp_mut::<Transform>()
+                .and_then(|c| c.as_mut_any().downcast_mut::<Transform>())
+            {
+                trans.rotation.z += if i % 2 == 0 { 0.01 } else { -0.01 };
+                let s = 1.0 + 0.2 * (self.time + i as f32).sin();
+                trans.scale = Vector3::new(s,s,1.0);
+            }
+        }
+    }
+
+    fn render(&mut self) -> Result<(),wgpu::SwapChainError>
+    {
+        let frame = self.swap_chain.get_current_frame()?.output;
+
+        for obj in self.objects.iter_mut() {
+            obj.as_mut().pin_get().render(RenderNecessary{
+                swap_chain_desc: &self.sc_desc,
+                draw_list: &mut self.draw_list,
+                sprites: &mut self.sprites
+            });
+        }
+        let count = self.sprites.len();
+        self.sprite_renderer.prepare(&self.device,&self.queue,&mut self.sprites,&pixel_projection(self.size.width,self.size.height));
+
+        let text = format!("{:.1} fps\n{} sprites in {} draw calls\natlas {}x{}",
+            1.0 / self.frame_time,count,self.sprite_renderer.draw_calls(),self.atlas_size.0,self.atlas_size.1);
+        let size = Overlay::text_size(14.0,text.as_str());
+        self.overlay.draw_rect(Vector2::new(4.0,4.0),size + Vector2::new(8.0,6.0),Vector4::new(0.0,0.0,0.0,0.6));
+        self.overlay.draw_text(Vector2::new(8.0,8.0),14.0,Vector4::new(1.0,1.0,1.0,1.0),text.as_str());
+        self.overlay_renderer.prepare(&self.device,&self.queue,&mut self.overlay,self.size.width,self.size.height);
+
+        let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor{
+            label: Some("Render Encoder")
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor{
+                label: Some("Render Pass"),
+                color_attachments: &[RenderPassColorAttachment{
+                    view: &frame.view,
+                    resolve_target: None,
+                    ops: Operations{ load: LoadOp::Clear(self.clear_color), store: true }
+                }],
+                depth_stencil_attachment: None
+            });
+            self.sprite_renderer.execute(&mut render_pass);
+            self.overlay_renderer.execute(&mut render_pass);
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+        Ok(())
+    }
+}
+
+fn load_image(mgr:&mut ResourceMgr,p:&str) -> Option<Rc<RgbaMips>>
+{
+    let (raw,overdue,path) = mgr.load_file(p)?;
+    mgr.loading::<ImageRes,_,_>(raw,&path,overdue)
+}
+
+fn main() {
+    env_logger::init();
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("sprites")
+        .build(&event_loop).unwrap();
+
+    let mut state = pollster::block_on(State::new(&window));
+
+    event_loop.run(move |e,_,control_flow|{
+        match e {
+            Event::WindowEvent { window_id,event} => {
+                if window_id == window.id() {
+                    if !state.input(&event) {
+                        match event {
+                            WindowEvent::CloseRequested | WindowEvent::KeyboardInput {
+                                input: KeyboardInput {
+                                    state: ElementState::Released,
+                                    virtual_keycode: Some(VirtualKeyCode::Escape), ..
+                                }, ..
+                            } => {
+                                *control_flow = ControlFlow::Exit;
+                            }
+                            WindowEvent::Resized(size) => {
+                                state.resize(size);
+                            }
+                            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                                state.resize(*new_inner_size);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            Event::RedrawRequested(_) => {
+                state.update();
+                match state.render() {
+                    Ok(_) => {}
+                    // Recreate the swap_chain if lost
+                    Err(wgpu::SwapChainError::Lost) => state.resize(state.size),
+                    // The system is out of memory, we should probably quit
+                    Err(wgpu::SwapChainError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                    // All other errors (Outdated, Timeout) should be resolved by the next frame
+                    Err(e) => eprintln!("{:?}", e),
+                }
+            }
+            Event::MainEventsCleared => {
+                window.request_redraw();
+            }
+            _=>{}
+        }
+    });
+}
@@ -11,28 +11,8 @@ use image::{ImageError, GenericImageView};
 use std::num::NonZeroU32;
 use cgmath::{Matrix4, SquareMatrix, Vector3, Zero, Rad};
 use winit::dpi::Pixel;
-
-#[repr(C)]
-pub struct FatPtr
-{
-    ptr: *const u8,
-    len: usize
-}
-
-impl FatPtr{
-    pub fn new<T>(t:&T) -> FatPtr
-    {
-        FatPtr{
-            ptr : unsafe { std::mem::transmute::<*const T, *const u8>(t as *const T) },
-            len : std::mem::size_of::<T>()
-        }
-    }
-    pub unsafe fn as_ref_arr(&self) -> &[u8]
-    {
-        let ptr  = std::mem::transmute::<*const FatPtr,*const u128>(self as *const FatPtr);
-        std::mem::transmute::<u128,&[u8]>(*ptr)
-    }
-}
+use utils::bytes::AsBytes;
+use utils::buffer::GpuBuffer;
 
 #[repr(C)]
 #[derive(Debug,Copy, Clone,bytemuck::Pod, bytemuck::Zeroable)]
@@ -57,7 +37,7 @@ const INDICES: &[u32] = &[
 ];
 
 #[repr(C)]
-#[derive(Debug,Copy, Clone)]
+#[derive(Debug,Copy, Clone,AsBytes)]
 struct Uniform {
     projection: Matrix4<f32>,
     view: Matrix4<f32>,
@@ -80,7 +60,7 @@ struct State{
     bind_group_layouts: Vec<BindGroupLayout>,
     bind_groups : Vec<BindGroup>,
     uniform : Uniform,
-    uniform_buf : Buffer,
+    uniform_buf : GpuBuffer<Uniform>,
     rotate : Vector3<f32>,
 }
 
@@ -169,12 +149,7 @@ impl State{
         });
         let uniform = Uniform::new(45.0,size.width as f32 / size.height as f32 );
 
-        let uniform_buf = device.create_buffer_init(&BufferInitDescriptor{
-            label: Some("Uniform Buffer"),
-            contents: //unsafe { std::slice::from_raw_parts(std::mem::transmute::<_, *const u8>(&uniform), size_of::<Uniform>()) },
-            unsafe { FatPtr::new(&uniform).as_ref_arr() },
-            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST
-        });
+        let uniform_buf = GpuBuffer::from_value(&device,Some("Uniform Buffer"),BufferUsage::UNIFORM,&uniform);
         let vertex_binding_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor{
             label: Some("Vertex Binding Group"),
             entries: &[
@@ -197,7 +172,7 @@ impl State{
             entries: &[
                 BindGroupEntry{
                     binding: 0,
-                    resource: uniform_buf.as_entire_binding()
+                    resource: uniform_buf.buffer().as_entire_binding()
                 }
             ]
         });
@@ -300,7 +275,7 @@ impl State{
     fn update(&mut self) {
         self.rotate.y += 0.1;
         self.uniform.set_model(cgmath::Matrix4::from_angle_y(Rad(self.rotate.y.sin())));
-        unsafe { self.queue.write_buffer(&self.uniform_buf, 0, FatPtr::new(&self.uniform).as_ref_arr()); }
+        self.uniform_buf.set(&self.queue,&self.uniform);
     }
     fn render(&mut self) -> Result<(),wgpu::SwapChainError>
     {
@@ -0,0 +1,148 @@
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use image::GenericImageView;
+use image::imageops::FilterType;
+use utils::resource_manager::pack::{PackWriter, RgbaMips};
+
+struct Options{
+    src: PathBuf,
+    out: PathBuf,
+    compress: bool,
+    rgba: bool,
+    mips: bool,
+    check_wgsl: bool,
+}
+
+fn usage() -> !
+{
+    eprintln!("usage: pack <asset dir> <out file> [--compress] [--rgba] [--mips] [--check-wgsl]");
+    eprintln!("    --compress    zlib compress entries that get smaller");
+    eprintln!("    --rgba        convert png/jpg/jpeg to raw rgba8, stored under the original name");
+    eprintln!("    --mips        generate the full mip chain for converted images");
+    eprintln!("    --check-wgsl  parse and validate every *.wgsl file, abort on error");
+    exit(1)
+}
+
+fn parse_args() -> Options
+{
+    let mut paths = Vec::new();
+    let mut opt = Options{
+        src: PathBuf::new(),
+        out: PathBuf::new(),
+        compress: false,
+        rgba: false,
+        mips: false,
+        check_wgsl: false
+    };
+    for a in std::env::args().skip(1) {
+        match a.as_str() {
+            "--compress" => { opt.compress = true; }
+            "--rgba" => { opt.rgba = true; }
+            "--mips" => { opt.mips = true; }
+            "--check-wgsl" => { opt.check_wgsl = true; }
+            "-h" | "--help" => { usage(); }
+            _ if a.starts_with("--") => { eprintln!("unknown option {}",a); usage(); }
+            _ => { paths.push(PathBuf::from(a)); }
+        }
+    }
+    if paths.len() != 2 { usage(); }
+    opt.out = paths.pop().unwrap();
+    opt.src = paths.pop().unwrap();
+    opt
+}
+
+fn walk(dir:&Path,out:&mut Vec<PathBuf>) -> std::io::Result<()>
+{
+    for e in std::fs::read_dir(dir)? {
+        let path = e?.path();
+        if path.is_dir() {
+            walk(path.as_path(),out)?;
+        }else{
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn to_rgba_mips(data:&[u8],mips:bool) -> Result<RgbaMips,image::ImageError>
+{
+    let img = image::load_from_memory(data)?;
+    let (width,height) = img.dimensions();
+    let mut levels = vec![img.to_rgba8().into_raw()];
+    if mips {
+        for level in 1..RgbaMips::level_count(width,height) {
+            let (w,h) = RgbaMips::mip_size(width,height,level);
+            levels.push(img.resize_exact(w,h,FilterType::Triangle).to_rgba8().into_raw());
+        }
+    }
+    Ok(RgbaMips{ width, height, levels })
+}
+
+fn check_wgsl(name:&str,data:&[u8]) -> Result<(),String>
+{
+    let src = std::str::from_utf8(data).map_err(|e| format!("{}: {}",name,e))?;
+    let module = naga::front::wgsl::parse_str(src).map_err(|e| format!("{}: {:?}",name,e))?;
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all(),naga::valid::Capabilities::empty())
+        .validate(&module)
+        .map_err(|e| format!("{}: {:?}",name,e))?;
+    Ok(())
+}
+
+fn main() {
+    let opt = parse_args();
+    let mut files = Vec::new();
+    if let Err(e) = walk(opt.src.as_path(),&mut files) {
+        eprintln!("failed to read {:?}: {}",opt.src,e);
+        exit(1);
+    }
+    files.sort();
+
+    let mut writer = PackWriter::new();
+    let mut failed = false;
+    for f in files.iter() {
+        let name = f.strip_prefix(opt.src.as_path()).unwrap().to_string_lossy().to_string();
+        let data = match std::fs::read(f) {
+            Ok(d) => d,
+            Err(e) => { eprintln!("{}: {}",name,e); failed = true; continue; }
+        };
+        let ext = f.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        match ext.as_str() {
+            "png" | "jpg" | "jpeg" if opt.rgba => {
+                match to_rgba_mips(data.as_slice(),opt.mips) {
+                    Ok(img) => {
+                        // kept under its name so load_file finds it, ImageRes decodes the blob
+                        println!("{} -> rgba {}x{} mips {}",name,img.width,img.height,img.levels.len());
+                        writer.add(name.as_str(),img.encode(),opt.compress);
+                    }
+                    Err(e) => { eprintln!("{}: {}",name,e); failed = true; }
+                }
+                continue;
+            }
+            "wgsl" if opt.check_wgsl => {
+                if let Err(e) = check_wgsl(name.as_str(),data.as_slice()) {
+                    eprintln!("{}",e);
+                    failed = true;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        println!("{}",name);
+        writer.add(name.as_str(),data,opt.compress);
+    }
+    if failed {
+        eprintln!("pack aborted, fix the errors above");
+        exit(1);
+    }
+    match writer.write_file(opt.out.as_path()) {
+        Ok(toc) => {
+            let raw:u64 = toc.iter().map(|e| e.raw_len).sum();
+            let stored:u64 = toc.iter().map(|e| e.stored_len).sum();
+            println!("wrote {} entries to {:?} ({} -> {} bytes)",toc.len(),opt.out,raw,stored);
+        }
+        Err(e) => {
+            eprintln!("failed to write {:?}: {}",opt.out,e);
+            exit(1);
+        }
+    }
+}
@@ -44,8 +44,8 @@ pub fn gen_impl_res_process_cache(input: proc_macro::TokenStream) -> proc_macro:
     let obj_name = Ident::new(input.to_string().as_str(),Span::call_site());
 
     let tokens = quote::quote!{
-        fn get_cache(&self, path: &String) -> Option<Rc<Self::Out>> {
-            if let Some(v) = self.#obj_name.get(path)
+        fn get_cache(&self, key: &CacheKey<Self::Param>) -> Option<Rc<Self::Out>> {
+            if let Some(v) = self.#obj_name.get(key)
             {
                 Some(v.clone())
             }else{
@@ -53,16 +53,22 @@ pub fn gen_impl_res_process_cache(input: proc_macro::TokenStream) -> proc_macro:
             }
         }
 
-        fn add_cache(&mut self, path: String, data: Rc<Self::Out>) {
-            self.#obj_name.insert(path,data);
+        fn add_cache(&mut self, key: CacheKey<Self::Param>, data: Rc<Self::Out>) {
+            self.#obj_name.insert(key,data);
         }
 
         fn clear_cache(&mut self) {
             self.#obj_name.clear();
         }
 
-        fn rm_cache(&mut self, path: &String) -> Option<Rc<Self::Out>> {
-            self.#obj_name.remove(path)
+        fn rm_cache(&mut self, key: &CacheKey<Self::Param>) -> Option<Rc<Self::Out>> {
+            self.#obj_name.remove(key)
+        }
+
+        fn rm_cache_path(&mut self, path: &String) -> usize {
+            let len = self.#obj_name.len();
+            self.#obj_name.retain(|k,_| k.path != *path);
+            len - self.#obj_name.len()
         }
     };
 
@@ -126,4 +132,196 @@ fn impl_as_any(ast:&DeriveInput) -> proc_macro2::TokenStream
             }
         }
     }
-}
\ No newline at end of file
+}
+/// `#[derive(VertexLayout)]` for a `#[repr(C)]` struct, every field becomes one or more
+/// vertex attributes (a `Matrix4<f32>` takes 4 locations).
+/// Locations count up from 0, `#[location(n)]` on a field restarts the count at n.
+/// `#[step(instance)]` on the struct makes it a per instance buffer.
+/// Needs `VertexLayout` in scope, field types must implement `VertexAttr`.
+#[proc_macro_derive(VertexLayout, attributes(location, step))]
+pub fn derive_vertex_layout(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let ast = syn::parse_macro_input!(input as DeriveInput);
+    match impl_vertex_layout(&ast) {
+        Ok(t) => t.into(),
+        Err(e) => e.to_compile_error().into()
+    }
+}
+
+fn is_repr_c(ast:&DeriveInput) -> bool
+{
+    ast.attrs.iter().any(|a|{
+        if !a.path.is_ident("repr") { return false; }
+        if let Ok(syn::Meta::List(list)) = a.parse_meta() {
+            list.nested.iter().any(|n| {
+                if let syn::NestedMeta::Meta(syn::Meta::Path(p)) = n { p.is_ident("C") || p.is_ident("transparent") } else { false }
+            })
+        }else{ false }
+    })
+}
+
+fn impl_vertex_layout(ast:&DeriveInput) -> syn::Result<proc_macro2::TokenStream>
+{
+    let struct_name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let fields = if let syn::Data::Struct(syn::DataStruct{ fields: syn::Fields::Named(f), .. }) = &ast.data {
+        f
+    }else{
+        return Err(syn::Error::new_spanned(struct_name,"VertexLayout only works with structs with named fields"));
+    };
+    if !is_repr_c(ast) {
+        return Err(syn::Error::new_spanned(struct_name,"VertexLayout needs #[repr(C)], the field order is the buffer layout"));
+    }
+
+    let mut step = Ident::new("Vertex",Span::call_site());
+    for a in ast.attrs.iter().filter(|a| a.path.is_ident("step")) {
+        let mode:Ident = a.parse_args()?;
+        step = match mode.to_string().as_str() {
+            "vertex" => Ident::new("Vertex",Span::call_site()),
+            "instance" => Ident::new("Instance",Span::call_site()),
+            _ => return Err(syn::Error::new_spanned(mode,"expected #[step(vertex)] or #[step(instance)]"))
+        };
+    }
+
+    let mut pushes = Vec::new();
+    for f in fields.named.iter() {
+        let name = f.ident.as_ref().unwrap();
+        let ty = &f.ty;
+        let mut location = quote::quote!{ None };
+        for a in f.attrs.iter().filter(|a| a.path.is_ident("location")) {
+            let n:syn::LitInt = a.parse_args()?;
+            let n = n.base10_parse::<u32>()?;
+            location = quote::quote!{ Some(#n) };
+        }
+        let name_str = name.to_string();
+        pushes.push(quote::quote!{
+            let offset = unsafe { std::ptr::addr_of!((*base.as_ptr()).#name) } as usize - base.as_ptr() as usize;
+            let (formats,size) = Self::attr_of::<#ty>();
+            res.push((#name_str,#location,offset as wgpu::BufferAddress,formats,size));
+        });
+    }
+
+    Ok(quote::quote!{
+        impl #impl_generics VertexLayout for #struct_name #ty_generics #where_clause {
+            fn step_mode() -> wgpu::InputStepMode
+            {
+                wgpu::InputStepMode::#step
+            }
+
+            fn fields() -> Vec<(&'static str,Option<u32>,wgpu::BufferAddress,&'static [wgpu::VertexFormat],u64)>
+            {
+                let mut res = Vec::new();
+                let base = std::mem::MaybeUninit::<Self>::uninit();
+                #(#pushes)*
+                res
+            }
+        }
+    })
+}
+
+/// `#[derive(UniformLayout)]` lays a struct out by the WGSL uniform/storage rules
+/// instead of the Rust ones, so it does not need `#[repr(C)]`.
+/// Needs `UniformLayout` and `AddressSpace` in scope.
+#[proc_macro_derive(UniformLayout)]
+pub fn derive_uniform_layout(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let ast = syn::parse_macro_input!(input as DeriveInput);
+    match impl_uniform_layout(&ast) {
+        Ok(t) => t.into(),
+        Err(e) => e.to_compile_error().into()
+    }
+}
+
+fn impl_uniform_layout(ast:&DeriveInput) -> syn::Result<proc_macro2::TokenStream>
+{
+    let struct_name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let fields = if let syn::Data::Struct(syn::DataStruct{ fields: syn::Fields::Named(f), .. }) = &ast.data {
+        f
+    }else{
+        return Err(syn::Error::new_spanned(struct_name,"UniformLayout only works with structs with named fields"));
+    };
+    let names:Vec<_> = fields.named.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+    let tys:Vec<_> = fields.named.iter().map(|f| &f.ty).collect();
+
+    Ok(quote::quote!{
+        impl #impl_generics UniformLayout for #struct_name #ty_generics #where_clause {
+            fn align(space: AddressSpace) -> u64
+            {
+                let mut align = 1;
+                #( align = align.max(<#tys as UniformLayout>::align(space)); )*
+                space.container_align(align)
+            }
+
+            fn size(space: AddressSpace) -> u64
+            {
+                let mut offset = 0;
+                #( offset = space.member_offset::<#tys>(offset) + <#tys as UniformLayout>::size(space); )*
+                AddressSpace::round_up(Self::align(space),offset)
+            }
+
+            fn wgsl_type(_space: AddressSpace) -> String
+            {
+                stringify!(#struct_name).to_string()
+            }
+
+            fn write(&self, space: AddressSpace, out: &mut [u8])
+            {
+                let mut offset = 0;
+                #(
+                    offset = space.member_offset::<#tys>(offset);
+                    self.#names.write(space,&mut out[offset as usize..]);
+                    offset += <#tys as UniformLayout>::size(space);
+                )*
+            }
+
+            fn declare(space: AddressSpace, out: &mut Vec<String>)
+            {
+                #( <#tys as UniformLayout>::declare(space,out); )*
+                let mut s = format!("struct {} {{\n",stringify!(#struct_name));
+                #( s += space.member_decl::<#tys>(stringify!(#names)).as_str(); )*
+                s += "};";
+                if !out.contains(&s) { out.push(s); }
+            }
+        }
+    })
+}
+
+/// `#[derive(AsBytes)]` for a `#[repr(C)]` struct. Fails to compile when a field is not
+/// `AsBytes` or when the fields do not add up to the size of the struct (padding).
+/// Needs `AsBytes` in scope.
+#[proc_macro_derive(AsBytes)]
+pub fn derive_as_bytes(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let ast = syn::parse_macro_input!(input as DeriveInput);
+    match impl_as_bytes(&ast) {
+        Ok(t) => t.into(),
+        Err(e) => e.to_compile_error().into()
+    }
+}
+
+fn impl_as_bytes(ast:&DeriveInput) -> syn::Result<proc_macro2::TokenStream>
+{
+    let struct_name = &ast.ident;
+    let fields = if let syn::Data::Struct(syn::DataStruct{ fields, .. }) = &ast.data {
+        fields
+    }else{
+        return Err(syn::Error::new_spanned(struct_name,"AsBytes only works with structs"));
+    };
+    if !is_repr_c(ast) {
+        return Err(syn::Error::new_spanned(struct_name,"AsBytes needs #[repr(C)], otherwise the field order and padding are unknown"));
+    }
+    if !ast.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&ast.generics,"AsBytes can not check the padding of a generic struct"));
+    }
+    let tys:Vec<_> = fields.iter().map(|f| &f.ty).collect();
+
+    Ok(quote::quote!{
+        const _: () = {
+            fn assert_as_bytes<T: AsBytes + ?Sized>() {}
+            #[allow(dead_code)]
+            fn assert_fields() { #( assert_as_bytes::<#tys>(); )* }
+            // an array of non zero length here means the struct has padding bytes
+            let _: [(); 0] = [(); std::mem::size_of::<#struct_name>() - (0 #( + std::mem::size_of::<#tys>() )*)];
+        };
+
+        unsafe impl AsBytes for #struct_name {}
+    })
+}
@@ -0,0 +1,206 @@
+use std::rc::Rc;
+use std::collections::HashMap;
+use crate::resource_manager::ResourceMgr;
+use crate::resource_manager::pack::RgbaMips;
+use crate::texture::ImageRes;
+
+/// A rectangle in atlas pixels.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct AtlasRect{
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AtlasRect {
+    pub fn overlaps(&self,other:&AtlasRect) -> bool
+    {
+        self.x < other.x + other.width && other.x < self.x + self.width &&
+            self.y < other.y + other.height && other.y < self.y + self.height
+    }
+}
+
+/// Places `sizes` on shelves, tallest first, in the smallest power of two atlas that
+/// holds them, each rectangle followed by `padding` free pixels to the right and below.
+/// Returns the atlas size and the rectangles in the order of `sizes`, `None` when
+/// they do not fit into `max_size` x `max_size`.
+pub fn pack_rects(sizes:&[(u32,u32)],padding:u32,max_size:u32) -> Option<(u32,u32,Vec<AtlasRect>)>
+{
+    let padded:Vec<(u32,u32)> = sizes.iter().map(|&(w,h)| (w + padding,h + padding)).collect();
+    let mut order:Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by(|&a,&b| padded[b].1.cmp(&padded[a].1).then(padded[b].0.cmp(&padded[a].0)));
+
+    let area:u64 = padded.iter().map(|&(w,h)| w as u64 * h as u64).sum();
+    let widest = padded.iter().map(|p| p.0).max().unwrap_or(1);
+    let tallest = padded.iter().map(|p| p.1).max().unwrap_or(1);
+    // start as wide as a square holding the area and grow the shorter side until it fits
+    let mut width = ((area as f64).sqrt().ceil() as u32).max(widest).max(1).next_power_of_two().min(max_size.max(1));
+    let mut height = tallest.max(1).next_power_of_two();
+    if widest > width { return None; }
+    while height <= max_size {
+        if let Some(rects) = pack_shelves(&padded,&order,width,height) {
+            let rects = rects.into_iter().zip(sizes.iter())
+                .map(|(r,&(w,h))| AtlasRect{ width: w, height: h, ..r }).collect();
+            return Some((width,height,rects));
+        }
+        if height < width || width * 2 > max_size { height *= 2; } else { width *= 2; }
+    }
+    None
+}
+
+fn pack_shelves(padded:&[(u32,u32)],order:&[usize],width:u32,height:u32) -> Option<Vec<AtlasRect>>
+{
+    let mut rects = vec![AtlasRect{ x: 0, y: 0, width: 0, height: 0 };padded.len()];
+    let (mut x,mut y,mut shelf) = (0u32,0u32,0u32);
+    for &i in order.iter() {
+        let (w,h) = padded[i];
+        if x + w > width {
+            y += shelf;
+            x = 0;
+            shelf = 0;
+        }
+        if x + w > width || y + h > height { return None; }
+        rects[i] = AtlasRect{ x, y, width: w, height: h };
+        x += w;
+        shelf = shelf.max(h);
+    }
+    Some(rects)
+}
+
+/// Many images combined into one, found again by name.
+pub struct TextureAtlas{
+    pub image: RgbaMips,
+    regions: HashMap<String,AtlasRect>,
+}
+
+impl TextureAtlas {
+    /// Packs the first mip level of every image, `None` when they do not fit into `max_size`.
+    pub fn build(images:&[(String,Rc<RgbaMips>)],padding:u32,max_size:u32) -> Option<TextureAtlas>
+    {
+        let sizes:Vec<_> = images.iter().map(|(_,img)| (img.width,img.height)).collect();
+        let (width,height,rects) = pack_rects(sizes.as_slice(),padding,max_size)?;
+        let mut pixels = vec![0u8;(width * height * 4) as usize];
+        let mut regions = HashMap::new();
+        for ((name,img),rect) in images.iter().zip(rects.into_iter()) {
+            let row = (img.width * 4) as usize;
+            let src = img.levels.first()?;
+            for y in 0..img.height {
+                let from = y as usize * row;
+                let to = (((rect.y + y) * width + rect.x) * 4) as usize;
+                pixels[to..to + row].copy_from_slice(&src[from..from + row]);
+            }
+            regions.insert(name.clone(),rect);
+        }
+        Some(TextureAtlas{
+            image: RgbaMips{ width, height, levels: vec![pixels] },
+            regions
+        })
+    }
+
+    pub fn region(&self,name:&str) -> Option<AtlasRect> { self.regions.get(name).copied() }
+
+    /// `[u0,v0,u1,v1]` of the region called `name`.
+    pub fn uv(&self,name:&str) -> Option<[f32;4]>
+    {
+        self.region(name).map(|r| self.uv_of(&r))
+    }
+
+    pub fn uv_of(&self,r:&AtlasRect) -> [f32;4]
+    {
+        let (w,h) = (self.image.width as f32,self.image.height as f32);
+        [r.x as f32 / w,r.y as f32 / h,(r.x + r.width) as f32 / w,(r.y + r.height) as f32 / h]
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> { self.regions.keys() }
+    pub fn len(&self) -> usize { self.regions.len() }
+    pub fn is_empty(&self) -> bool { self.regions.is_empty() }
+}
+
+/// Loads `paths` through `ImageRes`, which must be registered, and packs them into one
+/// atlas with a pixel of padding. Regions are named by the paths as given.
+pub fn load_atlas(mgr:&mut ResourceMgr,paths:&[&str],max_size:u32) -> Option<TextureAtlas>
+{
+    let mut images = Vec::with_capacity(paths.len());
+    for p in paths.iter() {
+        let (raw,overdue,path) = mgr.load_file(p)?;
+        images.push((p.to_string(),mgr.loading::<ImageRes,_,_>(raw,&path,overdue)?));
+    }
+    TextureAtlas::build(images.as_slice(),1,max_size)
+}
+
+mod test_atlas{
+    use std::rc::Rc;
+    use crate::atlas::{pack_rects, TextureAtlas, AtlasRect};
+    use crate::resource_manager::pack::RgbaMips;
+
+    fn check(sizes:&[(u32,u32)],padding:u32,width:u32,height:u32,rects:&[AtlasRect])
+    {
+        assert_eq!(rects.len(),sizes.len());
+        for (i,(r,&(w,h))) in rects.iter().zip(sizes.iter()).enumerate() {
+            assert_eq!((r.width,r.height),(w,h));
+            assert!(r.x + w + padding <= width && r.y + h + padding <= height,"{:?} outside {}x{}",r,width,height);
+            let grown = AtlasRect{ width: w + padding, height: h + padding, ..*r };
+            for o in rects[i + 1..].iter() {
+                assert!(!grown.overlaps(&AtlasRect{ width: o.width + padding, height: o.height + padding, ..*o }),"{:?} overlaps {:?}",r,o);
+            }
+        }
+    }
+
+    #[test]
+    fn pack()
+    {
+        let sizes = [(256,256),(256,256),(49,49),(18,14),(1,1),(100,30),(30,100),(64,64)];
+        let (w,h,rects) = pack_rects(&sizes,1,4096).unwrap();
+        check(&sizes,1,w,h,rects.as_slice());
+        assert!(w.is_power_of_two() && h.is_power_of_two());
+        assert!(w * h <= 1024 * 512,"{}x{}",w,h);
+
+        // many equal squares fill a square atlas exactly
+        let sizes = vec![(16,16);64];
+        let (w,h,rects) = pack_rects(&sizes,0,4096).unwrap();
+        assert_eq!((w,h),(128,128));
+        check(&sizes,0,w,h,rects.as_slice());
+
+        let (w,h,rects) = pack_rects(&[(300,20)],2,4096).unwrap();
+        assert_eq!((w,h),(512,32));
+        assert_eq!(rects[0],AtlasRect{ x: 0, y: 0, width: 300, height: 20 });
+    }
+
+    #[test]
+    fn too_large()
+    {
+        assert!(pack_rects(&[(600,10)],0,512).is_none());
+        assert!(pack_rects(&vec![(256,256);5],0,512).is_none());
+        assert!(pack_rects(&vec![(256,256);4],0,512).is_some());
+        let (_,_,rects) = pack_rects(&[],0,512).unwrap();
+        assert!(rects.is_empty());
+    }
+
+    #[test]
+    fn build()
+    {
+        let image = |w:u32,h:u32,v:u8| Rc::new(RgbaMips{ width: w, height: h, levels: vec![vec![v;(w * h * 4) as usize]] });
+        let atlas = TextureAtlas::build(&[
+            ("a".to_string(),image(4,4,10)),
+            ("b".to_string(),image(8,2,20)),
+            ("c".to_string(),image(1,1,30)),
+        ],1,64).unwrap();
+        assert_eq!(atlas.len(),3);
+        let img = &atlas.image;
+        for (name,v) in [("a",10u8),("b",20),("c",30)].iter() {
+            let r = atlas.region(name).unwrap();
+            for y in r.y..r.y + r.height {
+                for x in r.x..r.x + r.width {
+                    assert_eq!(img.levels[0][((y * img.width + x) * 4) as usize],*v);
+                }
+            }
+            // the padding column stays empty
+            assert_eq!(img.levels[0][((r.y * img.width + r.x + r.width) * 4) as usize],0);
+            let uv = atlas.uv(name).unwrap();
+            assert_eq!(uv[0],r.x as f32 / img.width as f32);
+            assert_eq!(uv[3],(r.y + r.height) as f32 / img.height as f32);
+        }
+        assert!(atlas.uv("d").is_none());
+    }
+}
@@ -0,0 +1,269 @@
+use cgmath::{Matrix, Matrix4, Vector3, Vector4, InnerSpace};
+
+/// Axis aligned bounding box.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Aabb{
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min:Vector3<f32>,max:Vector3<f32>) -> Aabb
+    {
+        Aabb{ min, max }
+    }
+
+    /// `None` when there are no points.
+    pub fn from_points<I:IntoIterator<Item = [f32;3]>>(points:I) -> Option<Aabb>
+    {
+        let mut it = points.into_iter();
+        let first = Vector3::from(it.next()?);
+        let mut res = Aabb{ min: first, max: first };
+        for p in it {
+            res.grow(Vector3::from(p));
+        }
+        Some(res)
+    }
+
+    pub fn grow(&mut self,p:Vector3<f32>)
+    {
+        self.min = Vector3::new(self.min.x.min(p.x),self.min.y.min(p.y),self.min.z.min(p.z));
+        self.max = Vector3::new(self.max.x.max(p.x),self.max.y.max(p.y),self.max.z.max(p.z));
+    }
+
+    pub fn union(&self,other:&Aabb) -> Aabb
+    {
+        let mut res = *self;
+        res.grow(other.min);
+        res.grow(other.max);
+        res
+    }
+
+    pub fn center(&self) -> Vector3<f32> { (self.min + self.max) * 0.5 }
+    pub fn half_extents(&self) -> Vector3<f32> { (self.max - self.min) * 0.5 }
+
+    /// The box around the transformed box, it only grows under rotation.
+    pub fn transform(&self,m:&Matrix4<f32>) -> Aabb
+    {
+        let c = (m * self.center().extend(1.0)).truncate();
+        let h = self.half_extents();
+        // each axis of the new box is the sum of the absolute projections of the old axes
+        let e = Vector3::new(
+            m.x.x.abs() * h.x + m.y.x.abs() * h.y + m.z.x.abs() * h.z,
+            m.x.y.abs() * h.x + m.y.y.abs() * h.y + m.z.y.abs() * h.z,
+            m.x.z.abs() * h.x + m.y.z.abs() * h.y + m.z.z.abs() * h.z
+        );
+        Aabb{ min: c - e, max: c + e }
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere
+    {
+        BoundingSphere{ center: self.center(), radius: self.half_extents().magnitude() }
+    }
+}
+
+impl From<([f32;3],[f32;3])> for Aabb {
+    fn from((min,max):([f32;3],[f32;3])) -> Self
+    {
+        Aabb{ min: min.into(), max: max.into() }
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct BoundingSphere{
+    pub center: Vector3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn new(center:Vector3<f32>,radius:f32) -> BoundingSphere
+    {
+        BoundingSphere{ center, radius }
+    }
+
+    /// Scaled by the longest axis of `m`, so it stays a bound under non uniform scale.
+    pub fn transform(&self,m:&Matrix4<f32>) -> BoundingSphere
+    {
+        let scale = m.x.truncate().magnitude2().max(m.y.truncate().magnitude2()).max(m.z.truncate().magnitude2()).sqrt();
+        BoundingSphere{
+            center: (m * self.center.extend(1.0)).truncate(),
+            radius: self.radius * scale
+        }
+    }
+}
+
+/// Points with `normal.dot(p) + d >= 0` are in front of the plane.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Plane{
+    pub normal: Vector3<f32>,
+    pub d: f32,
+}
+
+impl Plane {
+    /// From the coefficients `(a,b,c,d)` of `ax + by + cz + d = 0`, normalized.
+    pub fn from_coefficients(v:Vector4<f32>) -> Plane
+    {
+        let len = v.truncate().magnitude();
+        Plane{ normal: v.truncate() / len, d: v.w / len }
+    }
+
+    pub fn distance(&self,p:Vector3<f32>) -> f32
+    {
+        self.normal.dot(p) + self.d
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Containment{ Outside, Intersects, Inside }
+
+/// The six planes of a camera's view volume, normals pointing inwards.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Frustum{
+    /// left, right, bottom, top, near, far
+    pub planes: [Plane;6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a view-projection matrix with wgpu's clip volume,
+    /// -w <= x,y <= w and 0 <= z <= w (Gribb/Hartmann).
+    pub fn from_matrix(m:&Matrix4<f32>) -> Frustum
+    {
+        let (r0,r1,r2,r3) = (m.row(0),m.row(1),m.row(2),m.row(3));
+        Frustum{
+            planes: [
+                Plane::from_coefficients(r3 + r0),
+                Plane::from_coefficients(r3 - r0),
+                Plane::from_coefficients(r3 + r1),
+                Plane::from_coefficients(r3 - r1),
+                Plane::from_coefficients(r2),
+                Plane::from_coefficients(r3 - r2),
+            ]
+        }
+    }
+
+    pub fn contains_point(&self,p:Vector3<f32>) -> bool
+    {
+        self.planes.iter().all(|pl| pl.distance(p) >= 0.0)
+    }
+
+    pub fn classify_sphere(&self,s:&BoundingSphere) -> Containment
+    {
+        let mut res = Containment::Inside;
+        for pl in self.planes.iter() {
+            let dist = pl.distance(s.center);
+            if dist < -s.radius { return Containment::Outside; }
+            if dist < s.radius { res = Containment::Intersects; }
+        }
+        res
+    }
+
+    /// Conservative, a box near a corner of the frustum can count as intersecting
+    /// while being outside.
+    pub fn classify_aabb(&self,b:&Aabb) -> Containment
+    {
+        let (c,h) = (b.center(),b.half_extents());
+        let mut res = Containment::Inside;
+        for pl in self.planes.iter() {
+            let dist = pl.distance(c);
+            let r = pl.normal.x.abs() * h.x + pl.normal.y.abs() * h.y + pl.normal.z.abs() * h.z;
+            if dist < -r { return Containment::Outside; }
+            if dist < r { res = Containment::Intersects; }
+        }
+        res
+    }
+
+    pub fn intersects_sphere(&self,s:&BoundingSphere) -> bool
+    {
+        self.classify_sphere(s) != Containment::Outside
+    }
+
+    pub fn intersects_aabb(&self,b:&Aabb) -> bool
+    {
+        self.classify_aabb(b) != Containment::Outside
+    }
+}
+
+mod test_bounds{
+    use crate::bounds::{Aabb, BoundingSphere, Frustum, Containment};
+    use crate::camera::OPENGL_TO_WGPU_MATRIX;
+    use cgmath::{Matrix4, Vector3, Deg, Point3, SquareMatrix};
+
+    fn approx(a:Vector3<f32>,b:Vector3<f32>) -> bool
+    {
+        (a - b).x.abs() < 1e-4 && (a - b).y.abs() < 1e-4 && (a - b).z.abs() < 1e-4
+    }
+
+    /// 90 degree camera at the origin looking down -z, near 1, far 10.
+    fn frustum() -> Frustum
+    {
+        Frustum::from_matrix(&(OPENGL_TO_WGPU_MATRIX * cgmath::perspective(Deg(90f32),1.0,1.0,10.0)))
+    }
+
+    #[test]
+    fn planes()
+    {
+        let f = frustum();
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        let expect = [
+            (Vector3::new(s,0.0,-s),0.0),
+            (Vector3::new(-s,0.0,-s),0.0),
+            (Vector3::new(0.0,s,-s),0.0),
+            (Vector3::new(0.0,-s,-s),0.0),
+            (Vector3::new(0.0,0.0,-1.0),-1.0),
+            (Vector3::new(0.0,0.0,1.0),10.0),
+        ];
+        for (pl,(n,d)) in f.planes.iter().zip(expect.iter()) {
+            assert!(approx(pl.normal,*n),"{:?} != {:?}",pl.normal,n);
+            assert!((pl.d - d).abs() < 1e-3,"{} != {}",pl.d,d);
+        }
+        assert!(f.contains_point(Vector3::new(0.0,0.0,-5.0)));
+        assert!(!f.contains_point(Vector3::new(0.0,0.0,-0.5)));
+        assert!(!f.contains_point(Vector3::new(6.0,0.0,-5.0)));
+
+        // a view matrix moves the planes with the camera
+        let view = Matrix4::look_at_rh(Point3::new(100.0,0.0,0.0),Point3::new(100.0,0.0,-1.0),Vector3::unit_y());
+        let f = Frustum::from_matrix(&(OPENGL_TO_WGPU_MATRIX * cgmath::perspective(Deg(90f32),1.0,1.0,10.0) * view));
+        assert!(f.contains_point(Vector3::new(100.0,0.0,-5.0)));
+        assert!(!f.contains_point(Vector3::new(0.0,0.0,-5.0)));
+    }
+
+    #[test]
+    fn classify()
+    {
+        let f = frustum();
+        let sphere = |x,y,z,r| BoundingSphere::new(Vector3::new(x,y,z),r);
+        assert_eq!(f.classify_sphere(&sphere(0.0,0.0,-5.0,1.0)),Containment::Inside);
+        assert_eq!(f.classify_sphere(&sphere(0.0,0.0,-10.0,1.0)),Containment::Intersects);
+        assert_eq!(f.classify_sphere(&sphere(0.0,0.0,5.0,1.0)),Containment::Outside);
+        assert_eq!(f.classify_sphere(&sphere(9.0,0.0,-5.0,1.0)),Containment::Outside);
+
+        let aabb = |c:Vector3<f32>,h:f32| Aabb::new(c - Vector3::new(h,h,h),c + Vector3::new(h,h,h));
+        assert_eq!(f.classify_aabb(&aabb(Vector3::new(0.0,0.0,-5.0),1.0)),Containment::Inside);
+        assert_eq!(f.classify_aabb(&aabb(Vector3::new(5.0,0.0,-5.0),1.0)),Containment::Intersects);
+        assert_eq!(f.classify_aabb(&aabb(Vector3::new(0.0,0.0,-0.5),0.2)),Containment::Outside);
+        assert_eq!(f.classify_aabb(&aabb(Vector3::new(0.0,0.0,-20.0),2.0)),Containment::Outside);
+        assert!(f.intersects_aabb(&aabb(Vector3::new(0.0,-6.0,-5.0),1.5)));
+        assert!(!f.intersects_aabb(&aabb(Vector3::new(0.0,-9.0,-5.0),1.5)));
+    }
+
+    #[test]
+    fn transform()
+    {
+        let b = Aabb::from_points(vec![[-1.0,-1.0,-1.0],[1.0,2.0,1.0],[0.0,0.0,3.0]]).unwrap();
+        assert_eq!(b,Aabb::new(Vector3::new(-1.0,-1.0,-1.0),Vector3::new(1.0,2.0,3.0)));
+        assert!(Aabb::from_points(Vec::new()).is_none());
+
+        let m = Matrix4::from_translation(Vector3::new(10.0,0.0,0.0)) * Matrix4::from_angle_z(Deg(90f32)) * Matrix4::from_scale(2.0);
+        let t = b.transform(&m);
+        assert!(approx(t.min,Vector3::new(6.0,-2.0,-2.0)),"{:?}",t);
+        assert!(approx(t.max,Vector3::new(12.0,2.0,6.0)),"{:?}",t);
+        assert_eq!(b.transform(&Matrix4::identity()),b);
+
+        let s = b.bounding_sphere().transform(&Matrix4::from_nonuniform_scale(1.0,3.0,1.0));
+        assert!((s.radius - b.bounding_sphere().radius * 3.0).abs() < 1e-4);
+        for p in [[-1.0,-1.0,-1.0],[1.0,2.0,3.0],[1.0,-1.0,3.0]].iter() {
+            let p = Vector3::new(p[0],p[1] * 3.0,p[2]);
+            assert!((p - s.center).x.hypot((p - s.center).y).hypot((p - s.center).z) <= s.radius + 1e-4);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ops::{Range, RangeBounds, Bound};
+use wgpu::{Buffer, BufferAddress, BufferUsage, Device, Queue, BufferSlice, BindingResource, BufferDescriptor, CommandEncoderDescriptor};
+use wgpu::util::{DeviceExt, BufferInitDescriptor};
+use crate::bytes::AsBytes;
+
+/// A buffer holding `len` values of `T`, it can always be written from the queue.
+pub struct GpuBuffer<T:AsBytes>{
+    buffer: Buffer,
+    len: usize,
+    usage: BufferUsage,
+    _marker: PhantomData<T>,
+}
+
+impl<T:AsBytes> GpuBuffer<T> {
+    pub fn new(device:&Device,label:Option<&str>,usage:BufferUsage,data:&[T]) -> GpuBuffer<T>
+    {
+        let usage = usage | BufferUsage::COPY_DST;
+        let buffer = device.create_buffer_init(&BufferInitDescriptor{
+            label,
+            contents: data.as_bytes(),
+            usage
+        });
+        GpuBuffer{ buffer, len: data.len(), usage, _marker: PhantomData }
+    }
+
+    /// A buffer with a single value, e.g. a uniform.
+    pub fn from_value(device:&Device,label:Option<&str>,usage:BufferUsage,value:&T) -> GpuBuffer<T>
+    {
+        Self::new(device,label,usage,std::slice::from_ref(value))
+    }
+
+    /// Overwrites the values starting at `index`. The queue copies 4 bytes at a time, so the
+    /// offset and the size of `data` in bytes have to be multiples of 4.
+    pub fn write(&self,queue:&Queue,index:usize,data:&[T])
+    {
+        assert!(index + data.len() <= self.len,"write of {} values at {} overruns a buffer of {}",data.len(),index,self.len);
+        let offset = (index * size_of::<T>()) as BufferAddress;
+        let bytes = data.as_bytes();
+        assert_eq!(offset % wgpu::COPY_BUFFER_ALIGNMENT,0,"buffer writes need an offset that is a multiple of 4");
+        assert_eq!(bytes.len() as BufferAddress % wgpu::COPY_BUFFER_ALIGNMENT,0,"buffer writes need a size that is a multiple of 4");
+        queue.write_buffer(&self.buffer,offset,bytes);
+    }
+
+    pub fn set(&self,queue:&Queue,value:&T)
+    {
+        self.write(queue,0,std::slice::from_ref(value));
+    }
+
+    /// The bytes of the elements in `range`.
+    pub fn slice<R:RangeBounds<usize>>(&self,range:R) -> BufferSlice<'_>
+    {
+        self.buffer.slice(byte_range::<T,R>(range,self.len))
+    }
+
+    pub fn as_entire_binding(&self) -> BindingResource<'_>
+    {
+        self.buffer.as_entire_binding()
+    }
+
+    pub fn buffer(&self) -> &Buffer { &self.buffer }
+    pub fn len(&self) -> usize { self.len }
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+    pub fn usage(&self) -> BufferUsage { self.usage }
+}
+
+fn element_range<R:RangeBounds<usize>>(range:R,len:usize) -> Range<usize>
+{
+    let start = match range.start_bound() {
+        Bound::Included(&i) => i,
+        Bound::Excluded(&i) => i + 1,
+        Bound::Unbounded => 0
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&i) => i + 1,
+        Bound::Excluded(&i) => i,
+        Bound::Unbounded => len
+    };
+    assert!(start <= end && end <= len,"range {}..{} out of {} elements",start,end,len);
+    start..end
+}
+
+fn byte_range<T,R:RangeBounds<usize>>(range:R,len:usize) -> Range<BufferAddress>
+{
+    let r = element_range(range,len);
+    (r.start * size_of::<T>()) as BufferAddress..(r.end * size_of::<T>()) as BufferAddress
+}
+
+/// A growable `GpuBuffer`. Changes go to a copy kept on the cpu and only the changed
+/// elements are uploaded by `sync`. When the values outgrow the buffer a buffer of twice
+/// the size is created and the unchanged part is copied over on the gpu.
+pub struct GpuVec<T:AsBytes>{
+    data: Vec<T>,
+    buffer: Buffer,
+    capacity: usize,
+    usage: BufferUsage,
+    label: Option<String>,
+    dirty: Option<Range<usize>>,
+    /// Elements that are on the gpu, the rest of the buffer holds nothing.
+    uploaded: usize,
+}
+
+impl<T:AsBytes> GpuVec<T> {
+    pub fn with_capacity(device:&Device,label:Option<&str>,usage:BufferUsage,capacity:usize) -> GpuVec<T>
+    {
+        assert_eq!(size_of::<T>() % wgpu::COPY_BUFFER_ALIGNMENT as usize,0,"buffer copies need a size that is a multiple of 4");
+        let usage = usage | BufferUsage::COPY_SRC | BufferUsage::COPY_DST;
+        let capacity = capacity.max(1);
+        GpuVec{
+            data: Vec::with_capacity(capacity),
+            buffer: Self::create_buffer(device,label,usage,capacity),
+            capacity,
+            usage,
+            label: label.map(|l| l.to_string()),
+            dirty: None,
+            uploaded: 0
+        }
+    }
+
+    /// The values are uploaded on the first `sync`.
+    pub fn from_vec(device:&Device,label:Option<&str>,usage:BufferUsage,data:Vec<T>) -> GpuVec<T>
+    {
+        let mut res = Self::with_capacity(device,label,usage,data.len());
+        res.dirty = if data.is_empty() { None } else { Some(0..data.len()) };
+        res.data = data;
+        res
+    }
+
+    fn create_buffer(device:&Device,label:Option<&str>,usage:BufferUsage,capacity:usize) -> Buffer
+    {
+        device.create_buffer(&BufferDescriptor{
+            label,
+            size: (capacity * size_of::<T>()) as BufferAddress,
+            usage,
+            mapped_at_creation: false
+        })
+    }
+
+    fn mark(&mut self,range:Range<usize>)
+    {
+        if range.start >= range.end { return; }
+        self.dirty = Some(match self.dirty.take() {
+            Some(d) => d.start.min(range.start)..d.end.max(range.end),
+            None => range
+        });
+    }
+
+    pub fn push(&mut self,value:T)
+    {
+        self.data.push(value);
+        self.mark(self.data.len() - 1..self.data.len());
+    }
+
+    pub fn extend_from_slice(&mut self,values:&[T]) where T:Clone
+    {
+        let start = self.data.len();
+        self.data.extend_from_slice(values);
+        self.mark(start..self.data.len());
+    }
+
+    pub fn pop(&mut self) -> Option<T>
+    {
+        let res = self.data.pop();
+        self.truncate(self.data.len());
+        res
+    }
+
+    pub fn truncate(&mut self,len:usize)
+    {
+        self.data.truncate(len);
+        self.uploaded = self.uploaded.min(len);
+        if let Some(d) = self.dirty.take() {
+            let d = d.start..d.end.min(len);
+            if d.start < d.end { self.dirty = Some(d); }
+        }
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.truncate(0);
+    }
+
+    pub fn set(&mut self,index:usize,value:T)
+    {
+        self.data[index] = value;
+        self.mark(index..index + 1);
+    }
+
+    /// Mutable access to the elements in `range`, all of them are uploaded again.
+    pub fn slice_mut<R:RangeBounds<usize>>(&mut self,range:R) -> &mut [T]
+    {
+        let r = element_range(range,self.data.len());
+        self.mark(r.clone());
+        &mut self.data[r]
+    }
+
+    pub fn as_slice(&self) -> &[T] { self.data.as_slice() }
+    pub fn get(&self,index:usize) -> Option<&T> { self.data.get(index) }
+
+    /// Elements changed since the last `sync`.
+    pub fn dirty(&self) -> Option<Range<usize>> { self.dirty.clone() }
+
+    /// Uploads the changed elements, growing the buffer first if needed.
+    /// Returns true when the buffer was replaced, bind groups using it have to be recreated.
+    pub fn sync(&mut self,device:&Device,queue:&Queue) -> bool
+    {
+        let grown = self.data.len() > self.capacity;
+        if grown {
+            let capacity = self.data.len().max(self.capacity * 2);
+            let buffer = Self::create_buffer(device,self.label.as_deref(),self.usage,capacity);
+            // write_buffer runs before the copy, so only copy what is not about to be written
+            let dirty = self.dirty.clone().unwrap_or(self.uploaded..self.uploaded);
+            let keep = [0..dirty.start.min(self.uploaded),dirty.end.min(self.uploaded)..self.uploaded];
+            let old = std::mem::replace(&mut self.buffer,buffer);
+            let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor{ label: Some("GpuVec grow") });
+            for r in keep.iter().filter(|r| r.start < r.end) {
+                let offset = (r.start * size_of::<T>()) as BufferAddress;
+                encoder.copy_buffer_to_buffer(&old,offset,&self.buffer,offset,(r.len() * size_of::<T>()) as BufferAddress);
+            }
+            self.capacity = capacity;
+            if let Some(d) = self.dirty.take() {
+                self.upload(queue,d);
+            }
+            queue.submit(std::iter::once(encoder.finish()));
+        }else if let Some(d) = self.dirty.take() {
+            self.upload(queue,d);
+        }
+        grown
+    }
+
+    fn upload(&mut self,queue:&Queue,range:Range<usize>)
+    {
+        queue.write_buffer(&self.buffer,(range.start * size_of::<T>()) as BufferAddress,self.data[range.clone()].as_bytes());
+        self.uploaded = self.uploaded.max(range.end);
+    }
+
+    /// The bytes of the elements in `range`, call `sync` first.
+    pub fn slice<R:RangeBounds<usize>>(&self,range:R) -> BufferSlice<'_>
+    {
+        self.buffer.slice(byte_range::<T,R>(range,self.data.len()))
+    }
+
+    pub fn as_entire_binding(&self) -> BindingResource<'_>
+    {
+        self.buffer.as_entire_binding()
+    }
+
+    pub fn buffer(&self) -> &Buffer { &self.buffer }
+    pub fn len(&self) -> usize { self.data.len() }
+    pub fn is_empty(&self) -> bool { self.data.is_empty() }
+    pub fn capacity(&self) -> usize { self.capacity }
+    pub fn usage(&self) -> BufferUsage { self.usage }
+}
@@ -0,0 +1,122 @@
+use cgmath::{Vector1, Vector2, Vector3, Vector4, Point1, Point2, Point3, Matrix2, Matrix3, Matrix4, Quaternion};
+pub use gen_code::AsBytes;
+
+/// Plain data whose memory can be handed to the GPU as it is.
+/// For structs use `#[derive(AsBytes)]`, it needs `#[repr(C)]` and refuses fields that
+/// are not `AsBytes` themselves (pointers, references, `Vec`...) or leave padding between them.
+///
+/// # Safety
+/// Every byte of the value has to be initialized and must not depend on an address,
+/// so no padding and no pointers.
+pub unsafe trait AsBytes {
+    fn as_bytes(&self) -> &[u8]
+    {
+        unsafe { std::slice::from_raw_parts(self as *const Self as *const u8,std::mem::size_of_val(self)) }
+    }
+}
+
+macro_rules! impl_as_bytes {
+    ($($t:ty),*) => {
+        $( unsafe impl AsBytes for $t {} )*
+    };
+}
+
+impl_as_bytes!{ u8, i8, u16, i16, u32, i32, u64, i64, f32, f64 }
+
+unsafe impl<T:AsBytes,const N:usize> AsBytes for [T;N] {}
+unsafe impl<T:AsBytes> AsBytes for [T] {}
+
+// cgmath types are #[repr(C)] and made of one scalar type, so they have no padding
+macro_rules! impl_as_bytes_generic {
+    ($($t:ident),*) => {
+        $( unsafe impl<S:AsBytes> AsBytes for $t<S> {} )*
+    };
+}
+
+impl_as_bytes_generic!{ Vector1, Vector2, Vector3, Vector4, Point1, Point2, Point3, Matrix2, Matrix3, Matrix4, Quaternion }
+
+/// The bytes of a `bytemuck::Pod` value, what `impl_as_bytes_pod!` hands out.
+pub fn pod_bytes<T:bytemuck::Pod>(value:&T) -> &[u8]
+{
+    bytemuck::bytes_of(value)
+}
+
+/// `AsBytes` for types deriving `bytemuck::Pod`, which promises the same: no padding and no
+/// pointers. A blanket impl over `Pod` would collide with the impls above.
+#[macro_export]
+macro_rules! impl_as_bytes_pod {
+    ($($t:ty),* $(,)?) => {
+        $( unsafe impl $crate::bytes::AsBytes for $t {
+            fn as_bytes(&self) -> &[u8] { $crate::bytes::pod_bytes(self) }
+        } )*
+    };
+}
+
+mod test_bytes{
+    use crate::bytes::AsBytes;
+    use cgmath::{Matrix4, Vector3, SquareMatrix};
+
+    #[repr(C)]
+    #[derive(AsBytes)]
+    struct Instance{
+        model: Matrix4<f32>,
+        color: [f32;4],
+        id: u32,
+        offset: Vector3<f32>,
+    }
+
+    #[repr(C)]
+    #[derive(Debug,Copy,Clone,bytemuck::Pod,bytemuck::Zeroable)]
+    struct PodVertex{
+        position: [f32;3],
+        id: u32,
+    }
+
+    crate::impl_as_bytes_pod!{ PodVertex }
+
+    fn f32s(b:&[u8]) -> Vec<f32>
+    {
+        b.chunks_exact(4).map(|c| f32::from_ne_bytes([c[0],c[1],c[2],c[3]])).collect()
+    }
+
+    #[test]
+    fn primitives()
+    {
+        assert_eq!(1u32.as_bytes(),&1u32.to_ne_bytes());
+        assert_eq!([1u16,2].as_bytes().len(),4);
+        let empty:&[f32] = &[];
+        assert!(empty.as_bytes().is_empty());
+    }
+
+    #[test]
+    fn cgmath()
+    {
+        let v = Vector3::new(1.0f32,2.0,3.0);
+        assert_eq!(f32s(v.as_bytes()),vec![1.0,2.0,3.0]);
+        let m = Matrix4::<f32>::identity();
+        let b = m.as_bytes();
+        assert_eq!(b.len(),64);
+        assert_eq!(f32s(b)[5],1.0);
+        assert_eq!(f32s(b)[4],0.0);
+    }
+
+    #[test]
+    fn derived()
+    {
+        let i = Instance{ model: Matrix4::identity(), color: [0.5;4], id: 7, offset: Vector3::new(1.0,2.0,3.0) };
+        let b = i.as_bytes();
+        assert_eq!(b.len(),96);
+        assert_eq!(&b[80..84],&7u32.to_ne_bytes());
+        assert_eq!(f32s(&b[84..]),vec![1.0,2.0,3.0]);
+        assert_eq!(std::slice::from_ref(&i).as_bytes().len(),96);
+    }
+
+    #[test]
+    fn pod()
+    {
+        let v = [PodVertex{ position: [1.0,2.0,3.0], id: 7 },PodVertex{ position: [4.0,5.0,6.0], id: 8 }];
+        assert_eq!(v[1].as_bytes(),bytemuck::bytes_of(&v[1]));
+        assert_eq!(v.as_bytes(),bytemuck::cast_slice::<_,u8>(&v[..]));
+        assert_eq!(v[..].as_bytes().len(),32);
+    }
+}
@@ -0,0 +1,9 @@
+use cgmath::Matrix4;
+
+/// cgmath projections map depth to -1..1 like OpenGL, wgpu wants 0..1.
+pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
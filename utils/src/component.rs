@@ -2,6 +2,8 @@ use wgpu::{Device, Queue, SwapChainDescriptor};
 use std::any::{TypeId, Any};
 use crate::object::Object;
 use crate::AsAny;
+use crate::draw::DrawList;
+use crate::sprite::SpriteBatch;
 
 pub struct InitNecessary<'a>{
     pub device:&'a Device,
@@ -9,7 +11,21 @@ pub struct InitNecessary<'a>{
     pub swap_chain_desc:&'a SwapChainDescriptor
 }
 pub struct RenderNecessary<'a>{
-    pub swap_chain_desc:&'a SwapChainDescriptor
+    pub swap_chain_desc:&'a SwapChainDescriptor,
+    pub draw_list:&'a mut DrawList,
+    pub sprites:&'a mut SpriteBatch
+}
+
+impl<'a> RenderNecessary<'a> {
+    /// A shorter lived copy, to hand the same lists to several components.
+    pub fn reborrow(&mut self) -> RenderNecessary<'_>
+    {
+        RenderNecessary{
+            swap_chain_desc: self.swap_chain_desc,
+            draw_list: &mut *self.draw_list,
+            sprites: &mut *self.sprites
+        }
+    }
 }
 pub struct UpdateNecessary<'a>{
     pub delat:f32,
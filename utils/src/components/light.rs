@@ -0,0 +1,187 @@
+use crate::component::{Component, InitNecessary, RenderNecessary, UpdateNecessary};
+use std::any::Any;
+use crate::object::Object;
+use gen_code::{gen_impl_comp_common,AsAny};
+use cgmath::{Vector3, Rad, Deg};
+use crate::AsAny;
+use crate::shadow::ShadowSettings;
+
+/// A light infinitely far away, like the sun. `ShadowMap::update` renders its shadows.
+/// Like the other lights it is given in the space of the object's `Transform`, or in world
+/// space when the object has none.
+#[derive(AsAny)]
+pub struct DirectionalLight{
+    object:*const Object,
+    /// The direction the light travels in, does not have to be normalized.
+    pub direction:Vector3<f32>,
+    pub color:Vector3<f32>,
+    pub intensity:f32,
+    pub cast_shadows:bool,
+    pub shadow:ShadowSettings,
+}
+
+impl DirectionalLight {
+    pub fn new(direction:Vector3<f32>) -> DirectionalLight
+    {
+        DirectionalLight{
+            object: 0 as _,
+            direction,
+            color: Vector3::new(1f32,1f32,1f32),
+            intensity: 1f32,
+            cast_shadows: true,
+            shadow: ShadowSettings::default()
+        }
+    }
+}
+
+impl Component for DirectionalLight
+{
+    gen_impl_comp_common!{object}
+
+    fn on_add(&mut self) {
+
+    }
+
+    fn on_remove(&mut self) {
+
+    }
+
+    fn init(&mut self, _nec: InitNecessary<'_>) {
+
+    }
+
+    fn render(&mut self, _nec: RenderNecessary<'_>) {
+
+    }
+
+    fn start(&mut self) {
+
+    }
+
+    fn update(&mut self, _nec: UpdateNecessary<'_>) {
+
+    }
+
+    fn destroy(&mut self) {
+
+    }
+}
+
+/// Shines in every direction from `position`, fading out towards `range`.
+#[derive(AsAny)]
+pub struct PointLight{
+    object:*const Object,
+    pub position:Vector3<f32>,
+    pub color:Vector3<f32>,
+    pub intensity:f32,
+    pub range:f32,
+}
+
+impl PointLight {
+    pub fn new(position:Vector3<f32>,range:f32) -> PointLight
+    {
+        PointLight{
+            object: 0 as _,
+            position,
+            color: Vector3::new(1f32,1f32,1f32),
+            intensity: 1f32,
+            range
+        }
+    }
+}
+
+impl Component for PointLight
+{
+    gen_impl_comp_common!{object}
+
+    fn on_add(&mut self) {
+
+    }
+
+    fn on_remove(&mut self) {
+
+    }
+
+    fn init(&mut self, _nec: InitNecessary<'_>) {
+
+    }
+
+    fn render(&mut self, _nec: RenderNecessary<'_>) {
+
+    }
+
+    fn start(&mut self) {
+
+    }
+
+    fn update(&mut self, _nec: UpdateNecessary<'_>) {
+
+    }
+
+    fn destroy(&mut self) {
+
+    }
+}
+
+/// A point light limited to a cone around `direction`, fully lit inside `inner_angle`
+/// and fading out up to `outer_angle` (half angles).
+#[derive(AsAny)]
+pub struct SpotLight{
+    object:*const Object,
+    pub position:Vector3<f32>,
+    pub direction:Vector3<f32>,
+    pub color:Vector3<f32>,
+    pub intensity:f32,
+    pub range:f32,
+    pub inner_angle:Rad<f32>,
+    pub outer_angle:Rad<f32>,
+}
+
+impl SpotLight {
+    pub fn new(position:Vector3<f32>,direction:Vector3<f32>,range:f32) -> SpotLight
+    {
+        SpotLight{
+            object: 0 as _,
+            position,
+            direction,
+            color: Vector3::new(1f32,1f32,1f32),
+            intensity: 1f32,
+            range,
+            inner_angle: Deg(20f32).into(),
+            outer_angle: Deg(30f32).into()
+        }
+    }
+}
+
+impl Component for SpotLight
+{
+    gen_impl_comp_common!{object}
+
+    fn on_add(&mut self) {
+
+    }
+
+    fn on_remove(&mut self) {
+
+    }
+
+    fn init(&mut self, _nec: InitNecessary<'_>) {
+
+    }
+
+    fn render(&mut self, _nec: RenderNecessary<'_>) {
+
+    }
+
+    fn start(&mut self) {
+
+    }
+
+    fn update(&mut self, _nec: UpdateNecessary<'_>) {
+
+    }
+
+    fn destroy(&mut self) {
+
+    }
+}
@@ -0,0 +1,87 @@
+use crate::component::{Component, InitNecessary, RenderNecessary, UpdateNecessary};
+use std::any::Any;
+use std::rc::Rc;
+use crate::object::Object;
+use gen_code::{gen_impl_comp_common,AsAny};
+use cgmath::{Matrix4, Vector4, SquareMatrix};
+use crate::AsAny;
+use crate::components::Transform;
+use crate::mesh::GpuMesh;
+use crate::material::Material;
+use crate::draw::DrawCommand;
+
+/// Draws `mesh` with `material` at the world matrix of the object's `Transform`
+/// (identity without one) through the `DrawList` of `RenderNecessary`.
+/// Renderers sharing a mesh and material are instanced together.
+#[derive(AsAny)]
+pub struct MeshRenderer{
+    object:*const Object,
+    pub mesh:Rc<GpuMesh>,
+    pub material:Rc<Material>,
+    /// `None` draws the whole mesh.
+    pub submesh:Option<usize>,
+    /// Per instance color, for shaders that read location 9.
+    pub color:Vector4<f32>,
+    pub visible:bool,
+}
+
+impl MeshRenderer {
+    pub fn new(mesh:Rc<GpuMesh>,material:Rc<Material>) -> MeshRenderer
+    {
+        MeshRenderer{
+            object: 0 as _,
+            mesh,
+            material,
+            submesh: None,
+            color: Vector4::new(1f32,1f32,1f32,1f32),
+            visible: true
+        }
+    }
+
+    pub fn world_matrix(&self) -> Matrix4<f32>
+    {
+        self.object().get_comp::<Transform>()
+            .and_then(|c| c.as_any().downcast_ref::<Transform>())
+            .map_or(Matrix4::identity(),|t| t.get_world_matrix())
+    }
+}
+
+impl Component for MeshRenderer
+{
+    gen_impl_comp_common!{object}
+
+    fn on_add(&mut self) {
+
+    }
+
+    fn on_remove(&mut self) {
+
+    }
+
+    fn init(&mut self, _nec: InitNecessary<'_>) {
+
+    }
+
+    fn render(&mut self, nec: RenderNecessary<'_>) {
+        if !self.visible { return; }
+        nec.draw_list.push(DrawCommand{
+            mesh: self.mesh.clone(),
+            material: self.material.clone(),
+            submesh: self.submesh,
+            world: self.world_matrix(),
+            color: self.color
+        });
+    }
+
+    fn start(&mut self) {
+
+    }
+
+    fn update(&mut self, _nec: UpdateNecessary<'_>) {
+
+    }
+
+    fn destroy(&mut self) {
+
+    }
+}
@@ -7,6 +7,13 @@ use std::rc::{Rc, Weak};
 use std::ops::{Index, IndexMut};
 use crate::AsAny;
 
+mod light;
+mod mesh_renderer;
+mod sprite;
+pub use light::{DirectionalLight, PointLight, SpotLight};
+pub use mesh_renderer::MeshRenderer;
+pub use sprite::Sprite;
+
 #[derive(AsAny)]
 pub struct Transform{
     object:*const Object,
@@ -145,7 +152,7 @@ impl Component for Transform
     }
 
     fn render(&mut self, _nec: RenderNecessary<'_>) {
-        todo!()
+
     }
 
     fn start(&mut self) {
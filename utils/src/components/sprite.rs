@@ -0,0 +1,91 @@
+use crate::component::{Component, InitNecessary, RenderNecessary, UpdateNecessary};
+use std::any::Any;
+use std::rc::Rc;
+use crate::object::Object;
+use gen_code::{gen_impl_comp_common,AsAny};
+use cgmath::{Matrix4, Vector2, Vector4, SquareMatrix};
+use crate::AsAny;
+use crate::components::Transform;
+use crate::sprite::{SpriteTexture, SpriteCommand, sprite_corners};
+
+/// Draws the `region` of `texture` as a `size` quad placed by the object's `Transform`
+/// (identity without one) through the `SpriteBatch` of `RenderNecessary`.
+/// Higher `z` is drawn later, sprites sharing a texture are batched.
+#[derive(AsAny)]
+pub struct Sprite{
+    object:*const Object,
+    pub texture:Rc<SpriteTexture>,
+    /// `[u0,v0,u1,v1]`, e.g. from `TextureAtlas::uv`.
+    pub region:[f32;4],
+    pub size:Vector2<f32>,
+    /// The point of the sprite at the object's position, (0,0) top left, (1,1) bottom right.
+    pub pivot:Vector2<f32>,
+    pub tint:Vector4<f32>,
+    pub flip_x:bool,
+    pub flip_y:bool,
+    pub z:i32,
+    pub visible:bool,
+}
+
+impl Sprite {
+    pub fn new(texture:Rc<SpriteTexture>,region:[f32;4],size:Vector2<f32>) -> Sprite
+    {
+        Sprite{
+            object: 0 as _,
+            texture,
+            region,
+            size,
+            pivot: Vector2::new(0.5f32,0.5f32),
+            tint: Vector4::new(1f32,1f32,1f32,1f32),
+            flip_x: false,
+            flip_y: false,
+            z: 0,
+            visible: true
+        }
+    }
+
+    pub fn world_matrix(&self) -> Matrix4<f32>
+    {
+        self.object().get_comp::<Transform>()
+            .and_then(|c| c.as_any().downcast_ref::<Transform>())
+            .map_or(Matrix4::identity(),|t| t.get_world_matrix())
+    }
+}
+
+impl Component for Sprite
+{
+    gen_impl_comp_common!{object}
+
+    fn on_add(&mut self) {
+
+    }
+
+    fn on_remove(&mut self) {
+
+    }
+
+    fn init(&mut self, _nec: InitNecessary<'_>) {
+
+    }
+
+    fn render(&mut self, nec: RenderNecessary<'_>) {
+        if !self.visible { return; }
+        nec.sprites.push(SpriteCommand{
+            texture: self.texture.clone(),
+            z: self.z,
+            corners: sprite_corners(&self.world_matrix(),self.size,self.pivot,self.region,self.flip_x,self.flip_y,self.tint)
+        });
+    }
+
+    fn start(&mut self) {
+
+    }
+
+    fn update(&mut self, _nec: UpdateNecessary<'_>) {
+
+    }
+
+    fn destroy(&mut self) {
+
+    }
+}
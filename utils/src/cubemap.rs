@@ -0,0 +1,559 @@
+use std::rc::Rc;
+use std::num::NonZeroU32;
+use wgpu::{Device, Queue, Texture, TextureView, Sampler, TextureDescriptor, Extent3d, TextureDimension, TextureFormat, TextureUsage, ImageCopyTexture, Origin3d, ImageDataLayout, TextureViewDescriptor, TextureViewDimension, SamplerDescriptor, AddressMode, FilterMode, BufferUsage, BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindGroupDescriptor, BindGroupEntry, BindingType, BufferBindingType, BindingResource, ShaderStage, TextureSampleType, CommandEncoderDescriptor, RenderPassDescriptor, RenderPassColorAttachment, Operations, LoadOp, Color};
+use cgmath::{Vector3, Vector4, InnerSpace};
+use crate::buffer::GpuBuffer;
+use crate::bytes::AsBytes;
+use crate::pipeline::{PipelineCache, PipelineDesc, ColorFormat, FULLSCREEN_TRIANGLE_WGSL};
+use crate::resource_manager::ResourceMgr;
+use crate::resource_manager::pack::RgbaMips;
+use crate::texture::{ImageRes, HdrImage, HdrImageRes};
+
+/// Format of cubemaps converted from `HdrImage`s.
+pub const HDR_CUBE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// The direction through `(u,v)` of cube face `face` in the layer order wgpu uses,
+/// +x, -x, +y, -y, +z, -z, with `(0,0)` the top left of the face.
+pub fn cube_face_direction(face:u32,u:f32,v:f32) -> Vector3<f32>
+{
+    let (s,t) = (u * 2.0 - 1.0,v * 2.0 - 1.0);
+    match face {
+        0 => Vector3::new(1.0,-t,-s),
+        1 => Vector3::new(-1.0,-t,s),
+        2 => Vector3::new(s,1.0,t),
+        3 => Vector3::new(s,-1.0,-t),
+        4 => Vector3::new(s,-t,1.0),
+        _ => Vector3::new(-s,-t,-1.0)
+    }
+}
+
+/// Texture coordinates of `dir` in an equirectangular image, v 0 straight up.
+pub fn direction_to_equirect(dir:Vector3<f32>) -> (f32,f32)
+{
+    let d = dir.normalize();
+    (d.z.atan2(d.x) / (2.0 * std::f32::consts::PI) + 0.5,d.y.max(-1.0).min(1.0).acos() / std::f32::consts::PI)
+}
+
+/// The bits of the half float nearest to `v`, for uploading to 16 bit float textures.
+pub fn f32_to_f16(v:f32) -> u16
+{
+    let bits = v.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let mant = bits & 0x7f_ffff;
+    if exp == 0xff {
+        return sign | 0x7c00 | if mant != 0 { 0x200 } else { 0 };
+    }
+    let e = exp - 127 + 15;
+    if e >= 0x1f { return sign | 0x7c00; }
+    // round to nearest, ties to even, a carry out of the mantissa correctly bumps the exponent
+    let round = |half:u32,rem:u32,halfway:u32| half + (rem > halfway || (rem == halfway && half & 1 == 1)) as u32;
+    if e <= 0 {
+        if e < -10 { return sign; }
+        let m = mant | 0x80_0000;
+        let shift = (14 - e) as u32;
+        return sign | round(m >> shift,m & ((1 << shift) - 1),1 << (shift - 1)) as u16;
+    }
+    sign | round(((e as u32) << 10) | (mant >> 13),mant & 0x1fff,0x1000) as u16
+}
+
+/// The next mip level of `img`, half its size rounded down but at least 1x1. Every pixel is the
+/// average of the ones it covers, the last column and row also take in an odd one left over.
+pub fn downsample_hdr(img:&HdrImage) -> HdrImage
+{
+    let (width,height) = ((img.width / 2).max(1),(img.height / 2).max(1));
+    let mut data = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0f32;4];
+            let mut n = 0.0;
+            let ys = y * 2..if y + 1 == height { img.height } else { y * 2 + 2 };
+            let xs = x * 2..if x + 1 == width { img.width } else { x * 2 + 2 };
+            for sy in ys {
+                for sx in xs.clone() {
+                    let p = img.data[(sy * img.width + sx) as usize];
+                    for c in 0..4 { sum[c] += p[c]; }
+                    n += 1.0;
+                }
+            }
+            data.push([sum[0] / n,sum[1] / n,sum[2] / n,sum[3] / n]);
+        }
+    }
+    HdrImage{ width, height, data }
+}
+
+const EQUIRECT_WGSL: &str = r#"
+[[group(0), binding(0)]]
+var equirect: texture_2d<f32>;
+[[group(0), binding(1)]]
+var equirect_sampler: sampler;
+
+[[block]]
+struct Face {
+    // x: face index, y: level of the equirect to sample
+    index: vec4<f32>;
+};
+[[group(1), binding(0)]]
+var<uniform> face: Face;
+
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    // cube_face_direction
+    let s = in.uv.x * 2.0 - 1.0;
+    let t = in.uv.y * 2.0 - 1.0;
+    let f = face.index.x;
+    var dir: vec3<f32> = vec3<f32>(-s, -t, -1.0);
+    if (f < 0.5) { dir = vec3<f32>(1.0, -t, -s); }
+    elseif (f < 1.5) { dir = vec3<f32>(-1.0, -t, s); }
+    elseif (f < 2.5) { dir = vec3<f32>(s, 1.0, t); }
+    elseif (f < 3.5) { dir = vec3<f32>(s, -1.0, -t); }
+    elseif (f < 4.5) { dir = vec3<f32>(s, -t, 1.0); }
+    // direction_to_equirect
+    let d = normalize(dir);
+    let pi = 3.14159265;
+    let uv = vec2<f32>(atan2(d.z, d.x) / (2.0 * pi) + 0.5, acos(clamp(d.y, -1.0, 1.0)) / pi);
+    return textureSampleLevel(equirect, equirect_sampler, uv, face.index.y);
+}
+"#;
+
+/// The shader rendering one cubemap face from the equirectangular map.
+pub fn equirect_wgsl() -> String
+{
+    format!("{}{}",FULLSCREEN_TRIANGLE_WGSL,EQUIRECT_WGSL)
+}
+
+/// A cube texture with a view of all six faces, for skyboxes and environment lighting.
+pub struct GpuCubemap{
+    pub texture: Texture,
+    pub view: TextureView,
+    pub sampler: Sampler,
+    pub format: TextureFormat,
+    /// Width and height of a face.
+    pub size: u32,
+    pub mip_level_count: u32,
+}
+
+impl GpuCubemap {
+    fn create(device:&Device,size:u32,mip_level_count:u32,format:TextureFormat,usage:TextureUsage) -> GpuCubemap
+    {
+        let texture = device.create_texture(&TextureDescriptor{
+            label: Some("Cubemap"),
+            size: Extent3d{ width: size, height: size, depth_or_array_layers: 6 },
+            mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage
+        });
+        let view = texture.create_view(&TextureViewDescriptor{
+            label: Some("Cubemap View"),
+            dimension: Some(TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&SamplerDescriptor{
+            label: Some("Cubemap Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        GpuCubemap{ texture, view, sampler, format, size, mip_level_count }
+    }
+
+    /// From six square images of one size in the order of `cube_face_direction`, with the
+    /// mip levels all of them have. `None` when the faces do not match.
+    pub fn from_faces(device:&Device,queue:&Queue,faces:&[Rc<RgbaMips>],srgb:bool) -> Option<GpuCubemap>
+    {
+        if faces.len() != 6 { return None; }
+        let size = faces[0].width;
+        if faces.iter().any(|f| f.width != size || f.height != size || f.levels.is_empty()) { return None; }
+        let levels = faces.iter().map(|f| f.levels.len()).min()? as u32;
+        let format = if srgb { TextureFormat::Rgba8UnormSrgb } else { TextureFormat::Rgba8Unorm };
+        let cube = Self::create(device,size,levels,format,TextureUsage::SAMPLED | TextureUsage::COPY_DST);
+        for (i,face) in faces.iter().enumerate() {
+            for level in 0..levels {
+                let (w,h) = RgbaMips::mip_size(size,size,level);
+                queue.write_texture(ImageCopyTexture{
+                    texture: &cube.texture,
+                    mip_level: level,
+                    origin: Origin3d{ x: 0, y: 0, z: i as u32 }
+                }, face.levels[level as usize].as_slice(), ImageDataLayout{
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(w * 4),
+                    rows_per_image: NonZeroU32::new(h)
+                }, Extent3d{ width: w, height: h, depth_or_array_layers: 1 });
+            }
+        }
+        Some(cube)
+    }
+
+    /// Renders every mip level of every face of a `face_size` cubemap straight from the
+    /// equirectangular `img` on the GPU. Each level samples a box filtered copy of `img` about as
+    /// coarse as its own texels, so the smaller levels blur the environment for rough reflections.
+    pub fn from_equirect(device:&Device,queue:&Queue,pipelines:&mut PipelineCache,img:&HdrImage,face_size:u32) -> GpuCubemap
+    {
+        let face_size = face_size.max(1);
+        let levels = 32 - face_size.leading_zeros();
+        let cube = Self::create(device,face_size,levels,HDR_CUBE_FORMAT,TextureUsage::SAMPLED | TextureUsage::RENDER_ATTACHMENT);
+
+        let source_levels = RgbaMips::level_count(img.width,img.height);
+        let mut chain:Vec<HdrImage> = Vec::with_capacity(source_levels as usize - 1);
+        for _ in 1..source_levels {
+            let next = downsample_hdr(chain.last().unwrap_or(img));
+            chain.push(next);
+        }
+        let source = device.create_texture(&TextureDescriptor{
+            label: Some("Equirect"),
+            size: Extent3d{ width: img.width, height: img.height, depth_or_array_layers: 1 },
+            mip_level_count: source_levels,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: HDR_CUBE_FORMAT,
+            usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST
+        });
+        for (level,mip) in std::iter::once(img).chain(chain.iter()).enumerate() {
+            let halfs:Vec<u16> = mip.data.iter().flat_map(|p| p.iter().map(|&c| f32_to_f16(c))).collect();
+            queue.write_texture(ImageCopyTexture{
+                texture: &source,
+                mip_level: level as u32,
+                origin: Origin3d::ZERO
+            }, halfs.as_slice().as_bytes(), ImageDataLayout{
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(mip.width * 8),
+                rows_per_image: NonZeroU32::new(mip.height)
+            }, Extent3d{ width: mip.width, height: mip.height, depth_or_array_layers: 1 });
+        }
+        let source_view = source.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&SamplerDescriptor{
+            label: Some("Equirect Sampler"),
+            address_mode_u: AddressMode::Repeat,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let source_layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Equirect Layout"),
+            entries: &[
+                BindGroupLayoutEntry{
+                    binding: 0,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float{ filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding: 1,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Sampler{
+                        filtering: true,
+                        comparison: false
+                    },
+                    count: None
+                }
+            ]
+        }));
+        let source_group = device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Equirect Bind Group"),
+            layout: &source_layout,
+            entries: &[
+                BindGroupEntry{ binding: 0, resource: BindingResource::TextureView(&source_view) },
+                BindGroupEntry{ binding: 1, resource: BindingResource::Sampler(&sampler) }
+            ]
+        });
+        let face_layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Cube Face Layout"),
+            entries: &[
+                BindGroupLayoutEntry{
+                    binding: 0,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                }
+            ]
+        }));
+        // a face texel covers 90 / size degrees, an equirect texel 360 / width
+        let base_lod = (img.width as f32 / (4 * face_size) as f32).log2();
+        let face_buffers:Vec<_> = (0..levels).flat_map(|level| (0..6).map(move |face| (level,face))).map(|(level,face)| {
+            let lod = (base_lod + level as f32).max(0.0).min((source_levels - 1) as f32);
+            GpuBuffer::from_value(device,Some("Cube Face"),BufferUsage::UNIFORM,&Vector4::new(face as f32,lod,0.0,0.0))
+        }).collect();
+        let face_groups:Vec<_> = face_buffers.iter().map(|b| device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Cube Face Bind Group"),
+            layout: &face_layout,
+            entries: &[
+                BindGroupEntry{ binding: 0, resource: b.as_entire_binding() }
+            ]
+        })).collect();
+
+        let shader = pipelines.shader(device,"Equirect",equirect_wgsl().as_str());
+        let layouts = vec![pipelines.layout(&source_layout),pipelines.layout(&face_layout)];
+        let pipeline = pipelines.get(device,&PipelineDesc{
+            color: Some(ColorFormat::Format(HDR_CUBE_FORMAT)),
+            ..PipelineDesc::new(shader,layouts,Vec::new())
+        });
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor{
+            label: Some("Equirect To Cube")
+        });
+        for level in 0..levels {
+            for (face,group) in face_groups[(level * 6) as usize..(level * 6 + 6) as usize].iter().enumerate() {
+                let view = cube.texture.create_view(&TextureViewDescriptor{
+                    label: Some("Cube Face View"),
+                    dimension: Some(TextureViewDimension::D2),
+                    base_mip_level: level,
+                    mip_level_count: NonZeroU32::new(1),
+                    base_array_layer: face as u32,
+                    array_layer_count: NonZeroU32::new(1),
+                    ..Default::default()
+                });
+                let mut pass = encoder.begin_render_pass(&RenderPassDescriptor{
+                    label: Some("Cube Face Pass"),
+                    color_attachments: &[RenderPassColorAttachment{
+                        view: &view,
+                        resolve_target: None,
+                        ops: Operations{ load: LoadOp::Clear(Color::BLACK), store: true }
+                    }],
+                    depth_stencil_attachment: None
+                });
+                pass.set_pipeline(&pipeline);
+                pass.set_bind_group(0,&source_group,&[]);
+                pass.set_bind_group(1,group,&[]);
+                pass.draw(0..3,0..1);
+            }
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        cube
+    }
+}
+
+/// Loads six faces through `ImageRes`, which must be registered, in the order of `cube_face_direction`.
+pub fn load_cube_faces(mgr:&mut ResourceMgr,paths:&[&str;6]) -> Option<Vec<Rc<RgbaMips>>>
+{
+    let mut faces = Vec::with_capacity(6);
+    for p in paths.iter() {
+        let (raw,overdue,path) = mgr.load_file(p)?;
+        faces.push(mgr.loading::<ImageRes,_,_>(raw,&path,overdue)?);
+    }
+    Some(faces)
+}
+
+/// Loads a Radiance `.hdr` through `HdrImageRes`, which must be registered.
+pub fn load_hdr(mgr:&mut ResourceMgr,p:&str) -> Option<Rc<HdrImage>>
+{
+    let (raw,overdue,path) = mgr.load_file(p)?;
+    mgr.loading::<HdrImageRes,_,_>(raw,&path,overdue)
+}
+
+/// A cubemap bound for lit shaders the way `environment_wgsl` declares it,
+/// with the intensity of the environment light.
+pub struct EnvironmentMap{
+    cubemap: Rc<GpuCubemap>,
+    params: GpuBuffer<Vector4<f32>>,
+    layout: Rc<BindGroupLayout>,
+    bind_group: BindGroup,
+}
+
+impl EnvironmentMap {
+    pub fn new(device:&Device,cubemap:Rc<GpuCubemap>,intensity:f32) -> EnvironmentMap
+    {
+        let params = GpuBuffer::from_value(device,Some("Environment Params"),BufferUsage::UNIFORM,&Self::params_of(&cubemap,intensity));
+        let layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Environment Bind Group Layout"),
+            entries: Self::layout_entries().as_slice()
+        }));
+        let bind_group = device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Environment Bind Group"),
+            layout: &layout,
+            entries: &[
+                BindGroupEntry{ binding: 0, resource: BindingResource::TextureView(&cubemap.view) },
+                BindGroupEntry{ binding: 1, resource: BindingResource::Sampler(&cubemap.sampler) },
+                BindGroupEntry{ binding: 2, resource: params.as_entire_binding() }
+            ]
+        });
+        EnvironmentMap{ cubemap, params, layout, bind_group }
+    }
+
+    fn params_of(cubemap:&GpuCubemap,intensity:f32) -> Vector4<f32>
+    {
+        Vector4::new(intensity,cubemap.mip_level_count.saturating_sub(1) as f32,0.0,0.0)
+    }
+
+    pub fn layout_entries() -> Vec<BindGroupLayoutEntry>
+    {
+        vec![
+            BindGroupLayoutEntry{
+                binding: 0,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float{ filterable: true },
+                    view_dimension: TextureViewDimension::Cube,
+                    multisampled: false
+                },
+                count: None
+            },
+            BindGroupLayoutEntry{
+                binding: 1,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Sampler{
+                    filtering: true,
+                    comparison: false
+                },
+                count: None
+            },
+            BindGroupLayoutEntry{
+                binding: 2,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None
+                },
+                count: None
+            }
+        ]
+    }
+
+    pub fn set_intensity(&self,queue:&Queue,intensity:f32)
+    {
+        self.params.set(queue,&Self::params_of(&self.cubemap,intensity));
+    }
+
+    pub fn cubemap(&self) -> &Rc<GpuCubemap> { &self.cubemap }
+    pub fn layout(&self) -> &Rc<BindGroupLayout> { &self.layout }
+    pub fn bind_group(&self) -> &BindGroup { &self.bind_group }
+}
+
+/// WGSL of an `EnvironmentMap` bound at `group` and
+/// `environment_light(n, v, albedo, metallic, roughness)`, the light the environment adds to
+/// a metallic-roughness surface: diffuse from one of the blurriest mips around `n`, specular from the
+/// reflection, blurrier for rougher surfaces. `n` and `v` (towards the camera) have to be normalized.
+pub fn environment_wgsl(group:u32) -> String
+{
+    format!(r#"
+[[group({group}), binding(0)]]
+var env_map: texture_cube<f32>;
+[[group({group}), binding(1)]]
+var env_sampler: sampler;
+
+[[block]]
+struct Environment {{
+    // x: intensity, y: last mip level
+    params: vec4<f32>;
+}};
+[[group({group}), binding(2)]]
+var<uniform> environment: Environment;
+
+fn environment_light(n: vec3<f32>, v: vec3<f32>, albedo: vec3<f32>, metallic: f32, roughness: f32) -> vec3<f32>
+{{
+    let levels = environment.params.y;
+    let r = reflect(-v, n);
+    let specular = textureSampleLevel(env_map, env_sampler, r, roughness * levels).rgb;
+    let diffuse = textureSampleLevel(env_map, env_sampler, n, max(levels - 1.0, 0.0)).rgb;
+    let f0 = mix(vec3<f32>(0.04, 0.04, 0.04), albedo, vec3<f32>(metallic, metallic, metallic));
+    // Schlick fresnel, damped for rough surfaces
+    let n_v = max(dot(n, v), 0.0);
+    let g = 1.0 - roughness;
+    let f = f0 + (max(vec3<f32>(g, g, g), f0) - f0) * pow(1.0 - n_v, 5.0);
+    let kd = (vec3<f32>(1.0, 1.0, 1.0) - f) * (1.0 - metallic);
+    return (kd * albedo * diffuse + f * specular) * environment.params.x;
+}}
+"#,group = group)
+}
+
+mod test_cubemap{
+    use crate::cubemap::{cube_face_direction, direction_to_equirect, f32_to_f16, downsample_hdr};
+    use crate::texture::HdrImage;
+    use cgmath::{Vector3, InnerSpace};
+
+    /// The face and texture coordinates a GPU samples for `d`, from the cube map selection table.
+    fn select(d:Vector3<f32>) -> (u32,f32,f32)
+    {
+        let a = Vector3::new(d.x.abs(),d.y.abs(),d.z.abs());
+        let (face,sc,tc,ma) = if a.x >= a.y && a.x >= a.z {
+            if d.x > 0.0 { (0,-d.z,-d.y,a.x) } else { (1,d.z,-d.y,a.x) }
+        }else if a.y >= a.z {
+            if d.y > 0.0 { (2,d.x,d.z,a.y) } else { (3,d.x,-d.z,a.y) }
+        }else{
+            if d.z > 0.0 { (4,d.x,-d.y,a.z) } else { (5,-d.x,-d.y,a.z) }
+        };
+        (face,(sc / ma + 1.0) / 2.0,(tc / ma + 1.0) / 2.0)
+    }
+
+    #[test]
+    fn faces()
+    {
+        let axes = [Vector3::unit_x(),-Vector3::unit_x(),Vector3::unit_y(),-Vector3::unit_y(),Vector3::unit_z(),-Vector3::unit_z()];
+        for face in 0..6u32 {
+            assert_eq!(cube_face_direction(face,0.5,0.5),axes[face as usize]);
+            for &(u,v) in [(0.1,0.2),(0.9,0.3),(0.25,0.75),(0.6,0.95)].iter() {
+                let (f,su,sv) = select(cube_face_direction(face,u,v));
+                assert_eq!(f,face);
+                assert!((su - u).abs() < 1e-5 && (sv - v).abs() < 1e-5,"face {} {:?} != {:?}",face,(u,v),(su,sv));
+            }
+        }
+    }
+
+    #[test]
+    fn equirect()
+    {
+        let close = |a:(f32,f32),b:(f32,f32)| (a.0 - b.0).abs() < 1e-5 && (a.1 - b.1).abs() < 1e-5;
+        assert_eq!(direction_to_equirect(Vector3::unit_y()).1,0.0);
+        assert_eq!(direction_to_equirect(-Vector3::unit_y()).1,1.0);
+        assert!(close(direction_to_equirect(Vector3::unit_x()),(0.5,0.5)));
+        assert!(close(direction_to_equirect(Vector3::unit_z() * 3.0),(0.75,0.5)));
+        assert!(close(direction_to_equirect(-Vector3::unit_z()),(0.25,0.5)));
+        let (_,v) = direction_to_equirect(Vector3::new(1.0,1.0,0.0).normalize());
+        assert!((v - 0.25).abs() < 1e-5);
+    }
+
+    #[test]
+    fn half()
+    {
+        assert_eq!(f32_to_f16(0.0),0x0000);
+        assert_eq!(f32_to_f16(-0.0),0x8000);
+        assert_eq!(f32_to_f16(1.0),0x3c00);
+        assert_eq!(f32_to_f16(0.5),0x3800);
+        assert_eq!(f32_to_f16(-2.0),0xc000);
+        assert_eq!(f32_to_f16(0.1),0x2e66);
+        assert_eq!(f32_to_f16(65504.0),0x7bff);
+        assert_eq!(f32_to_f16(65520.0),0x7c00);
+        assert_eq!(f32_to_f16(1e6),0x7c00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY),0xfc00);
+        assert_eq!(f32_to_f16(f32::NAN) & 0x7e00,0x7e00);
+        // smallest subnormal, and half of it rounding to even zero
+        assert_eq!(f32_to_f16(5.960464e-8),0x0001);
+        assert_eq!(f32_to_f16(2.980232e-8),0x0000);
+        assert_eq!(f32_to_f16(6.097555e-5),0x03ff);
+        assert_eq!(f32_to_f16(1e-10),0x0000);
+    }
+
+    #[test]
+    fn downsample()
+    {
+        let img = HdrImage{ width: 3, height: 2, data: (0..6).map(|i| [i as f32,1.0,0.0,1.0]).collect() };
+        let one = downsample_hdr(&img);
+        assert_eq!((one.width,one.height),(1,1));
+        assert_eq!(one.data,vec![[2.5,1.0,0.0,1.0]]);
+        let img = HdrImage{ width: 5, height: 1, data: (0..5).map(|i| [i as f32,1.0,0.0,1.0]).collect() };
+        let half = downsample_hdr(&img);
+        assert_eq!((half.width,half.height),(2,1));
+        assert_eq!(half.data,vec![[0.5,1.0,0.0,1.0],[3.0,1.0,0.0,1.0]]);
+        let same = downsample_hdr(&one);
+        assert_eq!((same.width,same.height),(1,1));
+        assert_eq!(same.data,one.data);
+    }
+}
@@ -0,0 +1,384 @@
+use std::rc::Rc;
+use wgpu::{Device, Queue, RenderPass, BufferUsage, BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindGroupDescriptor, BindGroupEntry, BindingType, BufferBindingType, ShaderStage, RenderPipeline, TextureFormat, PrimitiveTopology};
+use cgmath::{Matrix4, Vector3, Vector4, SquareMatrix, InnerSpace};
+use crate::bytes::AsBytes;
+use crate::vertex::VertexLayout;
+use crate::buffer::{GpuBuffer, GpuVec};
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::components::Transform;
+use crate::pipeline::{PipelineCache, PipelineDesc, ColorFormat, DepthState};
+use crate::material::CompareDesc;
+
+/// Segments of each circle drawn by `DebugDraw::sphere`.
+pub const SPHERE_SEGMENTS: usize = 32;
+
+#[repr(C)]
+#[derive(Debug,Copy,Clone,PartialEq,VertexLayout,AsBytes)]
+pub struct DebugVertex{
+    pub position: Vector3<f32>,
+    pub color: Vector4<f32>,
+}
+
+/// Immediate mode lines, collected during a frame and handed to a `DebugRenderer`,
+/// which draws and forgets them. Lines go to the depth tested list unless
+/// `set_depth_test(false)` was called, then they are drawn over everything.
+pub struct DebugDraw{
+    depth_tested: Vec<DebugVertex>,
+    overlay: Vec<DebugVertex>,
+    depth_test: bool,
+}
+
+impl DebugDraw {
+    pub fn new() -> DebugDraw
+    {
+        DebugDraw{
+            depth_tested: Vec::new(),
+            overlay: Vec::new(),
+            depth_test: true
+        }
+    }
+
+    pub fn set_depth_test(&mut self,depth_test:bool)
+    {
+        self.depth_test = depth_test;
+    }
+
+    pub fn depth_test(&self) -> bool { self.depth_test }
+
+    /// Line list vertices, two per line.
+    pub fn depth_tested(&self) -> &[DebugVertex] { self.depth_tested.as_slice() }
+    pub fn overlay(&self) -> &[DebugVertex] { self.overlay.as_slice() }
+
+    pub fn clear(&mut self)
+    {
+        self.depth_tested.clear();
+        self.overlay.clear();
+    }
+
+    pub fn line(&mut self,a:Vector3<f32>,b:Vector3<f32>,color:Vector4<f32>)
+    {
+        let list = if self.depth_test { &mut self.depth_tested } else { &mut self.overlay };
+        list.push(DebugVertex{ position: a, color });
+        list.push(DebugVertex{ position: b, color });
+    }
+
+    /// The 12 edges of the box spanned by `corners`, ordered like a bit mask
+    /// (bit 0 x, bit 1 y, bit 2 z).
+    fn box_edges(&mut self,corners:&[Vector3<f32>;8],color:Vector4<f32>)
+    {
+        for i in 0..8usize {
+            for bit in [1usize,2,4].iter() {
+                if i & bit == 0 {
+                    self.line(corners[i],corners[i | bit],color);
+                }
+            }
+        }
+    }
+
+    pub fn aabb(&mut self,b:&Aabb,color:Vector4<f32>)
+    {
+        let mut corners = [b.min;8];
+        for (i,c) in corners.iter_mut().enumerate() {
+            *c = Vector3::new(
+                if i & 1 == 0 { b.min.x } else { b.max.x },
+                if i & 2 == 0 { b.min.y } else { b.max.y },
+                if i & 4 == 0 { b.min.z } else { b.max.z }
+            );
+        }
+        self.box_edges(&corners,color);
+    }
+
+    /// Three circles around the axes.
+    pub fn sphere(&mut self,s:&BoundingSphere,color:Vector4<f32>)
+    {
+        let point = |axis:usize,a:f32| {
+            let (sin,cos) = a.sin_cos();
+            let p = match axis {
+                0 => Vector3::new(0.0,cos,sin),
+                1 => Vector3::new(cos,0.0,sin),
+                _ => Vector3::new(cos,sin,0.0)
+            };
+            s.center + p * s.radius
+        };
+        let step = std::f32::consts::PI * 2.0 / SPHERE_SEGMENTS as f32;
+        for axis in 0..3 {
+            for i in 0..SPHERE_SEGMENTS {
+                self.line(point(axis,i as f32 * step),point(axis,(i + 1) as f32 * step),color);
+            }
+        }
+    }
+
+    /// The local axes of `m`, x red, y green, z blue, `size` long before scaling.
+    pub fn axis_matrix(&mut self,m:&Matrix4<f32>,size:f32)
+    {
+        let origin = m.w.truncate();
+        let colors = [Vector4::new(1.0,0.0,0.0,1.0),Vector4::new(0.0,1.0,0.0,1.0),Vector4::new(0.0,0.0,1.0,1.0)];
+        for (axis,color) in [m.x,m.y,m.z].iter().zip(colors.iter()) {
+            self.line(origin,origin + axis.truncate() * size,*color);
+        }
+    }
+
+    pub fn axis(&mut self,t:&Transform,size:f32)
+    {
+        self.axis_matrix(&t.get_world_matrix(),size);
+    }
+
+    /// A grid on the xz plane through `center`, `cells` by `cells` cells of `cell_size`.
+    pub fn grid(&mut self,center:Vector3<f32>,cell_size:f32,cells:u32,color:Vector4<f32>)
+    {
+        let half = cell_size * cells as f32 / 2.0;
+        for i in 0..=cells {
+            let o = i as f32 * cell_size - half;
+            self.line(center + Vector3::new(o,0.0,-half),center + Vector3::new(o,0.0,half),color);
+            self.line(center + Vector3::new(-half,0.0,o),center + Vector3::new(half,0.0,o),color);
+        }
+    }
+
+    /// The view volume of `view_proj` (wgpu clip space, depth 0..1).
+    /// Nothing is drawn when the matrix can not be inverted.
+    pub fn frustum(&mut self,view_proj:&Matrix4<f32>,color:Vector4<f32>)
+    {
+        let inv = match view_proj.invert() {
+            Some(m) => m,
+            None => return
+        };
+        let mut corners = [Vector3::new(0.0,0.0,0.0);8];
+        for (i,c) in corners.iter_mut().enumerate() {
+            let p = inv * Vector4::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { 0.0 } else { 1.0 },
+                1.0
+            );
+            *c = p.truncate() / p.w;
+        }
+        self.box_edges(&corners,color);
+    }
+
+    /// A line from `origin` along `dir` with a small cross at its tip, e.g. a light direction.
+    pub fn arrow(&mut self,origin:Vector3<f32>,dir:Vector3<f32>,color:Vector4<f32>)
+    {
+        let tip = origin + dir;
+        self.line(origin,tip,color);
+        let len = dir.magnitude();
+        if len <= 0.0 { return; }
+        let d = dir / len;
+        let side = if d.y.abs() < 0.9 { d.cross(Vector3::unit_y()) } else { d.cross(Vector3::unit_x()) }.normalize();
+        let up = side.cross(d);
+        let back = tip - d * len * 0.15;
+        for o in [side,-side,up,-up].iter() {
+            self.line(tip,back + o * len * 0.08,color);
+        }
+    }
+}
+
+impl Default for DebugDraw {
+    fn default() -> Self { DebugDraw::new() }
+}
+
+const DEBUG_WGSL: &str = r#"
+[[block]]
+struct Camera {
+    view_proj: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn main(in: VertexInput) -> VertexOutput
+{
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(in.position,1.0);
+    out.color = in.color;
+    return out;
+}
+
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    return in.color;
+}
+"#;
+
+/// Draws the lines of a `DebugDraw` as a line list over the scene. Call `prepare` before
+/// the render pass, it uploads and clears the lines, then `execute` inside a pass with the
+/// color format, depth format and sample count given to `new`.
+pub struct DebugRenderer{
+    camera: GpuBuffer<Matrix4<f32>>,
+    layout: Rc<BindGroupLayout>,
+    bind_group: BindGroup,
+    depth_pipeline: Rc<RenderPipeline>,
+    overlay_pipeline: Rc<RenderPipeline>,
+    vertices: GpuVec<DebugVertex>,
+    /// Depth tested vertices at the start of `vertices`, overlay ones after them.
+    depth_tested: u32,
+}
+
+impl DebugRenderer {
+    /// Without a depth format every line is drawn over the scene.
+    pub fn new(device:&Device,pipelines:&mut PipelineCache,color:ColorFormat,depth_format:Option<TextureFormat>,sample_count:u32) -> DebugRenderer
+    {
+        let camera = GpuBuffer::from_value(device,Some("Debug Camera"),BufferUsage::UNIFORM,&Matrix4::identity());
+        let layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Debug Camera Layout"),
+            entries: &[
+                BindGroupLayoutEntry{
+                    binding: 0,
+                    visibility: ShaderStage::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                }
+            ]
+        }));
+        let bind_group = device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Debug Camera Bind Group"),
+            layout: &layout,
+            entries: &[
+                BindGroupEntry{ binding: 0, resource: camera.as_entire_binding() }
+            ]
+        });
+
+        let shader = pipelines.shader(device,"Debug Draw",DEBUG_WGSL);
+        let layouts = vec![pipelines.layout(&layout)];
+        let desc = |compare| PipelineDesc{
+            color: Some(color),
+            depth: depth_format.map(|format| DepthState{ format, write: false, compare }),
+            topology: PrimitiveTopology::LineList,
+            sample_count,
+            ..PipelineDesc::new(shader,layouts.clone(),vec![DebugVertex::layout()])
+        };
+        DebugRenderer{
+            depth_pipeline: pipelines.get(device,&desc(CompareDesc::LessEqual)),
+            overlay_pipeline: pipelines.get(device,&desc(CompareDesc::Always)),
+            vertices: GpuVec::with_capacity(device,Some("Debug Lines"),BufferUsage::VERTEX,1024),
+            depth_tested: 0,
+            camera,
+            layout,
+            bind_group
+        }
+    }
+
+    pub fn layout(&self) -> &Rc<BindGroupLayout> { &self.layout }
+
+    /// Takes the lines of `draw` for this frame, `draw` is empty afterwards.
+    pub fn prepare(&mut self,device:&Device,queue:&Queue,draw:&mut DebugDraw,view_proj:&Matrix4<f32>)
+    {
+        self.camera.set(queue,view_proj);
+        self.vertices.clear();
+        self.vertices.extend_from_slice(draw.depth_tested());
+        self.vertices.extend_from_slice(draw.overlay());
+        self.depth_tested = draw.depth_tested().len() as u32;
+        self.vertices.sync(device,queue);
+        draw.clear();
+    }
+
+    pub fn execute<'a>(&'a self,pass:&mut RenderPass<'a>)
+    {
+        let len = self.vertices.len() as u32;
+        if len == 0 { return; }
+        pass.set_bind_group(0,&self.bind_group,&[]);
+        pass.set_vertex_buffer(0,self.vertices.slice(..));
+        if self.depth_tested > 0 {
+            pass.set_pipeline(&self.depth_pipeline);
+            pass.draw(0..self.depth_tested,0..1);
+        }
+        if len > self.depth_tested {
+            pass.set_pipeline(&self.overlay_pipeline);
+            pass.draw(self.depth_tested..len,0..1);
+        }
+    }
+}
+
+mod test_debug_draw{
+    use crate::debug_draw::{DebugDraw, SPHERE_SEGMENTS};
+    use crate::bounds::{Aabb, BoundingSphere};
+    use crate::camera::OPENGL_TO_WGPU_MATRIX;
+    use cgmath::{Matrix4, Vector3, Vector4, Deg, InnerSpace};
+
+    const WHITE: Vector4<f32> = Vector4::new(1.0,1.0,1.0,1.0);
+
+    #[test]
+    fn shapes()
+    {
+        let mut d = DebugDraw::new();
+        d.line(Vector3::new(0.0,0.0,0.0),Vector3::new(1.0,0.0,0.0),WHITE);
+        assert_eq!(d.depth_tested().len(),2);
+
+        d.clear();
+        let b = Aabb::new(Vector3::new(-1.0,-2.0,-3.0),Vector3::new(1.0,2.0,3.0));
+        d.aabb(&b,WHITE);
+        let v = d.depth_tested();
+        assert_eq!(v.len(),24);
+        // every edge runs along one axis and has both ends on the box
+        for e in v.chunks_exact(2) {
+            let diff = e[1].position - e[0].position;
+            assert_eq!([diff.x != 0.0,diff.y != 0.0,diff.z != 0.0].iter().filter(|&&c| c).count(),1);
+            for p in e.iter() {
+                assert_eq!(p.position.x.abs(),1.0);
+                assert_eq!(p.position.y.abs(),2.0);
+                assert_eq!(p.position.z.abs(),3.0);
+            }
+        }
+
+        d.clear();
+        d.sphere(&BoundingSphere::new(Vector3::new(1.0,1.0,1.0),2.0),WHITE);
+        assert_eq!(d.depth_tested().len(),SPHERE_SEGMENTS * 3 * 2);
+        assert!(d.depth_tested().iter().all(|v| ((v.position - Vector3::new(1.0,1.0,1.0)).magnitude() - 2.0).abs() < 1e-4));
+
+        d.clear();
+        d.grid(Vector3::new(0.0,0.0,0.0),1.0,4,WHITE);
+        assert_eq!(d.depth_tested().len(),5 * 2 * 2);
+        assert!(d.depth_tested().iter().all(|v| v.position.x.abs() <= 2.0 && v.position.z.abs() <= 2.0 && v.position.y == 0.0));
+
+        d.clear();
+        d.axis_matrix(&(Matrix4::from_translation(Vector3::new(1.0,2.0,3.0)) * Matrix4::from_scale(2.0)),0.5);
+        let v = d.depth_tested();
+        assert_eq!(v.len(),6);
+        assert_eq!(v[0].position,Vector3::new(1.0,2.0,3.0));
+        assert_eq!(v[1].position,Vector3::new(2.0,2.0,3.0));
+        assert_eq!(v[5].position,Vector3::new(1.0,2.0,4.0));
+    }
+
+    #[test]
+    fn frustum()
+    {
+        let mut d = DebugDraw::new();
+        d.frustum(&(OPENGL_TO_WGPU_MATRIX * cgmath::perspective(Deg(90f32),1.0,1.0,10.0)),WHITE);
+        let v = d.depth_tested();
+        assert_eq!(v.len(),24);
+        // corners of the near plane at z = -1 are 1 away from the axis, far ones 10
+        for p in v.iter().map(|v| v.position) {
+            let z = -p.z;
+            assert!((z - 1.0).abs() < 1e-3 || (z - 10.0).abs() < 1e-3,"{:?}",p);
+            assert!((p.x.abs() - z).abs() < 1e-3 && (p.y.abs() - z).abs() < 1e-3,"{:?}",p);
+        }
+    }
+
+    #[test]
+    fn overlay()
+    {
+        let mut d = DebugDraw::new();
+        d.line(Vector3::new(0.0,0.0,0.0),Vector3::new(1.0,0.0,0.0),WHITE);
+        d.set_depth_test(false);
+        d.aabb(&Aabb::new(Vector3::new(0.0,0.0,0.0),Vector3::new(1.0,1.0,1.0)),WHITE);
+        assert_eq!(d.depth_tested().len(),2);
+        assert_eq!(d.overlay().len(),24);
+        d.clear();
+        assert!(d.depth_tested().is_empty() && d.overlay().is_empty());
+        assert!(!d.depth_test());
+    }
+}
@@ -0,0 +1,389 @@
+use std::rc::Rc;
+use std::cell::Cell;
+use wgpu::{Device, Queue, Buffer, BufferUsage, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType, ShaderStage, TextureSampleType, TextureViewDimension, RenderPipeline, RenderPass, SamplerDescriptor, AddressMode, FilterMode, LoadOp};
+use wgpu::util::{DeviceExt, BufferInitDescriptor};
+use cgmath::Vector4;
+use crate::bytes::AsBytes;
+use crate::texture::is_srgb;
+use crate::pipeline::{PipelineCache, PipelineDesc, ColorFormat, FULLSCREEN_TRIANGLE_WGSL, SRGB_DECODE_WGSL};
+use crate::render_graph::{RenderGraph, RenderNode, GraphResources, GraphBinding, AttachmentId, BindGroupId, PassDesc, Target};
+
+/// Gap in pixels between the picture in picture and the corner of the window.
+const PIP_MARGIN: u32 = 8;
+
+/// How the attachment of a `DebugView` source is turned into colors.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum DebugSource{
+    /// Depth of a perspective projection, shown as the distance between the camera planes.
+    LinearDepth{ near: f32, far: f32 },
+    /// Depth as stored, for orthographic projections like the shadow map.
+    Depth,
+    /// Colors as they are, albedo or any other color target.
+    Color,
+    /// Normals in -1..1, shown as `n * 0.5 + 0.5`.
+    Normals,
+}
+
+impl DebugSource {
+    /// Depth sources bind a depth attachment, the others a float one.
+    pub fn is_depth(&self) -> bool
+    {
+        matches!(self,DebugSource::LinearDepth{ .. } | DebugSource::Depth)
+    }
+
+    /// The `params` uniform of the debug shaders. `decode` undoes the encoding of an sRGB
+    /// swap chain, so data like depth shows its value rather than a brightened one.
+    pub fn params(&self,decode:bool) -> Vector4<f32>
+    {
+        let w = if decode && *self != DebugSource::Color { 1.0 } else { 0.0 };
+        match *self {
+            DebugSource::Color => Vector4::new(0.0,0.0,0.0,w),
+            DebugSource::Normals => Vector4::new(1.0,0.0,0.0,w),
+            DebugSource::Depth => Vector4::new(2.0,0.0,0.0,w),
+            DebugSource::LinearDepth{ near, far } => Vector4::new(3.0,near,far,w)
+        }
+    }
+}
+
+/// Where a `DebugView` shows its source on top of the frame.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum DebugLayout{
+    Off,
+    /// The whole source scaled to a third of the window in the bottom right corner.
+    PictureInPicture,
+    /// The right half of the source over the right half of the frame, pixel for pixel.
+    SplitScreen,
+}
+
+impl DebugLayout {
+    pub fn next(self) -> DebugLayout
+    {
+        match self {
+            DebugLayout::Off => DebugLayout::PictureInPicture,
+            DebugLayout::PictureInPicture => DebugLayout::SplitScreen,
+            DebugLayout::SplitScreen => DebugLayout::Off
+        }
+    }
+
+    pub fn name(&self) -> &'static str
+    {
+        match self {
+            DebugLayout::Off => "off",
+            DebugLayout::PictureInPicture => "picture in picture",
+            DebugLayout::SplitScreen => "split screen"
+        }
+    }
+
+    /// The viewport `[x, y, width, height]` the source is stretched over and the scissor
+    /// rect drawn into, for a `width` x `height` frame. None draws nothing.
+    pub fn rects(&self,width:u32,height:u32) -> Option<([f32;4],[u32;4])>
+    {
+        match self {
+            DebugLayout::Off => None,
+            DebugLayout::PictureInPicture => {
+                let (w,h) = ((width / 3).max(1),(height / 3).max(1));
+                let x = width.saturating_sub(w + PIP_MARGIN);
+                let y = height.saturating_sub(h + PIP_MARGIN);
+                Some(([x as f32,y as f32,w as f32,h as f32],[x,y,w,h]))
+            }
+            DebugLayout::SplitScreen => {
+                let half = width / 2;
+                Some(([0.0,0.0,width as f32,height as f32],[half,0,width - half,height]))
+            }
+        }
+    }
+}
+
+/// View space distance of `depth` written through `OPENGL_TO_WGPU_MATRIX` times a cgmath
+/// perspective with the planes `near` and `far`.
+pub fn linearize_depth(depth:f32,near:f32,far:f32) -> f32
+{
+    near * far / (far - depth * (far - near))
+}
+
+const DEBUG_COMMON_WGSL: &str = r#"
+[[group(0), binding(1)]]
+var src_sampler: sampler;
+
+[[block]]
+struct Params {
+    // x: 0 color, 1 normals, 2 depth, 3 linear depth; y, z: near and far; w: decode
+    v: vec4<f32>;
+};
+[[group(0), binding(2)]]
+var<uniform> params: Params;
+
+fn output(c: vec3<f32>) -> vec4<f32>
+{
+    if (params.v.w > 0.5) {
+        return vec4<f32>(srgb_to_linear(c), 1.0);
+    }
+    return vec4<f32>(c, 1.0);
+}
+"#;
+
+const DEBUG_DEPTH_WGSL: &str = r#"
+[[group(0), binding(0)]]
+var src: texture_depth_2d;
+
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    let d = textureSample(src, src_sampler, in.uv);
+    var v: f32 = d;
+    if (params.v.x > 2.5) {
+        let near = params.v.y;
+        let far = params.v.z;
+        let distance = near * far / (far - d * (far - near));
+        v = (distance - near) / (far - near);
+    }
+    return output(vec3<f32>(v, v, v));
+}
+"#;
+
+const DEBUG_COLOR_WGSL: &str = r#"
+[[group(0), binding(0)]]
+var src: texture_2d<f32>;
+
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    let c = textureSample(src, src_sampler, in.uv).rgb;
+    if (params.v.x > 0.5) {
+        return output(c * 0.5 + vec3<f32>(0.5, 0.5, 0.5));
+    }
+    return output(c);
+}
+"#;
+
+/// The debug shader of a depth or a color source.
+pub fn debug_wgsl(depth:bool) -> String
+{
+    format!("{}{}{}{}",FULLSCREEN_TRIANGLE_WGSL,SRGB_DECODE_WGSL,DEBUG_COMMON_WGSL,if depth { DEBUG_DEPTH_WGSL } else { DEBUG_COLOR_WGSL })
+}
+
+struct DebugState{
+    selected: Cell<usize>,
+    layout: Cell<DebugLayout>,
+}
+
+struct DebugViewSource{
+    name: String,
+    source: DebugSource,
+    pipeline: Rc<RenderPipeline>,
+    bind_group: BindGroupId,
+    params: Rc<Buffer>,
+}
+
+/// Draws the selected source with the current layout, the pass itself never changes.
+struct DebugViewNode{
+    state: Rc<DebugState>,
+    sources: Rc<Vec<DebugViewSource>>,
+}
+
+impl RenderNode for DebugViewNode {
+    fn record<'a>(&'a self,pass:&mut RenderPass<'a>,res:&'a GraphResources)
+    {
+        let (width,height) = res.size();
+        let (viewport,scissor) = match self.state.layout.get().rects(width,height) {
+            Some(r) => r,
+            None => return
+        };
+        let source = &self.sources[self.state.selected.get()];
+        pass.set_viewport(viewport[0],viewport[1],viewport[2],viewport[3],0.0,1.0);
+        pass.set_scissor_rect(scissor[0],scissor[1],scissor[2],scissor[3]);
+        pass.set_pipeline(&source.pipeline);
+        pass.set_bind_group(0,res.bind_group(source.bind_group),&[]);
+        pass.draw(0..3,0..1);
+    }
+}
+
+/// Shows one of several attachments of a `RenderGraph` on top of the swap chain, to look
+/// at intermediate targets like depth, normals, albedo or a shadow map. The source and
+/// the layout are switched at runtime through `&self`, without touching the other passes.
+pub struct DebugView{
+    state: Rc<DebugState>,
+    sources: Rc<Vec<DebugViewSource>>,
+    /// The swap chain encodes to sRGB.
+    decode: bool,
+}
+
+impl DebugView {
+    /// Adds a pass reading every source after the passes drawing the swap chain. The
+    /// sources have to be single sampled. Create it after `RenderGraph::set_sample_count`
+    /// and before `RenderGraph::build`, it starts with the first source and `Off`.
+    pub fn new(device:&Device,graph:&mut RenderGraph,pipelines:&mut PipelineCache,sources:Vec<(&str,AttachmentId,DebugSource)>) -> DebugView
+    {
+        assert!(!sources.is_empty(),"a debug view needs at least one source");
+        for (name,id,_) in sources.iter() {
+            assert!(!graph.resources().attachment(*id).multisampled,"debug view source \"{}\" is multisampled, show its resolve target",name);
+        }
+        let format = graph.resources().format();
+        let decode = is_srgb(format);
+        let sampler = Rc::new(device.create_sampler(&SamplerDescriptor{
+            label: Some("Debug View Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            ..Default::default()
+        }));
+        let layouts:Vec<_> = [false,true].iter().map(|&depth| Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some(if depth { "Debug View Depth Layout" } else { "Debug View Color Layout" }),
+            entries: Self::layout_entries(depth).as_slice()
+        }))).collect();
+        let debug_pipelines:Vec<_> = [false,true].iter().map(|&depth| {
+            let shader = pipelines.shader(device,if depth { "Debug View Depth" } else { "Debug View Color" },debug_wgsl(depth).as_str());
+            let pipeline_layouts = vec![pipelines.layout(&layouts[depth as usize])];
+            pipelines.get(device,&PipelineDesc{
+                color: Some(ColorFormat::SwapChain),
+                sample_count: graph.sample_count(),
+                ..PipelineDesc::new(shader,pipeline_layouts,Vec::new())
+            })
+        }).collect();
+
+        let mut reads = Vec::with_capacity(sources.len());
+        let sources:Vec<_> = sources.into_iter().map(|(name,attachment,source)| {
+            let depth = source.is_depth();
+            let params = Rc::new(device.create_buffer_init(&BufferInitDescriptor{
+                label: Some("Debug View Params"),
+                contents: source.params(decode).as_bytes(),
+                usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST
+            }));
+            let bind_group = graph.add_bind_group(format!("Debug View {} Bind Group",name).as_str(),layouts[depth as usize].clone(),vec![
+                (0,GraphBinding::Attachment(attachment)),
+                (1,GraphBinding::Sampler(sampler.clone())),
+                (2,GraphBinding::Buffer(params.clone()))
+            ]);
+            if !reads.contains(&attachment) {
+                reads.push(attachment);
+            }
+            DebugViewSource{ name: name.to_string(), source, pipeline: debug_pipelines[depth as usize].clone(), bind_group, params }
+        }).collect();
+
+        let state = Rc::new(DebugState{ selected: Cell::new(0), layout: Cell::new(DebugLayout::Off) });
+        let sources = Rc::new(sources);
+        graph.add_pass(PassDesc{
+            label: "Debug View".to_string(),
+            colors: vec![(Target::SwapChain,LoadOp::Load)],
+            depth: None,
+            reads
+        },Box::new(DebugViewNode{ state: state.clone(), sources: sources.clone() }));
+        DebugView{ state, sources, decode }
+    }
+
+    /// Group 0 of `debug_wgsl(depth)`: the source, a non-filtering sampler and the params.
+    pub fn layout_entries(depth:bool) -> Vec<BindGroupLayoutEntry>
+    {
+        vec![
+            BindGroupLayoutEntry{
+                binding: 0,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Texture{
+                    sample_type: if depth { TextureSampleType::Depth } else { TextureSampleType::Float{ filterable: false } },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false
+                },
+                count: None
+            },
+            BindGroupLayoutEntry{
+                binding: 1,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Sampler{ filtering: false, comparison: false },
+                count: None
+            },
+            BindGroupLayoutEntry{
+                binding: 2,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Buffer{
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None
+                },
+                count: None
+            }
+        ]
+    }
+
+    pub fn layout(&self) -> DebugLayout { self.state.layout.get() }
+    pub fn set_layout(&self,layout:DebugLayout) { self.state.layout.set(layout) }
+    pub fn next_layout(&self) { self.set_layout(self.layout().next()) }
+
+    pub fn selected(&self) -> usize { self.state.selected.get() }
+    pub fn selected_name(&self) -> &str { self.sources[self.selected()].name.as_str() }
+    pub fn source_count(&self) -> usize { self.sources.len() }
+
+    /// Selects source `i`, ignored when out of range.
+    pub fn select(&self,i:usize)
+    {
+        if i < self.sources.len() {
+            self.state.selected.set(i);
+        }
+    }
+
+    pub fn next_source(&self) { self.select((self.selected() + 1) % self.sources.len()) }
+
+    /// Changes how source `i` is shown, like new camera planes. Returns false and changes
+    /// nothing when `source` needs the other kind of attachment.
+    pub fn set_source(&self,queue:&Queue,i:usize,source:DebugSource) -> bool
+    {
+        match self.sources.get(i) {
+            Some(s) if s.source.is_depth() == source.is_depth() => {
+                queue.write_buffer(&s.params,0,source.params(self.decode).as_bytes());
+                true
+            }
+            _ => false
+        }
+    }
+}
+
+mod test_debug_view{
+    use crate::debug_view::{DebugLayout, DebugSource, linearize_depth};
+    use crate::camera::OPENGL_TO_WGPU_MATRIX;
+    use cgmath::{Vector4, Deg, perspective};
+
+    #[test]
+    fn linear_depth()
+    {
+        let (near,far) = (0.1,100.0);
+        let proj = OPENGL_TO_WGPU_MATRIX * perspective(Deg(60f32),1.5,near,far);
+        for &distance in [0.1f32,0.5,3.0,9.0,42.0,100.0].iter() {
+            let clip = proj * Vector4::new(0.0,0.0,-distance,1.0);
+            let depth = clip.z / clip.w;
+            assert!((linearize_depth(depth,near,far) - distance).abs() < distance * 1e-3,"{} {}",distance,depth);
+        }
+        assert!((linearize_depth(0.0,near,far) - near).abs() < 1e-6);
+        assert!((linearize_depth(1.0,near,far) - far).abs() < far * 1e-4);
+    }
+
+    #[test]
+    fn layouts()
+    {
+        assert_eq!(DebugLayout::Off.rects(800,600),None);
+        let (viewport,scissor) = DebugLayout::PictureInPicture.rects(800,600).unwrap();
+        assert_eq!(scissor,[800 - 266 - 8,600 - 200 - 8,266,200]);
+        assert_eq!(viewport,[526.0,392.0,266.0,200.0]);
+        let (viewport,scissor) = DebugLayout::SplitScreen.rects(801,600).unwrap();
+        assert_eq!(viewport,[0.0,0.0,801.0,600.0]);
+        assert_eq!(scissor,[400,0,401,600]);
+        // a tiny window keeps the rects inside it
+        let (_,scissor) = DebugLayout::PictureInPicture.rects(5,2).unwrap();
+        assert_eq!(scissor,[0,0,1,1]);
+        let mut layout = DebugLayout::Off;
+        for _ in 0..3 { layout = layout.next(); }
+        assert_eq!(layout,DebugLayout::Off);
+    }
+
+    #[test]
+    fn params()
+    {
+        let depth = DebugSource::LinearDepth{ near: 0.1, far: 100.0 };
+        assert!(depth.is_depth() && DebugSource::Depth.is_depth());
+        assert!(!DebugSource::Normals.is_depth());
+        assert_eq!(depth.params(false),Vector4::new(3.0,0.1,100.0,0.0));
+        assert_eq!(DebugSource::Normals.params(true).w,1.0);
+        // colors are colors, the swap chain may encode them
+        assert_eq!(DebugSource::Color.params(true).w,0.0);
+    }
+}
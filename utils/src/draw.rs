@@ -0,0 +1,277 @@
+use std::rc::Rc;
+use std::hash::Hash;
+use std::collections::HashMap;
+use wgpu::{Device, Queue, RenderPass, BufferUsage};
+use cgmath::{Matrix4, Vector3, Vector4, InnerSpace};
+use crate::mesh::GpuMesh;
+use crate::material::{Material, RenderQueue};
+use crate::buffer::GpuVec;
+use crate::bytes::AsBytes;
+use crate::vertex::VertexLayout;
+use crate::bounds::Frustum;
+
+/// Vertex buffer slot the instance data of a `DrawList` is bound to, the mesh is at slot 0.
+pub const INSTANCE_SLOT: u32 = 1;
+
+/// Per draw data of a `DrawList`, the world matrix at locations 5..=8 and the color at 9.
+/// Materials drawn through a `DrawList` need `[MeshVertex::layout(),ModelInstance::layout()]`
+/// as their vertex layouts, shaders that do not want the color just leave location 9 out.
+#[repr(C)]
+#[derive(Debug,Copy,Clone,PartialEq,VertexLayout,AsBytes)]
+#[step(instance)]
+pub struct ModelInstance{
+    #[location(5)]
+    pub model: Matrix4<f32>,
+    pub color: Vector4<f32>,
+}
+
+pub struct DrawCommand{
+    pub mesh: Rc<GpuMesh>,
+    pub material: Rc<Material>,
+    /// `None` draws the whole mesh.
+    pub submesh: Option<usize>,
+    pub world: Matrix4<f32>,
+    pub color: Vector4<f32>,
+}
+
+/// Commands drawn with one instanced call, `first..first + count` in the instance buffer.
+/// `command` is the first command of the batch, it provides the mesh and material.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Batch{
+    pub command: usize,
+    pub first: u32,
+    pub count: u32,
+}
+
+/// Groups the commands with equal keys into batches. `keys[i]` is the (material, mesh)
+/// key of command i. Batches of one material are kept next to each other, materials in
+/// the order they were first used and within a material the batches in the order they
+/// were first used; inside a batch commands keep their recorded order.
+/// Returns the command indices in instance order and the batches.
+pub fn build_batches<M:Eq + Hash + Copy,K:Eq + Hash + Copy>(keys:&[(M,K)]) -> (Vec<usize>,Vec<Batch>)
+{
+    let mut material_order:HashMap<M,usize> = HashMap::new();
+    let mut batch_of:HashMap<(M,K),usize> = HashMap::new();
+    // (material order, commands)
+    let mut groups:Vec<(usize,Vec<usize>)> = Vec::new();
+    for (i,key) in keys.iter().enumerate() {
+        let next = material_order.len();
+        let m = *material_order.entry(key.0).or_insert(next);
+        let next = groups.len();
+        let g = *batch_of.entry(*key).or_insert(next);
+        if g == groups.len() { groups.push((m,Vec::new())); }
+        groups[g].1.push(i);
+    }
+    // stable, so batches of a material stay in first use order
+    groups.sort_by_key(|g| g.0);
+
+    let mut order = Vec::with_capacity(keys.len());
+    let mut batches = Vec::with_capacity(groups.len());
+    for (_,cmds) in groups.into_iter() {
+        batches.push(Batch{ command: cmds[0], first: order.len() as u32, count: cmds.len() as u32 });
+        order.extend(cmds);
+    }
+    (order,batches)
+}
+
+/// Orders the commands by render queue and batches them. `items[i]` is the queue,
+/// the distance to the camera and the (material, mesh) key of command i.
+/// Opaque and alpha tested commands are batched like `build_batches` after sorting them
+/// front to back, so a material is drawn first when it has the nearest object.
+/// Transparent commands are sorted back to front and only neighbours with equal keys
+/// share a batch, so the blending order stays correct.
+/// Returns the command indices in instance order and the batches.
+pub fn plan_draws<M:Eq + Hash + Copy,K:Eq + Hash + Copy>(items:&[(RenderQueue,f32,(M,K))]) -> (Vec<usize>,Vec<Batch>)
+{
+    let mut sorted:Vec<usize> = (0..items.len()).collect();
+    sorted.sort_by(|&a,&b|{
+        let (qa,da,_) = items[a];
+        let (qb,db,_) = items[b];
+        let dist = if qa == RenderQueue::Transparent { db.partial_cmp(&da) } else { da.partial_cmp(&db) };
+        qa.cmp(&qb).then(dist.unwrap_or(std::cmp::Ordering::Equal))
+    });
+
+    let mut order = Vec::with_capacity(items.len());
+    let mut batches:Vec<Batch> = Vec::new();
+    let mut start = 0;
+    while start < sorted.len() {
+        let queue = items[sorted[start]].0;
+        let end = start + sorted[start..].iter().take_while(|&&i| items[i].0 == queue).count();
+        let part = &sorted[start..end];
+        if queue == RenderQueue::Transparent {
+            for &i in part.iter() {
+                match batches.last_mut() {
+                    Some(b) if items[b.command].0 == queue && items[b.command].2 == items[i].2 => b.count += 1,
+                    _ => batches.push(Batch{ command: i, first: order.len() as u32, count: 1 })
+                }
+                order.push(i);
+            }
+        }else{
+            let keys:Vec<_> = part.iter().map(|&i| items[i].2).collect();
+            let (part_order,part_batches) = build_batches(keys.as_slice());
+            let base = order.len() as u32;
+            batches.extend(part_batches.into_iter().map(|b| Batch{ command: part[b.command], first: base + b.first, count: b.count }));
+            order.extend(part_order.into_iter().map(|i| part[i]));
+        }
+        start = end;
+    }
+    (order,batches)
+}
+
+/// Draw calls recorded by components in `Component::render`, replayed into a render pass
+/// by whoever owns the pass. Commands sharing a mesh, submesh and material are drawn
+/// as one instanced call, in the order of their materials' render queues, see `plan_draws`.
+pub struct DrawList{
+    commands: Vec<DrawCommand>,
+    batches: Vec<Batch>,
+    instances: GpuVec<ModelInstance>,
+}
+
+impl DrawList {
+    pub fn new(device:&Device) -> DrawList
+    {
+        DrawList{
+            commands: Vec::new(),
+            batches: Vec::new(),
+            instances: GpuVec::with_capacity(device,Some("Draw List Instances"),BufferUsage::VERTEX,64)
+        }
+    }
+
+    pub fn push(&mut self,cmd:DrawCommand)
+    {
+        self.commands.push(cmd);
+    }
+
+    /// Forget the commands of the last frame.
+    pub fn clear(&mut self)
+    {
+        self.commands.clear();
+        self.batches.clear();
+    }
+
+    pub fn commands(&self) -> &[DrawCommand] { self.commands.as_slice() }
+    pub fn len(&self) -> usize { self.commands.len() }
+    pub fn is_empty(&self) -> bool { self.commands.is_empty() }
+
+    /// Drops the commands whose mesh bounds, moved by their world matrix, are outside
+    /// `frustum`. Meshes without bounds are kept. Returns the number of dropped commands.
+    pub fn cull(&mut self,frustum:&Frustum) -> usize
+    {
+        let len = self.commands.len();
+        self.commands.retain(|c|{
+            c.mesh.aabb().map_or(true,|b| frustum.intersects_aabb(&b.transform(&c.world)))
+        });
+        len - self.commands.len()
+    }
+
+    /// The batches of the last `prepare`.
+    pub fn batches(&self) -> &[Batch] { self.batches.as_slice() }
+
+    /// Sorts and batches the commands for a camera at `eye` and uploads their instance
+    /// data, call it after recording and before `execute`.
+    pub fn prepare(&mut self,device:&Device,queue:&Queue,eye:Vector3<f32>)
+    {
+        let items:Vec<_> = self.commands.iter().map(|c|{
+            let center = match c.mesh.aabb() {
+                Some(b) => (c.world * b.center().extend(1.0)).truncate(),
+                None => c.world.w.truncate()
+            };
+            (c.material.desc.render_queue(),(center - eye).magnitude2(),(Rc::as_ptr(&c.material),(Rc::as_ptr(&c.mesh),c.submesh)))
+        }).collect();
+        let (order,batches) = plan_draws(items.as_slice());
+        self.batches = batches;
+        self.instances.clear();
+        for i in order.into_iter() {
+            let c = &self.commands[i];
+            self.instances.push(ModelInstance{ model: c.world, color: c.color });
+        }
+        self.instances.sync(device,queue);
+    }
+
+    /// Draws every batch, binding materials and meshes only when they change.
+    /// Bind groups shared by the materials (camera, lights...) are left to the caller.
+    pub fn execute<'a>(&'a self,pass:&mut RenderPass<'a>)
+    {
+        if self.batches.is_empty() { return; }
+        pass.set_vertex_buffer(INSTANCE_SLOT,self.instances.slice(..));
+        let mut last:Option<&DrawCommand> = None;
+        for b in self.batches.iter() {
+            let c = &self.commands[b.command];
+            if last.map_or(true,|l| !Rc::ptr_eq(&l.material,&c.material)) {
+                c.material.bind(pass);
+            }
+            if last.map_or(true,|l| !Rc::ptr_eq(&l.mesh,&c.mesh)) {
+                c.mesh.bind(pass,0);
+            }
+            let instances = b.first..b.first + b.count;
+            match c.submesh {
+                Some(s) => c.mesh.draw_submesh(pass,s,instances),
+                None => c.mesh.draw(pass,instances)
+            }
+            last = Some(c);
+        }
+    }
+}
+
+mod test_draw{
+    use crate::draw::{build_batches, plan_draws, Batch};
+    use crate::material::RenderQueue;
+
+    #[test]
+    fn batches()
+    {
+        // (material, mesh)
+        let keys = [(0,'a'),(1,'a'),(0,'b'),(0,'a'),(1,'a'),(2,'c'),(0,'b'),(0,'a')];
+        let (order,batches) = build_batches(&keys);
+        assert_eq!(batches,vec![
+            Batch{ command: 0, first: 0, count: 3 },
+            Batch{ command: 2, first: 3, count: 2 },
+            Batch{ command: 1, first: 5, count: 2 },
+            Batch{ command: 5, first: 7, count: 1 },
+        ]);
+        assert_eq!(order,vec![0,3,7,2,6,1,4,5]);
+        for b in batches.iter() {
+            let r = b.first as usize..(b.first + b.count) as usize;
+            assert!(order[r].iter().all(|&i| keys[i] == keys[b.command]));
+        }
+    }
+
+    #[test]
+    fn queues()
+    {
+        use RenderQueue::*;
+        let items = [
+            (Transparent,4.0,(0,'a')),
+            (Opaque,9.0,(1,'a')),
+            (Transparent,9.0,(0,'a')),
+            (Opaque,1.0,(2,'a')),
+            (AlphaTest,2.0,(3,'b')),
+            (Transparent,1.0,(0,'a')),
+            (Opaque,4.0,(1,'a')),
+            (Transparent,6.0,(4,'a')),
+        ];
+        let (order,batches) = plan_draws(&items);
+        // opaque front to back, material 1 batched, then alpha test,
+        // then transparent back to front without merging across command 7
+        assert_eq!(order,vec![3,6,1,4,2,7,0,5]);
+        assert_eq!(batches,vec![
+            Batch{ command: 3, first: 0, count: 1 },
+            Batch{ command: 6, first: 1, count: 2 },
+            Batch{ command: 4, first: 3, count: 1 },
+            Batch{ command: 2, first: 4, count: 1 },
+            Batch{ command: 7, first: 5, count: 1 },
+            Batch{ command: 0, first: 6, count: 2 },
+        ]);
+    }
+
+    #[test]
+    fn empty()
+    {
+        let (order,batches) = build_batches::<u32,u32>(&[]);
+        assert!(order.is_empty());
+        assert!(batches.is_empty());
+        let (order,batches) = plan_draws::<u32,u32>(&[]);
+        assert!(order.is_empty());
+        assert!(batches.is_empty());
+    }
+}
@@ -2,7 +2,30 @@ pub mod component;
 pub mod object;
 pub mod components;
 pub mod resource_manager;
-use std::mem::size_of;
+pub mod texture;
+pub mod material;
+pub mod mesh;
+pub mod vertex;
+pub mod reflect;
+pub mod uniform;
+pub mod bytes;
+pub mod buffer;
+pub mod msaa;
+pub mod render_graph;
+pub mod pipeline;
+pub mod shadow;
+pub mod lighting;
+pub mod draw;
+pub mod bounds;
+pub mod debug_draw;
+pub mod overlay;
+pub mod atlas;
+pub mod sprite;
+pub mod cubemap;
+pub mod skybox;
+pub mod post;
+pub mod debug_view;
+pub mod camera;
 use std::any::Any;
 
 pub trait AsAny{
@@ -10,34 +33,3 @@ pub trait AsAny{
     fn as_mut_any(&mut self) -> &mut dyn Any;
     fn into_any(self:Box<Self>) -> Box<dyn Any>;
 }
-
-#[repr(C)]
-pub struct FatPtr
-{
-    ptr: *const u8,
-    len: usize
-}
-
-impl FatPtr{
-    pub fn new<T>(t:&T) -> FatPtr
-    {
-        FatPtr{
-            ptr : unsafe { std::mem::transmute::<*const T, *const u8>(t as *const T) },
-            len : std::mem::size_of::<T>()
-        }
-    }
-    pub unsafe fn as_ref_arr(&self) -> &[u8]
-    {
-        let ptr  = std::mem::transmute::<*const FatPtr,*const u128>(self as *const FatPtr);
-        std::mem::transmute::<u128,&[u8]>(*ptr)
-    }
-}
-
-pub unsafe fn from_raw_parts<T>(t:&T) ->&[u8]
-{
-    std::slice::from_raw_parts(std::mem::transmute::<_, *const u8>(t), size_of::<T>())
-}
-pub unsafe fn from_raw_parts_ex<T>(arr:&[T]) ->&[u8]
-{
-    std::slice::from_raw_parts(std::mem::transmute::<_, *const u8>(&arr[0]), size_of::<T>() * arr.len())
-}
\ No newline at end of file
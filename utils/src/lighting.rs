@@ -0,0 +1,369 @@
+use std::rc::Rc;
+use std::cell::Cell;
+use cgmath::{Matrix4, Vector2, Vector3, InnerSpace, SquareMatrix, Angle};
+use wgpu::{Device, Queue, Buffer, BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindGroupDescriptor, BindGroupEntry, BindingType, BufferBindingType, BufferDescriptor, BufferUsage, ShaderStage};
+use crate::uniform::{UniformLayout, AddressSpace, array_stride};
+use crate::object::Object;
+use crate::component::Component;
+use crate::components::{Transform, DirectionalLight, PointLight, SpotLight};
+use crate::AsAny;
+
+pub const LIGHT_DIRECTIONAL: u32 = 0;
+pub const LIGHT_POINT: u32 = 1;
+pub const LIGHT_SPOT: u32 = 2;
+
+/// One light of the storage buffer, in world space.
+#[derive(Debug,Clone,Copy,PartialEq,UniformLayout)]
+pub struct LightRaw{
+    pub position: Vector3<f32>,
+    pub kind: u32,
+    /// The direction the light travels in, normalized.
+    pub direction: Vector3<f32>,
+    pub range: f32,
+    /// Color times intensity.
+    pub color: Vector3<f32>,
+    /// Cosine of the inner and the outer cone angle of a spot light.
+    pub cone: Vector2<f32>,
+}
+
+impl LightRaw {
+    pub fn directional(light:&DirectionalLight,world:&Matrix4<f32>) -> LightRaw
+    {
+        LightRaw{
+            position: Vector3::new(0f32,0f32,0f32),
+            kind: LIGHT_DIRECTIONAL,
+            direction: (world * light.direction.extend(0f32)).truncate().normalize(),
+            range: 0f32,
+            color: light.color * light.intensity,
+            cone: Vector2::new(-1f32,-1f32)
+        }
+    }
+
+    pub fn point(light:&PointLight,world:&Matrix4<f32>) -> LightRaw
+    {
+        LightRaw{
+            position: (world * light.position.extend(1f32)).truncate(),
+            kind: LIGHT_POINT,
+            direction: Vector3::new(0f32,-1f32,0f32),
+            range: light.range,
+            color: light.color * light.intensity,
+            cone: Vector2::new(-1f32,-1f32)
+        }
+    }
+
+    pub fn spot(light:&SpotLight,world:&Matrix4<f32>) -> LightRaw
+    {
+        LightRaw{
+            position: (world * light.position.extend(1f32)).truncate(),
+            kind: LIGHT_SPOT,
+            direction: (world * light.direction.extend(0f32)).truncate().normalize(),
+            range: light.range,
+            color: light.color * light.intensity,
+            cone: Vector2::new(light.inner_angle.cos(),light.outer_angle.cos())
+        }
+    }
+}
+
+fn comp<T:Component + 'static>(obj:&Object) -> Option<&T>
+{
+    obj.get_comp::<T>().and_then(|c| c.as_any().downcast_ref::<T>())
+}
+
+/// The lights of `objects` in world space, an object can carry one light of each kind.
+pub fn collect_lights<'a,I:IntoIterator<Item=&'a Object>>(objects:I) -> Vec<LightRaw>
+{
+    let mut res = Vec::new();
+    for obj in objects {
+        let world = comp::<Transform>(obj).map(|t| t.get_world_matrix()).unwrap_or_else(Matrix4::identity);
+        if let Some(l) = comp::<DirectionalLight>(obj) {
+            res.push(LightRaw::directional(l,&world));
+        }
+        if let Some(l) = comp::<PointLight>(obj) {
+            res.push(LightRaw::point(l,&world));
+        }
+        if let Some(l) = comp::<SpotLight>(obj) {
+            res.push(LightRaw::spot(l,&world));
+        }
+    }
+    res
+}
+
+/// Byte offset of the light array in the `Lights` struct, after the count.
+pub fn lights_offset() -> usize
+{
+    AddressSpace::round_up(LightRaw::align(AddressSpace::Storage),u32::size(AddressSpace::Storage)) as usize
+}
+
+/// Bytes of the `Lights` storage struct of `lighting_wgsl`. There is always room for one
+/// light, a runtime sized array can not be empty.
+pub fn pack_lights(lights:&[LightRaw]) -> Vec<u8>
+{
+    let stride = array_stride::<LightRaw>(AddressSpace::Storage) as usize;
+    let mut res = vec![0u8;lights_offset() + stride * lights.len().max(1)];
+    (lights.len() as u32).write(AddressSpace::Storage,res.as_mut_slice());
+    for (i,l) in lights.iter().enumerate() {
+        l.write(AddressSpace::Storage,&mut res[lights_offset() + i * stride..]);
+    }
+    res
+}
+
+/// The lights of a frame in a storage buffer, bound the way `lighting_wgsl` declares it.
+/// The buffer grows when more lights are written, the bind group is recreated with it.
+pub struct LightBuffer{
+    layout: Rc<BindGroupLayout>,
+    buffer: Buffer,
+    bind_group: BindGroup,
+    capacity: usize,
+    len: Cell<usize>,
+}
+
+impl LightBuffer {
+    pub fn new(device:&Device,capacity:usize) -> LightBuffer
+    {
+        let layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Light Bind Group Layout"),
+            entries: Self::layout_entries().as_slice()
+        }));
+        let capacity = capacity.max(1);
+        let (buffer,bind_group) = Self::create(device,&layout,capacity);
+        LightBuffer{ layout, buffer, bind_group, capacity, len: Cell::new(0) }
+    }
+
+    pub fn layout_entries() -> Vec<BindGroupLayoutEntry>
+    {
+        vec![
+            BindGroupLayoutEntry{
+                binding: 0,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Buffer{
+                    ty: BufferBindingType::Storage{ read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None
+                },
+                count: None
+            }
+        ]
+    }
+
+    fn create(device:&Device,layout:&BindGroupLayout,capacity:usize) -> (Buffer,BindGroup)
+    {
+        let buffer = device.create_buffer(&BufferDescriptor{
+            label: Some("Light Buffer"),
+            size: (lights_offset() + array_stride::<LightRaw>(AddressSpace::Storage) as usize * capacity) as u64,
+            usage: BufferUsage::STORAGE | BufferUsage::COPY_DST,
+            mapped_at_creation: false
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Light Bind Group"),
+            layout,
+            entries: &[
+                BindGroupEntry{ binding: 0, resource: buffer.as_entire_binding() }
+            ]
+        });
+        (buffer,bind_group)
+    }
+
+    pub fn write(&mut self,device:&Device,queue:&Queue,lights:&[LightRaw])
+    {
+        if lights.len() > self.capacity {
+            while self.capacity < lights.len() {
+                self.capacity *= 2;
+            }
+            let (buffer,bind_group) = Self::create(device,&self.layout,self.capacity);
+            self.buffer = buffer;
+            self.bind_group = bind_group;
+        }
+        self.len.set(lights.len());
+        queue.write_buffer(&self.buffer,0,pack_lights(lights).as_slice());
+    }
+
+    /// `write` for a buffer shared with a graph node, which keeps its bind group. Returns
+    /// false and writes nothing when `lights` do not fit into the capacity.
+    #[must_use]
+    pub fn update(&self,queue:&Queue,lights:&[LightRaw]) -> bool
+    {
+        if lights.len() > self.capacity { return false; }
+        self.len.set(lights.len());
+        queue.write_buffer(&self.buffer,0,pack_lights(lights).as_slice());
+        true
+    }
+
+    pub fn layout(&self) -> &Rc<BindGroupLayout> { &self.layout }
+    pub fn bind_group(&self) -> &BindGroup { &self.bind_group }
+    pub fn capacity(&self) -> usize { self.capacity }
+    pub fn len(&self) -> usize { self.len.get() }
+    pub fn is_empty(&self) -> bool { self.len.get() == 0 }
+}
+
+/// WGSL of the light buffer bound at `group` and the functions summing up every light:
+/// `blinn_phong(n, v, world_pos, albedo, specular, shininess)` and
+/// `pbr(n, v, world_pos, albedo, metallic, roughness)`, the metallic-roughness model.
+/// `n` and `v` (towards the camera) have to be normalized.
+pub fn lighting_wgsl(group:u32) -> String
+{
+    let mut decls = Vec::new();
+    LightRaw::declare(AddressSpace::Storage,&mut decls);
+    format!(r#"{decls}
+
+[[block]]
+struct Lights {{
+    count: u32;
+    lights: array<LightRaw>;
+}};
+
+[[group({group}), binding(0)]]
+var<storage> lights: [[access(read)]] Lights;
+
+// direction towards the light in xyz, attenuation in w
+fn light_incoming(light: LightRaw, world_pos: vec3<f32>) -> vec4<f32>
+{{
+    if (light.kind == {directional}u) {{
+        return vec4<f32>(-light.direction, 1.0);
+    }}
+    let d = light.position - world_pos;
+    let dist = length(d);
+    let l = d / dist;
+    // inverse square, smoothly cut off at the range
+    let window = clamp(1.0 - pow(dist / light.range, 4.0), 0.0, 1.0);
+    var atten: f32 = window * window / (dist * dist + 1.0);
+    if (light.kind == {spot}u) {{
+        let t = clamp((dot(-l, light.direction) - light.cone.y) / max(light.cone.x - light.cone.y, 0.0001), 0.0, 1.0);
+        atten = atten * t * t * (3.0 - 2.0 * t);
+    }}
+    return vec4<f32>(l, atten);
+}}
+
+fn blinn_phong(n: vec3<f32>, v: vec3<f32>, world_pos: vec3<f32>, albedo: vec3<f32>, specular: f32, shininess: f32) -> vec3<f32>
+{{
+    var color: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
+    var i: u32 = 0u;
+    loop {{
+        if (i >= lights.count) {{ break; }}
+        let light = lights.lights[i];
+        let li = light_incoming(light, world_pos);
+        let diffuse = max(dot(n, li.xyz), 0.0);
+        let h = normalize(li.xyz + v);
+        let spec = select(0.0, pow(max(dot(n, h), 0.0), shininess) * specular, diffuse > 0.0);
+        color = color + (albedo * diffuse + vec3<f32>(spec, spec, spec)) * light.color * li.w;
+        i = i + 1u;
+    }}
+    return color;
+}}
+
+fn pbr(n: vec3<f32>, v: vec3<f32>, world_pos: vec3<f32>, albedo: vec3<f32>, metallic: f32, roughness: f32) -> vec3<f32>
+{{
+    let pi = 3.14159265;
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let f0 = mix(vec3<f32>(0.04, 0.04, 0.04), albedo, vec3<f32>(metallic, metallic, metallic));
+    let n_v = max(dot(n, v), 0.0001);
+    var color: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
+    var i: u32 = 0u;
+    loop {{
+        if (i >= lights.count) {{ break; }}
+        let light = lights.lights[i];
+        let li = light_incoming(light, world_pos);
+        let h = normalize(li.xyz + v);
+        let n_l = max(dot(n, li.xyz), 0.0);
+        let n_h = max(dot(n, h), 0.0);
+        let v_h = max(dot(v, h), 0.0);
+        // GGX distribution, Smith-Schlick geometry and Schlick fresnel
+        let dd = n_h * n_h * (a2 - 1.0) + 1.0;
+        let ndf = a2 / (pi * dd * dd);
+        let g = (n_v / (n_v * (1.0 - k) + k)) * (n_l / (n_l * (1.0 - k) + k));
+        let f = f0 + (vec3<f32>(1.0, 1.0, 1.0) - f0) * pow(1.0 - v_h, 5.0);
+        let spec = f * (ndf * g / (4.0 * n_v * max(n_l, 0.0001)));
+        let kd = (vec3<f32>(1.0, 1.0, 1.0) - f) * (1.0 - metallic);
+        color = color + (kd * albedo / pi + spec) * light.color * (li.w * n_l);
+        i = i + 1u;
+    }}
+    return color;
+}}
+"#,decls = decls.join("\n\n"),group = group,directional = LIGHT_DIRECTIONAL,spot = LIGHT_SPOT)
+}
+
+mod test_lighting{
+    use crate::lighting::{LightRaw, collect_lights, pack_lights, lights_offset, LIGHT_DIRECTIONAL, LIGHT_POINT, LIGHT_SPOT};
+    use crate::uniform::{UniformLayout, AddressSpace};
+    use crate::object::Object;
+    use crate::components::{Transform, DirectionalLight, PointLight, SpotLight};
+    use cgmath::{Vector3, Deg, Angle};
+
+    fn f32_at(b:&[u8],offset:usize) -> f32
+    {
+        let mut v = [0u8;4];
+        v.copy_from_slice(&b[offset..offset + 4]);
+        f32::from_le_bytes(v)
+    }
+
+    fn u32_at(b:&[u8],offset:usize) -> u32
+    {
+        let mut v = [0u8;4];
+        v.copy_from_slice(&b[offset..offset + 4]);
+        u32::from_le_bytes(v)
+    }
+
+    #[test]
+    fn layout()
+    {
+        // kind and range fill the tails of the vec3s
+        assert_eq!(LightRaw::size(AddressSpace::Storage),64);
+        assert_eq!(lights_offset(),16);
+        assert!(LightRaw::wgsl_struct(AddressSpace::Storage).contains("cone: vec2<f32>;"));
+    }
+
+    #[test]
+    fn collect()
+    {
+        let mut sun = Object::new();
+        sun.as_mut().pin_get().add_comp(Box::new(DirectionalLight::new(Vector3::new(0.0,-2.0,0.0))));
+
+        let mut lamp = Object::new();
+        let mut t = Transform::new();
+        t.position = Vector3::new(1.0,2.0,3.0);
+        lamp.as_mut().pin_get().add_comp(Box::new(t));
+        let mut point = PointLight::new(Vector3::new(0.0,1.0,0.0),10.0);
+        point.intensity = 2.0;
+        lamp.as_mut().pin_get().add_comp(Box::new(point));
+        let mut spot = SpotLight::new(Vector3::new(0.0,0.0,0.0),Vector3::new(0.0,0.0,-1.0),5.0);
+        spot.outer_angle = Deg(60f32).into();
+        lamp.as_mut().pin_get().add_comp(Box::new(spot));
+
+        let lights = collect_lights(vec![&*sun,&*lamp]);
+        assert_eq!(lights.len(),3);
+        assert_eq!(lights[0].kind,LIGHT_DIRECTIONAL);
+        assert_eq!(lights[0].direction,Vector3::new(0.0,-1.0,0.0));
+        assert_eq!(lights[1].kind,LIGHT_POINT);
+        // moved by the transform of its object
+        assert_eq!(lights[1].position,Vector3::new(1.0,3.0,3.0));
+        assert_eq!(lights[1].color,Vector3::new(2.0,2.0,2.0));
+        assert_eq!(lights[2].kind,LIGHT_SPOT);
+        assert_eq!(lights[2].position,Vector3::new(1.0,2.0,3.0));
+        assert_eq!(lights[2].cone.x,Deg(20f32).cos());
+        assert!((lights[2].cone.y - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pack()
+    {
+        let mut sun = Object::new();
+        sun.as_mut().pin_get().add_comp(Box::new(DirectionalLight::new(Vector3::new(0.0,0.0,-1.0))));
+        let mut lamp = Object::new();
+        lamp.as_mut().pin_get().add_comp(Box::new(PointLight::new(Vector3::new(4.0,5.0,6.0),7.0)));
+        let b = pack_lights(collect_lights(vec![&*sun,&*lamp]).as_slice());
+        assert_eq!(b.len(),16 + 2 * 64);
+        assert_eq!(u32_at(&b,0),2);
+        assert_eq!(u32_at(&b,16 + 12),LIGHT_DIRECTIONAL);
+        assert_eq!(f32_at(&b,16 + 24),-1.0);
+        let point = 16 + 64;
+        assert_eq!([f32_at(&b,point),f32_at(&b,point + 4),f32_at(&b,point + 8)],[4.0,5.0,6.0]);
+        assert_eq!(u32_at(&b,point + 12),LIGHT_POINT);
+        assert_eq!(f32_at(&b,point + 28),7.0);
+        assert_eq!(f32_at(&b,point + 32),1.0);
+        // an empty buffer still holds one light
+        let empty = pack_lights(&[]);
+        assert_eq!(empty.len(),16 + 64);
+        assert_eq!(u32_at(&empty,0),0);
+    }
+}
@@ -0,0 +1,546 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::any::Any;
+use std::path::Path;
+use std::fmt::{Display, Formatter};
+use serde::Deserialize;
+use gen_code::{gen_impl_res_process_cache,AsAny};
+use wgpu::{Device, Queue, RenderPipeline, BindGroupLayout, BindGroup, Buffer, TextureFormat, BufferAddress, RenderPass, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStage, BindingType, TextureSampleType, TextureViewDimension, BindGroupDescriptor, BindGroupEntry, BindingResource, BufferBindingType, BufferUsage, BlendState, BlendComponent, BlendFactor, BlendOperation, PrimitiveTopology, Face, CompareFunction};
+use wgpu::util::{DeviceExt, BufferInitDescriptor};
+use crate::AsAny;
+use crate::resource_manager::{ResProcesser, ResourceMgr, TextRes, CacheKey};
+use crate::resource_manager::graph::ResKey;
+use crate::texture::{GpuTexture, ImageRes, GpuTextureRes, TextureParam};
+use crate::reflect::{check_vertex_layouts, ReflectError};
+use crate::pipeline::{PipelineCache, PipelineDesc, ColorFormat, DepthState};
+pub use crate::texture::{SamplerDesc, AddressModeDesc, FilterDesc};
+pub use crate::vertex::VertexLayoutDesc;
+
+/// Material file, written in RON:
+/// ```ron
+/// (
+///     shader: "shader.wgsl",
+///     textures: [ (path: "../textures/happy-tree.png") ],
+///     sampler: ( mag_filter: Linear, min_filter: Nearest ),
+///     blend: Alpha,
+///     queue: Some(Transparent),
+///     cull: Back,
+///     depth: Some(( write: true, compare: Less )),
+///     params: [ ( name: "tint", value: (1.0, 1.0, 1.0, 1.0) ) ],
+/// )
+/// ```
+/// Paths are relative to the material file.
+#[derive(Debug,Clone,Deserialize)]
+pub struct MaterialDesc{
+    pub shader: String,
+    #[serde(default = "default_entry")]
+    pub vs_entry: String,
+    #[serde(default = "default_entry")]
+    pub fs_entry: String,
+    #[serde(default)]
+    pub textures: Vec<TextureSlot>,
+    #[serde(default)]
+    pub sampler: SamplerDesc,
+    #[serde(default)]
+    pub blend: BlendMode,
+    /// Defaults to `Opaque` for `Replace` and `Transparent` for the other blend modes.
+    #[serde(default)]
+    pub queue: Option<RenderQueue>,
+    #[serde(default)]
+    pub cull: CullMode,
+    #[serde(default)]
+    pub depth: Option<DepthDesc>,
+    #[serde(default)]
+    pub params: Vec<MaterialParam>,
+}
+
+impl MaterialDesc {
+    pub fn render_queue(&self) -> RenderQueue
+    {
+        self.queue.unwrap_or(match self.blend {
+            BlendMode::Replace => RenderQueue::Opaque,
+            _ => RenderQueue::Transparent
+        })
+    }
+}
+
+fn default_entry() -> String { "main".to_string() }
+fn default_true() -> bool { true }
+
+#[derive(Debug,Clone,Deserialize)]
+pub struct TextureSlot{
+    pub path: String,
+    #[serde(default = "default_true")]
+    pub srgb: bool,
+}
+
+/// `Premultiplied` expects the shader to output color already multiplied by alpha,
+/// `Multiply` darkens what is behind by the output color.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,Deserialize)]
+pub enum BlendMode{ Replace, Alpha, Premultiplied, Additive, Multiply }
+
+impl Default for BlendMode {
+    fn default() -> Self { BlendMode::Replace }
+}
+
+impl BlendMode {
+    pub fn to_wgpu(&self) -> BlendState
+    {
+        let comp = |src_factor,dst_factor| BlendComponent{ src_factor, dst_factor, operation: BlendOperation::Add };
+        match self {
+            BlendMode::Replace => BlendState::REPLACE,
+            BlendMode::Alpha => BlendState{
+                color: comp(BlendFactor::SrcAlpha,BlendFactor::OneMinusSrcAlpha),
+                alpha: comp(BlendFactor::One,BlendFactor::OneMinusSrcAlpha)
+            },
+            BlendMode::Premultiplied => BlendState{
+                color: comp(BlendFactor::One,BlendFactor::OneMinusSrcAlpha),
+                alpha: comp(BlendFactor::One,BlendFactor::OneMinusSrcAlpha)
+            },
+            BlendMode::Additive => BlendState{
+                color: comp(BlendFactor::SrcAlpha,BlendFactor::One),
+                alpha: comp(BlendFactor::Zero,BlendFactor::One)
+            },
+            BlendMode::Multiply => BlendState{
+                color: comp(BlendFactor::Dst,BlendFactor::Zero),
+                alpha: comp(BlendFactor::Zero,BlendFactor::One)
+            }
+        }
+    }
+}
+
+/// The order `DrawList` draws in: opaque front to back, then alpha tested (shaders
+/// discard by alpha, with alpha to coverage under MSAA), then transparent back to front.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Hash,Deserialize)]
+pub enum RenderQueue{ Opaque, AlphaTest, Transparent }
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,Deserialize)]
+pub enum CullMode{ None, Front, Back }
+
+impl Default for CullMode {
+    fn default() -> Self { CullMode::None }
+}
+
+impl CullMode {
+    pub fn to_wgpu(&self) -> Option<Face>
+    {
+        match self {
+            CullMode::None => None,
+            CullMode::Front => Some(Face::Front),
+            CullMode::Back => Some(Face::Back)
+        }
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,Deserialize)]
+pub enum CompareDesc{ Never, Less, Equal, LessEqual, Greater, NotEqual, GreaterEqual, Always }
+
+impl Default for CompareDesc {
+    fn default() -> Self { CompareDesc::Less }
+}
+
+impl CompareDesc {
+    pub fn to_wgpu(&self) -> CompareFunction
+    {
+        match self {
+            CompareDesc::Never => CompareFunction::Never,
+            CompareDesc::Less => CompareFunction::Less,
+            CompareDesc::Equal => CompareFunction::Equal,
+            CompareDesc::LessEqual => CompareFunction::LessEqual,
+            CompareDesc::Greater => CompareFunction::Greater,
+            CompareDesc::NotEqual => CompareFunction::NotEqual,
+            CompareDesc::GreaterEqual => CompareFunction::GreaterEqual,
+            CompareDesc::Always => CompareFunction::Always
+        }
+    }
+}
+
+#[derive(Debug,Clone,Deserialize)]
+pub struct DepthDesc{
+    #[serde(default = "default_true")]
+    pub write: bool,
+    #[serde(default)]
+    pub compare: CompareDesc,
+}
+
+/// Every parameter takes one `vec4<f32>` slot of the parameter uniform, in declaration order.
+#[derive(Debug,Clone,Deserialize)]
+pub struct MaterialParam{
+    pub name: String,
+    pub value: (f32,f32,f32,f32),
+}
+
+/// Why `load_material` returned no material.
+#[derive(Debug,Clone,PartialEq)]
+pub enum MaterialError{
+    /// A file could not be read or processed, or a processor is not registered.
+    Load(String),
+    /// The material file is not a `MaterialDesc`.
+    Parse{ path: String, error: String },
+    /// The shader does not take the vertex layouts of the `MaterialEnv`.
+    Shader{ path: String, error: ReflectError },
+}
+
+impl Display for MaterialError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaterialError::Load(p) => write!(f,"could not load {}",p),
+            MaterialError::Parse{ path, error } => write!(f,"bad material {}: {}",path,error),
+            MaterialError::Shader{ path, error } => write!(f,"bad material shader {}: {}",path,error),
+        }
+    }
+}
+
+impl std::error::Error for MaterialError {}
+
+#[derive(AsAny)]
+pub struct MaterialDescRes{
+    cache: HashMap<CacheKey<()>,Rc<MaterialDesc>>,
+    last_error: RefCell<Option<MaterialError>>,
+}
+
+impl MaterialDescRes {
+    pub fn new() -> MaterialDescRes
+    {
+        MaterialDescRes{
+            cache:Default::default(),
+            last_error: RefCell::new(None)
+        }
+    }
+
+    /// Why the last file that failed to process is not a material, cleared by reading it.
+    pub fn take_error(&self) -> Option<MaterialError>
+    {
+        self.last_error.borrow_mut().take()
+    }
+}
+
+impl ResProcesser for MaterialDescRes {
+    type In = String;
+    type Out = MaterialDesc;
+    type Param = ();
+
+    gen_impl_res_process_cache!{cache}
+
+    fn process(&self, d: Rc<Self::In>, key: &CacheKey<()>) -> Option<Rc<Self::Out>> {
+        match ron::from_str::<MaterialDesc>(d.as_str()) {
+            Ok(desc) => Some(Rc::new(desc)),
+            Err(e) => {
+                *self.last_error.borrow_mut() = Some(MaterialError::Parse{ path: key.path.clone(), error: e.to_string() });
+                None
+            }
+        }
+    }
+}
+
+/// What a material's pipeline has to agree with but the material file does not describe:
+/// the render target formats, the vertex buffers and the bind groups shared by all
+/// materials (camera etc.).
+///
+/// Bind group order of a material pipeline:
+/// group 0 textures (texture i at binding 2i, its sampler at 2i + 1),
+/// then `shared_layouts`, then the parameter uniform if the material has params.
+pub struct MaterialEnv{
+    pub color_format: TextureFormat,
+    pub depth_format: TextureFormat,
+    pub sample_count: u32,
+    pub vertex_layouts: Vec<VertexLayoutDesc>,
+    pub shared_layouts: Vec<Rc<BindGroupLayout>>,
+}
+
+pub struct MaterialSource{
+    pub desc: Rc<MaterialDesc>,
+    pub shader: Rc<String>,
+    /// Names the shader in the `PipelineCache`, a reloaded shader replaces its old module.
+    pub shader_path: String,
+    /// One per slot, uploaded with the slot's `srgb` and the material's sampler.
+    pub textures: Vec<Rc<GpuTexture>>,
+}
+
+pub struct Material{
+    pub desc: Rc<MaterialDesc>,
+    pub pipeline: Rc<RenderPipeline>,
+    pub textures: Vec<Rc<GpuTexture>>,
+    pub texture_layout: Rc<BindGroupLayout>,
+    pub texture_group: BindGroup,
+    pub params: Option<(Buffer,Rc<BindGroupLayout>,BindGroup)>,
+    params_group: u32,
+}
+
+impl Material {
+    /// Set the pipeline and the material's own bind groups, shared groups are left to the caller.
+    pub fn bind<'a>(&'a self,pass:&mut RenderPass<'a>)
+    {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0,&self.texture_group,&[]);
+        if let Some((_,_,group)) = &self.params {
+            pass.set_bind_group(self.params_group,group,&[]);
+        }
+    }
+
+    pub fn params_group(&self) -> u32 { self.params_group }
+
+    pub fn param_index(&self,name:&str) -> Option<usize>
+    {
+        self.desc.params.iter().position(|p| p.name == name)
+    }
+
+    pub fn set_param(&self,queue:&Queue,name:&str,value:[f32;4]) -> bool
+    {
+        if let (Some(idx),Some((buf,_,_))) = (self.param_index(name),&self.params) {
+            queue.write_buffer(buf,(idx * 16) as BufferAddress,params_bytes(&[value]).as_slice());
+            true
+        }else{
+            false
+        }
+    }
+}
+
+fn params_bytes(params:&[[f32;4]]) -> Vec<u8>
+{
+    let mut res = Vec::with_capacity(params.len() * 16);
+    for p in params.iter() {
+        for f in p.iter() {
+            res.extend_from_slice(&f.to_le_bytes());
+        }
+    }
+    res
+}
+
+/// Materials with the same shader, state and texture count share one pipeline.
+#[derive(AsAny)]
+pub struct MaterialRes{
+    device: Rc<Device>,
+    queue: Rc<Queue>,
+    env: MaterialEnv,
+    pipelines: RefCell<PipelineCache>,
+    texture_layouts: RefCell<HashMap<usize,Rc<BindGroupLayout>>>,
+    params_layout: Rc<BindGroupLayout>,
+    cache: HashMap<CacheKey<()>,Rc<Material>>,
+    last_error: RefCell<Option<MaterialError>>,
+}
+
+impl MaterialRes {
+    pub fn new(device:Rc<Device>,queue:Rc<Queue>,env:MaterialEnv) -> MaterialRes
+    {
+        let params_layout = Rc::new(Self::create_params_layout(&device));
+        MaterialRes{
+            pipelines: RefCell::new(PipelineCache::new(env.color_format)),
+            texture_layouts: Default::default(),
+            params_layout,
+            device,
+            queue,
+            env,
+            cache:Default::default(),
+            last_error: RefCell::new(None)
+        }
+    }
+
+    /// Why the last material that failed to build could not, cleared by reading it.
+    pub fn take_error(&self) -> Option<MaterialError>
+    {
+        self.last_error.borrow_mut().take()
+    }
+
+    pub fn env(&self) -> &MaterialEnv { &self.env }
+
+    /// Changing the environment (e.g. a new swap chain format) drops every cached material.
+    pub fn set_env(&mut self,env:MaterialEnv)
+    {
+        self.pipelines = RefCell::new(PipelineCache::new(env.color_format));
+        self.env = env;
+        self.cache.clear();
+    }
+
+    /// Number of distinct pipelines built for the cached materials.
+    pub fn pipeline_count(&self) -> usize
+    {
+        self.pipelines.borrow().len()
+    }
+
+    fn texture_layout(&self,count:usize) -> Rc<BindGroupLayout>
+    {
+        let mut layouts = self.texture_layouts.borrow_mut();
+        if let Some(l) = layouts.get(&count) {
+            return l.clone();
+        }
+        let mut layout_entries = Vec::with_capacity(count * 2);
+        for i in 0..count {
+            let binding = i as u32 * 2;
+            layout_entries.push(BindGroupLayoutEntry{
+                binding,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float{ filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false
+                },
+                count: None
+            });
+            layout_entries.push(BindGroupLayoutEntry{
+                binding: binding + 1,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Sampler{
+                    filtering: true,
+                    comparison: false
+                },
+                count: None
+            });
+        }
+        let layout = Rc::new(self.device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Material Texture Layout"),
+            entries: layout_entries.as_slice()
+        }));
+        layouts.insert(count,layout.clone());
+        layout
+    }
+
+    fn create_texture_group(&self,textures:&[Rc<GpuTexture>]) -> (Rc<BindGroupLayout>,BindGroup)
+    {
+        let layout = self.texture_layout(textures.len());
+        let mut entries = Vec::with_capacity(textures.len() * 2);
+        for (i,tex) in textures.iter().enumerate() {
+            let binding = i as u32 * 2;
+            entries.push(BindGroupEntry{ binding, resource: BindingResource::TextureView(&tex.view) });
+            entries.push(BindGroupEntry{ binding: binding + 1, resource: BindingResource::Sampler(&tex.sampler) });
+        }
+        let group = self.device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Material Texture Group"),
+            layout: &layout,
+            entries: entries.as_slice()
+        });
+        (layout,group)
+    }
+
+    fn create_params_layout(device:&Device) -> BindGroupLayout
+    {
+        device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Material Params Layout"),
+            entries: &[
+                BindGroupLayoutEntry{
+                    binding: 0,
+                    visibility: ShaderStage::VERTEX | ShaderStage::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                }
+            ]
+        })
+    }
+
+    fn create_params_group(&self,desc:&MaterialDesc) -> Option<(Buffer,Rc<BindGroupLayout>,BindGroup)>
+    {
+        if desc.params.is_empty() { return None; }
+        let values:Vec<[f32;4]> = desc.params.iter().map(|p|{
+            [p.value.0,p.value.1,p.value.2,p.value.3]
+        }).collect();
+        let buf = self.device.create_buffer_init(&BufferInitDescriptor{
+            label: Some("Material Params"),
+            contents: params_bytes(values.as_slice()).as_slice(),
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST
+        });
+        let layout = self.params_layout.clone();
+        let group = self.device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Material Params Group"),
+            layout: &layout,
+            entries: &[
+                BindGroupEntry{ binding: 0, resource: buf.as_entire_binding() }
+            ]
+        });
+        Some((buf,layout,group))
+    }
+}
+
+impl ResProcesser for MaterialRes {
+    type In = MaterialSource;
+    type Out = Material;
+    type Param = ();
+
+    gen_impl_res_process_cache!{cache}
+
+    fn process(&self, d: Rc<Self::In>, _key: &CacheKey<()>) -> Option<Rc<Self::Out>> {
+        let desc = d.desc.clone();
+        if d.textures.len() != desc.textures.len() { return None; }
+        if let Err(error) = check_vertex_layouts(d.shader.as_str(),desc.vs_entry.as_str(),self.env.vertex_layouts.as_slice()) {
+            *self.last_error.borrow_mut() = Some(MaterialError::Shader{ path: d.shader_path.clone(), error });
+            return None;
+        }
+        let textures = d.textures.clone();
+        let (texture_layout,texture_group) = self.create_texture_group(textures.as_slice());
+        let params = self.create_params_group(desc.as_ref());
+        let params_group = 1 + self.env.shared_layouts.len() as u32;
+
+        let mut pipelines = self.pipelines.borrow_mut();
+        let shader = pipelines.shader(&self.device,d.shader_path.as_str(),d.shader.as_str());
+        let mut layouts = vec![pipelines.layout(&texture_layout)];
+        layouts.extend(self.env.shared_layouts.iter().map(|l| pipelines.layout(l)));
+        if let Some((_,layout,_)) = &params { layouts.push(pipelines.layout(layout)); }
+        let pipeline = pipelines.get(&self.device,&PipelineDesc{
+            vs_entry: desc.vs_entry.clone(),
+            fs_entry: desc.fs_entry.clone(),
+            color: Some(ColorFormat::Format(self.env.color_format)),
+            blend: desc.blend,
+            depth: desc.depth.as_ref().map(|depth| DepthState{
+                format: self.env.depth_format,
+                write: depth.write,
+                compare: depth.compare
+            }),
+            cull: desc.cull,
+            sample_count: self.env.sample_count,
+            alpha_to_coverage: desc.render_queue() == RenderQueue::AlphaTest,
+            ..PipelineDesc::new(shader,layouts,self.env.vertex_layouts.clone())
+        });
+        Some(Rc::new(Material{
+            desc,
+            pipeline,
+            textures,
+            texture_layout,
+            texture_group,
+            params,
+            params_group
+        }))
+    }
+}
+
+/// Load a material file and everything it references through `mgr`.
+/// `TextRes`, `ImageRes`, `GpuTextureRes`, `MaterialDescRes` and `MaterialRes` must be registered.
+/// The material is recorded as depending on its shader and textures, so touching
+/// any of them rebuilds it on the next load.
+pub fn load_material(mgr:&mut ResourceMgr,p:&str) -> Result<Rc<Material>,MaterialError>
+{
+    let load = |p:&str| MaterialError::Load(p.to_string());
+    let (raw,overdue,path) = mgr.load_file(p).ok_or_else(|| load(p))?;
+    let text = mgr.loading::<TextRes,_,_>(raw,&path,overdue).ok_or_else(|| load(p))?;
+    let desc = match mgr.loading::<MaterialDescRes,_,_>(text,&path,overdue) {
+        Some(desc) => desc,
+        None => return Err(mgr.processer::<MaterialDescRes>().and_then(|r| r.take_error()).unwrap_or_else(|| load(p)))
+    };
+    let dir = Path::new(p).parent().unwrap_or(Path::new(""));
+    let key = ResKey::of::<Material>(&path);
+    // textures dropped from the file must not keep invalidating the material
+    mgr.clear_dependencies(&key);
+    mgr.add_dependency(key.clone(),ResKey::of::<MaterialDesc>(&path));
+
+    let shader_file = dir.join(desc.shader.as_str()).to_string_lossy().to_string();
+    let (raw,overdue,shader_path) = mgr.load_file(shader_file.as_str()).ok_or_else(|| load(&shader_file))?;
+    let shader = mgr.loading::<TextRes,_,_>(raw,&shader_path,overdue).ok_or_else(|| load(&shader_file))?;
+    mgr.add_dependency(key.clone(),ResKey::of::<String>(&shader_path));
+
+    let mut textures = Vec::with_capacity(desc.textures.len());
+    for slot in desc.textures.iter() {
+        let img_file = dir.join(slot.path.as_str()).to_string_lossy().to_string();
+        let (raw,overdue,img_path) = mgr.load_file(img_file.as_str()).ok_or_else(|| load(&img_file))?;
+        let img = mgr.loading::<ImageRes,_,_>(raw,&img_path,overdue).ok_or_else(|| load(&img_file))?;
+        let param = TextureParam{ srgb: slot.srgb, sampler: desc.sampler.clone() };
+        textures.push(mgr.loading_with::<GpuTextureRes,_,_>(img,&img_path,param,overdue).ok_or_else(|| load(&img_file))?);
+        mgr.add_dependency(key.clone(),ResKey::of::<GpuTexture>(&img_path));
+    }
+    match mgr.loading::<MaterialRes,_,_>(Rc::new(MaterialSource{ desc, shader, shader_path, textures }),&path,false) {
+        Some(material) => Ok(material),
+        None => Err(mgr.processer::<MaterialRes>().and_then(|r| r.take_error()).unwrap_or_else(|| load(p)))
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::Path;
+use cgmath::{Matrix4, Matrix3, Vector3, Vector4, Point3, Transform, SquareMatrix, Matrix, InnerSpace};
+use ::gltf::{Document, Gltf, Node};
+use ::gltf::buffer::Source;
+use ::gltf::mesh::Mode;
+use crate::resource_manager::ResourceMgr;
+use crate::resource_manager::graph::ResKey;
+use crate::mesh::MeshData;
+
+/// A parsed glTF document with every buffer it references already loaded.
+pub struct GltfSource{
+    pub document: Document,
+    pub buffers: Vec<Vec<u8>>,
+}
+
+/// Flattens the default scene (or every mesh when there is no scene) into one
+/// `MeshData` in scene space, each triangle primitive becomes a submesh.
+pub fn parse_gltf(d:&GltfSource) -> Option<MeshData>
+{
+    let mut res = MeshData::default();
+    if let Some(scene) = d.document.default_scene().or_else(|| d.document.scenes().next()) {
+        for node in scene.nodes() {
+            add_node(&mut res,d,&node,Matrix4::identity())?;
+        }
+    }else{
+        for mesh in d.document.meshes() {
+            add_mesh(&mut res,d,&mesh,Matrix4::identity())?;
+        }
+    }
+    if res.positions.is_empty() { None } else { Some(res) }
+}
+
+fn add_node(res:&mut MeshData,src:&GltfSource,node:&Node,parent:Matrix4<f32>) -> Option<()>
+{
+    let world = parent * Matrix4::from(node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        add_mesh(res,src,&mesh,world)?;
+    }
+    for child in node.children() {
+        add_node(res,src,&child,world)?;
+    }
+    Some(())
+}
+
+fn add_mesh(res:&mut MeshData,src:&GltfSource,mesh:&::gltf::Mesh,world:Matrix4<f32>) -> Option<()>
+{
+    let normal_mat = {
+        let m = Matrix3::from_cols(world.x.truncate(),world.y.truncate(),world.z.truncate());
+        m.invert().unwrap_or(m).transpose()
+    };
+    let name = mesh.name().unwrap_or("").to_string();
+    for prim in mesh.primitives() {
+        if prim.mode() != Mode::Triangles { continue; }
+        let reader = prim.reader(|b| src.buffers.get(b.index()).map(|d| d.as_slice()));
+        let positions = reader.read_positions()?
+            .map(|p| world.transform_point(Point3::from(p)).into())
+            .collect();
+        let normals = if let Some(it) = reader.read_normals() {
+            it.map(|n| (normal_mat * Vector3::from(n)).normalize().into()).collect()
+        }else { Vec::new() };
+        let tangents = if let Some(it) = reader.read_tangents() {
+            it.map(|t| {
+                let v = world.transform_vector(Vector4::from(t).truncate()).normalize();
+                [v.x,v.y,v.z,t[3]]
+            }).collect()
+        }else { Vec::new() };
+        let uvs = reader.read_tex_coords(0).map(|it| it.into_f32().collect()).unwrap_or_default();
+        let colors = reader.read_colors(0).map(|it| it.into_rgba_f32().collect()).unwrap_or_default();
+        let indices = reader.read_indices().map(|it| it.into_u32().collect()).unwrap_or_default();
+        res.append(MeshData{
+            positions,
+            normals,
+            uvs,
+            tangents,
+            colors,
+            indices,
+            submeshes: Vec::new()
+        },name.clone(),prim.material().index());
+    }
+    Some(())
+}
+
+/// Parses a `.gltf`/`.glb` file and loads the buffers it references. External buffers
+/// are read through the manager relative to the document and recorded as inputs of `key`,
+/// the returned flag tells whether any of them was reloaded.
+pub fn load_gltf_source(mgr:&mut ResourceMgr,p:&str,raw:&[u8],key:&ResKey) -> Option<(GltfSource,bool)>
+{
+    let Gltf{ document, mut blob } = Gltf::from_slice(raw).ok()?;
+    let dir = Path::new(p).parent().unwrap_or(Path::new(""));
+    let mut overdue = false;
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            Source::Bin => blob.take()?,
+            Source::Uri(uri) if uri.starts_with("data:") => {
+                let start = uri.find(";base64,")? + ";base64,".len();
+                base64::decode(&uri[start..]).ok()?
+            }
+            Source::Uri(uri) => {
+                let (raw,o,bin_path) = mgr.load_file(dir.join(uri).to_str()?)?;
+                overdue |= o;
+                mgr.add_dependency(key.clone(),ResKey::raw(&bin_path));
+                raw.as_ref().clone()
+            }
+        };
+        if data.len() < buffer.length() { return None; }
+        buffers.push(data);
+    }
+    Some((GltfSource{ document, buffers },overdue))
+}
@@ -0,0 +1,386 @@
+pub mod obj;
+pub mod gltf;
+
+use std::rc::Rc;
+use std::collections::HashMap;
+use std::any::Any;
+use std::path::Path;
+use gen_code::{gen_impl_res_process_cache,AsAny};
+use wgpu::{Device, BufferUsage, IndexFormat, RenderPass};
+use cgmath::{Vector3, InnerSpace};
+use crate::AsAny;
+use crate::resource_manager::{ResProcesser, ResourceMgr, CacheKey};
+use crate::vertex::VertexLayout;
+use crate::buffer::GpuBuffer;
+use crate::resource_manager::graph::ResKey;
+use crate::bounds::Aabb;
+pub use self::obj::parse_obj;
+pub use self::gltf::{GltfSource, parse_gltf, load_gltf_source};
+
+/// A range of `MeshData::indices` drawn with one material,
+/// one per OBJ object/group or glTF primitive.
+#[derive(Debug,Clone,PartialEq)]
+pub struct SubMesh{
+    pub name: String,
+    pub first_index: u32,
+    pub index_count: u32,
+    pub material: Option<usize>,
+}
+
+/// Triangle list mesh on the CPU. All vertex channels have the same length
+/// once loaded, missing normals and tangents are generated and missing
+/// uvs/colors are filled with zero/white.
+#[derive(Debug,Clone,Default)]
+pub struct MeshData{
+    pub positions: Vec<[f32;3]>,
+    pub normals: Vec<[f32;3]>,
+    pub uvs: Vec<[f32;2]>,
+    pub tangents: Vec<[f32;4]>,
+    pub colors: Vec<[f32;4]>,
+    pub indices: Vec<u32>,
+    pub submeshes: Vec<SubMesh>,
+}
+
+impl MeshData {
+    pub fn vertex_count(&self) -> usize
+    {
+        self.positions.len()
+    }
+
+    /// Axis aligned (min, max), `None` for an empty mesh.
+    pub fn bounds(&self) -> Option<([f32;3],[f32;3])>
+    {
+        let first = *self.positions.first()?;
+        let mut min = first;
+        let mut max = first;
+        for p in self.positions.iter() {
+            for i in 0..3 {
+                min[i] = min[i].min(p[i]);
+                max[i] = max[i].max(p[i]);
+            }
+        }
+        Some((min,max))
+    }
+
+    /// Appends `part` as a new submesh, generating whatever channels it lacks.
+    pub fn append(&mut self,mut part:MeshData,name:String,material:Option<usize>)
+    {
+        part.fill_missing();
+        let base = self.positions.len() as u32;
+        self.submeshes.push(SubMesh{
+            name,
+            first_index: self.indices.len() as u32,
+            index_count: part.indices.len() as u32,
+            material
+        });
+        self.positions.extend(part.positions);
+        self.normals.extend(part.normals);
+        self.uvs.extend(part.uvs);
+        self.tangents.extend(part.tangents);
+        self.colors.extend(part.colors);
+        self.indices.extend(part.indices.iter().map(|i| i + base));
+    }
+
+    pub fn fill_missing(&mut self)
+    {
+        let n = self.positions.len();
+        if self.indices.is_empty() {
+            self.indices = (0..n as u32).collect();
+        }
+        if self.normals.len() != n {
+            self.compute_normals();
+        }
+        if self.uvs.len() != n {
+            self.uvs = vec![[0.0,0.0];n];
+        }
+        if self.colors.len() != n {
+            self.colors = vec![[1.0,1.0,1.0,1.0];n];
+        }
+        if self.tangents.len() != n {
+            self.compute_tangents();
+        }
+    }
+
+    /// Smooth normals, each triangle weighted by its area.
+    pub fn compute_normals(&mut self)
+    {
+        let mut normals = vec![Vector3::new(0.0f32,0.0,0.0);self.positions.len()];
+        for tri in self.indices.chunks_exact(3) {
+            let (a,b,c) = (tri[0] as usize,tri[1] as usize,tri[2] as usize);
+            let pa = Vector3::from(self.positions[a]);
+            let n = (Vector3::from(self.positions[b]) - pa).cross(Vector3::from(self.positions[c]) - pa);
+            normals[a] += n;
+            normals[b] += n;
+            normals[c] += n;
+        }
+        self.normals = normals.into_iter().map(|n| {
+            if n.magnitude2() > 0.0 { n.normalize().into() } else { [0.0,0.0,1.0] }
+        }).collect();
+    }
+
+    /// Per vertex tangents from the uv layout, w is the bitangent sign.
+    /// Needs normals and uvs.
+    pub fn compute_tangents(&mut self)
+    {
+        let n = self.positions.len();
+        let mut tan = vec![Vector3::new(0.0f32,0.0,0.0);n];
+        let mut bitan = vec![Vector3::new(0.0f32,0.0,0.0);n];
+        for tri in self.indices.chunks_exact(3) {
+            let (a,b,c) = (tri[0] as usize,tri[1] as usize,tri[2] as usize);
+            let pa = Vector3::from(self.positions[a]);
+            let e1 = Vector3::from(self.positions[b]) - pa;
+            let e2 = Vector3::from(self.positions[c]) - pa;
+            let (u1,v1) = (self.uvs[b][0] - self.uvs[a][0],self.uvs[b][1] - self.uvs[a][1]);
+            let (u2,v2) = (self.uvs[c][0] - self.uvs[a][0],self.uvs[c][1] - self.uvs[a][1]);
+            let det = u1 * v2 - u2 * v1;
+            if det.abs() < f32::EPSILON { continue; }
+            let r = 1.0 / det;
+            let t = (e1 * v2 - e2 * v1) * r;
+            let bt = (e2 * u1 - e1 * u2) * r;
+            for &i in [a,b,c].iter() {
+                tan[i] += t;
+                bitan[i] += bt;
+            }
+        }
+        self.tangents = (0..n).map(|i| {
+            let normal = Vector3::from(self.normals[i]);
+            // Gram-Schmidt, fall back to any vector perpendicular to the normal
+            let mut t = tan[i] - normal * normal.dot(tan[i]);
+            if t.magnitude2() < 1e-12 {
+                let axis = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+                t = axis - normal * normal.dot(axis);
+            }
+            let t = t.normalize();
+            let w = if normal.cross(t).dot(bitan[i]) < 0.0 { -1.0 } else { 1.0 };
+            [t.x,t.y,t.z,w]
+        }).collect();
+    }
+
+    pub fn to_vertices(&self) -> Vec<MeshVertex>
+    {
+        (0..self.positions.len()).map(|i| MeshVertex{
+            position: self.positions[i],
+            color: self.colors[i],
+            tex_coords: self.uvs[i],
+            normal: self.normals[i],
+            tangent: self.tangents[i]
+        }).collect()
+    }
+}
+
+/// Interleaved vertex of a `GpuMesh`, locations 0..=2 match the `Vertex` used by the examples.
+#[repr(C)]
+#[derive(Debug,Copy,Clone,bytemuck::Pod,bytemuck::Zeroable,VertexLayout)]
+pub struct MeshVertex{
+    pub position: [f32;3],
+    pub color: [f32;4],
+    pub tex_coords: [f32;2],
+    pub normal: [f32;3],
+    pub tangent: [f32;4],
+}
+
+crate::impl_as_bytes_pod!{ MeshVertex }
+
+pub struct GpuMesh{
+    pub vertex_buffer: GpuBuffer<MeshVertex>,
+    pub index_buffer: GpuBuffer<u32>,
+    pub vertex_count: u32,
+    pub index_count: u32,
+    pub submeshes: Vec<SubMesh>,
+    pub bounds: Option<([f32;3],[f32;3])>,
+}
+
+impl GpuMesh {
+    pub fn new(device:&Device,data:&MeshData) -> GpuMesh
+    {
+        let vertex_buffer = GpuBuffer::new(device,Some("Mesh Vertices"),BufferUsage::VERTEX,data.to_vertices().as_slice());
+        let index_buffer = GpuBuffer::new(device,Some("Mesh Indices"),BufferUsage::INDEX,data.indices.as_slice());
+        GpuMesh{
+            vertex_buffer,
+            index_buffer,
+            vertex_count: data.vertex_count() as u32,
+            index_count: data.indices.len() as u32,
+            submeshes: data.submeshes.clone(),
+            bounds: data.bounds()
+        }
+    }
+
+    /// Binds the vertex buffer to `slot` and the index buffer.
+    pub fn bind<'a>(&'a self,pass:&mut RenderPass<'a>,slot:u32)
+    {
+        pass.set_vertex_buffer(slot,self.vertex_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..),IndexFormat::Uint32);
+    }
+
+    pub fn draw_submesh<'a>(&'a self,pass:&mut RenderPass<'a>,i:usize,instances:std::ops::Range<u32>)
+    {
+        let s = &self.submeshes[i];
+        pass.draw_indexed(s.first_index..s.first_index + s.index_count,0,instances);
+    }
+
+    pub fn draw<'a>(&'a self,pass:&mut RenderPass<'a>,instances:std::ops::Range<u32>)
+    {
+        pass.draw_indexed(0..self.index_count,0,instances);
+    }
+    /// Local space bounding box, `None` for a mesh without vertices.
+    pub fn aabb(&self) -> Option<Aabb>
+    {
+        self.bounds.map(Aabb::from)
+    }
+}
+
+#[derive(AsAny)]
+pub struct GpuMeshRes{
+    device: Rc<Device>,
+    cache: HashMap<CacheKey<()>,Rc<GpuMesh>>,
+}
+
+impl GpuMeshRes {
+    pub fn new(device:Rc<Device>) -> GpuMeshRes
+    {
+        GpuMeshRes{
+            device,
+            cache:Default::default()
+        }
+    }
+}
+
+impl ResProcesser for GpuMeshRes {
+    type In = MeshData;
+    type Out = GpuMesh;
+    type Param = ();
+
+    gen_impl_res_process_cache!{cache}
+
+    fn process(&self, d: Rc<Self::In>, _key: &CacheKey<()>) -> Option<Rc<Self::Out>> {
+        Some(Rc::new(GpuMesh::new(&self.device,d.as_ref())))
+    }
+}
+
+pub enum MeshSource{
+    Obj(Rc<Vec<u8>>),
+    Gltf(GltfSource),
+}
+
+/// Parses OBJ and glTF files into `MeshData`.
+#[derive(AsAny)]
+pub struct MeshRes{
+    cache: HashMap<CacheKey<()>,Rc<MeshData>>,
+}
+
+impl MeshRes {
+    pub fn new() -> MeshRes
+    {
+        MeshRes{
+            cache:Default::default()
+        }
+    }
+}
+
+impl ResProcesser for MeshRes {
+    type In = MeshSource;
+    type Out = MeshData;
+    type Param = ();
+
+    gen_impl_res_process_cache!{cache}
+
+    fn process(&self, d: Rc<Self::In>, _key: &CacheKey<()>) -> Option<Rc<Self::Out>> {
+        match d.as_ref() {
+            MeshSource::Obj(data) => parse_obj(data.as_slice()),
+            MeshSource::Gltf(src) => parse_gltf(src)
+        }.map(Rc::new)
+    }
+}
+
+fn load_mesh_data(mgr:&mut ResourceMgr,p:&str) -> Option<(Rc<MeshData>,String)>
+{
+    let ext = Path::new(p).extension()?.to_str()?.to_ascii_lowercase();
+    let (raw,mut overdue,path) = mgr.load_file(p)?;
+    let key = ResKey::of::<MeshData>(&path);
+    mgr.clear_dependencies(&key);
+    mgr.add_dependency(key.clone(),ResKey::raw(&path));
+    let src = match ext.as_str() {
+        "obj" => MeshSource::Obj(raw),
+        "gltf" | "glb" => {
+            let (src,o) = load_gltf_source(mgr,p,raw.as_slice(),&key)?;
+            overdue |= o;
+            MeshSource::Gltf(src)
+        }
+        _ => return None
+    };
+    let data = mgr.loading::<MeshRes,_,_>(Rc::new(src),&path,overdue)?;
+    Some((data,path))
+}
+
+/// Loads an `.obj`, `.gltf` or `.glb` file, needs `MeshRes` registered.
+pub fn load_mesh(mgr:&mut ResourceMgr,p:&str) -> Option<Rc<MeshData>>
+{
+    load_mesh_data(mgr,p).map(|(d,_)| d)
+}
+
+/// Like `load_mesh` and uploads the result, also needs `GpuMeshRes`.
+pub fn load_gpu_mesh(mgr:&mut ResourceMgr,p:&str) -> Option<Rc<GpuMesh>>
+{
+    let (data,path) = load_mesh_data(mgr,p)?;
+    mgr.loading::<GpuMeshRes,_,_>(data,&path,false)
+}
+
+mod test_mesh{
+    use crate::resource_manager::ResourceMgr;
+    use crate::mesh::{MeshRes, load_mesh};
+
+    fn mgr() -> ResourceMgr
+    {
+        let mut mgr = ResourceMgr::new("test_res".to_string());
+        mgr.add_process(Box::new(MeshRes::new()));
+        mgr
+    }
+
+    fn check_channels(m:&crate::mesh::MeshData)
+    {
+        let n = m.vertex_count();
+        assert_eq!(m.normals.len(),n);
+        assert_eq!(m.uvs.len(),n);
+        assert_eq!(m.tangents.len(),n);
+        assert_eq!(m.colors.len(),n);
+        assert!(m.indices.iter().all(|&i| (i as usize) < n));
+        for (nl,t) in m.normals.iter().zip(m.tangents.iter()) {
+            let len = (nl[0]*nl[0] + nl[1]*nl[1] + nl[2]*nl[2]).sqrt();
+            assert!((len - 1.0).abs() < 1e-4);
+            let d = nl[0]*t[0] + nl[1]*t[1] + nl[2]*t[2];
+            assert!(d.abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn obj()
+    {
+        let mut mgr = mgr();
+        let m = load_mesh(&mut mgr,"cube.obj").unwrap();
+        // 6 faces * 4 corners, every corner has its own normal
+        assert_eq!(m.vertex_count(),24);
+        assert_eq!(m.indices.len(),36);
+        assert_eq!(m.submeshes.len(),2);
+        assert_eq!(m.submeshes[0].index_count + m.submeshes[1].index_count,36);
+        assert_eq!(m.bounds(),Some(([-1.0,-1.0,-1.0],[1.0,1.0,1.0])));
+        check_channels(m.as_ref());
+    }
+
+    #[test]
+    fn gltf()
+    {
+        let mut mgr = mgr();
+        for p in ["quad.gltf","quad.glb","quad_embedded.gltf"].iter() {
+            let m = load_mesh(&mut mgr,p).unwrap();
+            assert_eq!(m.vertex_count(),4);
+            assert_eq!(m.indices.len(),6);
+            assert_eq!(m.submeshes.len(),1);
+            assert_eq!(m.submeshes[0].name,"quad");
+            // the node translates the quad by (0,0,2)
+            assert_eq!(m.bounds(),Some(([-0.5,-0.5,2.0],[0.5,0.5,2.0])));
+            check_channels(m.as_ref());
+        }
+        // both formats go through the same processor
+        assert_eq!(load_mesh(&mut mgr,"cube.obj").unwrap().vertex_count(),24);
+    }
+}
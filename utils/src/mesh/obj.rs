@@ -0,0 +1,29 @@
+use crate::mesh::MeshData;
+
+/// Wavefront OBJ, faces are triangulated and every object/group becomes a submesh.
+/// `.mtl` files are not read, submeshes only keep the material index.
+pub fn parse_obj(d:&[u8]) -> Option<MeshData>
+{
+    let opt = tobj::LoadOptions{
+        single_index: true,
+        triangulate: true,
+        ..Default::default()
+    };
+    let (models,_) = tobj::load_obj_buf(&mut &d[..],&opt,|_| Err(tobj::LoadError::OpenFileFailed)).ok()?;
+    let mut res = MeshData::default();
+    for m in models.into_iter() {
+        let mesh = m.mesh;
+        let part = MeshData{
+            positions: mesh.positions.chunks_exact(3).map(|p| [p[0],p[1],p[2]]).collect(),
+            normals: mesh.normals.chunks_exact(3).map(|n| [n[0],n[1],n[2]]).collect(),
+            // OBJ puts v = 0 at the bottom, wgpu at the top
+            uvs: mesh.texcoords.chunks_exact(2).map(|t| [t[0],1.0 - t[1]]).collect(),
+            tangents: Vec::new(),
+            colors: mesh.vertex_color.chunks_exact(3).map(|c| [c[0],c[1],c[2],1.0]).collect(),
+            indices: mesh.indices,
+            submeshes: Vec::new()
+        };
+        res.append(part,m.name,mesh.material_id);
+    }
+    if res.positions.is_empty() { None } else { Some(res) }
+}
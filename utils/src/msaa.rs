@@ -0,0 +1,131 @@
+use std::fmt::{Display, Formatter};
+use wgpu::{Device, Texture, TextureView, TextureFormat, TextureUsage, TextureDescriptor, TextureDimension, Extent3d, SwapChainDescriptor, RenderPassColorAttachment, RenderPassDepthStencilAttachment, Operations, LoadOp, Color};
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct SampleCountError{
+    pub count: u32,
+    pub supported: Vec<u32>,
+}
+
+impl Display for SampleCountError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f,"sample count {} is not portable, WebGPU only guarantees {:?}",self.count,self.supported)
+    }
+}
+
+impl std::error::Error for SampleCountError {}
+
+/// Sample counts every attachment format can be rendered with on any adapter, 1 and 4 as WebGPU
+/// guarantees them. wgpu can not be asked which counts an adapter supports for a format, so 2, 8
+/// and 16 are refused even on hardware that has them.
+pub fn supported_sample_counts() -> Vec<u32>
+{
+    vec![1,4]
+}
+
+pub fn check_sample_count(count:u32) -> Result<u32,SampleCountError>
+{
+    let supported = supported_sample_counts();
+    if supported.contains(&count) {
+        Ok(count)
+    }else{
+        Err(SampleCountError{ count, supported })
+    }
+}
+
+pub fn create_attachment(device:&Device,label:&str,format:TextureFormat,(width,height):(u32,u32),sample_count:u32,usage:TextureUsage) -> (Texture,TextureView)
+{
+    let texture = device.create_texture(&TextureDescriptor{
+        label: Some(label),
+        size: Extent3d{ width, height, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count,
+        dimension: TextureDimension::D2,
+        format,
+        usage
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    (texture,view)
+}
+
+/// The color and depth buffers drawn into instead of the swap chain frame. With more than
+/// one sample the color buffer is resolved into the frame at the end of the pass.
+pub struct MsaaTargets{
+    sample_count: u32,
+    depth_format: Option<TextureFormat>,
+    color: Option<(Texture,TextureView)>,
+    depth: Option<(Texture,TextureView)>,
+    size: (u32,u32,TextureFormat),
+}
+
+impl MsaaTargets {
+    pub fn new(device:&Device,sc_desc:&SwapChainDescriptor,sample_count:u32,depth_format:Option<TextureFormat>) -> MsaaTargets
+    {
+        let mut res = MsaaTargets{
+            sample_count,
+            depth_format,
+            color: None,
+            depth: None,
+            size: (sc_desc.width,sc_desc.height,sc_desc.format)
+        };
+        res.create(device);
+        res
+    }
+
+    fn create(&mut self,device:&Device)
+    {
+        let (width,height,format) = self.size;
+        self.color = if self.sample_count > 1 {
+            Some(create_attachment(device,"MSAA Color",format,(width,height),self.sample_count,TextureUsage::RENDER_ATTACHMENT))
+        }else{
+            None
+        };
+        self.depth = self.depth_format.map(|f| create_attachment(device,"MSAA Depth",f,(width,height),self.sample_count,TextureUsage::RENDER_ATTACHMENT));
+    }
+
+    /// Recreates the buffers when the swap chain changed.
+    pub fn resize(&mut self,device:&Device,sc_desc:&SwapChainDescriptor)
+    {
+        let size = (sc_desc.width,sc_desc.height,sc_desc.format);
+        if size != self.size {
+            self.size = size;
+            self.create(device);
+        }
+    }
+
+    pub fn set_sample_count(&mut self,device:&Device,sample_count:u32)
+    {
+        if sample_count != self.sample_count {
+            self.sample_count = sample_count;
+            self.create(device);
+        }
+    }
+
+    pub fn sample_count(&self) -> u32 { self.sample_count }
+
+    pub fn color_attachment<'a>(&'a self,frame:&'a TextureView,load:LoadOp<Color>) -> RenderPassColorAttachment<'a>
+    {
+        match self.color {
+            Some((_,ref view)) => RenderPassColorAttachment{
+                view,
+                resolve_target: Some(frame),
+                ops: Operations{ load, store: true }
+            },
+            None => RenderPassColorAttachment{
+                view: frame,
+                resolve_target: None,
+                ops: Operations{ load, store: true }
+            }
+        }
+    }
+
+    /// `None` when the targets were created without a depth format.
+    pub fn depth_attachment(&self,load:LoadOp<f32>) -> Option<RenderPassDepthStencilAttachment<'_>>
+    {
+        self.depth.as_ref().map(|(_,view)| RenderPassDepthStencilAttachment{
+            view,
+            depth_ops: Some(Operations{ load, store: true }),
+            stencil_ops: None
+        })
+    }
+}
@@ -1,4 +1,4 @@
-use crate::component::Component;
+use crate::component::{Component, RenderNecessary};
 use std::collections::{ HashMap};
 use std::any::{Any, TypeId};
 use std::marker::PhantomPinned;
@@ -87,6 +87,14 @@ impl Object
         None
     }
 
+    /// Lets every component record its draw calls, in priority order.
+    pub fn render(&mut self,mut nec:RenderNecessary<'_>)
+    {
+        for c in self.components.iter_mut() {
+            c.render(nec.reborrow());
+        }
+    }
+
     fn self_ptr(&self) -> *const Object{
         self as *const Object
     }
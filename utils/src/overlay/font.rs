@@ -0,0 +1,97 @@
+use crate::resource_manager::pack::RgbaMips;
+
+/// Width and height of a glyph in pixels.
+pub const GLYPH_WIDTH: u32 = 5;
+pub const GLYPH_HEIGHT: u32 = 7;
+/// Glyph cells in the atlas are one pixel larger so linear sampling never bleeds.
+const CELL_WIDTH: u32 = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: u32 = GLYPH_HEIGHT + 1;
+const COLUMNS: u32 = 16;
+const FIRST: u8 = b' ';
+const LAST: u8 = b'~';
+
+/// The classic 5x7 font for ' '..='~', five columns per glyph, bit 0 is the top row.
+const GLYPHS: [[u8;5];95] = [
+    [0x00,0x00,0x00,0x00,0x00],[0x00,0x00,0x5f,0x00,0x00],[0x00,0x07,0x00,0x07,0x00],[0x14,0x7f,0x14,0x7f,0x14],
+    [0x24,0x2a,0x7f,0x2a,0x12],[0x23,0x13,0x08,0x64,0x62],[0x36,0x49,0x55,0x22,0x50],[0x00,0x05,0x03,0x00,0x00],
+    [0x00,0x1c,0x22,0x41,0x00],[0x00,0x41,0x22,0x1c,0x00],[0x14,0x08,0x3e,0x08,0x14],[0x08,0x08,0x3e,0x08,0x08],
+    [0x00,0x50,0x30,0x00,0x00],[0x08,0x08,0x08,0x08,0x08],[0x00,0x60,0x60,0x00,0x00],[0x20,0x10,0x08,0x04,0x02],
+    [0x3e,0x51,0x49,0x45,0x3e],[0x00,0x42,0x7f,0x40,0x00],[0x42,0x61,0x51,0x49,0x46],[0x21,0x41,0x45,0x4b,0x31],
+    [0x18,0x14,0x12,0x7f,0x10],[0x27,0x45,0x45,0x45,0x39],[0x3c,0x4a,0x49,0x49,0x30],[0x01,0x71,0x09,0x05,0x03],
+    [0x36,0x49,0x49,0x49,0x36],[0x06,0x49,0x49,0x29,0x1e],[0x00,0x36,0x36,0x00,0x00],[0x00,0x56,0x36,0x00,0x00],
+    [0x08,0x14,0x22,0x41,0x00],[0x14,0x14,0x14,0x14,0x14],[0x00,0x41,0x22,0x14,0x08],[0x02,0x01,0x51,0x09,0x06],
+    [0x32,0x49,0x79,0x41,0x3e],[0x7e,0x11,0x11,0x11,0x7e],[0x7f,0x49,0x49,0x49,0x36],[0x3e,0x41,0x41,0x41,0x22],
+    [0x7f,0x41,0x41,0x22,0x1c],[0x7f,0x49,0x49,0x49,0x41],[0x7f,0x09,0x09,0x09,0x01],[0x3e,0x41,0x49,0x49,0x7a],
+    [0x7f,0x08,0x08,0x08,0x7f],[0x00,0x41,0x7f,0x41,0x00],[0x20,0x40,0x41,0x3f,0x01],[0x7f,0x08,0x14,0x22,0x41],
+    [0x7f,0x40,0x40,0x40,0x40],[0x7f,0x02,0x0c,0x02,0x7f],[0x7f,0x04,0x08,0x10,0x7f],[0x3e,0x41,0x41,0x41,0x3e],
+    [0x7f,0x09,0x09,0x09,0x06],[0x3e,0x41,0x51,0x21,0x5e],[0x7f,0x09,0x19,0x29,0x46],[0x46,0x49,0x49,0x49,0x31],
+    [0x01,0x01,0x7f,0x01,0x01],[0x3f,0x40,0x40,0x40,0x3f],[0x1f,0x20,0x40,0x20,0x1f],[0x3f,0x40,0x38,0x40,0x3f],
+    [0x63,0x14,0x08,0x14,0x63],[0x07,0x08,0x70,0x08,0x07],[0x61,0x51,0x49,0x45,0x43],[0x00,0x7f,0x41,0x41,0x00],
+    [0x02,0x04,0x08,0x10,0x20],[0x00,0x41,0x41,0x7f,0x00],[0x04,0x02,0x01,0x02,0x04],[0x40,0x40,0x40,0x40,0x40],
+    [0x00,0x01,0x02,0x04,0x00],[0x20,0x54,0x54,0x54,0x78],[0x7f,0x48,0x44,0x44,0x38],[0x38,0x44,0x44,0x44,0x20],
+    [0x38,0x44,0x44,0x48,0x7f],[0x38,0x54,0x54,0x54,0x18],[0x08,0x7e,0x09,0x01,0x02],[0x0c,0x52,0x52,0x52,0x3e],
+    [0x7f,0x08,0x04,0x04,0x78],[0x00,0x44,0x7d,0x40,0x00],[0x20,0x40,0x44,0x3d,0x00],[0x7f,0x10,0x28,0x44,0x00],
+    [0x00,0x41,0x7f,0x40,0x00],[0x7c,0x04,0x18,0x04,0x78],[0x7c,0x08,0x04,0x04,0x78],[0x38,0x44,0x44,0x44,0x38],
+    [0x7c,0x14,0x14,0x14,0x08],[0x08,0x14,0x14,0x18,0x7c],[0x7c,0x08,0x04,0x04,0x08],[0x48,0x54,0x54,0x54,0x20],
+    [0x04,0x3f,0x44,0x40,0x20],[0x3c,0x40,0x40,0x20,0x7c],[0x1c,0x20,0x40,0x20,0x1c],[0x3c,0x40,0x30,0x40,0x3c],
+    [0x44,0x28,0x10,0x28,0x44],[0x0c,0x50,0x50,0x50,0x3c],[0x44,0x64,0x54,0x4c,0x44],[0x00,0x08,0x36,0x41,0x00],
+    [0x00,0x00,0x7f,0x00,0x00],[0x00,0x41,0x36,0x08,0x00],[0x10,0x08,0x08,0x10,0x08],
+];
+
+/// The built in bitmap font baked into an atlas, 16 glyph cells per row.
+/// The cell after `~` holds a solid block, used for filled rectangles.
+pub struct BitmapFont{
+    atlas: RgbaMips,
+}
+
+impl BitmapFont {
+    pub fn new() -> BitmapFont
+    {
+        let count = (LAST - FIRST) as u32 + 2;
+        let rows = (count + COLUMNS - 1) / COLUMNS;
+        let (width,height) = (COLUMNS * CELL_WIDTH,rows * CELL_HEIGHT);
+        // white everywhere, coverage in alpha, so the vertex color tints it
+        let mut pixels = vec![255u8;(width * height * 4) as usize];
+        for a in pixels.iter_mut().skip(3).step_by(4) { *a = 0; }
+        for i in 0..count {
+            let (cx,cy) = ((i % COLUMNS) * CELL_WIDTH,(i / COLUMNS) * CELL_HEIGHT);
+            for x in 0..GLYPH_WIDTH {
+                let column = GLYPHS.get(i as usize).map_or(0xff,|g| g[x as usize]);
+                for y in 0..GLYPH_HEIGHT {
+                    if column & (1 << y) != 0 {
+                        pixels[(((cy + y) * width + cx + x) * 4 + 3) as usize] = 255;
+                    }
+                }
+            }
+        }
+        BitmapFont{ atlas: RgbaMips{ width, height, levels: vec![pixels] } }
+    }
+
+    pub fn atlas(&self) -> &RgbaMips { &self.atlas }
+
+    fn cell_uv(&self,index:u32) -> [f32;4]
+    {
+        let (x,y) = ((index % COLUMNS) * CELL_WIDTH,(index / COLUMNS) * CELL_HEIGHT);
+        let (w,h) = (self.atlas.width as f32,self.atlas.height as f32);
+        [x as f32 / w,y as f32 / h,(x + GLYPH_WIDTH) as f32 / w,(y + GLYPH_HEIGHT) as f32 / h]
+    }
+
+    /// Atlas uv rectangle `[u0,v0,u1,v1]` of `c`, characters outside ' '..='~' show as '?'.
+    pub fn glyph_uv(&self,c:char) -> [f32;4]
+    {
+        let c = if (FIRST as char..=LAST as char).contains(&c) { c as u8 } else { b'?' };
+        self.cell_uv((c - FIRST) as u32)
+    }
+
+    /// A uv rectangle fully inside the solid block.
+    pub fn solid_uv(&self) -> [f32;4]
+    {
+        let [u0,v0,u1,v1] = self.cell_uv((LAST - FIRST) as u32 + 1);
+        let (du,dv) = ((u1 - u0) / 4.0,(v1 - v0) / 4.0);
+        [u0 + du,v0 + dv,u1 - du,v1 - dv]
+    }
+}
+
+impl Default for BitmapFont {
+    fn default() -> Self { BitmapFont::new() }
+}
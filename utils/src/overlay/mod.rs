@@ -0,0 +1,330 @@
+mod font;
+pub use font::{BitmapFont, GLYPH_WIDTH, GLYPH_HEIGHT};
+
+use std::rc::Rc;
+use wgpu::{Device, Queue, RenderPass, CommandEncoder, TextureView, BufferUsage, BindGroup, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindGroupDescriptor, BindGroupEntry, BindingType, BufferBindingType, BindingResource, ShaderStage, RenderPipeline, TextureSampleType, TextureViewDimension, SamplerDescriptor, FilterMode, RenderPassDescriptor, RenderPassColorAttachment, Operations, LoadOp};
+use cgmath::{Matrix4, Vector2, Vector4, SquareMatrix};
+use crate::bytes::AsBytes;
+use crate::vertex::VertexLayout;
+use crate::buffer::{GpuBuffer, GpuVec};
+use crate::texture::GpuTexture;
+use crate::pipeline::{PipelineCache, PipelineDesc, ColorFormat};
+use crate::material::BlendMode;
+use crate::camera::OPENGL_TO_WGPU_MATRIX;
+
+#[repr(C)]
+#[derive(Debug,Copy,Clone,PartialEq,VertexLayout,AsBytes)]
+pub struct OverlayVertex{
+    pub position: Vector2<f32>,
+    pub uv: Vector2<f32>,
+    pub color: Vector4<f32>,
+}
+
+/// Maps pixel coordinates, origin at the top left and y down, to clip space.
+pub fn pixel_projection(width:u32,height:u32) -> Matrix4<f32>
+{
+    OPENGL_TO_WGPU_MATRIX * cgmath::ortho(0.0,width.max(1) as f32,height.max(1) as f32,0.0,-1.0,1.0)
+}
+
+/// Immediate mode 2D quads and text in pixels, collected during a frame and drawn
+/// by an `OverlayRenderer` in the order they were added.
+pub struct Overlay{
+    font: BitmapFont,
+    vertices: Vec<OverlayVertex>,
+}
+
+impl Overlay {
+    pub fn new() -> Overlay
+    {
+        Overlay{
+            font: BitmapFont::new(),
+            vertices: Vec::new()
+        }
+    }
+
+    pub fn font(&self) -> &BitmapFont { &self.font }
+
+    /// Triangle list vertices, six per quad.
+    pub fn vertices(&self) -> &[OverlayVertex] { self.vertices.as_slice() }
+
+    pub fn clear(&mut self)
+    {
+        self.vertices.clear();
+    }
+
+    /// A quad from `pos` to `pos + size` showing the atlas rectangle `uv`.
+    pub fn quad(&mut self,pos:Vector2<f32>,size:Vector2<f32>,uv:[f32;4],color:Vector4<f32>)
+    {
+        let [u0,v0,u1,v1] = uv;
+        let corner = |x:f32,y:f32,u:f32,v:f32| OverlayVertex{
+            position: Vector2::new(pos.x + x * size.x,pos.y + y * size.y),
+            uv: Vector2::new(u,v),
+            color
+        };
+        let (a,b,c,d) = (corner(0.0,0.0,u0,v0),corner(1.0,0.0,u1,v0),corner(1.0,1.0,u1,v1),corner(0.0,1.0,u0,v1));
+        self.vertices.extend_from_slice(&[a,b,c,a,c,d]);
+    }
+
+    pub fn draw_rect(&mut self,pos:Vector2<f32>,size:Vector2<f32>,color:Vector4<f32>)
+    {
+        let uv = self.font.solid_uv();
+        self.quad(pos,size,uv,color);
+    }
+
+    /// Draws `text` with its top left corner at `pos`, `size` is the glyph height in pixels,
+    /// whole multiples of `GLYPH_HEIGHT` keep the pixels sharp. `\n` starts a new line.
+    pub fn draw_text(&mut self,pos:Vector2<f32>,size:f32,color:Vector4<f32>,text:&str)
+    {
+        let scale = size / GLYPH_HEIGHT as f32;
+        let glyph = Vector2::new(GLYPH_WIDTH as f32 * scale,size);
+        let mut cursor = pos;
+        for c in text.chars() {
+            if c == '\n' {
+                cursor = Vector2::new(pos.x,cursor.y + Self::line_height(size));
+                continue;
+            }
+            if c != ' ' {
+                let uv = self.font.glyph_uv(c);
+                self.quad(cursor,glyph,uv,color);
+            }
+            cursor.x += Self::advance(size);
+        }
+    }
+
+    fn advance(size:f32) -> f32 { (GLYPH_WIDTH + 1) as f32 * size / GLYPH_HEIGHT as f32 }
+    fn line_height(size:f32) -> f32 { (GLYPH_HEIGHT + 2) as f32 * size / GLYPH_HEIGHT as f32 }
+
+    /// The pixel size of `text` drawn by `draw_text`, including the spacing after the last glyph.
+    pub fn text_size(size:f32,text:&str) -> Vector2<f32>
+    {
+        let lines = text.split('\n');
+        let (count,longest) = lines.fold((0,0),|(n,w),l| (n + 1,w.max(l.chars().count())));
+        Vector2::new(longest as f32 * Self::advance(size),count as f32 * Self::line_height(size))
+    }
+}
+
+impl Default for Overlay {
+    fn default() -> Self { Overlay::new() }
+}
+
+const OVERLAY_WGSL: &str = r#"
+[[block]]
+struct Screen {
+    projection: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> screen: Screen;
+[[group(0), binding(1)]]
+var atlas: texture_2d<f32>;
+[[group(0), binding(2)]]
+var atlas_sampler: sampler;
+
+struct VertexInput {
+    [[location(0)]] position: vec2<f32>;
+    [[location(1)]] uv: vec2<f32>;
+    [[location(2)]] color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+    [[location(1)]] color: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn main(in: VertexInput) -> VertexOutput
+{
+    var out: VertexOutput;
+    out.clip_position = screen.projection * vec4<f32>(in.position,0.0,1.0);
+    out.uv = in.uv;
+    out.color = in.color;
+    return out;
+}
+
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    return in.color * textureSample(atlas,atlas_sampler,in.uv);
+}
+"#;
+
+/// Draws an `Overlay` alpha blended over whatever is already in the target, without
+/// depth or multisampling. `prepare` uploads and clears the overlay, then either record it
+/// into a pass with `execute` or let `render` open its own pass over the frame.
+pub struct OverlayRenderer{
+    screen: GpuBuffer<Matrix4<f32>>,
+    _atlas: GpuTexture,
+    bind_group: BindGroup,
+    pipeline: Rc<RenderPipeline>,
+    vertices: GpuVec<OverlayVertex>,
+}
+
+impl OverlayRenderer {
+    pub fn new(device:&Device,queue:&Queue,pipelines:&mut PipelineCache,color:ColorFormat,font:&BitmapFont) -> OverlayRenderer
+    {
+        let screen = GpuBuffer::from_value(device,Some("Overlay Projection"),BufferUsage::UNIFORM,&Matrix4::identity());
+        let atlas = GpuTexture::from_image(device,queue,font.atlas(),false,&SamplerDescriptor{
+            label: Some("Overlay Sampler"),
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+        let layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Overlay Layout"),
+            entries: &[
+                BindGroupLayoutEntry{
+                    binding: 0,
+                    visibility: ShaderStage::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding: 1,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float{ filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding: 2,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Sampler{
+                        filtering: true,
+                        comparison: false
+                    },
+                    count: None
+                }
+            ]
+        }));
+        let bind_group = device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Overlay Bind Group"),
+            layout: &layout,
+            entries: &[
+                BindGroupEntry{ binding: 0, resource: screen.as_entire_binding() },
+                BindGroupEntry{ binding: 1, resource: BindingResource::TextureView(&atlas.view) },
+                BindGroupEntry{ binding: 2, resource: BindingResource::Sampler(&atlas.sampler) }
+            ]
+        });
+
+        let shader = pipelines.shader(device,"Overlay",OVERLAY_WGSL);
+        let layouts = vec![pipelines.layout(&layout)];
+        let pipeline = pipelines.get(device,&PipelineDesc{
+            color: Some(color),
+            blend: BlendMode::Alpha,
+            ..PipelineDesc::new(shader,layouts,vec![OverlayVertex::layout()])
+        });
+        OverlayRenderer{
+            vertices: GpuVec::with_capacity(device,Some("Overlay Vertices"),BufferUsage::VERTEX,1024),
+            _atlas: atlas,
+            screen,
+            bind_group,
+            pipeline
+        }
+    }
+
+    /// Takes the quads of `overlay` for a `width` x `height` target, `overlay` is empty afterwards.
+    pub fn prepare(&mut self,device:&Device,queue:&Queue,overlay:&mut Overlay,width:u32,height:u32)
+    {
+        self.screen.set(queue,&pixel_projection(width,height));
+        self.vertices.clear();
+        self.vertices.extend_from_slice(overlay.vertices());
+        self.vertices.sync(device,queue);
+        overlay.clear();
+    }
+
+    pub fn execute<'a>(&'a self,pass:&mut RenderPass<'a>)
+    {
+        if self.vertices.is_empty() { return; }
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0,&self.bind_group,&[]);
+        pass.set_vertex_buffer(0,self.vertices.slice(..));
+        pass.draw(0..self.vertices.len() as u32,0..1);
+    }
+
+    /// A final pass that keeps the content of `frame` and draws the overlay on top.
+    pub fn render(&self,encoder:&mut CommandEncoder,frame:&TextureView)
+    {
+        if self.vertices.is_empty() { return; }
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor{
+            label: Some("Overlay Pass"),
+            color_attachments: &[RenderPassColorAttachment{
+                view: frame,
+                resolve_target: None,
+                ops: Operations{ load: LoadOp::Load, store: true }
+            }],
+            depth_stencil_attachment: None
+        });
+        self.execute(&mut pass);
+    }
+}
+
+mod test_overlay{
+    use crate::overlay::{Overlay, BitmapFont, pixel_projection, GLYPH_WIDTH, GLYPH_HEIGHT};
+    use cgmath::{Vector2, Vector4};
+
+    const WHITE: Vector4<f32> = Vector4::new(1.0,1.0,1.0,1.0);
+
+    fn alpha(font:&BitmapFont,u:f32,v:f32) -> u8
+    {
+        let a = font.atlas();
+        let (x,y) = ((u * a.width as f32) as u32,(v * a.height as f32) as u32);
+        a.levels[0][((y * a.width + x) * 4 + 3) as usize]
+    }
+
+    #[test]
+    fn font()
+    {
+        let font = BitmapFont::new();
+        let a = font.atlas();
+        assert_eq!(a.levels[0].len(),(a.width * a.height * 4) as usize);
+        // 'I' is a vertical bar with serifs, column 2 is set in every row
+        let [u0,v0,u1,v1] = font.glyph_uv('I');
+        let (du,dv) = ((u1 - u0) / GLYPH_WIDTH as f32,(v1 - v0) / GLYPH_HEIGHT as f32);
+        for y in 0..GLYPH_HEIGHT {
+            assert_eq!(alpha(&font,u0 + du * 2.5,v0 + dv * (y as f32 + 0.5)),255);
+        }
+        assert_eq!(alpha(&font,u0 + du * 0.5,v0 + dv * 3.5),0);
+        // nothing set in the padding right of the glyph
+        assert_eq!(alpha(&font,u1 + du * 0.5,v0 + dv * 0.5),0);
+        assert!(font.glyph_uv(' ').iter().zip(font.glyph_uv('!').iter()).any(|(a,b)| a != b));
+        assert_eq!(font.glyph_uv('\u{e9}'),font.glyph_uv('?'));
+        let [u0,v0,u1,v1] = font.solid_uv();
+        assert_eq!(alpha(&font,(u0 + u1) / 2.0,(v0 + v1) / 2.0),255);
+    }
+
+    #[test]
+    fn text()
+    {
+        let mut o = Overlay::new();
+        o.draw_text(Vector2::new(10.0,20.0),14.0,WHITE,"ab c\nd");
+        // spaces take room but no quad
+        assert_eq!(o.vertices().len(),4 * 6);
+        let v = o.vertices();
+        assert_eq!(v[0].position,Vector2::new(10.0,20.0));
+        assert_eq!(v[2].position,Vector2::new(20.0,34.0));
+        assert_eq!(v[6].position,Vector2::new(22.0,20.0));
+        assert_eq!(v[12].position,Vector2::new(46.0,20.0));
+        assert_eq!(v[18].position,Vector2::new(10.0,38.0));
+        assert_eq!(Overlay::text_size(14.0,"ab c\nd"),Vector2::new(48.0,36.0));
+
+        o.clear();
+        o.draw_rect(Vector2::new(0.0,0.0),Vector2::new(5.0,5.0),WHITE);
+        assert_eq!(o.vertices().len(),6);
+    }
+
+    #[test]
+    fn projection()
+    {
+        let m = pixel_projection(800,600);
+        let clip = |x:f32,y:f32| m * Vector4::new(x,y,0.0,1.0);
+        assert_eq!(clip(0.0,0.0),Vector4::new(-1.0,1.0,0.5,1.0));
+        assert_eq!(clip(800.0,600.0),Vector4::new(1.0,-1.0,0.5,1.0));
+    }
+}
@@ -0,0 +1,289 @@
+use std::rc::Rc;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use wgpu::{Device, ShaderModule, RenderPipeline, BindGroupLayout, TextureFormat, PrimitiveTopology, ShaderModuleDescriptor, ShaderFlags, PipelineLayoutDescriptor, RenderPipelineDescriptor, VertexState, FragmentState, ColorTargetState, PrimitiveState, FrontFace, PolygonMode, DepthStencilState, MultisampleState};
+use crate::vertex::VertexLayoutDesc;
+use crate::material::{BlendMode, CullMode, CompareDesc};
+
+/// Vertex stage `main` drawing one triangle over the whole target with 3 vertices and no
+/// vertex buffer. It passes `VertexOutput` with `uv`, 0,0 the top left corner, and sits on
+/// the far plane, so passes testing depth only cover what nothing was drawn over.
+pub const FULLSCREEN_TRIANGLE_WGSL: &str = r#"
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+[[stage(vertex)]]
+fn main([[builtin(vertex_index)]] index: u32) -> VertexOutput
+{
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 1.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+"#;
+
+/// `srgb_to_linear`, the exact sRGB decoding curve, for shaders writing colors that are
+/// already display encoded into an sRGB target, which would encode them a second time.
+pub const SRGB_DECODE_WGSL: &str = r#"
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32>
+{
+    let v = max(c, vec3<f32>(0.0, 0.0, 0.0));
+    let low = v / 12.92;
+    let high = pow((v + vec3<f32>(0.055, 0.055, 0.055)) / 1.055, vec3<f32>(2.4, 2.4, 2.4));
+    return mix(high, low, step(v, vec3<f32>(0.04045, 0.04045, 0.04045)));
+}
+"#;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct ShaderId(usize);
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct LayoutId(usize);
+
+/// `SwapChain` follows `PipelineCache::set_swap_chain_format`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum ColorFormat{ SwapChain, Format(TextureFormat) }
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct DepthState{
+    pub format: TextureFormat,
+    pub write: bool,
+    pub compare: CompareDesc,
+}
+
+/// Everything a pipeline is built from. Shaders and bind group layouts are registered
+/// with the cache first and referred to by id.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct PipelineDesc{
+    pub shader: ShaderId,
+    pub vs_entry: String,
+    pub fs_entry: String,
+    pub layouts: Vec<LayoutId>,
+    pub vertex_layouts: Vec<VertexLayoutDesc>,
+    /// `None` leaves out the fragment stage, for depth only passes.
+    pub color: Option<ColorFormat>,
+    pub blend: BlendMode,
+    pub depth: Option<DepthState>,
+    pub topology: PrimitiveTopology,
+    pub cull: CullMode,
+    pub sample_count: u32,
+    /// Only takes effect with more than one sample.
+    pub alpha_to_coverage: bool,
+}
+
+impl PipelineDesc {
+    /// A triangle list drawn into the swap chain with `main` entry points, no depth,
+    /// blending or culling.
+    pub fn new(shader:ShaderId,layouts:Vec<LayoutId>,vertex_layouts:Vec<VertexLayoutDesc>) -> PipelineDesc
+    {
+        PipelineDesc{
+            shader,
+            vs_entry: "main".to_string(),
+            fs_entry: "main".to_string(),
+            layouts,
+            vertex_layouts,
+            color: Some(ColorFormat::SwapChain),
+            blend: BlendMode::Replace,
+            depth: None,
+            topology: PrimitiveTopology::TriangleList,
+            cull: CullMode::None,
+            sample_count: 1,
+            alpha_to_coverage: false
+        }
+    }
+}
+
+/// Builds each distinct `PipelineDesc` once and hands out shared pipelines.
+pub struct PipelineCache{
+    swap_chain_format: TextureFormat,
+    shaders: Vec<ShaderModule>,
+    /// Name to id and the hash of the source it was compiled from.
+    shader_ids: HashMap<String,(ShaderId,u64)>,
+    layouts: Vec<Rc<BindGroupLayout>>,
+    pipelines: HashMap<PipelineDesc,Rc<RenderPipeline>>,
+}
+
+impl PipelineCache {
+    pub fn new(swap_chain_format:TextureFormat) -> PipelineCache
+    {
+        PipelineCache{
+            swap_chain_format,
+            shaders: Vec::new(),
+            shader_ids: Default::default(),
+            layouts: Vec::new(),
+            pipelines: Default::default()
+        }
+    }
+
+    /// Compiles a WGSL source registered under `name`, usually its path. The same name with the
+    /// same source keeps its id, a changed source (a reload) replaces the module in place and
+    /// drops the pipelines built from the old one.
+    pub fn shader(&mut self,device:&Device,name:&str,src:&str) -> ShaderId
+    {
+        let mut hasher = DefaultHasher::new();
+        src.hash(&mut hasher);
+        let hash = hasher.finish();
+        let existing = self.shader_ids.get(name).copied();
+        if let Some((id,h)) = existing {
+            if h == hash { return id; }
+        }
+        let module = device.create_shader_module(&ShaderModuleDescriptor{
+            label: Some(name),
+            source: wgpu::ShaderSource::Wgsl(src.into()),
+            flags: ShaderFlags::all()
+        });
+        let id = match existing {
+            Some((id,_)) => {
+                self.shaders[id.0] = module;
+                self.pipelines.retain(|desc,_| desc.shader != id);
+                id
+            }
+            None => {
+                self.shaders.push(module);
+                ShaderId(self.shaders.len() - 1)
+            }
+        };
+        self.shader_ids.insert(name.to_string(),(id,hash));
+        id
+    }
+
+    /// Layouts are told apart by identity, registering the same `Rc` again returns its id.
+    pub fn layout(&mut self,layout:&Rc<BindGroupLayout>) -> LayoutId
+    {
+        match self.layouts.iter().position(|l| Rc::ptr_eq(l,layout)) {
+            Some(i) => LayoutId(i),
+            None => {
+                self.layouts.push(layout.clone());
+                LayoutId(self.layouts.len() - 1)
+            }
+        }
+    }
+
+    pub fn get(&mut self,device:&Device,desc:&PipelineDesc) -> Rc<RenderPipeline>
+    {
+        if let Some(p) = self.pipelines.get(desc) {
+            return p.clone();
+        }
+        let pipeline = Rc::new(self.create(device,desc));
+        self.pipelines.insert(desc.clone(),pipeline.clone());
+        pipeline
+    }
+
+    pub fn swap_chain_format(&self) -> TextureFormat { self.swap_chain_format }
+
+    /// Drops the pipelines drawing into the swap chain, `get` rebuilds them for the new format.
+    pub fn set_swap_chain_format(&mut self,format:TextureFormat)
+    {
+        if format == self.swap_chain_format { return; }
+        self.swap_chain_format = format;
+        self.pipelines.retain(|desc,_| desc.color != Some(ColorFormat::SwapChain));
+    }
+
+    pub fn len(&self) -> usize { self.pipelines.len() }
+    pub fn is_empty(&self) -> bool { self.pipelines.is_empty() }
+
+    fn create(&self,device:&Device,desc:&PipelineDesc) -> RenderPipeline
+    {
+        let bind_group_layouts:Vec<_> = desc.layouts.iter().map(|l| self.layouts[l.0].as_ref()).collect();
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor{
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: bind_group_layouts.as_slice(),
+            push_constant_ranges: &[]
+        });
+        let shader = &self.shaders[desc.shader.0];
+        let vertex_buffers:Vec<_> = desc.vertex_layouts.iter().map(|l| l.as_layout()).collect();
+        let targets:Vec<_> = desc.color.iter().map(|c| ColorTargetState{
+            format: match c {
+                ColorFormat::SwapChain => self.swap_chain_format,
+                ColorFormat::Format(f) => *f
+            },
+            blend: Some(desc.blend.to_wgpu()),
+            write_mask: wgpu::ColorWrite::all()
+        }).collect();
+        device.create_render_pipeline(&RenderPipelineDescriptor{
+            label: Some("Render Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState{
+                module: shader,
+                entry_point: desc.vs_entry.as_str(),
+                buffers: vertex_buffers.as_slice()
+            },
+            fragment: if targets.is_empty() { None } else { Some(FragmentState{
+                module: shader,
+                entry_point: desc.fs_entry.as_str(),
+                targets: targets.as_slice()
+            }) },
+            primitive: PrimitiveState{
+                topology: desc.topology,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: desc.cull.to_wgpu(),
+                clamp_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false
+            },
+            depth_stencil: desc.depth.map(|d| DepthStencilState{
+                format: d.format,
+                depth_write_enabled: d.write,
+                depth_compare: d.compare.to_wgpu(),
+                stencil: Default::default(),
+                bias: Default::default()
+            }),
+            multisample: MultisampleState{
+                count: desc.sample_count,
+                mask: u64::MAX,
+                alpha_to_coverage_enabled: desc.alpha_to_coverage && desc.sample_count > 1
+            }
+        })
+    }
+}
+
+mod test_pipeline{
+    use crate::pipeline::{PipelineDesc, ShaderId, LayoutId, DepthState};
+    use crate::material::CompareDesc;
+    use crate::vertex::VertexLayoutDesc;
+    use wgpu::{TextureFormat, InputStepMode};
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    fn hash(desc:&PipelineDesc) -> u64
+    {
+        let mut hasher = DefaultHasher::new();
+        desc.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn desc() -> PipelineDesc
+    {
+        let vertex = VertexLayoutDesc{ array_stride: 12, step_mode: InputStepMode::Vertex, attributes: wgpu::vertex_attr_array![0 => Float32x3].to_vec() };
+        PipelineDesc{
+            depth: Some(DepthState{ format: TextureFormat::Depth32Float, write: true, compare: CompareDesc::Less }),
+            sample_count: 4,
+            ..PipelineDesc::new(ShaderId(0),vec![LayoutId(0),LayoutId(1)],vec![vertex])
+        }
+    }
+
+    #[test]
+    fn equal()
+    {
+        assert_eq!(desc(),desc());
+        assert_eq!(hash(&desc()),hash(&desc()));
+    }
+
+    #[test]
+    fn differ()
+    {
+        let a2c = PipelineDesc{ alpha_to_coverage: true, ..desc() };
+        assert_ne!(a2c,desc());
+        assert_ne!(hash(&a2c),hash(&desc()));
+        let samples = PipelineDesc{ sample_count: 1, ..desc() };
+        assert_ne!(samples,desc());
+        assert_ne!(hash(&samples),hash(&desc()));
+        let shader = PipelineDesc{ shader: ShaderId(1), ..desc() };
+        assert_ne!(shader,desc());
+    }
+}
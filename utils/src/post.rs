@@ -0,0 +1,483 @@
+use std::rc::Rc;
+use wgpu::{Device, Queue, Buffer, BufferUsage, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType, ShaderStage, TextureSampleType, TextureViewDimension, TextureFormat, TextureUsage, RenderPipeline, RenderPass, SamplerDescriptor, AddressMode, FilterMode, LoadOp, Color};
+use wgpu::util::{DeviceExt, BufferInitDescriptor};
+use cgmath::Vector4;
+use crate::bytes::AsBytes;
+use crate::pipeline::{PipelineCache, PipelineDesc, ColorFormat, FULLSCREEN_TRIANGLE_WGSL, SRGB_DECODE_WGSL};
+use crate::texture::is_srgb;
+use crate::render_graph::{RenderGraph, RenderNode, GraphResources, GraphBinding, AttachmentDesc, AttachmentSize, AttachmentId, BindGroupId, PassDesc, Target};
+
+/// Format the scene is rendered in before post processing.
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum Tonemapper{
+    /// `c / (1 + c)`, keeps the hue, washes out bright colors slowly.
+    Reinhard,
+    /// The filmic curve of the ACES fit by Krzysztof Narkowicz.
+    Aces,
+}
+
+/// One full-screen effect of a `PostStack` with its parameters.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum PostEffect{
+    /// Scales the colors by `2^stops`.
+    Exposure(f32),
+    /// Adds a blurred copy of what is brighter than `threshold`, scaled by `intensity`.
+    Bloom{ threshold: f32, intensity: f32 },
+    /// Maps the HDR colors into 0..1.
+    Tonemap(Tonemapper),
+    /// Darkens from `radius` on, 0 the center and 1 the corners, by `strength` at the corners.
+    Vignette{ radius: f32, strength: f32 },
+    /// Encodes for display with `c^(1/gamma)`. Following effects see encoded colors, the
+    /// final blit decodes them again for an sRGB swap chain.
+    Gamma(f32),
+    /// Fast approximate anti-aliasing, blurring along edges at most `span` pixels. Best last,
+    /// after tonemapping and gamma.
+    Fxaa{ span: f32 },
+}
+
+impl PostEffect {
+    pub fn name(&self) -> &'static str
+    {
+        match self {
+            PostEffect::Exposure(_) => "Exposure",
+            PostEffect::Bloom{ .. } => "Bloom",
+            PostEffect::Tonemap(_) => "Tonemap",
+            PostEffect::Vignette{ .. } => "Vignette",
+            PostEffect::Gamma(_) => "Gamma",
+            PostEffect::Fxaa{ .. } => "Fxaa"
+        }
+    }
+
+    /// The parameters as the `params` uniform of the effect's shaders.
+    pub fn params(&self) -> Vector4<f32>
+    {
+        match *self {
+            PostEffect::Exposure(stops) => Vector4::new(2f32.powf(stops),0.0,0.0,0.0),
+            PostEffect::Bloom{ threshold, intensity } => Vector4::new(threshold,intensity,0.0,0.0),
+            PostEffect::Tonemap(t) => Vector4::new(match t { Tonemapper::Reinhard => 0.0, Tonemapper::Aces => 1.0 },0.0,0.0,0.0),
+            PostEffect::Vignette{ radius, strength } => Vector4::new(radius,strength,0.0,0.0),
+            PostEffect::Gamma(gamma) => Vector4::new(1.0 / gamma.max(1e-3),0.0,0.0,0.0),
+            PostEffect::Fxaa{ span } => Vector4::new(span,0.0,0.0,0.0)
+        }
+    }
+
+    /// Whether `other` only differs in its parameters.
+    pub fn same_kind(&self,other:&PostEffect) -> bool
+    {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    /// Passes the effect adds to the graph.
+    pub fn pass_count(&self) -> usize
+    {
+        match self {
+            // bright parts, horizontal and vertical blur at half size, then added back
+            PostEffect::Bloom{ .. } => 4,
+            _ => 1
+        }
+    }
+}
+
+/// Whether the final blit has to undo the display encoding of a `Gamma` effect, as the
+/// swap chain `format` encodes itself.
+pub fn blit_decodes(effects:&[PostEffect],format:TextureFormat) -> bool
+{
+    is_srgb(format) && effects.iter().any(|e| matches!(e,PostEffect::Gamma(_)))
+}
+
+fn blit_params(effects:&[PostEffect],format:TextureFormat) -> Vector4<f32>
+{
+    Vector4::new(if blit_decodes(effects,format) { 1.0 } else { 0.0 },0.0,0.0,0.0)
+}
+
+const POST_COMMON_WGSL: &str = r#"
+[[group(0), binding(0)]]
+var src: texture_2d<f32>;
+[[group(0), binding(1)]]
+var src_sampler: sampler;
+
+[[block]]
+struct Params {
+    v: vec4<f32>;
+};
+[[group(0), binding(2)]]
+var<uniform> params: Params;
+
+fn texel() -> vec2<f32>
+{
+    return vec2<f32>(1.0, 1.0) / vec2<f32>(textureDimensions(src));
+}
+
+fn src_color(uv: vec2<f32>) -> vec3<f32>
+{
+    return textureSampleLevel(src, src_sampler, uv, 0.0).rgb;
+}
+"#;
+
+const EXPOSURE_WGSL: &str = r#"
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    return vec4<f32>(src_color(in.uv) * params.v.x, 1.0);
+}
+"#;
+
+const TONEMAP_WGSL: &str = r#"
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    let c = max(src_color(in.uv), vec3<f32>(0.0, 0.0, 0.0));
+    if (params.v.x > 0.5) {
+        let a = c * (c * 2.51 + vec3<f32>(0.03, 0.03, 0.03));
+        let b = c * (c * 2.43 + vec3<f32>(0.59, 0.59, 0.59)) + vec3<f32>(0.14, 0.14, 0.14);
+        return vec4<f32>(clamp(a / b, vec3<f32>(0.0, 0.0, 0.0), vec3<f32>(1.0, 1.0, 1.0)), 1.0);
+    }
+    return vec4<f32>(c / (c + vec3<f32>(1.0, 1.0, 1.0)), 1.0);
+}
+"#;
+
+const VIGNETTE_WGSL: &str = r#"
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    // 1 in the corners
+    let d = length(in.uv - vec2<f32>(0.5, 0.5)) * 1.41421356;
+    let x = clamp((d - params.v.x) / max(1.0 - params.v.x, 0.0001), 0.0, 1.0);
+    let f = 1.0 - params.v.y * x * x * (3.0 - 2.0 * x);
+    return vec4<f32>(src_color(in.uv) * f, 1.0);
+}
+"#;
+
+const GAMMA_WGSL: &str = r#"
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    let g = params.v.x;
+    return vec4<f32>(pow(max(src_color(in.uv), vec3<f32>(0.0, 0.0, 0.0)), vec3<f32>(g, g, g)), 1.0);
+}
+"#;
+
+const FXAA_WGSL: &str = r#"
+fn luma(c: vec3<f32>) -> f32
+{
+    return dot(c, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    let t = texel();
+    let nw = luma(src_color(in.uv + vec2<f32>(-1.0, -1.0) * t));
+    let ne = luma(src_color(in.uv + vec2<f32>(1.0, -1.0) * t));
+    let sw = luma(src_color(in.uv + vec2<f32>(-1.0, 1.0) * t));
+    let se = luma(src_color(in.uv + vec2<f32>(1.0, 1.0) * t));
+    let center = src_color(in.uv);
+    let m = luma(center);
+    let luma_min = min(m, min(min(nw, ne), min(sw, se)));
+    let luma_max = max(m, max(max(nw, ne), max(sw, se)));
+
+    // across the edge
+    var dir: vec2<f32> = vec2<f32>(sw + se - nw - ne, nw + sw - ne - se);
+    let reduce = max((nw + ne + sw + se) * 0.25 / 8.0, 1.0 / 128.0);
+    let scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + reduce);
+    let span = params.v.x;
+    dir = clamp(dir * scale, vec2<f32>(-span, -span), vec2<f32>(span, span)) * t;
+
+    let a = (src_color(in.uv - dir / 6.0) + src_color(in.uv + dir / 6.0)) * 0.5;
+    let b = a * 0.5 + (src_color(in.uv - dir * 0.5) + src_color(in.uv + dir * 0.5)) * 0.25;
+    let lb = luma(b);
+    if (lb < luma_min || lb > luma_max) {
+        return vec4<f32>(a, 1.0);
+    }
+    return vec4<f32>(b, 1.0);
+}
+"#;
+
+const BLOOM_BRIGHT_WGSL: &str = r#"
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    let c = src_color(in.uv);
+    let brightest = max(c.r, max(c.g, c.b));
+    return vec4<f32>(c * (max(brightest - params.v.x, 0.0) / max(brightest, 0.0001)), 1.0);
+}
+"#;
+
+/// 9 tap gaussian in 5 samples, linear filtering averages the pairs.
+const BLOOM_BLUR_WGSL: &str = r#"
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    let offset = params.v.zw * texel();
+    var c: vec3<f32> = src_color(in.uv) * 0.2270270270;
+    c = c + (src_color(in.uv + offset * 1.3846153846) + src_color(in.uv - offset * 1.3846153846)) * 0.3162162162;
+    c = c + (src_color(in.uv + offset * 3.2307692308) + src_color(in.uv - offset * 3.2307692308)) * 0.0702702703;
+    return vec4<f32>(c, 1.0);
+}
+"#;
+
+const BLOOM_COMPOSITE_WGSL: &str = r#"
+[[group(0), binding(3)]]
+var bloom: texture_2d<f32>;
+
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    let glow = textureSampleLevel(bloom, src_sampler, in.uv, 0.0).rgb;
+    return vec4<f32>(src_color(in.uv) + glow * params.v.y, 1.0);
+}
+"#;
+
+const BLIT_WGSL: &str = r#"
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    let c = clamp(src_color(in.uv), vec3<f32>(0.0, 0.0, 0.0), vec3<f32>(1.0, 1.0, 1.0));
+    if (params.v.x > 0.5) {
+        // the swap chain encodes again what the gamma effect did
+        return vec4<f32>(srgb_to_linear(c), 1.0);
+    }
+    return vec4<f32>(c, 1.0);
+}
+"#;
+
+/// The WGSL of a post processing pass: the source texture, its sampler and the `params`
+/// uniform in group 0, a full-screen vertex stage, `srgb_to_linear` and `fragment`.
+pub fn post_wgsl(fragment:&str) -> String
+{
+    format!("{}\n{}\n{}\n{}",FULLSCREEN_TRIANGLE_WGSL,SRGB_DECODE_WGSL,POST_COMMON_WGSL,fragment)
+}
+
+fn fragment_of(effect:&PostEffect) -> &'static str
+{
+    match effect {
+        PostEffect::Exposure(_) => EXPOSURE_WGSL,
+        PostEffect::Tonemap(_) => TONEMAP_WGSL,
+        PostEffect::Vignette{ .. } => VIGNETTE_WGSL,
+        PostEffect::Gamma(_) => GAMMA_WGSL,
+        PostEffect::Fxaa{ .. } => FXAA_WGSL,
+        PostEffect::Bloom{ .. } => BLOOM_COMPOSITE_WGSL
+    }
+}
+
+/// Draws one full-screen triangle with the bind group of its pass.
+struct PostNode{
+    pipeline: Rc<RenderPipeline>,
+    bind_group: BindGroupId,
+}
+
+impl RenderNode for PostNode {
+    fn record<'a>(&'a self,pass:&mut RenderPass<'a>,res:&'a GraphResources)
+    {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0,res.bind_group(self.bind_group),&[]);
+        pass.draw(0..3,0..1);
+    }
+}
+
+/// A chain of full-screen effects from an HDR target into the swap chain, as passes of a
+/// `RenderGraph`. The scene renders into `target()`, single sampled, every effect into
+/// an attachment of its own and a last pass copies the result into the swap chain. The
+/// attachments follow the swap chain size through `RenderGraph::resize`.
+pub struct PostStack{
+    effects: Vec<PostEffect>,
+    target: AttachmentId,
+    params: Vec<Rc<Buffer>>,
+    blit_params: Rc<Buffer>,
+    format: TextureFormat,
+}
+
+impl PostStack {
+    /// Adds the attachments and passes of `effects` to `graph`, call `RenderGraph::build`
+    /// once the passes rendering the scene into `target()` are added as well.
+    pub fn new(device:&Device,graph:&mut RenderGraph,pipelines:&mut PipelineCache,effects:Vec<PostEffect>) -> PostStack
+    {
+        let hdr = |graph:&mut RenderGraph,label:String,scale:f32| graph.add_attachment(AttachmentDesc{
+            label,
+            format: HDR_FORMAT,
+            size: AttachmentSize::Relative(scale),
+            usage: TextureUsage::empty(),
+            multisampled: false
+        });
+        let params_buffer = |label:&str,v:Vector4<f32>| Rc::new(device.create_buffer_init(&BufferInitDescriptor{
+            label: Some(label),
+            contents: v.as_bytes(),
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST
+        }));
+        let target = hdr(graph,"HDR Color".to_string(),1.0);
+        let layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Post Bind Group Layout"),
+            entries: Self::layout_entries(false).as_slice()
+        }));
+        let bloom_layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Bloom Bind Group Layout"),
+            entries: Self::layout_entries(true).as_slice()
+        }));
+        let sampler = Rc::new(device.create_sampler(&SamplerDescriptor{
+            label: Some("Post Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        }));
+
+        let layouts = vec![pipelines.layout(&layout)];
+        let bloom_layouts = vec![pipelines.layout(&bloom_layout)];
+        let pipeline = |pipelines:&mut PipelineCache,shader_name:&str,fragment:&str,layouts:&Vec<_>,color:ColorFormat| {
+            let shader = pipelines.shader(device,shader_name,post_wgsl(fragment).as_str());
+            pipelines.get(device,&PipelineDesc{
+                color: Some(color),
+                ..PipelineDesc::new(shader,layouts.clone(),Vec::new())
+            })
+        };
+        let add_pass = |graph:&mut RenderGraph,label:String,pipeline:Rc<RenderPipeline>,layout:&Rc<BindGroupLayout>,reads:Vec<AttachmentId>,params:&Rc<Buffer>,output:Target| {
+            let mut entries = vec![
+                (0,GraphBinding::Attachment(reads[0])),
+                (1,GraphBinding::Sampler(sampler.clone())),
+                (2,GraphBinding::Buffer(params.clone()))
+            ];
+            if let Some(&extra) = reads.get(1) {
+                entries.push((3,GraphBinding::Attachment(extra)));
+            }
+            let bind_group = graph.add_bind_group(format!("{} Bind Group",label).as_str(),layout.clone(),entries);
+            graph.add_pass(PassDesc{
+                label,
+                colors: vec![(output,LoadOp::Clear(Color::BLACK))],
+                depth: None,
+                reads
+            },Box::new(PostNode{ pipeline, bind_group }));
+        };
+
+        let mut input = target;
+        let mut params = Vec::with_capacity(effects.len());
+        for (i,effect) in effects.iter().enumerate() {
+            let name = effect.name();
+            let buffer = params_buffer(format!("{} Params",name).as_str(),effect.params());
+            let output = hdr(graph,format!("Post {} {}",i,name),1.0);
+            if let PostEffect::Bloom{ .. } = effect {
+                let bright = hdr(graph,format!("Post {} Bloom Bright",i),0.5);
+                let blur_x = hdr(graph,format!("Post {} Bloom Blur X",i),0.5);
+                let blur_y = hdr(graph,format!("Post {} Bloom Blur Y",i),0.5);
+                let bright_pipeline = pipeline(pipelines,"Post Bloom Bright",BLOOM_BRIGHT_WGSL,&layouts,ColorFormat::Format(HDR_FORMAT));
+                add_pass(graph,format!("Post {} Bloom Bright",i),bright_pipeline,&layout,vec![input],&buffer,Target::Attachment(bright));
+                for &(from,to,dir) in [(bright,blur_x,(1.0,0.0)),(blur_x,blur_y,(0.0,1.0))].iter() {
+                    let blur_params = params_buffer("Bloom Blur Params",Vector4::new(0.0,0.0,dir.0,dir.1));
+                    let blur_pipeline = pipeline(pipelines,"Post Bloom Blur",BLOOM_BLUR_WGSL,&layouts,ColorFormat::Format(HDR_FORMAT));
+                    add_pass(graph,format!("Post {} Bloom Blur {}",i,if dir.0 > 0.0 { "X" } else { "Y" }),blur_pipeline,&layout,vec![from],&blur_params,Target::Attachment(to));
+                }
+                let composite = pipeline(pipelines,"Post Bloom",BLOOM_COMPOSITE_WGSL,&bloom_layouts,ColorFormat::Format(HDR_FORMAT));
+                add_pass(graph,format!("Post {} Bloom",i),composite,&bloom_layout,vec![input,blur_y],&buffer,Target::Attachment(output));
+            }else{
+                let p = pipeline(pipelines,format!("Post {}",name).as_str(),fragment_of(effect),&layouts,ColorFormat::Format(HDR_FORMAT));
+                add_pass(graph,format!("Post {} {}",i,name),p,&layout,vec![input],&buffer,Target::Attachment(output));
+            }
+            params.push(buffer);
+            input = output;
+        }
+
+        let format = graph.resources().format();
+        let blit_params = params_buffer("Blit Params",blit_params(effects.as_slice(),format));
+        let blit = pipeline(pipelines,"Post Blit",BLIT_WGSL,&layouts,ColorFormat::SwapChain);
+        add_pass(graph,"Post Blit".to_string(),blit,&layout,vec![input],&blit_params,Target::SwapChain);
+
+        PostStack{ effects, target, params, blit_params, format }
+    }
+
+    /// Group 0 of the post shaders, with the second texture of the bloom composite when `bloom`.
+    pub fn layout_entries(bloom:bool) -> Vec<BindGroupLayoutEntry>
+    {
+        let texture = |binding:u32| BindGroupLayoutEntry{
+            binding,
+            visibility: ShaderStage::FRAGMENT,
+            ty: BindingType::Texture{
+                sample_type: TextureSampleType::Float{ filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false
+            },
+            count: None
+        };
+        let mut entries = vec![
+            texture(0),
+            BindGroupLayoutEntry{
+                binding: 1,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Sampler{ filtering: true, comparison: false },
+                count: None
+            },
+            BindGroupLayoutEntry{
+                binding: 2,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Buffer{
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None
+                },
+                count: None
+            }
+        ];
+        if bloom {
+            entries.push(texture(3));
+        }
+        entries
+    }
+
+    /// The HDR attachment the scene has to render into, in `HDR_FORMAT`.
+    pub fn target(&self) -> AttachmentId { self.target }
+    pub fn effects(&self) -> &[PostEffect] { self.effects.as_slice() }
+
+    /// Changes the parameters of effect `i`. Returns false and changes nothing when
+    /// `effect` is of another kind, a different chain needs a new graph.
+    pub fn set(&mut self,queue:&Queue,i:usize,effect:PostEffect) -> bool
+    {
+        match self.effects.get(i) {
+            Some(e) if e.same_kind(&effect) => {}
+            _ => return false
+        }
+        self.effects[i] = effect;
+        queue.write_buffer(&self.params[i],0,effect.params().as_bytes());
+        true
+    }
+
+    /// Whether the final blit decodes depends on the swap chain format, call this with the
+    /// new format whenever the swap chain is recreated in another one.
+    pub fn set_format(&mut self,queue:&Queue,format:TextureFormat)
+    {
+        if format == self.format { return; }
+        self.format = format;
+        queue.write_buffer(&self.blit_params,0,blit_params(self.effects.as_slice(),format).as_bytes());
+    }
+}
+
+mod test_post{
+    use crate::post::{PostEffect, Tonemapper, blit_decodes};
+    use wgpu::TextureFormat;
+
+    #[test]
+    fn params()
+    {
+        assert_eq!(PostEffect::Exposure(1.0).params().x,2.0);
+        assert_eq!(PostEffect::Exposure(-2.0).params().x,0.25);
+        assert_eq!(PostEffect::Gamma(2.0).params().x,0.5);
+        assert_eq!(PostEffect::Tonemap(Tonemapper::Aces).params().x,1.0);
+        assert_eq!(PostEffect::Tonemap(Tonemapper::Reinhard).params().x,0.0);
+        let bloom = PostEffect::Bloom{ threshold: 1.0, intensity: 0.3 };
+        assert_eq!((bloom.params().x,bloom.params().y),(1.0,0.3));
+        assert_eq!(bloom.pass_count(),4);
+        assert_eq!(PostEffect::Fxaa{ span: 8.0 }.pass_count(),1);
+        assert!(bloom.same_kind(&PostEffect::Bloom{ threshold: 2.0, intensity: 1.0 }));
+        assert!(!PostEffect::Tonemap(Tonemapper::Aces).same_kind(&PostEffect::Gamma(2.2)));
+    }
+
+    #[test]
+    fn blit()
+    {
+        let with_gamma = [PostEffect::Tonemap(Tonemapper::Aces),PostEffect::Gamma(2.2)];
+        assert!(blit_decodes(&with_gamma,TextureFormat::Bgra8UnormSrgb));
+        assert!(!blit_decodes(&with_gamma,TextureFormat::Rgba16Float));
+        assert!(!blit_decodes(&with_gamma[..1],TextureFormat::Bgra8UnormSrgb));
+    }
+}
@@ -0,0 +1,630 @@
+use std::fmt::{Display, Formatter};
+use std::collections::HashMap;
+use naga::{Module, TypeInner, ScalarKind, Binding, ShaderStage, Handle, Type, GlobalVariable, StorageClass, StorageAccess, Expression, ImageClass, ImageDimension};
+use naga::valid::{Validator, ValidationFlags, Capabilities};
+use wgpu::{VertexFormat, BindingType, BindGroupLayoutEntry, BindGroupLayoutDescriptor, BufferBindingType, TextureSampleType, TextureViewDimension};
+use crate::vertex::VertexLayoutDesc;
+
+/// One `[[location(n)]]` input of a vertex entry point.
+#[derive(Debug,Clone,PartialEq)]
+pub struct ShaderInput{
+    pub name: String,
+    pub location: u32,
+    pub kind: ScalarKind,
+    pub components: u32,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum ReflectError{
+    Parse(String),
+    Invalid(String),
+    NoEntryPoint(String),
+    MissingAttribute{ location: u32, name: String },
+    TypeMismatch{ location: u32, name: String, shader: String, format: VertexFormat },
+    DuplicateLocation(u32),
+    /// The formats a field type maps to do not cover the field, a wrong `VertexAttr`.
+    AttributeSize{ location: u32, name: String, formats: u64, field: u64 },
+    /// Storage textures are not reflected.
+    Unsupported{ group: u32, binding: u32, name: String },
+    MissingGroup(u32),
+    MissingBinding{ group: u32, binding: u32, name: String },
+    BindingMismatch{ group: u32, binding: u32, name: String, shader: String, layout: String },
+    BindingVisibility{ group: u32, binding: u32, name: String, stages: wgpu::ShaderStage },
+}
+
+impl Display for ReflectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReflectError::Parse(e) => write!(f,"shader does not parse: {}",e),
+            ReflectError::Invalid(e) => write!(f,"shader is not valid: {}",e),
+            ReflectError::NoEntryPoint(e) => write!(f,"no vertex entry point named \"{}\"",e),
+            ReflectError::MissingAttribute{ location, name } =>
+                write!(f,"shader input \"{}\" at location {} is not provided by any vertex buffer",name,location),
+            ReflectError::TypeMismatch{ location, name, shader, format } =>
+                write!(f,"shader input \"{}\" at location {} is {} but the vertex buffer provides {:?}",name,location,shader,format),
+            ReflectError::DuplicateLocation(l) => write!(f,"location {} is provided by more than one vertex attribute",l),
+            ReflectError::AttributeSize{ location, name, formats, field } =>
+                write!(f,"vertex field \"{}\" at location {} is {} bytes but its formats take {}",name,location,field,formats),
+            ReflectError::Unsupported{ group, binding, name } =>
+                write!(f,"shader binding \"{}\" (group {}, binding {}) is a storage texture, these are not reflected",name,group,binding),
+            ReflectError::MissingGroup(g) => write!(f,"the shader uses group {} but no layout is given for it",g),
+            ReflectError::MissingBinding{ group, binding, name } =>
+                write!(f,"shader binding \"{}\" (group {}, binding {}) is not in the bind group layout",name,group,binding),
+            ReflectError::BindingMismatch{ group, binding, name, shader, layout } =>
+                write!(f,"shader binding \"{}\" (group {}, binding {}) is {} but the layout has {}",name,group,binding,shader,layout),
+            ReflectError::BindingVisibility{ group, binding, name, stages } =>
+                write!(f,"shader binding \"{}\" (group {}, binding {}) is used in {:?} but the layout does not make it visible there",name,group,binding,stages),
+        }
+    }
+}
+
+impl std::error::Error for ReflectError {}
+
+pub fn parse_wgsl(src:&str) -> Result<Module,ReflectError>
+{
+    naga::front::wgsl::parse_str(src).map_err(|e| ReflectError::Parse(format!("{:?}",e)))
+}
+
+fn scalar_type(module:&Module,ty:Handle<Type>) -> Option<(ScalarKind,u32)>
+{
+    match module.types[ty].inner {
+        TypeInner::Scalar{ kind, .. } => Some((kind,1)),
+        TypeInner::Vector{ size, kind, .. } => Some((kind,size as u32)),
+        _ => None
+    }
+}
+
+fn type_name(kind:ScalarKind,components:u32) -> String
+{
+    let s = match kind {
+        ScalarKind::Float => "f32",
+        ScalarKind::Sint => "i32",
+        ScalarKind::Uint => "u32",
+        ScalarKind::Bool => "bool"
+    };
+    if components == 1 { s.to_string() } else { format!("vec{}<{}>",components,s) }
+}
+
+/// Scalar kind and component count a vertex format shows up as in the shader.
+/// Normalized formats are floats in the shader.
+pub fn format_type(format:VertexFormat) -> (ScalarKind,u32)
+{
+    use VertexFormat::*;
+    match format {
+        Uint8x2 | Uint16x2 | Uint32x2 => (ScalarKind::Uint,2),
+        Uint32x3 => (ScalarKind::Uint,3),
+        Uint8x4 | Uint16x4 | Uint32x4 => (ScalarKind::Uint,4),
+        Uint32 => (ScalarKind::Uint,1),
+        Sint8x2 | Sint16x2 | Sint32x2 => (ScalarKind::Sint,2),
+        Sint32x3 => (ScalarKind::Sint,3),
+        Sint8x4 | Sint16x4 | Sint32x4 => (ScalarKind::Sint,4),
+        Sint32 => (ScalarKind::Sint,1),
+        Float32 | Float64 => (ScalarKind::Float,1),
+        Unorm8x2 | Snorm8x2 | Unorm16x2 | Snorm16x2 | Float16x2 | Float32x2 | Float64x2 => (ScalarKind::Float,2),
+        Float32x3 | Float64x3 => (ScalarKind::Float,3),
+        Unorm8x4 | Snorm8x4 | Unorm16x4 | Snorm16x4 | Float16x4 | Float32x4 | Float64x4 => (ScalarKind::Float,4)
+    }
+}
+
+/// The location inputs of the vertex entry point `entry`, builtins are skipped and
+/// struct arguments are flattened.
+pub fn vertex_inputs(module:&Module,entry:&str) -> Result<Vec<ShaderInput>,ReflectError>
+{
+    let ep = module.entry_points.iter()
+        .find(|ep| ep.stage == ShaderStage::Vertex && ep.name == entry)
+        .ok_or_else(|| ReflectError::NoEntryPoint(entry.to_string()))?;
+    let mut res = Vec::new();
+    let mut push = |name:&Option<String>,binding:&Option<Binding>,ty:Handle<Type>|{
+        if let Some(Binding::Location{ location, .. }) = binding {
+            if let Some((kind,components)) = scalar_type(module,ty) {
+                res.push(ShaderInput{
+                    name: name.clone().unwrap_or_default(),
+                    location: *location,
+                    kind,
+                    components
+                });
+            }
+        }
+    };
+    for arg in ep.function.arguments.iter() {
+        if arg.binding.is_some() {
+            push(&arg.name,&arg.binding,arg.ty);
+        }else if let TypeInner::Struct{ ref members, .. } = module.types[arg.ty].inner {
+            for m in members.iter() {
+                push(&m.name,&m.binding,m.ty);
+            }
+        }
+    }
+    res.sort_by_key(|i| i.location);
+    Ok(res)
+}
+
+/// Checks that every input of the vertex entry point is fed by an attribute of the
+/// same scalar kind and component count.
+pub fn check_vertex_inputs(module:&Module,entry:&str,layouts:&[VertexLayoutDesc]) -> Result<(),ReflectError>
+{
+    let mut formats = std::collections::HashMap::new();
+    for a in layouts.iter().flat_map(|l| l.attributes.iter()) {
+        if formats.insert(a.shader_location,a.format).is_some() {
+            return Err(ReflectError::DuplicateLocation(a.shader_location));
+        }
+    }
+    for input in vertex_inputs(module,entry)?.into_iter() {
+        let format = *formats.get(&input.location).ok_or_else(|| ReflectError::MissingAttribute{
+            location: input.location,
+            name: input.name.clone()
+        })?;
+        if format_type(format) != (input.kind,input.components) {
+            return Err(ReflectError::TypeMismatch{
+                location: input.location,
+                shader: type_name(input.kind,input.components),
+                name: input.name,
+                format
+            });
+        }
+    }
+    Ok(())
+}
+
+pub fn check_vertex_layouts(src:&str,entry:&str,layouts:&[VertexLayoutDesc]) -> Result<(),ReflectError>
+{
+    check_vertex_inputs(&parse_wgsl(src)?,entry,layouts)
+}
+
+/// One `[[group(g), binding(b)]]` resource of a shader.
+#[derive(Debug,Clone,PartialEq)]
+pub struct ShaderBinding{
+    pub name: String,
+    pub group: u32,
+    pub binding: u32,
+    /// Stages whose entry points use the resource, directly or through the functions they call.
+    pub visibility: wgpu::ShaderStage,
+    pub ty: BindingType,
+}
+
+impl ShaderBinding {
+    pub fn entry(&self) -> BindGroupLayoutEntry
+    {
+        BindGroupLayoutEntry{
+            binding: self.binding,
+            visibility: self.visibility,
+            ty: self.ty,
+            count: None
+        }
+    }
+}
+
+/// The layout of one bind group as the shader declares it.
+#[derive(Debug,Clone,PartialEq)]
+pub struct GroupLayout{
+    pub group: u32,
+    pub entries: Vec<BindGroupLayoutEntry>,
+}
+
+impl GroupLayout {
+    pub fn descriptor<'a>(&'a self,label:Option<&'a str>) -> BindGroupLayoutDescriptor<'a>
+    {
+        BindGroupLayoutDescriptor{
+            label,
+            entries: self.entries.as_slice()
+        }
+    }
+}
+
+fn stage(stage:ShaderStage) -> wgpu::ShaderStage
+{
+    match stage {
+        ShaderStage::Vertex => wgpu::ShaderStage::VERTEX,
+        ShaderStage::Fragment => wgpu::ShaderStage::FRAGMENT,
+        ShaderStage::Compute => wgpu::ShaderStage::COMPUTE
+    }
+}
+
+/// Images sampled without a depth reference, by the samplers used on them.
+fn sampled_images(module:&Module) -> HashMap<Handle<GlobalVariable>,Vec<Handle<GlobalVariable>>>
+{
+    let mut res:HashMap<_,Vec<_>> = HashMap::new();
+    let functions = module.functions.iter().map(|(_,f)| f).chain(module.entry_points.iter().map(|ep| &ep.function));
+    for f in functions {
+        for (_,e) in f.expressions.iter() {
+            if let Expression::ImageSample{ image, sampler, depth_ref: None, .. } = *e {
+                if let (Expression::GlobalVariable(i),Expression::GlobalVariable(s)) = (&f.expressions[image],&f.expressions[sampler]) {
+                    res.entry(*s).or_default().push(*i);
+                }
+            }
+        }
+    }
+    res
+}
+
+// naga 0.5 keeps the access of a storage buffer on the variable
+fn read_only(var:&GlobalVariable) -> bool
+{
+    !var.storage_access.contains(StorageAccess::STORE)
+}
+
+fn binding_type(module:&Module,handle:Handle<GlobalVariable>,sampled:&HashMap<Handle<GlobalVariable>,Vec<Handle<GlobalVariable>>>) -> Option<BindingType>
+{
+    let var = &module.global_variables[handle];
+    match module.types[var.ty].inner {
+        TypeInner::Image{ dim, arrayed, ref class } => {
+            let view_dimension = match (dim,arrayed) {
+                (ImageDimension::D1,_) => TextureViewDimension::D1,
+                (ImageDimension::D2,false) => TextureViewDimension::D2,
+                (ImageDimension::D2,true) => TextureViewDimension::D2Array,
+                (ImageDimension::D3,_) => TextureViewDimension::D3,
+                (ImageDimension::Cube,false) => TextureViewDimension::Cube,
+                (ImageDimension::Cube,true) => TextureViewDimension::CubeArray
+            };
+            let (sample_type,multisampled) = match *class {
+                ImageClass::Sampled{ kind: ScalarKind::Sint, multi } => (TextureSampleType::Sint,multi),
+                ImageClass::Sampled{ kind: ScalarKind::Uint, multi } => (TextureSampleType::Uint,multi),
+                ImageClass::Sampled{ multi, .. } => (TextureSampleType::Float{ filterable: true },multi),
+                ImageClass::Depth{ .. } => (TextureSampleType::Depth,false),
+                ImageClass::Storage{ .. } => return None
+            };
+            Some(BindingType::Texture{ sample_type, view_dimension, multisampled })
+        }
+        TypeInner::Sampler{ comparison } => {
+            // depth and integer textures can only be read through samplers that do not filter
+            let filtering = comparison || !sampled.get(&handle).map_or(false,|images| images.iter().any(|i|{
+                !matches!(binding_type(module,*i,sampled),Some(BindingType::Texture{ sample_type: TextureSampleType::Float{ .. }, .. }))
+            }));
+            Some(BindingType::Sampler{ filtering, comparison })
+        }
+        _ => Some(BindingType::Buffer{
+            ty: match var.class {
+                StorageClass::Storage{ .. } => BufferBindingType::Storage{ read_only: read_only(var) },
+                _ => BufferBindingType::Uniform
+            },
+            has_dynamic_offset: false,
+            min_binding_size: None
+        })
+    }
+}
+
+/// Every resource of the module, ordered by group and binding.
+pub fn shader_bindings(module:&Module) -> Result<Vec<ShaderBinding>,ReflectError>
+{
+    let sampled = sampled_images(module);
+    // every function refers to all globals, the validator tells which are used
+    let info = Validator::new(ValidationFlags::all(),Capabilities::all()).validate(module)
+        .map_err(|e| ReflectError::Invalid(format!("{:?}",e)))?;
+    let mut res = Vec::new();
+    for (handle,var) in module.global_variables.iter() {
+        let rb = match var.binding {
+            Some(ref rb) => rb,
+            None => continue
+        };
+        let name = var.name.clone().unwrap_or_default();
+        let ty = binding_type(module,handle,&sampled).ok_or_else(|| ReflectError::Unsupported{
+            group: rb.group,
+            binding: rb.binding,
+            name: name.clone()
+        })?;
+        let visibility = module.entry_points.iter().enumerate()
+            .filter(|(i,_)| !info.get_entry_point(*i)[handle].is_empty())
+            .fold(wgpu::ShaderStage::NONE,|s,(_,ep)| s | stage(ep.stage));
+        res.push(ShaderBinding{ name, group: rb.group, binding: rb.binding, visibility, ty });
+    }
+    res.sort_by_key(|b| (b.group,b.binding));
+    Ok(res)
+}
+
+/// A layout for every group the module uses, in group order.
+pub fn bind_group_layouts(module:&Module) -> Result<Vec<GroupLayout>,ReflectError>
+{
+    let mut res:Vec<GroupLayout> = Vec::new();
+    for b in shader_bindings(module)?.iter() {
+        match res.last_mut() {
+            Some(l) if l.group == b.group => l.entries.push(b.entry()),
+            _ => res.push(GroupLayout{ group: b.group, entries: vec![b.entry()] })
+        }
+    }
+    Ok(res)
+}
+
+pub fn reflect_bind_group_layouts(src:&str) -> Result<Vec<GroupLayout>,ReflectError>
+{
+    bind_group_layouts(&parse_wgsl(src)?)
+}
+
+fn binding_type_name(ty:&BindingType) -> String
+{
+    match *ty {
+        BindingType::Buffer{ ty: BufferBindingType::Uniform, .. } => "a uniform buffer".to_string(),
+        BindingType::Buffer{ ty: BufferBindingType::Storage{ read_only: true }, .. } => "a read-only storage buffer".to_string(),
+        BindingType::Buffer{ .. } => "a storage buffer".to_string(),
+        BindingType::Sampler{ comparison: true, .. } => "sampler_comparison".to_string(),
+        BindingType::Sampler{ filtering: true, .. } => "a filtering sampler".to_string(),
+        BindingType::Sampler{ .. } => "a non-filtering sampler".to_string(),
+        BindingType::Texture{ sample_type, view_dimension, multisampled } => {
+            let dim = match view_dimension {
+                TextureViewDimension::D1 => "1d",
+                TextureViewDimension::D2 => "2d",
+                TextureViewDimension::D2Array => "2d_array",
+                TextureViewDimension::Cube => "cube",
+                TextureViewDimension::CubeArray => "cube_array",
+                TextureViewDimension::D3 => "3d"
+            };
+            let ms = if multisampled { "multisampled_" } else { "" };
+            match sample_type {
+                TextureSampleType::Depth => format!("texture_depth_{}{}",ms,dim),
+                TextureSampleType::Float{ .. } => format!("texture_{}{}<f32>",ms,dim),
+                TextureSampleType::Sint => format!("texture_{}{}<i32>",ms,dim),
+                TextureSampleType::Uint => format!("texture_{}{}<u32>",ms,dim)
+            }
+        }
+        BindingType::StorageTexture{ format, .. } => format!("a storage texture of {:?}",format)
+    }
+}
+
+/// Whether a layout entry of type `layout` can be bound where the shader declares `shader`.
+fn compatible(shader:&BindingType,layout:&BindingType) -> bool
+{
+    match (*shader,*layout) {
+        (BindingType::Buffer{ ty: s, .. },BindingType::Buffer{ ty: l, .. }) => match (s,l) {
+            (BufferBindingType::Uniform,BufferBindingType::Uniform) => true,
+            (BufferBindingType::Storage{ read_only },BufferBindingType::Storage{ read_only: l }) => read_only || !l,
+            _ => false
+        },
+        (BindingType::Sampler{ filtering, comparison },BindingType::Sampler{ filtering: l_filtering, comparison: l_comparison }) =>
+            comparison == l_comparison && (comparison || filtering || !l_filtering),
+        (BindingType::Texture{ sample_type, view_dimension, multisampled },BindingType::Texture{ sample_type: l_sample_type, view_dimension: l_dim, multisampled: l_ms }) =>
+            view_dimension == l_dim && multisampled == l_ms && match (sample_type,l_sample_type) {
+                (TextureSampleType::Float{ .. },TextureSampleType::Float{ .. }) => true,
+                (s,l) => s == l
+            },
+        _ => false
+    }
+}
+
+/// Checks that `entries` can be used as bind group `group` of the module: every binding
+/// of the group is there, has a matching type and is visible to the stages using it.
+/// Entries the shader does not use are ignored.
+pub fn check_bind_group_layout(module:&Module,group:u32,entries:&[BindGroupLayoutEntry]) -> Result<(),ReflectError>
+{
+    for b in shader_bindings(module)?.into_iter().filter(|b| b.group == group) {
+        let entry = entries.iter().find(|e| e.binding == b.binding).ok_or_else(|| ReflectError::MissingBinding{
+            group,
+            binding: b.binding,
+            name: b.name.clone()
+        })?;
+        if !compatible(&b.ty,&entry.ty) {
+            return Err(ReflectError::BindingMismatch{
+                group,
+                binding: b.binding,
+                shader: binding_type_name(&b.ty),
+                layout: binding_type_name(&entry.ty),
+                name: b.name
+            });
+        }
+        if !entry.visibility.contains(b.visibility) {
+            return Err(ReflectError::BindingVisibility{
+                group,
+                binding: b.binding,
+                name: b.name,
+                stages: b.visibility - entry.visibility
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Checks the layouts of a pipeline, `layouts[i]` is bind group `i`.
+pub fn check_bind_group_layouts(src:&str,layouts:&[&[BindGroupLayoutEntry]]) -> Result<(),ReflectError>
+{
+    let module = parse_wgsl(src)?;
+    if let Some(b) = shader_bindings(&module)?.iter().find(|b| b.group as usize >= layouts.len()) {
+        return Err(ReflectError::MissingGroup(b.group));
+    }
+    for (group,entries) in layouts.iter().enumerate() {
+        check_bind_group_layout(&module,group as u32,entries)?;
+    }
+    Ok(())
+}
+
+mod test_reflect{
+    use crate::reflect::{format_type, check_vertex_layouts, ReflectError, parse_wgsl, vertex_inputs, shader_bindings, bind_group_layouts, check_bind_group_layout, check_bind_group_layouts};
+    use naga::ScalarKind;
+    use crate::vertex::VertexLayoutDesc;
+    use wgpu::{InputStepMode, VertexFormat, BindingType, BindGroupLayoutEntry, BufferBindingType, TextureSampleType, TextureViewDimension, ShaderStage};
+
+    const SHADER:&str = r#"
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn main(in: VertexInput,[[location(5)]] id: u32,[[builtin(vertex_index)]] idx: u32) -> VertexOutput
+{
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(in.position,1.0);
+    out.color = in.color;
+    return out;
+}
+"#;
+
+    fn layouts(color:VertexFormat) -> Vec<VertexLayoutDesc>
+    {
+        vec![
+            VertexLayoutDesc{
+                array_stride: 28,
+                step_mode: InputStepMode::Vertex,
+                attributes: vec![
+                    wgpu::VertexAttribute{ format: VertexFormat::Float32x3, offset: 0, shader_location: 0 },
+                    wgpu::VertexAttribute{ format: color, offset: 12, shader_location: 1 },
+                ]
+            },
+            VertexLayoutDesc{
+                array_stride: 4,
+                step_mode: InputStepMode::Instance,
+                attributes: wgpu::vertex_attr_array![5 => Uint32].to_vec()
+            }
+        ]
+    }
+
+    #[test]
+    fn inputs()
+    {
+        let module = parse_wgsl(SHADER).unwrap();
+        let inputs:Vec<_> = vertex_inputs(&module,"main").unwrap().into_iter().map(|i| (i.name,i.location,i.components)).collect();
+        assert_eq!(inputs,vec![("position".to_string(),0,3),("color".to_string(),1,4),("id".to_string(),5,1)]);
+        assert_eq!(vertex_inputs(&module,"vs"),Err(ReflectError::NoEntryPoint("vs".to_string())));
+    }
+
+    #[test]
+    fn check()
+    {
+        assert_eq!(check_vertex_layouts(SHADER,"main",layouts(VertexFormat::Float32x4).as_slice()),Ok(()));
+        let e = check_vertex_layouts(SHADER,"main",layouts(VertexFormat::Float32x3).as_slice()).unwrap_err();
+        assert_eq!(e.to_string(),"shader input \"color\" at location 1 is vec4<f32> but the vertex buffer provides Float32x3");
+        let e = check_vertex_layouts(SHADER,"main",&layouts(VertexFormat::Float32x4)[..1]).unwrap_err();
+        assert_eq!(e,ReflectError::MissingAttribute{ location: 5, name: "id".to_string() });
+    }
+
+    #[test]
+    fn formats()
+    {
+        assert_eq!(format_type(VertexFormat::Unorm8x4),(ScalarKind::Float,4));
+        assert_eq!(format_type(VertexFormat::Uint16x2),(ScalarKind::Uint,2));
+        assert_eq!(format_type(VertexFormat::Sint32),(ScalarKind::Sint,1));
+        assert_eq!(format_type(VertexFormat::Float64x3),(ScalarKind::Float,3));
+    }
+
+    const BINDINGS:&str = r#"
+[[block]]
+struct Uniforms {
+    view: mat4x4<f32>;
+};
+[[block]]
+struct Light {
+    color: vec4<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> uniforms: Uniforms;
+[[group(1), binding(1)]]
+var<uniform> light: Light;
+
+[[group(0), binding(0)]]
+var t_diffuse: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_diffuse: sampler;
+[[group(0), binding(2)]]
+var t_depth: texture_depth_2d;
+[[group(0), binding(3)]]
+var s_depth: sampler;
+[[group(0), binding(4)]]
+var s_shadow: sampler_comparison;
+
+[[stage(vertex)]]
+fn vs_main([[location(0)]] position: vec3<f32>) -> [[builtin(position)]] vec4<f32>
+{
+    return uniforms.view * vec4<f32>(position,1.0);
+}
+
+fn light_color() -> vec4<f32>
+{
+    return light.color;
+}
+
+[[stage(fragment)]]
+fn fs_main([[builtin(position)]] p: vec4<f32>) -> [[location(0)]] vec4<f32>
+{
+    let uv = p.xy;
+    let shadow = textureSampleCompare(t_depth,s_shadow,uv,p.z);
+    let depth = textureSample(t_depth,s_depth,uv);
+    return textureSample(t_diffuse,s_diffuse,uv) * light_color() * shadow * depth;
+}
+"#;
+
+    fn texture(binding:u32,sample_type:TextureSampleType) -> BindGroupLayoutEntry
+    {
+        BindGroupLayoutEntry{
+            binding,
+            visibility: ShaderStage::FRAGMENT,
+            ty: BindingType::Texture{ sample_type, view_dimension: TextureViewDimension::D2, multisampled: false },
+            count: None
+        }
+    }
+
+    fn sampler(binding:u32,filtering:bool,comparison:bool) -> BindGroupLayoutEntry
+    {
+        BindGroupLayoutEntry{
+            binding,
+            visibility: ShaderStage::FRAGMENT,
+            ty: BindingType::Sampler{ filtering, comparison },
+            count: None
+        }
+    }
+
+    fn uniform(visibility:ShaderStage) -> BindGroupLayoutEntry
+    {
+        BindGroupLayoutEntry{
+            binding: 0,
+            visibility,
+            ty: BindingType::Buffer{ ty: BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
+            count: None
+        }
+    }
+
+    #[test]
+    fn bindings()
+    {
+        let module = parse_wgsl(BINDINGS).unwrap();
+        let bindings:Vec<_> = shader_bindings(&module).unwrap().into_iter().map(|b| (b.name,b.group,b.binding,b.visibility)).collect();
+        assert_eq!(bindings,vec![
+            ("t_diffuse".to_string(),0,0,ShaderStage::FRAGMENT),
+            ("s_diffuse".to_string(),0,1,ShaderStage::FRAGMENT),
+            ("t_depth".to_string(),0,2,ShaderStage::FRAGMENT),
+            ("s_depth".to_string(),0,3,ShaderStage::FRAGMENT),
+            ("s_shadow".to_string(),0,4,ShaderStage::FRAGMENT),
+            ("uniforms".to_string(),1,0,ShaderStage::VERTEX),
+            ("light".to_string(),1,1,ShaderStage::FRAGMENT),
+        ]);
+        let layouts = bind_group_layouts(&module).unwrap();
+        assert_eq!(layouts.iter().map(|l| l.group).collect::<Vec<_>>(),vec![0,1]);
+        assert_eq!(layouts[0].entries,vec![
+            texture(0,TextureSampleType::Float{ filterable: true }),
+            sampler(1,true,false),
+            texture(2,TextureSampleType::Depth),
+            sampler(3,false,false),
+            sampler(4,true,true),
+        ]);
+        assert_eq!(layouts[1].entries[0],uniform(ShaderStage::VERTEX));
+        assert_eq!(layouts[1].entries[1],BindGroupLayoutEntry{ binding: 1, ..uniform(ShaderStage::FRAGMENT) });
+    }
+
+    #[test]
+    fn check_bindings()
+    {
+        let module = parse_wgsl(BINDINGS).unwrap();
+        let mut group0 = bind_group_layouts(&module).unwrap().remove(0).entries;
+        assert_eq!(check_bind_group_layout(&module,0,group0.as_slice()),Ok(()));
+        // the filtering sampler may be bound to a layout that does not filter
+        group0[1] = sampler(1,false,false);
+        assert_eq!(check_bind_group_layout(&module,0,group0.as_slice()),Ok(()));
+        group0[3] = sampler(3,true,false);
+        let e = check_bind_group_layout(&module,0,group0.as_slice()).unwrap_err();
+        assert_eq!(e.to_string(),"shader binding \"s_depth\" (group 0, binding 3) is a non-filtering sampler but the layout has a filtering sampler");
+        group0[3] = sampler(3,false,false);
+        group0[2] = texture(2,TextureSampleType::Float{ filterable: false });
+        let e = check_bind_group_layout(&module,0,group0.as_slice()).unwrap_err();
+        assert_eq!(e.to_string(),"shader binding \"t_depth\" (group 0, binding 2) is texture_depth_2d but the layout has texture_2d<f32>");
+        group0.remove(2);
+        assert_eq!(check_bind_group_layout(&module,0,group0.as_slice()),Err(ReflectError::MissingBinding{ group: 0, binding: 2, name: "t_depth".to_string() }));
+
+        let group1 = [uniform(ShaderStage::FRAGMENT)];
+        let e = check_bind_group_layout(&module,1,&group1).unwrap_err();
+        assert_eq!(e,ReflectError::BindingVisibility{ group: 1, binding: 0, name: "uniforms".to_string(), stages: ShaderStage::VERTEX });
+        let group0 = bind_group_layouts(&module).unwrap().remove(0).entries;
+        assert_eq!(check_bind_group_layouts(BINDINGS,&[group0.as_slice()]),Err(ReflectError::MissingGroup(1)));
+    }
+}
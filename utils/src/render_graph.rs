@@ -0,0 +1,490 @@
+use std::rc::Rc;
+use std::fmt::{Display, Formatter};
+use crate::msaa::create_attachment;
+use wgpu::{Device, Texture, TextureView, TextureFormat, TextureUsage, SwapChainDescriptor, BindGroup, BindGroupLayout, BindGroupDescriptor, BindGroupEntry, BindingResource, Buffer, Sampler, CommandEncoder, RenderPass, RenderPassDescriptor, RenderPassColorAttachment, RenderPassDepthStencilAttachment, Operations, LoadOp, Color};
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct AttachmentId(usize);
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct PassId(usize);
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct BindGroupId(usize);
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum AttachmentSize{
+    /// Scale of the swap chain size, recreated on resize.
+    Relative(f32),
+    Fixed(u32,u32),
+}
+
+impl AttachmentSize {
+    pub fn resolve(&self,width:u32,height:u32) -> (u32,u32)
+    {
+        match *self {
+            AttachmentSize::Relative(s) => (((width as f32 * s) as u32).max(1),((height as f32 * s) as u32).max(1)),
+            AttachmentSize::Fixed(w,h) => (w,h)
+        }
+    }
+}
+
+/// A texture owned by the graph. `RENDER_ATTACHMENT` and `SAMPLED` are added to `usage`
+/// when a pass writes or reads it.
+#[derive(Debug,Clone)]
+pub struct AttachmentDesc{
+    pub label: String,
+    pub format: TextureFormat,
+    pub size: AttachmentSize,
+    pub usage: TextureUsage,
+    /// Uses the sample count of the graph, needed for attachments rendered together with
+    /// the swap chain.
+    pub multisampled: bool,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum Target{ SwapChain, Attachment(AttachmentId) }
+
+/// What a pass renders to and which attachments it samples, the pass order follows from it.
+#[derive(Debug,Clone)]
+pub struct PassDesc{
+    pub label: String,
+    pub colors: Vec<(Target,LoadOp<Color>)>,
+    pub depth: Option<(AttachmentId,LoadOp<f32>)>,
+    pub reads: Vec<AttachmentId>,
+}
+
+pub enum GraphBinding{
+    Attachment(AttachmentId),
+    Sampler(Rc<Sampler>),
+    Buffer(Rc<Buffer>),
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum GraphError{
+    Cycle(Vec<String>),
+    ReadWrite{ pass: String, attachment: String },
+    NeverWritten{ pass: String, attachment: String },
+    MixedSamples{ pass: String },
+}
+
+impl Display for GraphError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphError::Cycle(passes) => write!(f,"passes depend on each other: {}",passes.join(", ")),
+            GraphError::ReadWrite{ pass, attachment } =>
+                write!(f,"pass \"{}\" reads attachment \"{}\" it also renders to",pass,attachment),
+            GraphError::NeverWritten{ pass, attachment } =>
+                write!(f,"pass \"{}\" reads attachment \"{}\" but no pass renders to it",pass,attachment),
+            GraphError::MixedSamples{ pass } =>
+                write!(f,"pass \"{}\" renders to multisampled and single sampled targets together",pass),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+/// Records the draw calls of one pass, the graph has already begun it.
+pub trait RenderNode {
+    fn record<'a>(&'a self,pass:&mut RenderPass<'a>,res:&'a GraphResources);
+}
+
+struct Attachment{
+    desc: AttachmentDesc,
+    usage: TextureUsage,
+    texture: Option<(Texture,TextureView)>,
+}
+
+struct GraphBindGroup{
+    label: String,
+    layout: Rc<BindGroupLayout>,
+    entries: Vec<(u32,GraphBinding)>,
+    bind_group: Option<BindGroup>,
+}
+
+impl GraphBindGroup {
+    fn uses(&self,id:AttachmentId) -> bool
+    {
+        self.entries.iter().any(|(_,b)| matches!(b,GraphBinding::Attachment(a) if *a == id))
+    }
+}
+
+/// Attachments and bind groups of the graph, handed to the nodes while recording.
+pub struct GraphResources{
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    sample_count: u32,
+    /// Drawn into instead of the swap chain when multisampling, resolved into the frame.
+    msaa_color: Option<(Texture,TextureView)>,
+    attachments: Vec<Attachment>,
+    bind_groups: Vec<GraphBindGroup>,
+}
+
+impl GraphResources {
+    pub fn size(&self) -> (u32,u32) { (self.width,self.height) }
+    pub fn format(&self) -> TextureFormat { self.format }
+    /// Pipelines drawing to the swap chain or multisampled attachments have to use it.
+    pub fn sample_count(&self) -> u32 { self.sample_count }
+    pub fn attachment(&self,id:AttachmentId) -> &AttachmentDesc { &self.attachments[id.0].desc }
+
+    pub fn texture(&self,id:AttachmentId) -> &Texture
+    {
+        &self.attachments[id.0].texture.as_ref().expect("RenderGraph::build was not called").0
+    }
+
+    pub fn view(&self,id:AttachmentId) -> &TextureView
+    {
+        &self.attachments[id.0].texture.as_ref().expect("RenderGraph::build was not called").1
+    }
+
+    pub fn bind_group(&self,id:BindGroupId) -> &BindGroup
+    {
+        self.bind_groups[id.0].bind_group.as_ref().expect("RenderGraph::build was not called")
+    }
+
+    fn target_samples(&self,target:Target) -> u32
+    {
+        match target {
+            Target::Attachment(a) if !self.attachments[a.0].desc.multisampled => 1,
+            _ => self.sample_count
+        }
+    }
+
+    fn create(&mut self,device:&Device)
+    {
+        if self.sample_count > 1 && self.msaa_color.is_none() {
+            self.msaa_color = Some(create_attachment(device,"MSAA Color",self.format,(self.width,self.height),self.sample_count,TextureUsage::RENDER_ATTACHMENT));
+        }
+        let mut created = Vec::new();
+        for (i,a) in self.attachments.iter_mut().enumerate() {
+            if a.texture.is_some() { continue; }
+            let size = a.desc.size.resolve(self.width,self.height);
+            let sample_count = if a.desc.multisampled { self.sample_count } else { 1 };
+            a.texture = Some(create_attachment(device,a.desc.label.as_str(),a.desc.format,size,sample_count,a.usage));
+            created.push(AttachmentId(i));
+        }
+        for g in self.bind_groups.iter_mut() {
+            if created.iter().any(|a| g.uses(*a)) {
+                g.bind_group = None;
+            }
+        }
+        let attachments = &self.attachments;
+        for g in self.bind_groups.iter_mut().filter(|g| g.bind_group.is_none()) {
+            let entries:Vec<_> = g.entries.iter().map(|(binding,b)| BindGroupEntry{
+                binding: *binding,
+                resource: match b {
+                    GraphBinding::Attachment(a) => BindingResource::TextureView(&attachments[a.0].texture.as_ref().unwrap().1),
+                    GraphBinding::Sampler(s) => BindingResource::Sampler(s.as_ref()),
+                    GraphBinding::Buffer(buf) => buf.as_entire_binding()
+                }
+            }).collect();
+            g.bind_group = Some(device.create_bind_group(&BindGroupDescriptor{
+                label: Some(g.label.as_str()),
+                layout: g.layout.as_ref(),
+                entries: entries.as_slice()
+            }));
+        }
+    }
+}
+
+/// Passes over the swap chain and transient attachments. Attachments sized relative to
+/// the swap chain are recreated by `resize` together with the bind groups using them,
+/// passes run after the passes that render what they read.
+pub struct RenderGraph{
+    passes: Vec<PassDesc>,
+    nodes: Vec<Box<dyn RenderNode>>,
+    order: Vec<usize>,
+    res: GraphResources,
+    built: bool,
+}
+
+impl RenderGraph {
+    pub fn new(sc_desc:&SwapChainDescriptor) -> RenderGraph
+    {
+        RenderGraph{
+            passes: Vec::new(),
+            nodes: Vec::new(),
+            order: Vec::new(),
+            res: GraphResources{
+                width: sc_desc.width,
+                height: sc_desc.height,
+                format: sc_desc.format,
+                sample_count: 1,
+                msaa_color: None,
+                attachments: Vec::new(),
+                bind_groups: Vec::new()
+            },
+            built: false
+        }
+    }
+
+    pub fn add_attachment(&mut self,desc:AttachmentDesc) -> AttachmentId
+    {
+        self.built = false;
+        let usage = desc.usage;
+        self.res.attachments.push(Attachment{ desc, usage, texture: None });
+        AttachmentId(self.res.attachments.len() - 1)
+    }
+
+    pub fn add_pass(&mut self,desc:PassDesc,node:Box<dyn RenderNode>) -> PassId
+    {
+        self.built = false;
+        self.passes.push(desc);
+        self.nodes.push(node);
+        PassId(self.passes.len() - 1)
+    }
+
+    /// A bind group that is recreated whenever one of its attachments is.
+    pub fn add_bind_group(&mut self,label:&str,layout:Rc<BindGroupLayout>,entries:Vec<(u32,GraphBinding)>) -> BindGroupId
+    {
+        self.built = false;
+        self.res.bind_groups.push(GraphBindGroup{ label: label.to_string(), layout, entries, bind_group: None });
+        BindGroupId(self.res.bind_groups.len() - 1)
+    }
+
+    /// Orders the passes and creates what is missing, call it after adding passes.
+    pub fn build(&mut self,device:&Device) -> Result<(),GraphError>
+    {
+        self.order = sort_passes(self.passes.as_slice(),|a| self.res.attachments[a.0].desc.label.clone())?;
+        check_samples(self.passes.as_slice(),|t| self.res.target_samples(t))?;
+        for (i,a) in self.res.attachments.iter_mut().enumerate() {
+            let id = AttachmentId(i);
+            let mut usage = a.desc.usage;
+            if self.passes.iter().any(|p| p.writes().any(|(t,_)| t == Target::Attachment(id))) {
+                usage |= TextureUsage::RENDER_ATTACHMENT;
+            }
+            if self.passes.iter().any(|p| p.reads.contains(&id)) || self.res.bind_groups.iter().any(|g| g.uses(id)) {
+                usage |= TextureUsage::SAMPLED;
+            }
+            if usage != a.usage {
+                a.usage = usage;
+                a.texture = None;
+            }
+        }
+        self.res.create(device);
+        self.built = true;
+        Ok(())
+    }
+
+    pub fn resize(&mut self,device:&Device,sc_desc:&SwapChainDescriptor)
+    {
+        if (self.res.width,self.res.height,self.res.format) == (sc_desc.width,sc_desc.height,sc_desc.format) {
+            return;
+        }
+        self.res.width = sc_desc.width;
+        self.res.height = sc_desc.height;
+        self.res.format = sc_desc.format;
+        self.res.msaa_color = None;
+        for a in self.res.attachments.iter_mut() {
+            if let AttachmentSize::Relative(_) = a.desc.size {
+                a.texture = None;
+            }
+        }
+        if self.built {
+            self.res.create(device);
+        }
+    }
+
+    /// Draws the swap chain and the multisampled attachments with `sample_count` samples,
+    /// the swap chain is resolved at the end of each pass. The count should come from
+    /// `msaa::check_sample_count`, call `build` again afterwards.
+    pub fn set_sample_count(&mut self,sample_count:u32)
+    {
+        if sample_count == self.res.sample_count { return; }
+        self.built = false;
+        self.res.sample_count = sample_count;
+        self.res.msaa_color = None;
+        for a in self.res.attachments.iter_mut().filter(|a| a.desc.multisampled) {
+            a.texture = None;
+        }
+    }
+
+    pub fn sample_count(&self) -> u32 { self.res.sample_count }
+
+    pub fn execute(&self,encoder:&mut CommandEncoder,frame:&TextureView)
+    {
+        assert!(self.built,"RenderGraph::build has to be called after adding passes");
+        for &i in self.order.iter() {
+            let desc = &self.passes[i];
+            let colors:Vec<_> = desc.colors.iter().map(|(target,load)|{
+                let (view,resolve_target) = match (target,&self.res.msaa_color) {
+                    (Target::SwapChain,Some((_,msaa))) => (msaa,Some(frame)),
+                    (Target::SwapChain,None) => (frame,None),
+                    (Target::Attachment(a),_) => (self.res.view(*a),None)
+                };
+                RenderPassColorAttachment{ view, resolve_target, ops: Operations{ load: *load, store: true } }
+            }).collect();
+            let depth = desc.depth.map(|(a,load)| RenderPassDepthStencilAttachment{
+                view: self.res.view(a),
+                depth_ops: Some(Operations{ load, store: true }),
+                stencil_ops: None
+            });
+            let mut pass = encoder.begin_render_pass(&RenderPassDescriptor{
+                label: Some(desc.label.as_str()),
+                color_attachments: colors.as_slice(),
+                depth_stencil_attachment: depth
+            });
+            self.nodes[i].record(&mut pass,&self.res);
+        }
+    }
+
+    /// Passes in the order they run, valid after `build`.
+    pub fn order(&self) -> Vec<PassId>
+    {
+        self.order.iter().map(|&i| PassId(i)).collect()
+    }
+
+    pub fn resources(&self) -> &GraphResources { &self.res }
+}
+
+impl PassDesc {
+    /// Targets rendered to and whether their content is loaded.
+    fn writes(&self) -> impl Iterator<Item=(Target,bool)> + '_
+    {
+        self.colors.iter().map(|(t,load)| (*t,matches!(load,LoadOp::Load)))
+            .chain(self.depth.iter().map(|(a,load)| (Target::Attachment(*a),matches!(load,LoadOp::Load))))
+    }
+}
+
+/// All targets of a pass need the same sample count.
+pub fn check_samples<F:Fn(Target) -> u32>(passes:&[PassDesc],sample_count:F) -> Result<(),GraphError>
+{
+    for p in passes.iter() {
+        let mut samples = p.writes().map(|(t,_)| sample_count(t));
+        if let Some(first) = samples.next() {
+            if samples.any(|m| m != first) {
+                return Err(GraphError::MixedSamples{ pass: p.label.clone() });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writers of a target that clear it run before the ones that load it, otherwise in the
+/// order they were added. Readers of an attachment run after all its writers.
+/// Among independent passes the order they were added is kept.
+pub fn sort_passes<F:Fn(AttachmentId) -> String>(passes:&[PassDesc],attachment_name:F) -> Result<Vec<usize>,GraphError>
+{
+    let mut writers:Vec<(Target,Vec<(bool,usize)>)> = Vec::new();
+    for (i,p) in passes.iter().enumerate() {
+        for (t,load) in p.writes() {
+            match writers.iter_mut().find(|(w,_)| *w == t) {
+                Some((_,list)) => list.push((load,i)),
+                None => writers.push((t,vec![(load,i)]))
+            }
+        }
+    }
+    let mut deps = vec![Vec::new();passes.len()];
+    for (_,list) in writers.iter_mut() {
+        list.sort_by_key(|(load,_)| *load);
+        list.dedup_by_key(|(_,i)| *i);
+        for w in list.windows(2) {
+            deps[w[1].1].push(w[0].1);
+        }
+    }
+    for (i,p) in passes.iter().enumerate() {
+        for a in p.reads.iter() {
+            let list = writers.iter().find(|(w,_)| *w == Target::Attachment(*a)).map(|(_,l)| l);
+            match list {
+                None => return Err(GraphError::NeverWritten{ pass: p.label.clone(), attachment: attachment_name(*a) }),
+                Some(l) if l.iter().any(|(_,w)| *w == i) => return Err(GraphError::ReadWrite{ pass: p.label.clone(), attachment: attachment_name(*a) }),
+                Some(l) => deps[i].extend(l.iter().map(|(_,w)| *w))
+            }
+        }
+    }
+
+    let mut order = Vec::new();
+    let mut done = vec![false;passes.len()];
+    while order.len() < passes.len() {
+        let next = (0..passes.len()).find(|&i| !done[i] && deps[i].iter().all(|&d| done[d]));
+        match next {
+            Some(i) => {
+                done[i] = true;
+                order.push(i);
+            }
+            None => {
+                let rest = (0..passes.len()).filter(|&i| !done[i]).map(|i| passes[i].label.clone()).collect();
+                return Err(GraphError::Cycle(rest));
+            }
+        }
+    }
+    Ok(order)
+}
+
+mod test_render_graph{
+    use crate::render_graph::{sort_passes, check_samples, PassDesc, Target, AttachmentId, AttachmentSize, GraphError};
+    use wgpu::{LoadOp, Color};
+
+    fn pass(label:&str,colors:Vec<(Target,bool)>,depth:Option<usize>,reads:Vec<usize>) -> PassDesc
+    {
+        PassDesc{
+            label: label.to_string(),
+            colors: colors.into_iter().map(|(t,load)| (t,if load { LoadOp::Load } else { LoadOp::Clear(Color::BLACK) })).collect(),
+            depth: depth.map(|d| (AttachmentId(d),LoadOp::Clear(1.0))),
+            reads: reads.into_iter().map(AttachmentId).collect()
+        }
+    }
+
+    fn name(a:AttachmentId) -> String
+    {
+        format!("att{}",a.0)
+    }
+
+    #[test]
+    fn order()
+    {
+        // the overlay is added first but draws over what the scene pass clears
+        let passes = vec![
+            pass("overlay",vec![(Target::SwapChain,true)],None,vec![]),
+            pass("scene",vec![(Target::SwapChain,false)],Some(0),vec![]),
+        ];
+        assert_eq!(sort_passes(passes.as_slice(),name),Ok(vec![1,0]));
+        // the depth view samples what the scene pass renders
+        let passes = vec![
+            pass("depth view",vec![(Target::SwapChain,true)],None,vec![0]),
+            pass("shadow",vec![],Some(1),vec![]),
+            pass("scene",vec![(Target::Attachment(AttachmentId(2)),false)],Some(0),vec![1]),
+            pass("post",vec![(Target::SwapChain,false)],None,vec![2]),
+        ];
+        let order:Vec<_> = sort_passes(passes.as_slice(),name).unwrap().into_iter().map(|i| passes[i].label.as_str()).collect();
+        assert_eq!(order,vec!["shadow","scene","post","depth view"]);
+    }
+
+    #[test]
+    fn errors()
+    {
+        let passes = vec![pass("a",vec![(Target::Attachment(AttachmentId(0)),false)],None,vec![0])];
+        assert_eq!(sort_passes(passes.as_slice(),name),Err(GraphError::ReadWrite{ pass: "a".to_string(), attachment: "att0".to_string() }));
+        let passes = vec![pass("a",vec![(Target::SwapChain,false)],None,vec![3])];
+        assert_eq!(sort_passes(passes.as_slice(),name).unwrap_err().to_string(),"pass \"a\" reads attachment \"att3\" but no pass renders to it");
+        let passes = vec![
+            pass("a",vec![(Target::Attachment(AttachmentId(0)),false)],None,vec![1]),
+            pass("b",vec![(Target::Attachment(AttachmentId(1)),false)],None,vec![0]),
+        ];
+        assert_eq!(sort_passes(passes.as_slice(),name),Err(GraphError::Cycle(vec!["a".to_string(),"b".to_string()])));
+    }
+
+    #[test]
+    fn samples()
+    {
+        // attachment 0 is multisampled
+        let samples = |t:Target| if t == Target::Attachment(AttachmentId(1)) { 1 } else { 4 };
+        let passes = vec![
+            pass("scene",vec![(Target::SwapChain,false)],Some(0),vec![]),
+            pass("shadow",vec![],Some(1),vec![]),
+        ];
+        assert_eq!(check_samples(passes.as_slice(),samples),Ok(()));
+        let passes = vec![pass("scene",vec![(Target::SwapChain,false)],Some(1),vec![])];
+        assert_eq!(check_samples(passes.as_slice(),|_| 1),Ok(()));
+        assert_eq!(check_samples(passes.as_slice(),samples).unwrap_err().to_string(),"pass \"scene\" renders to multisampled and single sampled targets together");
+    }
+
+    #[test]
+    fn size()
+    {
+        assert_eq!(AttachmentSize::Relative(0.5).resolve(801,600),(400,300));
+        assert_eq!(AttachmentSize::Relative(0.5).resolve(1,1),(1,1));
+        assert_eq!(AttachmentSize::Fixed(1024,1024).resolve(800,600),(1024,1024));
+    }
+}
@@ -0,0 +1,152 @@
+use std::any::TypeId;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Identifies one cached resource: the data type it is cached as and its path.
+/// Raw file data read by `ResourceMgr::load_file` is keyed as `Vec<u8>`.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct ResKey{
+    pub ty: TypeId,
+    pub path: String,
+}
+
+impl ResKey {
+    pub fn raw(path:&str) -> ResKey
+    {
+        ResKey::of::<Vec<u8>>(path)
+    }
+
+    pub fn of<T:'static>(path:&str) -> ResKey
+    {
+        ResKey{
+            ty: TypeId::of::<T>(),
+            path: path.to_string()
+        }
+    }
+
+    pub fn is_raw(&self) -> bool
+    {
+        self.ty == TypeId::of::<Vec<u8>>()
+    }
+}
+
+/// Records which resources were derived from which inputs, so that
+/// invalidating an input can cascade to everything built from it.
+pub struct ResGraph{
+    dependents: HashMap<ResKey,HashSet<ResKey>>,
+    inputs: HashMap<ResKey,HashSet<ResKey>>,
+}
+
+impl ResGraph {
+    pub fn new() -> ResGraph
+    {
+        ResGraph{
+            dependents: Default::default(),
+            inputs: Default::default()
+        }
+    }
+
+    pub fn add_edge(&mut self,input:ResKey,output:ResKey)
+    {
+        if input == output { return; }
+        self.inputs.entry(output.clone()).or_default().insert(input.clone());
+        self.dependents.entry(input).or_default().insert(output);
+    }
+
+    pub fn inputs_of(&self,key:&ResKey) -> Vec<ResKey>
+    {
+        if let Some(v) = self.inputs.get(key){
+            v.iter().cloned().collect()
+        }else{
+            Vec::new()
+        }
+    }
+
+    pub fn dependents_of(&self,key:&ResKey) -> Vec<ResKey>
+    {
+        if let Some(v) = self.dependents.get(key){
+            v.iter().cloned().collect()
+        }else{
+            Vec::new()
+        }
+    }
+
+    /// Every resource derived directly or transitively from `key`, nearest first.
+    /// `key` itself is not included, cycles are visited once.
+    pub fn collect_dependents(&self,key:&ResKey) -> Vec<ResKey>
+    {
+        let mut res = Vec::new();
+        let mut visited = HashSet::new();
+        visited.insert(key.clone());
+        let mut queue = VecDeque::new();
+        queue.push_back(key);
+        while let Some(k) = queue.pop_front() {
+            if let Some(deps) = self.dependents.get(k) {
+                for d in deps.iter() {
+                    if visited.insert(d.clone()) {
+                        res.push(d.clone());
+                        queue.push_back(d);
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Forget the inputs recorded for `key`, used before a resource is rebuilt
+    /// so stale inputs do not keep invalidating it.
+    pub fn clear_inputs(&mut self,key:&ResKey)
+    {
+        if let Some(inputs) = self.inputs.remove(key) {
+            for i in inputs.iter() {
+                if let Some(deps) = self.dependents.get_mut(i) {
+                    deps.remove(key);
+                    if deps.is_empty() { self.dependents.remove(i); }
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.dependents.clear();
+        self.inputs.clear();
+    }
+}
+
+mod test_graph{
+    use crate::resource_manager::graph::{ResGraph, ResKey};
+
+    #[test]
+    fn cascade()
+    {
+        let mut g = ResGraph::new();
+        let raw = ResKey::raw("mat.ron");
+        let text = ResKey::of::<String>("mat.ron");
+        let mat = ResKey::of::<Vec<char>>("mat.ron");
+        let tex = ResKey::raw("tex.png");
+        g.add_edge(raw.clone(),text.clone());
+        g.add_edge(text.clone(),mat.clone());
+        g.add_edge(tex.clone(),mat.clone());
+
+        assert_eq!(g.collect_dependents(&raw),vec![text.clone(),mat.clone()]);
+        assert_eq!(g.collect_dependents(&tex),vec![mat.clone()]);
+        assert!(g.collect_dependents(&mat).is_empty());
+        assert_eq!(g.inputs_of(&mat).len(),2);
+
+        g.clear_inputs(&mat);
+        assert!(g.collect_dependents(&tex).is_empty());
+        assert_eq!(g.collect_dependents(&raw),vec![text]);
+    }
+
+    #[test]
+    fn cycle()
+    {
+        let mut g = ResGraph::new();
+        let a = ResKey::raw("a");
+        let b = ResKey::raw("b");
+        g.add_edge(a.clone(),b.clone());
+        g.add_edge(b.clone(),a.clone());
+        g.add_edge(a.clone(),a.clone());
+        assert_eq!(g.collect_dependents(&a),vec![b]);
+    }
+}
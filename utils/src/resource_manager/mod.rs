@@ -5,36 +5,69 @@ use gen_code::{gen_impl_res_process_cache,AsAny};
 use std::any::{TypeId, Any};
 use std::time::SystemTime;
 use std::io::Read;
+use std::hash::Hash;
 use crate::AsAny;
+use crate::resource_manager::pack::PackFile;
+use crate::resource_manager::graph::{ResGraph, ResKey};
+
+pub mod pack;
+pub mod graph;
+
+/// Cache key of a processed resource: the same file processed with different
+/// parameters (e.g. sRGB vs linear) is cached separately.
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct CacheKey<P>{
+    pub path: String,
+    pub param: P,
+}
+
+impl<P> CacheKey<P> {
+    pub fn new(path:String,param:P) -> CacheKey<P>
+    {
+        CacheKey{ path, param }
+    }
+}
+
+impl<P:Default> CacheKey<P> {
+    pub fn path(path:String) -> CacheKey<P>
+    {
+        CacheKey{ path, param: Default::default() }
+    }
+}
 
 pub trait ResProcesser : AsAny
 {
     type In;
     type Out;
-    fn process(&self,d:Rc<Self::In>) -> Option<Rc<Self::Out>>;
-    fn process_cache(&mut self,path:&String,d:Rc<Self::In>,cache_overdue:bool) -> Option<Rc<Self::Out>>
+    /// Processing parameters, part of the cache key. `()` for processors without any.
+    type Param : Clone + Eq + Hash + Default;
+    fn process(&self,d:Rc<Self::In>,key:&CacheKey<Self::Param>) -> Option<Rc<Self::Out>>;
+    fn process_cache(&mut self,key:&CacheKey<Self::Param>,d:Rc<Self::In>,cache_overdue:bool) -> Option<Rc<Self::Out>>
     {
-        if cache_overdue { self.rm_cache(path);}
-        if let Some(data) = self.get_cache(path){
+        // the input changed, every variant processed from it is stale
+        if cache_overdue { self.rm_cache_path(&key.path);}
+        if let Some(data) = self.get_cache(key){
             Some(data)
         }else{
-            if let Some(data) = self.process(d){
-                self.add_cache(path.clone(),data.clone());
+            if let Some(data) = self.process(d,key){
+                self.add_cache(key.clone(),data.clone());
                 Some(data)
             }else { None }
         }
     }
 
-    fn get_cache(&self,path:&String) -> Option<Rc<Self::Out>>;
-    fn add_cache(&mut self,path:String,data:Rc<Self::Out>);
+    fn get_cache(&self,key:&CacheKey<Self::Param>) -> Option<Rc<Self::Out>>;
+    fn add_cache(&mut self,key:CacheKey<Self::Param>,data:Rc<Self::Out>);
     fn clear_cache(&mut self);
-    fn rm_cache(&mut self,path:&String) -> Option<Rc<Self::Out>>;
+    fn rm_cache(&mut self,key:&CacheKey<Self::Param>) -> Option<Rc<Self::Out>>;
+    /// Remove every cached variant of `path`, returns how many were removed.
+    fn rm_cache_path(&mut self,path:&String) -> usize;
 
 }
 
 #[derive(AsAny)]
 pub struct TextRes{
-    cache: HashMap<String,Rc<String>>,
+    cache: HashMap<CacheKey<()>,Rc<String>>,
 }
 
 impl TextRes {
@@ -49,10 +82,11 @@ impl TextRes {
 impl ResProcesser for TextRes {
     type In = Vec<u8>;
     type Out = String;
+    type Param = ();
 
     gen_impl_res_process_cache!{cache}
 
-    fn process(&self, d: Rc<Self::In>) -> Option<Rc<String>> {
+    fn process(&self, d: Rc<Self::In>, _key: &CacheKey<()>) -> Option<Rc<String>> {
         if let Ok(v) = String::from_utf8(d.as_ref().clone())
         {
             Some(Rc::new(v))
@@ -67,6 +101,18 @@ pub struct ResourceMgr{
     root: String,
     cache: HashMap<String,(Rc<Vec<u8>>,SystemTime)>,
     process: HashMap<TypeId,Box<dyn Any>>,
+    removers: HashMap<TypeId,fn(&mut Box<dyn Any>,&String) -> usize>,
+    graph: ResGraph,
+    packs: Vec<PackFile>,
+}
+
+fn rm_cache_erased<T:ResProcesser + 'static>(p:&mut Box<dyn Any>,path:&String) -> usize
+{
+    if let Some(p) = p.downcast_mut::<T>(){
+        p.rm_cache_path(path)
+    }else{
+        0
+    }
 }
 
 impl ResourceMgr {
@@ -74,9 +120,29 @@ impl ResourceMgr {
         ResourceMgr{
             root,
             cache: Default::default(),
-            process: Default::default()
+            process: Default::default(),
+            removers: Default::default(),
+            graph: ResGraph::new(),
+            packs: Vec::new()
+        }
+    }
+    /// Mount a pack file written by the `pack` tool. Files that are not found on disk
+    /// are looked up in the mounted packs, the last mounted pack wins.
+    pub fn mount_pack(&mut self,p:&str) -> bool
+    {
+        let path = Path::new(self.root.as_str()).join(p);
+        if let Ok(pack) = PackFile::open(path.as_path())
+        {
+            self.packs.push(pack);
+            true
+        }else{
+            false
         }
     }
+    pub fn unmount_packs(&mut self)
+    {
+        self.packs.clear();
+    }
     pub fn get_cache(&self,path:&String) -> Option<&(Rc<Vec<u8>>,SystemTime)>
     {
         self.cache.get(path)
@@ -88,6 +154,7 @@ impl ResourceMgr {
     pub fn clear_cache(&mut self)
     {
         self.cache.clear();
+        self.graph.clear();
     }
     pub fn rm_cache(&mut self,path:&String) -> Option<(Rc<Vec<u8>>,SystemTime)>
     {
@@ -120,6 +187,7 @@ impl ResourceMgr {
             }else{
                 false
             };
+            if has_cache { self.invalidate(&ResKey::raw(&path_str)); }
             let mut data = Vec::new();
             if let Ok(len) = file.read_to_end(&mut data)
             {
@@ -131,6 +199,33 @@ impl ResourceMgr {
             }else{
                 None
             }
+        }else{
+            self.load_packed(p,path_str)
+        }
+    }
+
+    fn load_packed(&mut self,p:&str,path_str:String) -> Option<(Rc<Vec<u8>>,bool,String)>
+    {
+        let idx = self.packs.iter().rposition(|it| it.contains(p))?;
+        let modify_time = self.packs[idx].modify_time();
+        let has_cache = if let Some((cache,time)) = self.get_cache(&path_str)
+        {
+            if *time >= modify_time {
+                return Some((cache.clone(),false,path_str));
+            }else {
+                true
+            }
+        }else{
+            false
+        };
+        if has_cache { self.invalidate(&ResKey::raw(&path_str)); }
+        if let Ok(data) = self.packs[idx].read(p)
+        {
+            if data.len() > 0{
+                let d = Rc::new(data);
+                self.add_cache(path_str.clone(),d.clone(),modify_time);
+                Some((d,has_cache,path_str))
+            }else{None}
         }else{
             None
         }
@@ -141,11 +236,81 @@ impl ResourceMgr {
               T : ResProcesser<In = I,Out = O> + AsAny
     {
         self.process.insert(TypeId::of::<O>(),p.into_any());
+        self.removers.insert(TypeId::of::<O>(),rm_cache_erased::<T>);
+    }
+
+    /// The registered processor of type `T`, to read what it keeps besides its cache.
+    pub fn processer<T:ResProcesser + 'static>(&self) -> Option<&T>
+        where T::Out : 'static
+    {
+        self.process.get(&TypeId::of::<T::Out>()).and_then(|p| p.downcast_ref::<T>())
+    }
+
+    /// Record that the resource `output` was built from `input`, e.g. a material from
+    /// the textures and shader it references. `loading` records the edge from its
+    /// input to its output automatically.
+    pub fn add_dependency(&mut self,output:ResKey,input:ResKey)
+    {
+        self.graph.add_edge(input,output);
+    }
+
+    /// Forget the inputs recorded for `output`. Loaders call it before recording the
+    /// inputs of a resource again, so inputs it no longer uses stop invalidating it.
+    pub fn clear_dependencies(&mut self,output:&ResKey)
+    {
+        self.graph.clear_inputs(output);
+    }
+
+    pub fn graph(&self) -> &ResGraph
+    {
+        &self.graph
+    }
+
+    /// Drop the cache of `key` and of everything derived from it.
+    /// Returns the keys whose cache was actually removed.
+    pub fn invalidate(&mut self,key:&ResKey) -> Vec<ResKey>
+    {
+        let mut keys = vec![key.clone()];
+        keys.extend(self.graph.collect_dependents(key));
+        let mut res = Vec::new();
+        for k in keys.into_iter() {
+            let removed = if k.is_raw() {
+                self.cache.remove(&k.path).is_some()
+            }else if let (Some(p),Some(rm)) = (self.process.get_mut(&k.ty),self.removers.get(&k.ty)) {
+                rm(p,&k.path) > 0
+            }else{
+                false
+            };
+            if removed { res.push(k); }
+        }
+        res
+    }
+
+    /// Check every cached file on disk and invalidate the ones modified since
+    /// they were loaded, together with their dependents.
+    pub fn check_modified(&mut self) -> Vec<ResKey>
+    {
+        let modified:Vec<String> = self.cache.iter().filter_map(|(path,(_,time))|{
+            let t = std::fs::metadata(path).ok()?.modified().ok()?;
+            if t > *time { Some(path.clone()) } else { None }
+        }).collect();
+        let mut res = Vec::new();
+        for p in modified.iter() {
+            res.extend(self.invalidate(&ResKey::raw(p)));
+        }
+        res
     }
 
     pub fn loading<T:ResProcesser<In = I,Out = O>,I,O>(&mut self,i:Rc<I>,path:&String,cache_overdue:bool) -> Option<Rc<O>>
     where O : 'static, I : 'static,T :'static,
     T : ResProcesser<In = I,Out = O>
+    {
+        self.loading_with::<T,I,O>(i,path,Default::default(),cache_overdue)
+    }
+
+    pub fn loading_with<T:ResProcesser<In = I,Out = O>,I,O>(&mut self,i:Rc<I>,path:&String,param:T::Param,cache_overdue:bool) -> Option<Rc<O>>
+    where O : 'static, I : 'static,T :'static,
+    T : ResProcesser<In = I,Out = O>
     {
         let proc = if let Some(v) = self.process.get_mut(&TypeId::of::<O>())
         {
@@ -153,7 +318,11 @@ impl ResourceMgr {
         }else{
             return None;
         };
-        proc.process_cache(path,i,cache_overdue)
+        let res = proc.process_cache(&CacheKey::new(path.clone(),param),i,cache_overdue);
+        if res.is_some() {
+            self.graph.add_edge(ResKey::of::<I>(path),ResKey::of::<O>(path));
+        }
+        res
     }
 
     pub fn clear_cache_by<T:ResProcesser<In = I,Out = O>,I,O>(&mut self) -> bool
@@ -173,11 +342,17 @@ impl ResourceMgr {
     pub fn rm_cache_by<T:ResProcesser<In = I,Out = O>,I,O>(&mut self,path:&String) -> Option<Rc<O>>
         where O : 'static, I : 'static,T :'static,
               T : ResProcesser<In = I,Out = O> + AsAny
+    {
+        self.rm_cache_by_key::<T,I,O>(&CacheKey::path(path.clone()))
+    }
+    pub fn rm_cache_by_key<T:ResProcesser<In = I,Out = O>,I,O>(&mut self,key:&CacheKey<T::Param>) -> Option<Rc<O>>
+        where O : 'static, I : 'static,T :'static,
+              T : ResProcesser<In = I,Out = O> + AsAny
     {
         if let Some(v) = self.process.get_mut(&TypeId::of::<O>())
         {
             if let Some(p) = v.downcast_mut::<T>(){
-                p.rm_cache(path)
+                p.rm_cache(key)
             }else { None }
         }else{
             None
@@ -222,7 +397,7 @@ macro_rules! load_chain
 }
 
 mod test_load_file{
-    use crate::resource_manager::{ResourceMgr, TextRes, ResProcesser};
+    use crate::resource_manager::{ResourceMgr, TextRes, ResProcesser, CacheKey};
     use std::path::Path;
     use std::process::Command;
     use std::io::Write;
@@ -232,10 +407,13 @@ mod test_load_file{
     use gen_code::{AsAny,gen_impl_res_process_cache};
     use std::collections::HashMap;
     use std::rc::Rc;
+    use crate::resource_manager::pack::PackWriter;
+    use crate::resource_manager::graph::ResKey;
 
+    /// param: upper case the chars
     #[derive(AsAny)]
     pub struct CharArrRes{
-        cache: HashMap<String,Rc<Vec<char>>>,
+        cache: HashMap<CacheKey<bool>,Rc<Vec<char>>>,
     }
     impl CharArrRes {
         pub fn new() ->CharArrRes
@@ -249,9 +427,14 @@ mod test_load_file{
     impl ResProcesser for CharArrRes {
         type In = String;
         type Out = Vec<char>;
+        type Param = bool;
 
-        fn process(&self, d: Rc<Self::In>) -> Option<Rc<Self::Out>> {
-            Some(Rc::new(d.chars().collect()))
+        fn process(&self, d: Rc<Self::In>, key: &CacheKey<bool>) -> Option<Rc<Self::Out>> {
+            if key.param {
+                Some(Rc::new(d.to_uppercase().chars().collect()))
+            }else{
+                Some(Rc::new(d.chars().collect()))
+            }
         }
 
         gen_impl_res_process_cache!{cache}
@@ -272,5 +455,79 @@ mod test_load_file{
         dbg!(load_chain!(mgr,"test_load.txt",TextRes));
         dbg!(load_chain!(mgr,"test_load.txt",TextRes,CharArrRes));
     }
+
+    #[test]
+    fn test_dependency()
+    {
+        let mut mgr = ResourceMgr::new("".to_string());
+        mgr.add_process(Box::new(TextRes::new()));
+        mgr.add_process(Box::new(CharArrRes::new()));
+        load_chain!(mgr,"test_load.txt",TextRes,CharArrRes).unwrap();
+        let (_,_,s) = mgr.load_file("test_load.txt").unwrap();
+
+        // a resource built from more than one input
+        let combined = "combined".to_string();
+        mgr.loading::<CharArrRes,_,_>(Rc::new("abc".to_string()),&combined,false).unwrap();
+        mgr.add_dependency(ResKey::of::<Vec<char>>(&combined),ResKey::raw(&s));
+
+        let removed = mgr.invalidate(&ResKey::raw(&s));
+        assert_eq!(removed.len(),4);
+        assert!(removed.contains(&ResKey::of::<String>(&s)));
+        assert!(removed.contains(&ResKey::of::<Vec<char>>(&combined)));
+        assert!(mgr.rm_cache_by::<CharArrRes,_,_>(&s).is_none());
+        assert!(mgr.get_cache(&s).is_none());
+        // the edges survive, reloading and invalidating again cascades the same way
+        load_chain!(mgr,"test_load.txt",TextRes,CharArrRes).unwrap();
+        assert_eq!(mgr.invalidate(&ResKey::raw(&s)).len(),3);
+
+        // rebuilt without the file, it no longer goes stale with it
+        mgr.loading::<CharArrRes,_,_>(Rc::new("abc".to_string()),&combined,false).unwrap();
+        mgr.clear_dependencies(&ResKey::of::<Vec<char>>(&combined));
+        load_chain!(mgr,"test_load.txt",TextRes,CharArrRes).unwrap();
+        assert!(!mgr.invalidate(&ResKey::raw(&s)).contains(&ResKey::of::<Vec<char>>(&combined)));
+    }
+
+    #[test]
+    fn test_param()
+    {
+        let mut mgr = ResourceMgr::new("".to_string());
+        mgr.add_process(Box::new(TextRes::new()));
+        mgr.add_process(Box::new(CharArrRes::new()));
+        assert!(mgr.processer::<CharArrRes>().is_some());
+        let (res,b,s) = mgr.load_file("test_load.txt").unwrap();
+        let text = mgr.loading::<TextRes,_,_>(res,&s,b).unwrap();
+        let lower = mgr.loading_with::<CharArrRes,_,_>(text.clone(),&s,false,false).unwrap();
+        let upper = mgr.loading_with::<CharArrRes,_,_>(text.clone(),&s,true,false).unwrap();
+        assert_eq!(lower.iter().collect::<String>(),*text);
+        assert_eq!(upper.iter().collect::<String>(),text.to_uppercase());
+        // both variants are cached under the same path
+        let upper2 = mgr.loading_with::<CharArrRes,_,_>(text.clone(),&s,true,false).unwrap();
+        assert!(Rc::ptr_eq(&upper,&upper2));
+        assert!(Rc::ptr_eq(&lower,&mgr.loading::<CharArrRes,_,_>(text.clone(),&s,false).unwrap()));
+
+        // invalidating the file drops every variant
+        mgr.invalidate(&ResKey::raw(&s));
+        assert!(mgr.rm_cache_by_key::<CharArrRes,_,_>(&CacheKey::new(s.clone(),true)).is_none());
+        assert!(mgr.rm_cache_by::<CharArrRes,_,_>(&s).is_none());
+    }
+
+    #[test]
+    fn test_pack()
+    {
+        let dir = std::env::temp_dir();
+        let mut w = PackWriter::new();
+        w.add("packed/hello.txt",b"hello from pack".to_vec(),true);
+        w.write_file(dir.join("test_pack.wpak").as_path()).unwrap();
+
+        let mut mgr = ResourceMgr::new(dir.to_str().unwrap().to_string());
+        mgr.add_process(Box::new(TextRes::new()));
+        assert!(mgr.load_file("packed/hello.txt").is_none());
+        assert!(mgr.mount_pack("test_pack.wpak"));
+        let v = load_chain!(mgr,"packed/hello.txt",TextRes).unwrap();
+        assert_eq!(v.as_str(),"hello from pack");
+        // second load is served from the cache
+        let (_,overdue,_) = mgr.load_file("packed/hello.txt").unwrap();
+        assert!(!overdue);
+    }
 }
 
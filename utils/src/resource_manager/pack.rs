@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+pub const PACK_MAGIC: [u8;4] = *b"WPAK";
+pub const PACK_VERSION: u32 = 1;
+/// magic + version + entry count + toc size
+const HEADER_LEN: u64 = 4 + 4 + 4 + 8;
+/// A toc entry with an empty name: name length, offset, stored and raw length, checksum, flags.
+const MIN_ENTRY_LEN: u64 = 2 + 8 + 8 + 8 + 4 + 4;
+
+pub const FLAG_COMPRESSED: u32 = 1;
+
+#[derive(Debug,Clone)]
+pub struct PackEntry{
+    pub name: String,
+    pub offset: u64,
+    pub stored_len: u64,
+    pub raw_len: u64,
+    pub checksum: u32,
+    pub flags: u32,
+}
+
+impl PackEntry {
+    pub fn is_compressed(&self) -> bool
+    {
+        self.flags & FLAG_COMPRESSED != 0
+    }
+}
+
+/// Normalize a resource path to the form used as pack key ('/' separated, no leading "./").
+pub fn pack_key(p:&str) -> String
+{
+    let s = p.replace('\\',"/");
+    let mut s = s.as_str();
+    while let Some(rest) = s.strip_prefix("./") { s = rest; }
+    s.trim_start_matches('/').to_string()
+}
+
+pub struct PackWriter{
+    entries: Vec<(String,Vec<u8>,bool)>,
+}
+
+impl PackWriter {
+    pub fn new() -> PackWriter
+    {
+        PackWriter{ entries: Vec::new() }
+    }
+
+    pub fn add(&mut self,name:&str,data:Vec<u8>,compress:bool)
+    {
+        let key = pack_key(name);
+        self.entries.retain(|(n,_,_)| *n != key);
+        self.entries.push((key,data,compress));
+    }
+
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    /// Layout: header | toc | data. The toc is written before the data so the
+    /// reader can build its index with a single read.
+    pub fn write_to<W:Write>(&self,w:&mut W) -> io::Result<Vec<PackEntry>>
+    {
+        let mut stored = Vec::with_capacity(self.entries.len());
+        for (name,data,compress) in self.entries.iter() {
+            let checksum = crc32fast::hash(data.as_slice());
+            let mut flags = 0u32;
+            let mut bytes = None;
+            if *compress {
+                let mut enc = ZlibEncoder::new(Vec::new(),Compression::default());
+                enc.write_all(data.as_slice())?;
+                let c = enc.finish()?;
+                // keep the raw bytes when compression does not pay off
+                if c.len() < data.len() {
+                    flags |= FLAG_COMPRESSED;
+                    bytes = Some(c);
+                }
+            }
+            stored.push((name,data,bytes,checksum,flags));
+        }
+
+        let toc_len:u64 = stored.iter().map(|(name,..)|{ 2 + name.len() as u64 + 8 + 8 + 8 + 4 + 4 }).sum();
+        let mut offset = HEADER_LEN + toc_len;
+        let mut toc = Vec::with_capacity(stored.len());
+        for (name,data,bytes,checksum,flags) in stored.iter() {
+            let stored_len = (if let Some(b) = bytes { b.len() } else { data.len() }) as u64;
+            toc.push(PackEntry{
+                name: name.to_string(),
+                offset,
+                stored_len,
+                raw_len: data.len() as u64,
+                checksum: *checksum,
+                flags: *flags
+            });
+            offset += stored_len;
+        }
+
+        w.write_all(&PACK_MAGIC)?;
+        w.write_all(&PACK_VERSION.to_le_bytes())?;
+        w.write_all(&(toc.len() as u32).to_le_bytes())?;
+        w.write_all(&toc_len.to_le_bytes())?;
+        for e in toc.iter() {
+            w.write_all(&(e.name.len() as u16).to_le_bytes())?;
+            w.write_all(e.name.as_bytes())?;
+            w.write_all(&e.offset.to_le_bytes())?;
+            w.write_all(&e.stored_len.to_le_bytes())?;
+            w.write_all(&e.raw_len.to_le_bytes())?;
+            w.write_all(&e.checksum.to_le_bytes())?;
+            w.write_all(&e.flags.to_le_bytes())?;
+        }
+        for (_,data,bytes,..) in stored.iter() {
+            if let Some(b) = bytes { w.write_all(b.as_slice())?; } else { w.write_all(data.as_slice())?; }
+        }
+        Ok(toc)
+    }
+
+    pub fn write_file(&self,path:&Path) -> io::Result<Vec<PackEntry>>
+    {
+        let mut file = io::BufWriter::new(File::create(path)?);
+        let toc = self.write_to(&mut file)?;
+        file.flush()?;
+        Ok(toc)
+    }
+}
+
+pub struct PackFile<R:Read + Seek = File>{
+    reader: R,
+    entries: HashMap<String,PackEntry>,
+    modify_time: SystemTime,
+}
+
+impl PackFile<File> {
+    pub fn open(path:&Path) -> io::Result<PackFile<File>>
+    {
+        let file = File::open(path)?;
+        let modify_time = if let Ok(meta) = file.metadata()
+        {
+            if let Ok(t) = meta.modified(){ t } else { SystemTime::UNIX_EPOCH }
+        }else {
+            SystemTime::UNIX_EPOCH
+        };
+        PackFile::from_reader(file,modify_time)
+    }
+}
+
+impl<R:Read + Seek> PackFile<R> {
+    pub fn from_reader(mut reader:R,modify_time:SystemTime) -> io::Result<PackFile<R>>
+    {
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        let mut header = [0u8;HEADER_LEN as usize];
+        reader.read_exact(&mut header)?;
+        if header[0..4] != PACK_MAGIC {
+            return Err(invalid_data("not a pack file"));
+        }
+        let version = read_u32(&header[4..8]);
+        if version != PACK_VERSION {
+            return Err(invalid_data("unsupported pack version"));
+        }
+        let count = read_u32(&header[8..12]) as usize;
+        let toc_len = read_u64(&header[12..20]);
+        // the header is not trusted with an allocation larger than the file
+        if toc_len > file_len - HEADER_LEN {
+            return Err(invalid_data("pack table of contents is larger than the file"));
+        }
+        if count as u64 > toc_len / MIN_ENTRY_LEN {
+            return Err(invalid_data("pack entry count does not fit the table of contents"));
+        }
+
+        let mut toc = vec![0u8;toc_len as usize];
+        reader.read_exact(&mut toc)?;
+        let mut entries = HashMap::new();
+        let mut cur = toc.as_slice();
+        for _ in 0..count {
+            let name_len = read_u16(take(&mut cur,2)?) as usize;
+            let name = String::from_utf8(take(&mut cur,name_len)?.to_vec())
+                .map_err(|_| invalid_data("pack entry name is not utf8"))?;
+            let entry = PackEntry{
+                name: name.clone(),
+                offset: read_u64(take(&mut cur,8)?),
+                stored_len: read_u64(take(&mut cur,8)?),
+                raw_len: read_u64(take(&mut cur,8)?),
+                checksum: read_u32(take(&mut cur,4)?),
+                flags: read_u32(take(&mut cur,4)?)
+            };
+            if entry.offset.checked_add(entry.stored_len).map_or(true,|end| end > file_len) {
+                return Err(invalid_data("pack entry lies outside the file"));
+            }
+            entries.insert(name,entry);
+        }
+        Ok(PackFile{ reader, entries, modify_time })
+    }
+
+    pub fn modify_time(&self) -> SystemTime { self.modify_time }
+
+    pub fn contains(&self,name:&str) -> bool
+    {
+        self.entries.contains_key(&pack_key(name))
+    }
+
+    pub fn entry(&self,name:&str) -> Option<&PackEntry>
+    {
+        self.entries.get(&pack_key(name))
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &PackEntry>
+    {
+        self.entries.values()
+    }
+
+    /// Read, decompress and verify one entry.
+    pub fn read(&mut self,name:&str) -> io::Result<Vec<u8>>
+    {
+        let entry = if let Some(e) = self.entries.get(&pack_key(name)){ e.clone() } else {
+            return Err(io::Error::new(io::ErrorKind::NotFound,format!("{} not in pack",name)));
+        };
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        let mut stored = vec![0u8;entry.stored_len as usize];
+        self.reader.read_exact(&mut stored)?;
+        let data = if entry.is_compressed() {
+            // one byte past raw_len is enough to tell a wrong length, without inflating it all
+            let mut data = Vec::new();
+            ZlibDecoder::new(stored.as_slice()).take(entry.raw_len.saturating_add(1)).read_to_end(&mut data)?;
+            data
+        }else{
+            stored
+        };
+        if data.len() as u64 != entry.raw_len || crc32fast::hash(data.as_slice()) != entry.checksum {
+            return Err(invalid_data("pack entry checksum mismatch"));
+        }
+        Ok(data)
+    }
+}
+
+/// Raw RGBA8 image with its mip chain, as written by the pack tool.
+/// Layout: width u32 | height u32 | mip count u32 | level 0 | level 1 ...
+pub struct RgbaMips{
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl RgbaMips {
+    pub fn mip_size(width:u32,height:u32,level:u32) -> (u32,u32)
+    {
+        ((width >> level).max(1),(height >> level).max(1))
+    }
+
+    /// Levels of a full chain down to 1x1, the count wgpu allows for the size.
+    pub fn level_count(width:u32,height:u32) -> u32
+    {
+        32 - width.max(height).max(1).leading_zeros()
+    }
+
+    pub fn encode(&self) -> Vec<u8>
+    {
+        let mut out = Vec::with_capacity(12 + self.levels.iter().map(|l| l.len()).sum::<usize>());
+        out.extend_from_slice(&self.width.to_le_bytes());
+        out.extend_from_slice(&self.height.to_le_bytes());
+        out.extend_from_slice(&(self.levels.len() as u32).to_le_bytes());
+        for l in self.levels.iter() {
+            out.extend_from_slice(l.as_slice());
+        }
+        out
+    }
+
+    /// `None` for anything the pack tool can not have written, the header is not trusted
+    /// with allocations or arithmetic.
+    pub fn decode(data:&[u8]) -> Option<RgbaMips>
+    {
+        if data.len() < 12 { return None; }
+        let width = read_u32(&data[0..4]);
+        let height = read_u32(&data[4..8]);
+        let count = read_u32(&data[8..12]);
+        if count > Self::level_count(width,height) { return None; }
+        let mut cur = &data[12..];
+        let mut levels = Vec::new();
+        for i in 0..count {
+            let (w,h) = Self::mip_size(width,height,i);
+            let len = (w as usize).checked_mul(h as usize)?.checked_mul(4)?;
+            if cur.len() < len { return None; }
+            levels.push(cur[..len].to_vec());
+            cur = &cur[len..];
+        }
+        Some(RgbaMips{ width, height, levels })
+    }
+}
+
+fn invalid_data(msg:&str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData,msg)
+}
+
+fn take<'a>(cur:&mut &'a [u8],n:usize) -> io::Result<&'a [u8]>
+{
+    if cur.len() < n { return Err(invalid_data("truncated pack toc")); }
+    let (a,b) = cur.split_at(n);
+    *cur = b;
+    Ok(a)
+}
+
+fn read_u16(b:&[u8]) -> u16 { u16::from_le_bytes([b[0],b[1]]) }
+fn read_u32(b:&[u8]) -> u32 { u32::from_le_bytes([b[0],b[1],b[2],b[3]]) }
+fn read_u64(b:&[u8]) -> u64
+{
+    let mut a = [0u8;8];
+    a.copy_from_slice(&b[0..8]);
+    u64::from_le_bytes(a)
+}
+
+mod test_pack{
+    use crate::resource_manager::pack::{PackWriter, PackFile, RgbaMips, pack_key};
+    use std::io::Cursor;
+    use std::time::SystemTime;
+
+    #[test]
+    fn round_trip()
+    {
+        let text = "fn main() {}\n".repeat(64);
+        let mut w = PackWriter::new();
+        w.add("shaders/a.wgsl",text.clone().into_bytes(),true);
+        w.add("./data\\b.bin",vec![1,2,3,4],true);
+        let mut buf = Vec::new();
+        let toc = w.write_to(&mut buf).unwrap();
+        assert!(toc.iter().find(|e| e.name == "shaders/a.wgsl").unwrap().is_compressed());
+        // too small to benefit from compression
+        assert!(!toc.iter().find(|e| e.name == "data/b.bin").unwrap().is_compressed());
+
+        let mut pack = PackFile::from_reader(Cursor::new(buf),SystemTime::UNIX_EPOCH).unwrap();
+        assert_eq!(pack.read("shaders/a.wgsl").unwrap(),text.into_bytes());
+        assert_eq!(pack.read("data/b.bin").unwrap(),vec![1,2,3,4]);
+        assert!(pack.read("missing").is_err());
+    }
+
+    #[test]
+    fn checksum_mismatch()
+    {
+        let mut w = PackWriter::new();
+        w.add("a.txt",b"hello pack".to_vec(),false);
+        let mut buf = Vec::new();
+        w.write_to(&mut buf).unwrap();
+        let last = buf.len() - 1;
+        buf[last] ^= 0xff;
+        let mut pack = PackFile::from_reader(Cursor::new(buf),SystemTime::UNIX_EPOCH).unwrap();
+        assert!(pack.read("a.txt").is_err());
+    }
+
+    #[test]
+    fn corrupt_header()
+    {
+        let mut w = PackWriter::new();
+        w.add("a.txt",b"hello pack".to_vec(),false);
+        let mut buf = Vec::new();
+        w.write_to(&mut buf).unwrap();
+        let mut huge = buf.clone();
+        huge[12..20].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(PackFile::from_reader(Cursor::new(huge),SystemTime::UNIX_EPOCH).is_err());
+        let mut many = buf.clone();
+        many[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(PackFile::from_reader(Cursor::new(many),SystemTime::UNIX_EPOCH).is_err());
+        // cut into the data of the entry
+        buf.truncate(buf.len() - 1);
+        assert!(PackFile::from_reader(Cursor::new(buf),SystemTime::UNIX_EPOCH).is_err());
+    }
+
+    #[test]
+    fn rgba_mips()
+    {
+        let img = RgbaMips{ width: 4, height: 2, levels: vec![vec![7u8;4*2*4],vec![8u8;2*1*4],vec![9u8;4]] };
+        let d = RgbaMips::decode(img.encode().as_slice()).unwrap();
+        assert_eq!((d.width,d.height,d.levels.len()),(4,2,3));
+        assert_eq!(d.levels[2],vec![9u8;4]);
+        assert_eq!(pack_key("./a\\b/c.png"),"a/b/c.png");
+        assert_eq!(RgbaMips::level_count(1,1),1);
+        assert_eq!(RgbaMips::level_count(4,2),3);
+        assert_eq!(RgbaMips::level_count(1000,500),10);
+        assert_eq!(RgbaMips::mip_size(1000,500,9),(1,1));
+        // corrupt headers: more levels than the size has, sizes overflowing u32
+        let mut bad = img.encode();
+        bad[8..12].copy_from_slice(&40u32.to_le_bytes());
+        assert!(RgbaMips::decode(bad.as_slice()).is_none());
+        let mut bad = img.encode();
+        bad[0..8].copy_from_slice(&[0xff;8]);
+        assert!(RgbaMips::decode(bad.as_slice()).is_none());
+    }
+}
@@ -0,0 +1,487 @@
+use std::rc::Rc;
+use std::num::NonZeroU64;
+use cgmath::{Matrix4, Vector3, Vector4, Point3, Rad, InnerSpace, SquareMatrix, EuclideanSpace, Transform, Zero};
+use wgpu::{Device, Queue, Buffer, BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindGroupDescriptor, BindGroupEntry, BindingResource, BufferBinding, BindingType, BufferBindingType, BufferDescriptor, BufferUsage, ShaderStage, TextureSampleType, TextureViewDimension, TextureFormat, TextureUsage, SamplerDescriptor, AddressMode, FilterMode, CompareFunction, RenderPass, RenderPipeline, LoadOp};
+use crate::uniform::{UniformLayout, AddressSpace};
+use crate::render_graph::{RenderGraph, RenderNode, GraphResources, GraphBinding, AttachmentDesc, AttachmentSize, AttachmentId, BindGroupId, PassDesc};
+use crate::pipeline::DepthState;
+use crate::material::CompareDesc;
+use crate::components::DirectionalLight;
+use crate::lighting::LightRaw;
+use crate::camera::OPENGL_TO_WGPU_MATRIX;
+
+pub const MAX_CASCADES: usize = 4;
+pub const SHADOW_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+/// Distance between the cascade matrices of the depth pass, the minimum alignment of a
+/// dynamic uniform offset.
+const CASCADE_STRIDE: u64 = 256;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct ShadowSettings{
+    /// Subtracted from the light space depth of a fragment before comparing, against acne.
+    pub bias: f32,
+    /// The PCF kernel is `2 * pcf_radius + 1` texels wide, 0 takes a single sample.
+    pub pcf_radius: u32,
+    /// Blends the cascade splits from uniform (0) to logarithmic (1).
+    pub split_lambda: f32,
+    /// Shadows end here even if the camera sees further.
+    pub max_distance: f32,
+    /// How far in front of a cascade, towards the light, casters are still drawn.
+    pub caster_margin: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self
+    {
+        ShadowSettings{
+            bias: 0.002,
+            pcf_radius: 1,
+            split_lambda: 0.75,
+            max_distance: 50f32,
+            caster_margin: 20f32
+        }
+    }
+}
+
+/// The camera the cascades are fitted to.
+#[derive(Debug,Clone,Copy)]
+pub struct CameraFrustum{
+    /// World to view matrix, the camera looks down -z.
+    pub view: Matrix4<f32>,
+    pub fovy: Rad<f32>,
+    pub aspect: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl CameraFrustum {
+    /// World space corners of the part of the frustum between the view distances `near` and `far`.
+    pub fn corners(&self,near:f32,far:f32) -> [Point3<f32>;8]
+    {
+        let inv = self.view.invert().expect("camera view matrix is not invertible");
+        let tan = (self.fovy.0 / 2f32).tan();
+        let mut res = [Point3::origin();8];
+        for (i,&z) in [near,far].iter().enumerate() {
+            let h = z * tan;
+            let w = h * self.aspect;
+            for (j,&(x,y)) in [(-w,-h),(w,-h),(w,h),(-w,h)].iter().enumerate() {
+                res[i * 4 + j] = inv.transform_point(Point3::new(x,y,-z));
+            }
+        }
+        res
+    }
+}
+
+/// Far view distance of each cascade, the last one is `far`.
+pub fn cascade_splits(near:f32,far:f32,count:usize,lambda:f32) -> Vec<f32>
+{
+    (1..=count).map(|i|{
+        if i == count { return far; }
+        let p = i as f32 / count as f32;
+        let log = near * (far / near).powf(p);
+        let uniform = near + (far - near) * p;
+        lambda * log + (1f32 - lambda) * uniform
+    }).collect()
+}
+
+/// Light view projection of a cascade covering `corners`. The bounds are a sphere around them
+/// snapped to shadow map texels, so the shadow edges do not swim when the camera moves or turns.
+pub fn cascade_matrix(direction:Vector3<f32>,corners:&[Point3<f32>;8],map_size:u32,caster_margin:f32) -> Matrix4<f32>
+{
+    assert!(map_size > 1,"the shadow map needs more than one texel per side");
+    let center = corners.iter().fold(Vector3::zero(),|s,c| s + c.to_vec()) / 8f32;
+    let radius = corners.iter().map(|c| (c.to_vec() - center).magnitude()).fold(0f32,f32::max);
+    // rounded so the size of a texel stays the same from frame to frame
+    let radius = (radius * 16f32).ceil() / 16f32;
+    let dir = direction.normalize();
+    let up = if dir.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+    let light_view = Matrix4::look_to_rh(Point3::origin(),dir,up);
+    let c = light_view.transform_point(Point3::from_vec(center));
+    // half a texel of padding keeps the sphere inside after snapping
+    let texel = 2f32 * radius / (map_size - 1) as f32;
+    let half = radius + texel / 2f32;
+    let x = (c.x / texel).round() * texel;
+    let y = (c.y / texel).round() * texel;
+    let projection = cgmath::ortho(x - half,x + half,y - half,y + half,-c.z - radius - caster_margin,-c.z + radius);
+    OPENGL_TO_WGPU_MATRIX * projection * light_view
+}
+
+#[derive(Debug,Clone,Copy,UniformLayout)]
+pub struct ShadowUniform{
+    pub light_view_proj: [Matrix4<f32>;MAX_CASCADES],
+    /// Far view distance of each cascade.
+    pub splits: Vector4<f32>,
+    /// Bias, cascade count, PCF radius and the size of a texel of a cascade in uv.
+    pub params: Vector4<f32>,
+}
+
+/// Draws itself into the shadow map. The shadow pipeline and the cascade bind group
+/// (group 0) are set, a caster binds its vertex buffers and draws.
+pub trait ShadowCaster {
+    fn draw_depth<'a>(&'a self,pass:&mut RenderPass<'a>);
+}
+
+/// Lets the main pass and the shadow pass share a mesh.
+impl<T:ShadowCaster + ?Sized> ShadowCaster for Rc<T> {
+    fn draw_depth<'a>(&'a self,pass:&mut RenderPass<'a>)
+    {
+        self.as_ref().draw_depth(pass)
+    }
+}
+
+/// Cascaded shadow map of one directional light. The cascades sit side by side in one
+/// depth attachment of the graph, the main pass binds it through `bind_group` and calls
+/// `shadow_factor` from the WGSL of `shadow_wgsl`.
+pub struct ShadowMap{
+    map_size: u32,
+    cascades: usize,
+    atlas: AttachmentId,
+    layout: Rc<BindGroupLayout>,
+    bind_group: BindGroupId,
+    uniform_buf: Rc<Buffer>,
+    cascade_layout: Rc<BindGroupLayout>,
+    cascade_buf: Buffer,
+    cascade_bind_group: BindGroup,
+}
+
+impl ShadowMap {
+    pub fn new(device:&Device,graph:&mut RenderGraph,map_size:u32,cascades:usize) -> ShadowMap
+    {
+        assert!(cascades > 0 && cascades <= MAX_CASCADES,"1 to {} shadow cascades are supported",MAX_CASCADES);
+        assert!(map_size > 1,"the shadow map needs more than one texel per side");
+        let max_size = device.limits().max_texture_dimension_2d;
+        assert!(map_size as u64 * cascades as u64 <= max_size as u64,"{} cascades of {} texels do not fit into the device's {} texel wide textures",cascades,map_size,max_size);
+        let atlas = graph.add_attachment(AttachmentDesc{
+            label: "Shadow Map".to_string(),
+            format: SHADOW_FORMAT,
+            size: AttachmentSize::Fixed(map_size * cascades as u32,map_size),
+            usage: TextureUsage::empty(),
+            multisampled: false
+        });
+        let layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Shadow Bind Group Layout"),
+            entries: Self::layout_entries().as_slice()
+        }));
+        let sampler = device.create_sampler(&SamplerDescriptor{
+            label: Some("Shadow Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            compare: Some(CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let uniform_buf = Rc::new(device.create_buffer(&BufferDescriptor{
+            label: Some("Shadow Uniform Buffer"),
+            size: ShadowUniform::size(AddressSpace::Uniform),
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+            mapped_at_creation: false
+        }));
+        let bind_group = graph.add_bind_group("Shadow Bind Group",layout.clone(),vec![
+            (0,GraphBinding::Attachment(atlas)),
+            (1,GraphBinding::Sampler(Rc::new(sampler))),
+            (2,GraphBinding::Buffer(uniform_buf.clone()))
+        ]);
+
+        let cascade_layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Shadow Cascade Bind Group Layout"),
+            entries: Self::cascade_entries().as_slice()
+        }));
+        let cascade_buf = device.create_buffer(&BufferDescriptor{
+            label: Some("Shadow Cascade Buffer"),
+            size: CASCADE_STRIDE * MAX_CASCADES as u64,
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+            mapped_at_creation: false
+        });
+        let cascade_bind_group = device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Shadow Cascade Bind Group"),
+            layout: &cascade_layout,
+            entries: &[
+                BindGroupEntry{
+                    binding: 0,
+                    resource: BindingResource::Buffer(BufferBinding{
+                        buffer: &cascade_buf,
+                        offset: 0,
+                        size: NonZeroU64::new(Matrix4::<f32>::size(AddressSpace::Uniform))
+                    })
+                }
+            ]
+        });
+
+        ShadowMap{
+            map_size,
+            cascades,
+            atlas,
+            layout,
+            bind_group,
+            uniform_buf,
+            cascade_layout,
+            cascade_buf,
+            cascade_bind_group
+        }
+    }
+
+    /// Layout of the group `shadow_wgsl` declares: the shadow map, its comparison sampler
+    /// and the `ShadowUniform`.
+    pub fn layout_entries() -> Vec<BindGroupLayoutEntry>
+    {
+        vec![
+            BindGroupLayoutEntry{
+                binding: 0,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Texture{
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false
+                },
+                count: None
+            },
+            BindGroupLayoutEntry{
+                binding: 1,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Sampler{ filtering: true, comparison: true },
+                count: None
+            },
+            BindGroupLayoutEntry{
+                binding: 2,
+                visibility: ShaderStage::FRAGMENT,
+                ty: BindingType::Buffer{
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: NonZeroU64::new(ShadowUniform::size(AddressSpace::Uniform))
+                },
+                count: None
+            }
+        ]
+    }
+
+    /// Layout of group 0 of the depth pass, a `mat4x4<f32>` uniform at a dynamic offset
+    /// per cascade.
+    pub fn cascade_entries() -> Vec<BindGroupLayoutEntry>
+    {
+        vec![
+            BindGroupLayoutEntry{
+                binding: 0,
+                visibility: ShaderStage::VERTEX,
+                ty: BindingType::Buffer{
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: NonZeroU64::new(Matrix4::<f32>::size(AddressSpace::Uniform))
+                },
+                count: None
+            }
+        ]
+    }
+
+    /// The depth state of the caster pipelines.
+    pub fn depth_state() -> DepthState
+    {
+        DepthState{ format: SHADOW_FORMAT, write: true, compare: CompareDesc::LessEqual }
+    }
+
+    /// The depth only pass rendering the cascades, add it with a `ShadowNode`. The passes
+    /// sampling the map have to list `attachment()` in their reads.
+    pub fn pass(&self) -> PassDesc
+    {
+        PassDesc{
+            label: "Shadow Pass".to_string(),
+            colors: vec![],
+            depth: Some((self.atlas,LoadOp::Clear(1f32))),
+            reads: vec![]
+        }
+    }
+
+    /// Fits the cascades to the camera and uploads them, call it before executing the graph.
+    /// A light without `cast_shadows` leaves everything lit. `world` is the matrix of the
+    /// light's object, its direction is turned by it like `collect_lights` does.
+    pub fn update(&self,queue:&Queue,light:&DirectionalLight,world:&Matrix4<f32>,camera:&CameraFrustum)
+    {
+        let direction = LightRaw::directional(light,world).direction;
+        let settings = &light.shadow;
+        let far = camera.far.min(settings.max_distance);
+        let splits = cascade_splits(camera.near,far,self.cascades,settings.split_lambda);
+        let mut uniform = ShadowUniform{
+            light_view_proj: [Matrix4::identity();MAX_CASCADES],
+            splits: Vector4::zero(),
+            params: Vector4::new(
+                settings.bias,
+                if light.cast_shadows { self.cascades as f32 } else { 0f32 },
+                settings.pcf_radius as f32,
+                1f32 / self.map_size as f32
+            )
+        };
+        let mut near = camera.near;
+        for (i,&split) in splits.iter().enumerate() {
+            let m = cascade_matrix(direction,&camera.corners(near,split),self.map_size,settings.caster_margin);
+            uniform.light_view_proj[i] = m;
+            uniform.splits[i] = split;
+            queue.write_buffer(&self.cascade_buf,i as u64 * CASCADE_STRIDE,m.to_bytes().as_slice());
+            near = split;
+        }
+        queue.write_buffer(&self.uniform_buf,0,uniform.to_bytes().as_slice());
+    }
+
+    /// Restricts drawing to the tile of cascade `i` and binds its matrix to group 0.
+    pub fn begin_cascade<'a>(&'a self,pass:&mut RenderPass<'a>,i:usize)
+    {
+        let size = self.map_size as f32;
+        pass.set_viewport(i as f32 * size,0f32,size,size,0f32,1f32);
+        pass.set_bind_group(0,&self.cascade_bind_group,&[(i as u64 * CASCADE_STRIDE) as u32]);
+    }
+
+    pub fn attachment(&self) -> AttachmentId { self.atlas }
+    pub fn bind_group(&self) -> BindGroupId { self.bind_group }
+    pub fn layout(&self) -> &Rc<BindGroupLayout> { &self.layout }
+    pub fn cascade_layout(&self) -> &Rc<BindGroupLayout> { &self.cascade_layout }
+    pub fn cascades(&self) -> usize { self.cascades }
+    pub fn map_size(&self) -> u32 { self.map_size }
+}
+
+/// Renders every cascade of `map` with `pipeline`, a depth only pipeline using
+/// `ShadowMap::depth_state` and the cascade layout as group 0.
+pub struct ShadowNode{
+    pub map: Rc<ShadowMap>,
+    pub pipeline: Rc<RenderPipeline>,
+    pub casters: Vec<Box<dyn ShadowCaster>>,
+}
+
+impl RenderNode for ShadowNode {
+    fn record<'a>(&'a self,pass:&mut RenderPass<'a>,_res:&'a GraphResources)
+    {
+        pass.set_pipeline(&self.pipeline);
+        for i in 0..self.map.cascades() {
+            self.map.begin_cascade(pass,i);
+            for c in self.casters.iter() {
+                c.draw_depth(pass);
+            }
+        }
+    }
+}
+
+/// WGSL of the shadow lookup with the bindings in `group`. `shadow_factor(world_pos, view_depth)`
+/// returns how lit a fragment is from 0 to 1, `view_depth` is its distance along the camera
+/// direction. It samples with a comparison sampler, so only fragment shaders can call it.
+pub fn shadow_wgsl(group:u32) -> String
+{
+    format!(r#"{uniform}
+
+[[group({group}), binding(0)]]
+var t_shadow: texture_depth_2d;
+[[group({group}), binding(1)]]
+var s_shadow: sampler_comparison;
+[[group({group}), binding(2)]]
+var<uniform> shadow: ShadowUniform;
+
+fn shadow_cascade(view_depth: f32) -> i32
+{{
+    let count = i32(shadow.params.y);
+    var i: i32 = 0;
+    loop {{
+        if (i >= count) {{ break; }}
+        if (view_depth < shadow.splits[i]) {{ break; }}
+        i = i + 1;
+    }}
+    return i;
+}}
+
+fn shadow_sample(tile: vec2<f32>, cascade: i32, depth: f32) -> f32
+{{
+    // kept half a texel inside the tile so filtering does not reach the next cascade
+    let border = shadow.params.w * 0.5;
+    let uv = clamp(tile, vec2<f32>(border, border), vec2<f32>(1.0 - border, 1.0 - border));
+    let atlas = vec2<f32>((f32(cascade) + uv.x) / shadow.params.y, uv.y);
+    return textureSampleCompare(t_shadow, s_shadow, atlas, depth);
+}}
+
+fn shadow_factor(world_pos: vec3<f32>, view_depth: f32) -> f32
+{{
+    let cascade = shadow_cascade(view_depth);
+    if (cascade >= i32(shadow.params.y)) {{
+        return 1.0;
+    }}
+    let p = shadow.light_view_proj[cascade] * vec4<f32>(world_pos, 1.0);
+    let ndc = p.xyz / p.w;
+    let tile = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    let depth = ndc.z - shadow.params.x;
+    let radius = i32(shadow.params.z);
+    var sum: f32 = 0.0;
+    var y: i32 = -radius;
+    loop {{
+        if (y > radius) {{ break; }}
+        var x: i32 = -radius;
+        loop {{
+            if (x > radius) {{ break; }}
+            sum = sum + shadow_sample(tile + vec2<f32>(f32(x), f32(y)) * shadow.params.w, cascade, depth);
+            x = x + 1;
+        }}
+        y = y + 1;
+    }}
+    let n = f32(2 * radius + 1);
+    return sum / (n * n);
+}}
+"#,uniform = ShadowUniform::wgsl_struct(AddressSpace::Uniform),group = group)
+}
+
+mod test_shadow{
+    use crate::shadow::{cascade_splits, cascade_matrix, CameraFrustum};
+    use cgmath::{Matrix4, Vector3, Vector4, Point3, Deg, Rad, EuclideanSpace};
+
+    #[test]
+    fn splits()
+    {
+        let s = cascade_splits(0.1,100.0,4,0.75);
+        assert_eq!(s.len(),4);
+        assert!(s.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(*s.last().unwrap(),100.0);
+        // all logarithmic puts the first split much closer than uniform
+        assert!(cascade_splits(0.1,100.0,4,1.0)[0] < 1.0);
+        let u = cascade_splits(1.0,101.0,4,0.0);
+        for (i,v) in u.iter().enumerate() {
+            assert!((v - 1.0 - 25.0 * (i + 1) as f32).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn cascade_covers_frustum()
+    {
+        let camera = CameraFrustum{
+            view: Matrix4::look_at_rh(Point3::new(3.0,4.0,10.0),Point3::origin(),Vector3::unit_y()),
+            fovy: Rad::from(Deg(60f32)),
+            aspect: 1.5,
+            near: 0.1,
+            far: 40.0
+        };
+        let splits = cascade_splits(camera.near,camera.far,3,0.75);
+        let mut near = camera.near;
+        for &far in splits.iter() {
+            let corners = camera.corners(near,far);
+            let m = cascade_matrix(Vector3::new(-1.0,-2.0,-0.5),&corners,1024,10.0);
+            for c in corners.iter() {
+                let p = m * Vector4::new(c.x,c.y,c.z,1.0);
+                let p = p.truncate() / p.w;
+                assert!(p.x.abs() <= 1.0 && p.y.abs() <= 1.0,"{:?} outside the cascade",p);
+                assert!(p.z >= 0.0 && p.z <= 1.0,"{:?} outside the depth range",p);
+            }
+            near = far;
+        }
+    }
+
+    #[test]
+    fn cascade_is_stable()
+    {
+        // moving the camera less than a texel sideways to the light keeps the matrix
+        let camera = |x:f32| CameraFrustum{
+            view: Matrix4::from_translation(Vector3::new(-x,-2.0,0.0)),
+            fovy: Rad::from(Deg(60f32)),
+            aspect: 1.0,
+            near: 0.1,
+            far: 20.0
+        };
+        let dir = Vector3::new(0.0,-1.0,0.0);
+        let a = cascade_matrix(dir,&camera(0.0).corners(0.1,20.0),1024,10.0);
+        let b = cascade_matrix(dir,&camera(0.001).corners(0.1,20.0),1024,10.0);
+        assert_eq!(a,b);
+    }
+}
@@ -0,0 +1,154 @@
+use std::rc::Rc;
+use wgpu::{Device, Queue, RenderPass, BufferUsage, BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindGroupDescriptor, BindGroupEntry, BindingType, BufferBindingType, BindingResource, ShaderStage, TextureSampleType, TextureViewDimension, RenderPipeline, TextureFormat};
+use cgmath::{Matrix4, Vector4, SquareMatrix};
+use crate::buffer::GpuBuffer;
+use crate::cubemap::GpuCubemap;
+use crate::pipeline::{PipelineCache, PipelineDesc, ColorFormat, DepthState, FULLSCREEN_TRIANGLE_WGSL};
+use crate::material::CompareDesc;
+
+const SKYBOX_WGSL: &str = r#"
+[[block]]
+struct Sky {
+    inv_view_proj: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> sky: Sky;
+[[group(0), binding(1)]]
+var sky_map: texture_cube<f32>;
+[[group(0), binding(2)]]
+var sky_sampler: sampler;
+
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    let ndc = vec2<f32>(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0);
+    let p = sky.inv_view_proj * vec4<f32>(ndc, 1.0, 1.0);
+    return vec4<f32>(textureSample(sky_map, sky_sampler, p.xyz / p.w).rgb, 1.0);
+}
+"#;
+
+/// The shader drawing the sky on the far plane.
+pub fn skybox_wgsl() -> String
+{
+    format!("{}{}",FULLSCREEN_TRIANGLE_WGSL,SKYBOX_WGSL)
+}
+
+/// Maps clip space positions to world directions as seen from the camera position:
+/// the inverse of `proj` times `view` without its translation.
+pub fn sky_matrix(view:&Matrix4<f32>,proj:&Matrix4<f32>) -> Matrix4<f32>
+{
+    let mut rotation = *view;
+    rotation.w = Vector4::new(0.0,0.0,0.0,1.0);
+    (proj * rotation).invert().unwrap_or_else(Matrix4::identity)
+}
+
+/// Draws a cubemap behind everything else. Execute it after the opaque geometry of a pass
+/// with depth, where it only covers pixels still at the far plane.
+pub struct Skybox{
+    pipeline: Rc<RenderPipeline>,
+    sky: GpuBuffer<Matrix4<f32>>,
+    layout: Rc<BindGroupLayout>,
+    bind_group: BindGroup,
+}
+
+impl Skybox {
+    /// Without a depth format the sky covers the whole target, for drawing it first.
+    pub fn new(device:&Device,pipelines:&mut PipelineCache,cubemap:&GpuCubemap,color:ColorFormat,depth_format:Option<TextureFormat>,sample_count:u32) -> Skybox
+    {
+        let sky = GpuBuffer::from_value(device,Some("Sky"),BufferUsage::UNIFORM,&Matrix4::identity());
+        let layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Skybox Layout"),
+            entries: &[
+                BindGroupLayoutEntry{
+                    binding: 0,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding: 1,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float{ filterable: true },
+                        view_dimension: TextureViewDimension::Cube,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding: 2,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Sampler{
+                        filtering: true,
+                        comparison: false
+                    },
+                    count: None
+                }
+            ]
+        }));
+        let bind_group = device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Skybox Bind Group"),
+            layout: &layout,
+            entries: &[
+                BindGroupEntry{ binding: 0, resource: sky.as_entire_binding() },
+                BindGroupEntry{ binding: 1, resource: BindingResource::TextureView(&cubemap.view) },
+                BindGroupEntry{ binding: 2, resource: BindingResource::Sampler(&cubemap.sampler) }
+            ]
+        });
+
+        let shader = pipelines.shader(device,"Skybox",skybox_wgsl().as_str());
+        let layouts = vec![pipelines.layout(&layout)];
+        let pipeline = pipelines.get(device,&PipelineDesc{
+            color: Some(color),
+            // the far plane passes where nothing was drawn, and the sky never hides anything
+            depth: depth_format.map(|format| DepthState{ format, write: false, compare: CompareDesc::LessEqual }),
+            sample_count,
+            ..PipelineDesc::new(shader,layouts,Vec::new())
+        });
+        Skybox{ pipeline, sky, layout, bind_group }
+    }
+
+    pub fn layout(&self) -> &Rc<BindGroupLayout> { &self.layout }
+
+    pub fn update(&self,queue:&Queue,view:&Matrix4<f32>,proj:&Matrix4<f32>)
+    {
+        self.sky.set(queue,&sky_matrix(view,proj));
+    }
+
+    pub fn execute<'a>(&'a self,pass:&mut RenderPass<'a>)
+    {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0,&self.bind_group,&[]);
+        pass.draw(0..3,0..1);
+    }
+}
+
+mod test_skybox{
+    use crate::skybox::sky_matrix;
+    use cgmath::{Matrix4, Point3, Vector3, Vector4, Deg, InnerSpace, perspective};
+
+    #[test]
+    fn direction()
+    {
+        let proj = perspective(Deg(60.0),1.5,0.1,100.0);
+        let dir = |eye:Point3<f32>,target:Point3<f32>,x:f32,y:f32| {
+            let p = sky_matrix(&Matrix4::look_at_rh(eye,target,Vector3::unit_y()),&proj) * Vector4::new(x,y,1.0,1.0);
+            (p.truncate() / p.w).normalize()
+        };
+        let look = Vector3::new(1.0,-0.5,2.0).normalize();
+        for eye in [Point3::new(0.0,0.0,0.0),Point3::new(50.0,-20.0,3.0)].iter() {
+            let center = dir(*eye,*eye + look,0.0,0.0);
+            assert!((center - look).magnitude() < 1e-4,"{:?}",center);
+            // top of the screen looks further up
+            assert!(dir(*eye,*eye + look,0.0,1.0).y > center.y);
+        }
+        // only the rotation matters
+        let a = dir(Point3::new(0.0,0.0,0.0),Point3::new(0.0,0.0,-1.0),0.3,-0.7);
+        let b = dir(Point3::new(9.0,9.0,9.0),Point3::new(9.0,9.0,8.0),0.3,-0.7);
+        assert!((a - b).magnitude() < 1e-4);
+    }
+}
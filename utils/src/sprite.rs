@@ -0,0 +1,332 @@
+use std::rc::Rc;
+use std::hash::Hash;
+use std::ops::Range;
+use wgpu::{Device, Queue, RenderPass, BufferUsage, BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindGroupDescriptor, BindGroupEntry, BindingType, BufferBindingType, BindingResource, ShaderStage, RenderPipeline, TextureSampleType, TextureViewDimension, SamplerDescriptor};
+use cgmath::{Matrix4, Vector2, Vector4, SquareMatrix};
+use crate::vertex::VertexLayout;
+use crate::buffer::{GpuBuffer, GpuVec};
+use crate::texture::GpuTexture;
+use crate::pipeline::{PipelineCache, PipelineDesc, ColorFormat};
+use crate::material::BlendMode;
+use crate::resource_manager::pack::RgbaMips;
+use crate::draw::{build_batches, Batch};
+use crate::overlay::OverlayVertex;
+
+/// A texture sprites are drawn from, usually a `TextureAtlas`. Created by `SpriteRenderer::create_texture`.
+pub struct SpriteTexture{
+    pub texture: GpuTexture,
+    bind_group: BindGroup,
+}
+
+/// One quad, corners in the order top left, top right, bottom right, bottom left.
+pub struct SpriteCommand{
+    pub texture: Rc<SpriteTexture>,
+    pub z: i32,
+    pub corners: [OverlayVertex;4],
+}
+
+/// The corners of a `size` sprite placed by `world`, `pivot` is the point of the sprite
+/// at the origin of `world`, (0,0) top left and (1,1) bottom right. Flipping swaps the uvs,
+/// so the sprite stays where it is.
+pub fn sprite_corners(world:&Matrix4<f32>,size:Vector2<f32>,pivot:Vector2<f32>,uv:[f32;4],flip_x:bool,flip_y:bool,color:Vector4<f32>) -> [OverlayVertex;4]
+{
+    let [mut u0,mut v0,mut u1,mut v1] = uv;
+    if flip_x { std::mem::swap(&mut u0,&mut u1); }
+    if flip_y { std::mem::swap(&mut v0,&mut v1); }
+    let corner = |x:f32,y:f32,u:f32,v:f32| {
+        let p = world * Vector4::new((x - pivot.x) * size.x,(y - pivot.y) * size.y,0.0,1.0);
+        OverlayVertex{ position: Vector2::new(p.x,p.y), uv: Vector2::new(u,v), color }
+    };
+    [corner(0.0,0.0,u0,v0),corner(1.0,0.0,u1,v0),corner(1.0,1.0,u1,v1),corner(0.0,1.0,u0,v1)]
+}
+
+/// Orders sprites by z, lower first, and batches each z layer by texture. `keys[i]` is the
+/// z and texture of sprite i. Sprites of one layer do not keep their order among each other,
+/// a layer starts with the texture the layer before ended with so it joins that batch.
+/// Returns the sprite indices in draw order and the batches.
+pub fn plan_sprites<K:Eq + Hash + Copy>(keys:&[(i32,K)]) -> (Vec<usize>,Vec<Batch>)
+{
+    let mut sorted:Vec<usize> = (0..keys.len()).collect();
+    sorted.sort_by_key(|&i| keys[i].0);
+
+    let mut order = Vec::with_capacity(keys.len());
+    let mut batches:Vec<Batch> = Vec::new();
+    let mut start = 0;
+    while start < sorted.len() {
+        let z = keys[sorted[start]].0;
+        let end = start + sorted[start..].iter().take_while(|&&i| keys[i].0 == z).count();
+        let mut part = sorted[start..end].to_vec();
+        if let Some(last) = batches.last() {
+            let last = keys[last.command].1;
+            part.sort_by_key(|&i| keys[i].1 != last);
+        }
+        let layer:Vec<_> = part.iter().map(|&i| (keys[i].1,())).collect();
+        let (part_order,part_batches) = build_batches(layer.as_slice());
+        for b in part_batches.into_iter() {
+            let command = part[part_order[b.first as usize]];
+            match batches.last_mut() {
+                Some(l) if keys[l.command].1 == keys[command].1 => l.count += b.count,
+                _ => batches.push(Batch{ command, first: order.len() as u32, count: b.count })
+            }
+            order.extend(part_order[b.first as usize..(b.first + b.count) as usize].iter().map(|&i| part[i]));
+        }
+        start = end;
+    }
+    (order,batches)
+}
+
+/// Sprites recorded by `Sprite` components in `Component::render`, drawn by a `SpriteRenderer`.
+pub struct SpriteBatch{
+    commands: Vec<SpriteCommand>,
+}
+
+impl SpriteBatch {
+    pub fn new() -> SpriteBatch
+    {
+        SpriteBatch{ commands: Vec::new() }
+    }
+
+    pub fn push(&mut self,cmd:SpriteCommand)
+    {
+        self.commands.push(cmd);
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.commands.clear();
+    }
+
+    pub fn commands(&self) -> &[SpriteCommand] { self.commands.as_slice() }
+    pub fn len(&self) -> usize { self.commands.len() }
+    pub fn is_empty(&self) -> bool { self.commands.is_empty() }
+}
+
+impl Default for SpriteBatch {
+    fn default() -> Self { SpriteBatch::new() }
+}
+
+const SPRITE_WGSL: &str = r#"
+[[block]]
+struct Camera {
+    view_proj: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+[[group(1), binding(0)]]
+var sprite_texture: texture_2d<f32>;
+[[group(1), binding(1)]]
+var sprite_sampler: sampler;
+
+struct VertexInput {
+    [[location(0)]] position: vec2<f32>;
+    [[location(1)]] uv: vec2<f32>;
+    [[location(2)]] color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+    [[location(1)]] color: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn main(in: VertexInput) -> VertexOutput
+{
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(in.position,0.0,1.0);
+    out.uv = in.uv;
+    out.color = in.color;
+    return out;
+}
+
+[[stage(fragment)]]
+fn main(in: VertexOutput) -> [[location(0)]] vec4<f32>
+{
+    return in.color * textureSample(sprite_texture,sprite_sampler,in.uv);
+}
+"#;
+
+/// Draws a `SpriteBatch` alpha blended, without depth, one draw call per batch of
+/// `plan_sprites`. Call `prepare` before the pass and `execute` inside it.
+pub struct SpriteRenderer{
+    camera: GpuBuffer<Matrix4<f32>>,
+    camera_group: BindGroup,
+    texture_layout: Rc<BindGroupLayout>,
+    pipeline: Rc<RenderPipeline>,
+    vertices: GpuVec<OverlayVertex>,
+    draws: Vec<(Rc<SpriteTexture>,Range<u32>)>,
+}
+
+impl SpriteRenderer {
+    pub fn new(device:&Device,pipelines:&mut PipelineCache,color:ColorFormat,sample_count:u32) -> SpriteRenderer
+    {
+        let camera = GpuBuffer::from_value(device,Some("Sprite Camera"),BufferUsage::UNIFORM,&Matrix4::identity());
+        let camera_layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Sprite Camera Layout"),
+            entries: &[
+                BindGroupLayoutEntry{
+                    binding: 0,
+                    visibility: ShaderStage::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                }
+            ]
+        }));
+        let camera_group = device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Sprite Camera Bind Group"),
+            layout: &camera_layout,
+            entries: &[
+                BindGroupEntry{ binding: 0, resource: camera.as_entire_binding() }
+            ]
+        });
+        let texture_layout = Rc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor{
+            label: Some("Sprite Texture Layout"),
+            entries: &[
+                BindGroupLayoutEntry{
+                    binding: 0,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float{ filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry{
+                    binding: 1,
+                    visibility: ShaderStage::FRAGMENT,
+                    ty: BindingType::Sampler{
+                        filtering: true,
+                        comparison: false
+                    },
+                    count: None
+                }
+            ]
+        }));
+
+        let shader = pipelines.shader(device,"Sprite",SPRITE_WGSL);
+        let layouts = vec![pipelines.layout(&camera_layout),pipelines.layout(&texture_layout)];
+        let pipeline = pipelines.get(device,&PipelineDesc{
+            color: Some(color),
+            blend: BlendMode::Alpha,
+            sample_count,
+            ..PipelineDesc::new(shader,layouts,vec![OverlayVertex::layout()])
+        });
+        SpriteRenderer{
+            vertices: GpuVec::with_capacity(device,Some("Sprite Vertices"),BufferUsage::VERTEX,1024),
+            draws: Vec::new(),
+            camera,
+            camera_group,
+            texture_layout,
+            pipeline
+        }
+    }
+
+    /// An sRGB texture of `img` usable by sprites drawn with this renderer.
+    pub fn create_texture(&self,device:&Device,queue:&Queue,img:&RgbaMips,sampler:&SamplerDescriptor) -> Rc<SpriteTexture>
+    {
+        let texture = GpuTexture::from_image(device,queue,img,true,sampler);
+        let bind_group = device.create_bind_group(&BindGroupDescriptor{
+            label: Some("Sprite Texture Bind Group"),
+            layout: &self.texture_layout,
+            entries: &[
+                BindGroupEntry{ binding: 0, resource: BindingResource::TextureView(&texture.view) },
+                BindGroupEntry{ binding: 1, resource: BindingResource::Sampler(&texture.sampler) }
+            ]
+        });
+        Rc::new(SpriteTexture{ texture, bind_group })
+    }
+
+    /// Sorts and batches the sprites of `batch` and uploads them, `batch` is empty afterwards.
+    pub fn prepare(&mut self,device:&Device,queue:&Queue,batch:&mut SpriteBatch,view_proj:&Matrix4<f32>)
+    {
+        self.camera.set(queue,view_proj);
+        let keys:Vec<_> = batch.commands().iter().map(|c| (c.z,Rc::as_ptr(&c.texture))).collect();
+        let (order,batches) = plan_sprites(keys.as_slice());
+        self.vertices.clear();
+        for i in order.into_iter() {
+            let [a,b,c,d] = batch.commands()[i].corners;
+            self.vertices.extend_from_slice(&[a,b,c,a,c,d]);
+        }
+        self.draws = batches.into_iter()
+            .map(|b| (batch.commands()[b.command].texture.clone(),b.first * 6..(b.first + b.count) * 6))
+            .collect();
+        self.vertices.sync(device,queue);
+        batch.clear();
+    }
+
+    /// Draw calls of the last `prepare`.
+    pub fn draw_calls(&self) -> usize { self.draws.len() }
+
+    pub fn execute<'a>(&'a self,pass:&mut RenderPass<'a>)
+    {
+        if self.draws.is_empty() { return; }
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0,&self.camera_group,&[]);
+        pass.set_vertex_buffer(0,self.vertices.slice(..));
+        for (texture,range) in self.draws.iter() {
+            pass.set_bind_group(1,&texture.bind_group,&[]);
+            pass.draw(range.clone(),0..1);
+        }
+    }
+}
+
+mod test_sprite{
+    use crate::sprite::{plan_sprites, sprite_corners};
+    use crate::draw::Batch;
+    use cgmath::{Matrix4, Vector2, Vector3, Vector4, Deg, SquareMatrix};
+
+    #[test]
+    fn plan()
+    {
+        // (z, texture)
+        let keys = [(1,'a'),(0,'b'),(0,'a'),(1,'b'),(0,'b'),(2,'a'),(1,'a')];
+        let (order,batches) = plan_sprites(&keys);
+        // layer 0 ends with 'a', so layer 1 starts with 'a' and continues that batch
+        assert_eq!(order,vec![1,4,2,0,6,3,5]);
+        assert_eq!(batches,vec![
+            Batch{ command: 1, first: 0, count: 2 },
+            Batch{ command: 2, first: 2, count: 3 },
+            Batch{ command: 3, first: 5, count: 1 },
+            Batch{ command: 5, first: 6, count: 1 },
+        ]);
+        // z never goes down
+        assert!(order.windows(2).all(|w| keys[w[0]].0 <= keys[w[1]].0));
+
+        let (order,batches) = plan_sprites(&[(0,'a'),(0,'a'),(5,'a')]);
+        assert_eq!(order,vec![0,1,2]);
+        assert_eq!(batches,vec![Batch{ command: 0, first: 0, count: 3 }]);
+
+        let (order,batches) = plan_sprites::<char>(&[]);
+        assert!(order.is_empty() && batches.is_empty());
+    }
+
+    #[test]
+    fn corners()
+    {
+        let white = Vector4::new(1.0,1.0,1.0,1.0);
+        let uv = [0.25,0.5,0.75,1.0];
+        let c = sprite_corners(&Matrix4::identity(),Vector2::new(20.0,10.0),Vector2::new(0.5,0.5),uv,false,false,white);
+        assert_eq!(c[0].position,Vector2::new(-10.0,-5.0));
+        assert_eq!(c[2].position,Vector2::new(10.0,5.0));
+        assert_eq!(c[0].uv,Vector2::new(0.25,0.5));
+        assert_eq!(c[2].uv,Vector2::new(0.75,1.0));
+
+        // a top left pivot puts the sprite right and below the origin
+        let world = Matrix4::from_translation(Vector3::new(100.0,50.0,0.0));
+        let c = sprite_corners(&world,Vector2::new(20.0,10.0),Vector2::new(0.0,0.0),uv,true,false,white);
+        assert_eq!(c[0].position,Vector2::new(100.0,50.0));
+        assert_eq!(c[2].position,Vector2::new(120.0,60.0));
+        // flipped horizontally, same place, mirrored uvs
+        assert_eq!(c[0].uv,Vector2::new(0.75,0.5));
+        assert_eq!(c[1].uv,Vector2::new(0.25,0.5));
+
+        let c = sprite_corners(&Matrix4::from_angle_z(Deg(90f32)),Vector2::new(2.0,2.0),Vector2::new(0.0,0.0),uv,false,true,white);
+        assert!(c[1].position.x.abs() < 1e-5 && (c[1].position.y - 2.0).abs() < 1e-5,"{:?}",c[1].position);
+        assert_eq!(c[0].uv,Vector2::new(0.25,1.0));
+    }
+}
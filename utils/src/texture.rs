@@ -0,0 +1,241 @@
+use std::rc::Rc;
+use std::collections::HashMap;
+use std::any::Any;
+use std::num::NonZeroU32;
+use std::io::Cursor;
+use image::codecs::hdr::HdrDecoder;
+use serde::Deserialize;
+use gen_code::{gen_impl_res_process_cache,AsAny};
+use wgpu::{Device, Queue, Texture, TextureView, Sampler, TextureDescriptor, Extent3d, TextureDimension, TextureFormat, TextureUsage, ImageCopyTexture, Origin3d, ImageDataLayout, TextureViewDescriptor, SamplerDescriptor, AddressMode, FilterMode};
+use crate::AsAny;
+use crate::resource_manager::{ResProcesser, CacheKey};
+use crate::resource_manager::pack::RgbaMips;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,Deserialize)]
+pub enum AddressModeDesc{ ClampToEdge, Repeat, MirrorRepeat }
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,Deserialize)]
+pub enum FilterDesc{ Nearest, Linear }
+
+#[derive(Debug,Clone,PartialEq,Eq,Hash,Deserialize)]
+#[serde(default)]
+pub struct SamplerDesc{
+    pub address_mode: AddressModeDesc,
+    pub mag_filter: FilterDesc,
+    pub min_filter: FilterDesc,
+    pub mipmap_filter: FilterDesc,
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        SamplerDesc{
+            address_mode: AddressModeDesc::ClampToEdge,
+            mag_filter: FilterDesc::Linear,
+            min_filter: FilterDesc::Nearest,
+            mipmap_filter: FilterDesc::Nearest
+        }
+    }
+}
+
+impl SamplerDesc {
+    pub fn to_wgpu(&self) -> SamplerDescriptor<'static>
+    {
+        let address_mode = match self.address_mode {
+            AddressModeDesc::ClampToEdge => AddressMode::ClampToEdge,
+            AddressModeDesc::Repeat => AddressMode::Repeat,
+            AddressModeDesc::MirrorRepeat => AddressMode::MirrorRepeat
+        };
+        let filter = |f:FilterDesc| match f {
+            FilterDesc::Nearest => FilterMode::Nearest,
+            FilterDesc::Linear => FilterMode::Linear
+        };
+        SamplerDescriptor{
+            label: Some("Sampler"),
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            mag_filter: filter(self.mag_filter),
+            min_filter: filter(self.min_filter),
+            mipmap_filter: filter(self.mipmap_filter),
+            ..Default::default()
+        }
+    }
+}
+
+/// Decodes png/jpeg (anything the `image` crate reads) or the raw rgba blobs
+/// written by the pack tool into a `RgbaMips`.
+#[derive(AsAny)]
+pub struct ImageRes{
+    cache: HashMap<CacheKey<()>,Rc<RgbaMips>>,
+}
+
+impl ImageRes {
+    pub fn new() -> ImageRes
+    {
+        ImageRes{
+            cache:Default::default()
+        }
+    }
+}
+
+impl ResProcesser for ImageRes {
+    type In = Vec<u8>;
+    type Out = RgbaMips;
+    type Param = ();
+
+    gen_impl_res_process_cache!{cache}
+
+    fn process(&self, d: Rc<Self::In>, _key: &CacheKey<()>) -> Option<Rc<Self::Out>> {
+        if let Ok(img) = image::load_from_memory(d.as_slice())
+        {
+            let img = img.to_rgba8();
+            Some(Rc::new(RgbaMips{
+                width: img.width(),
+                height: img.height(),
+                levels: vec![img.into_raw()]
+            }))
+        }else{
+            RgbaMips::decode(d.as_slice()).map(Rc::new)
+        }
+    }
+}
+
+/// A float image, e.g. an equirectangular environment, rgb with alpha 1.
+#[derive(Debug,Clone,PartialEq)]
+pub struct HdrImage{
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<[f32;4]>,
+}
+
+/// Decodes Radiance `.hdr` files into a `HdrImage`.
+#[derive(AsAny)]
+pub struct HdrImageRes{
+    cache: HashMap<CacheKey<()>,Rc<HdrImage>>,
+}
+
+impl HdrImageRes {
+    pub fn new() -> HdrImageRes
+    {
+        HdrImageRes{
+            cache:Default::default()
+        }
+    }
+}
+
+impl ResProcesser for HdrImageRes {
+    type In = Vec<u8>;
+    type Out = HdrImage;
+    type Param = ();
+
+    gen_impl_res_process_cache!{cache}
+
+    fn process(&self, d: Rc<Self::In>, _key: &CacheKey<()>) -> Option<Rc<Self::Out>> {
+        let decoder = HdrDecoder::new(Cursor::new(d.as_slice())).ok()?;
+        let meta = decoder.metadata();
+        let pixels = decoder.read_image_hdr().ok()?;
+        Some(Rc::new(HdrImage{
+            width: meta.width,
+            height: meta.height,
+            data: pixels.into_iter().map(|p| [p[0],p[1],p[2],1.0]).collect()
+        }))
+    }
+}
+
+/// Whether `format` stores colors sRGB encoded, converting them when sampled and rendered
+/// to. Only the 8 bit color formats are listed, the ones a swap chain or attachment has.
+pub fn is_srgb(format:TextureFormat) -> bool
+{
+    matches!(format,TextureFormat::Bgra8UnormSrgb | TextureFormat::Rgba8UnormSrgb)
+}
+
+pub struct GpuTexture{
+    pub texture: Texture,
+    pub view: TextureView,
+    pub sampler: Sampler,
+    pub format: TextureFormat,
+    pub size: Extent3d,
+}
+
+impl GpuTexture {
+    pub fn from_image(device:&Device,queue:&Queue,img:&RgbaMips,srgb:bool,sampler:&SamplerDescriptor) -> GpuTexture
+    {
+        let format = if srgb { TextureFormat::Rgba8UnormSrgb } else { TextureFormat::Rgba8Unorm };
+        let size = Extent3d{
+            width: img.width,
+            height: img.height,
+            depth_or_array_layers: 1
+        };
+        let texture = device.create_texture(&TextureDescriptor{
+            label: Some("Texture"),
+            size,
+            mip_level_count: img.levels.len().max(1) as u32,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST
+        });
+        for (i,level) in img.levels.iter().enumerate() {
+            let (w,h) = RgbaMips::mip_size(img.width,img.height,i as u32);
+            queue.write_texture(ImageCopyTexture{
+                texture: &texture,
+                mip_level: i as u32,
+                origin: Origin3d::ZERO
+            }, level.as_slice(), ImageDataLayout{
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(w * 4),
+                rows_per_image: NonZeroU32::new(h)
+            }, Extent3d{ width: w, height: h, depth_or_array_layers: 1 });
+        }
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(sampler);
+        GpuTexture{ texture, view, sampler, format, size }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct TextureParam{
+    pub srgb: bool,
+    pub sampler: SamplerDesc,
+}
+
+impl Default for TextureParam {
+    fn default() -> Self {
+        TextureParam{
+            srgb: true,
+            sampler: Default::default()
+        }
+    }
+}
+
+/// Uploads a decoded image, the same image can be cached as sRGB and as linear
+/// or with different samplers.
+#[derive(AsAny)]
+pub struct GpuTextureRes{
+    device: Rc<Device>,
+    queue: Rc<Queue>,
+    cache: HashMap<CacheKey<TextureParam>,Rc<GpuTexture>>,
+}
+
+impl GpuTextureRes {
+    pub fn new(device:Rc<Device>,queue:Rc<Queue>) -> GpuTextureRes
+    {
+        GpuTextureRes{
+            device,
+            queue,
+            cache:Default::default()
+        }
+    }
+}
+
+impl ResProcesser for GpuTextureRes {
+    type In = RgbaMips;
+    type Out = GpuTexture;
+    type Param = TextureParam;
+
+    gen_impl_res_process_cache!{cache}
+
+    fn process(&self, d: Rc<Self::In>, key: &CacheKey<TextureParam>) -> Option<Rc<Self::Out>> {
+        Some(Rc::new(GpuTexture::from_image(&self.device,&self.queue,d.as_ref(),key.param.srgb,&key.param.sampler.to_wgpu())))
+    }
+}
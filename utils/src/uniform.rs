@@ -0,0 +1,317 @@
+use cgmath::{Vector2, Vector3, Vector4, Matrix2, Matrix3, Matrix4};
+pub use gen_code::UniformLayout;
+
+/// Which WGSL layout rules apply. Uniform buffers round the alignment of structs
+/// and arrays (and the stride of arrays) up to 16, storage buffers do not.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum AddressSpace{ Uniform, Storage }
+
+impl AddressSpace {
+    pub fn round_up(align:u64,n:u64) -> u64
+    {
+        (n + align - 1) / align * align
+    }
+
+    /// Alignment of a struct or array whose largest member alignment is `align`.
+    pub fn container_align(self,align:u64) -> u64
+    {
+        match self {
+            AddressSpace::Uniform => Self::round_up(16,align),
+            AddressSpace::Storage => align
+        }
+    }
+
+    /// Offset of a member of type `T` placed after `offset` bytes of earlier members.
+    pub fn member_offset<T:UniformLayout>(self,offset:u64) -> u64
+    {
+        Self::round_up(T::align(self),offset)
+    }
+
+    /// One line of a WGSL struct declaration. The layout WGSL derives by itself is the
+    /// storage one, members that differ in a uniform buffer get explicit align/size.
+    pub fn member_decl<T:UniformLayout>(self,name:&str) -> String
+    {
+        let mut attrs = Vec::new();
+        if T::align(self) != T::align(AddressSpace::Storage) {
+            attrs.push(format!("align({})",T::align(self)));
+        }
+        if T::size(self) != T::size(AddressSpace::Storage) {
+            attrs.push(format!("size({})",T::size(self)));
+        }
+        if attrs.is_empty() {
+            format!("    {}: {};\n",name,T::wgsl_type(self))
+        }else{
+            format!("    [[{}]] {}: {};\n",attrs.join(", "),name,T::wgsl_type(self))
+        }
+    }
+}
+
+/// A type with a WGSL host-shareable layout, use `#[derive(UniformLayout)]` for structs.
+pub trait UniformLayout {
+    fn align(space:AddressSpace) -> u64;
+    fn size(space:AddressSpace) -> u64;
+    fn wgsl_type(space:AddressSpace) -> String;
+    /// Writes the value to the start of `out`, padding bytes are left as they are.
+    fn write(&self,space:AddressSpace,out:&mut [u8]);
+    /// Pushes the struct declarations this type needs, dependencies first.
+    fn declare(_space:AddressSpace,_out:&mut Vec<String>) {}
+
+    fn to_bytes_in(&self,space:AddressSpace) -> Vec<u8>
+    {
+        let mut res = vec![0u8;Self::size(space) as usize];
+        self.write(space,res.as_mut_slice());
+        res
+    }
+
+    /// Padded bytes for a uniform buffer.
+    fn to_bytes(&self) -> Vec<u8>
+    {
+        self.to_bytes_in(AddressSpace::Uniform)
+    }
+
+    /// WGSL declarations of this struct and the structs it contains, the last one
+    /// is marked as `[[block]]` so it can be bound directly.
+    fn wgsl_struct(space:AddressSpace) -> String
+    {
+        let mut decls = Vec::new();
+        Self::declare(space,&mut decls);
+        if let Some(last) = decls.last_mut() {
+            last.insert_str(0,"[[block]]\n");
+        }
+        decls.join("\n\n")
+    }
+}
+
+macro_rules! impl_scalar {
+    ($($t:ty => $name:expr),*) => {
+        $(
+            impl UniformLayout for $t {
+                fn align(_space:AddressSpace) -> u64 { 4 }
+                fn size(_space:AddressSpace) -> u64 { 4 }
+                fn wgsl_type(_space:AddressSpace) -> String { $name.to_string() }
+                fn write(&self,_space:AddressSpace,out:&mut [u8])
+                {
+                    out[..4].copy_from_slice(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar!{ f32 => "f32", i32 => "i32", u32 => "u32" }
+
+macro_rules! impl_vector {
+    ($($v:ident $n:expr => [$($c:ident),+]);*) => {
+        $(
+            impl<S:UniformLayout> UniformLayout for $v<S> {
+                fn align(space:AddressSpace) -> u64
+                {
+                    // vec3 is aligned like vec4
+                    S::align(space) * if $n == 2 { 2 } else { 4 }
+                }
+                fn size(space:AddressSpace) -> u64 { S::size(space) * $n }
+                fn wgsl_type(space:AddressSpace) -> String { format!("vec{}<{}>",$n,S::wgsl_type(space)) }
+                fn write(&self,space:AddressSpace,out:&mut [u8])
+                {
+                    let mut offset = 0;
+                    $(
+                        self.$c.write(space,&mut out[offset..]);
+                        offset += S::size(space) as usize;
+                    )+
+                    let _ = offset;
+                }
+            }
+        )*
+    };
+}
+
+impl_vector!{
+    Vector2 2 => [x,y];
+    Vector3 3 => [x,y,z];
+    Vector4 4 => [x,y,z,w]
+}
+
+macro_rules! impl_matrix {
+    ($($m:ident $v:ident $n:expr => [$($c:ident),+]);*) => {
+        $(
+            /// Stored as an array of column vectors.
+            impl UniformLayout for $m<f32> {
+                fn align(space:AddressSpace) -> u64 { $v::<f32>::align(space) }
+                fn size(space:AddressSpace) -> u64 { Self::column_stride(space) * $n }
+                fn wgsl_type(_space:AddressSpace) -> String { format!("mat{}x{}<f32>",$n,$n) }
+                fn write(&self,space:AddressSpace,out:&mut [u8])
+                {
+                    let stride = Self::column_stride(space) as usize;
+                    let mut offset = 0;
+                    $(
+                        self.$c.write(space,&mut out[offset..]);
+                        offset += stride;
+                    )+
+                    let _ = offset;
+                }
+            }
+
+            impl ColumnStride for $m<f32> {
+                fn column_stride(space:AddressSpace) -> u64
+                {
+                    AddressSpace::round_up($v::<f32>::align(space),$v::<f32>::size(space))
+                }
+            }
+        )*
+    };
+}
+
+trait ColumnStride {
+    fn column_stride(space:AddressSpace) -> u64;
+}
+
+impl_matrix!{
+    Matrix2 Vector2 2 => [x,y];
+    Matrix3 Vector3 3 => [x,y,z];
+    Matrix4 Vector4 4 => [x,y,z,w]
+}
+
+/// `array<T, N>`, in a uniform buffer the element stride is rounded up to 16.
+impl<T:UniformLayout,const N:usize> UniformLayout for [T;N] {
+    fn align(space:AddressSpace) -> u64
+    {
+        space.container_align(T::align(space))
+    }
+
+    fn size(space:AddressSpace) -> u64
+    {
+        array_stride::<T>(space) * N as u64
+    }
+
+    fn wgsl_type(space:AddressSpace) -> String
+    {
+        let stride = array_stride::<T>(space);
+        if stride != array_stride::<T>(AddressSpace::Storage) {
+            format!("[[stride({})]] array<{}, {}>",stride,T::wgsl_type(space),N)
+        }else{
+            format!("array<{}, {}>",T::wgsl_type(space),N)
+        }
+    }
+
+    fn write(&self,space:AddressSpace,out:&mut [u8])
+    {
+        let stride = array_stride::<T>(space) as usize;
+        for (i,v) in self.iter().enumerate() {
+            v.write(space,&mut out[i * stride..]);
+        }
+    }
+
+    fn declare(space:AddressSpace,out:&mut Vec<String>)
+    {
+        T::declare(space,out);
+    }
+}
+
+pub fn array_stride<T:UniformLayout>(space:AddressSpace) -> u64
+{
+    let stride = AddressSpace::round_up(T::align(space),T::size(space));
+    match space {
+        AddressSpace::Uniform => AddressSpace::round_up(16,stride),
+        AddressSpace::Storage => stride
+    }
+}
+
+mod test_uniform{
+    use crate::uniform::{UniformLayout, AddressSpace};
+    use cgmath::{Vector3, Matrix3, Matrix4, SquareMatrix};
+
+    #[derive(UniformLayout)]
+    struct Light{
+        position: Vector3<f32>,
+        intensity: f32,
+        color: Vector3<f32>,
+    }
+
+    #[derive(UniformLayout)]
+    struct Scene{
+        view: Matrix4<f32>,
+        normal: Matrix3<f32>,
+        light: Light,
+        weights: [f32;3],
+        count: u32,
+    }
+
+    fn f32_at(b:&[u8],offset:usize) -> f32
+    {
+        let mut v = [0u8;4];
+        v.copy_from_slice(&b[offset..offset + 4]);
+        f32::from_le_bytes(v)
+    }
+
+    #[test]
+    fn vec3()
+    {
+        // a scalar packs into the tail of a vec3, another vec3 does not
+        assert_eq!(Light::align(AddressSpace::Storage),16);
+        assert_eq!(Light::size(AddressSpace::Storage),32);
+        let l = Light{ position: Vector3::new(1.0,2.0,3.0), intensity: 4.0, color: Vector3::new(5.0,6.0,7.0) };
+        let b = l.to_bytes_in(AddressSpace::Storage);
+        assert_eq!(b.len(),32);
+        let v:Vec<f32> = [0,4,8,12,16,20,24].iter().map(|&o| f32_at(&b,o)).collect();
+        assert_eq!(v,vec![1.0,2.0,3.0,4.0,5.0,6.0,7.0]);
+    }
+
+    #[test]
+    fn mat3()
+    {
+        assert_eq!(Matrix3::<f32>::size(AddressSpace::Uniform),48);
+        let m = Matrix3::new(1.0,2.0,3.0,4.0,5.0,6.0,7.0,8.0,9.0f32);
+        let b = m.to_bytes();
+        assert_eq!(b.len(),48);
+        // columns start every 16 bytes, the 4th float of each is padding
+        assert_eq!((f32_at(&b,0),f32_at(&b,16),f32_at(&b,32)),(1.0,4.0,7.0));
+        assert_eq!((f32_at(&b,12),f32_at(&b,28),f32_at(&b,44)),(0.0,0.0,0.0));
+    }
+
+    #[test]
+    fn array()
+    {
+        assert_eq!(<[f32;3]>::size(AddressSpace::Uniform),48);
+        assert_eq!(<[f32;3]>::size(AddressSpace::Storage),12);
+        assert_eq!(<[Vector3<f32>;2]>::size(AddressSpace::Storage),32);
+        let b = [1.0f32,2.0,3.0].to_bytes();
+        assert_eq!((f32_at(&b,0),f32_at(&b,16),f32_at(&b,32)),(1.0,2.0,3.0));
+    }
+
+    #[test]
+    fn nested()
+    {
+        // view 0..64, normal 64..112, light 112..144, weights 144..192, count 192
+        assert_eq!(Scene::size(AddressSpace::Uniform),208);
+        // weights 144..156, count 156
+        assert_eq!(Scene::size(AddressSpace::Storage),160);
+        let s = Scene{
+            view: Matrix4::identity(),
+            normal: Matrix3::identity(),
+            light: Light{ position: Vector3::new(0.0,0.0,0.0), intensity: 2.0, color: Vector3::new(1.0,1.0,1.0) },
+            weights: [0.5,0.25,0.125],
+            count: 3
+        };
+        let b = s.to_bytes();
+        assert_eq!(b.len(),208);
+        assert_eq!(f32_at(&b,112 + 12),2.0);
+        assert_eq!(f32_at(&b,144 + 32),0.125);
+        assert_eq!(u32::from_le_bytes([b[192],b[193],b[194],b[195]]),3);
+
+        assert_eq!(Scene::wgsl_struct(AddressSpace::Uniform),
+"struct Light {
+    position: vec3<f32>;
+    intensity: f32;
+    color: vec3<f32>;
+};
+
+[[block]]
+struct Scene {
+    view: mat4x4<f32>;
+    normal: mat3x3<f32>;
+    light: Light;
+    [[align(16), size(48)]] weights: [[stride(16)]] array<f32, 3>;
+    count: u32;
+};");
+    }
+}
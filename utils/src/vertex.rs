@@ -0,0 +1,202 @@
+use std::mem::size_of;
+use wgpu::{BufferAddress, InputStepMode, VertexAttribute, VertexBufferLayout, VertexFormat};
+use cgmath::{Vector2, Vector3, Vector4, Point3, Matrix2, Matrix3, Matrix4};
+pub use gen_code::VertexLayout;
+use crate::reflect::ReflectError;
+
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct VertexLayoutDesc{
+    pub array_stride: BufferAddress,
+    pub step_mode: InputStepMode,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+impl VertexLayoutDesc {
+    pub fn as_layout(&self) -> VertexBufferLayout<'_>
+    {
+        VertexBufferLayout{
+            array_stride: self.array_stride,
+            step_mode: self.step_mode,
+            attributes: self.attributes.as_slice()
+        }
+    }
+}
+
+/// A field type usable in a vertex buffer, matrices take one attribute per column.
+pub trait VertexAttr {
+    const FORMATS: &'static [VertexFormat];
+}
+
+macro_rules! impl_vertex_attr {
+    ($($t:ty => [$($f:ident),+]);* $(;)?) => {
+        $(
+            impl VertexAttr for $t {
+                const FORMATS: &'static [VertexFormat] = &[$(VertexFormat::$f),+];
+            }
+        )*
+    };
+}
+
+impl_vertex_attr!{
+    f32 => [Float32];
+    [f32;1] => [Float32];
+    [f32;2] => [Float32x2];
+    [f32;3] => [Float32x3];
+    [f32;4] => [Float32x4];
+    u32 => [Uint32];
+    [u32;2] => [Uint32x2];
+    [u32;3] => [Uint32x3];
+    [u32;4] => [Uint32x4];
+    i32 => [Sint32];
+    [i32;2] => [Sint32x2];
+    [i32;3] => [Sint32x3];
+    [i32;4] => [Sint32x4];
+    Vector2<f32> => [Float32x2];
+    Vector3<f32> => [Float32x3];
+    Vector4<f32> => [Float32x4];
+    Point3<f32> => [Float32x3];
+    Matrix2<f32> => [Float32x2,Float32x2];
+    Matrix3<f32> => [Float32x3,Float32x3,Float32x3];
+    Matrix4<f32> => [Float32x4,Float32x4,Float32x4,Float32x4];
+    [[f32;4];4] => [Float32x4,Float32x4,Float32x4,Float32x4];
+}
+
+/// One field of a vertex struct: name, `#[location(n)]`, offset, formats and size.
+pub type VertexField = (&'static str,Option<u32>,BufferAddress,&'static [VertexFormat],u64);
+
+/// Vertex buffer layout of a `#[repr(C)]` struct, use `#[derive(VertexLayout)]`.
+pub trait VertexLayout : Sized {
+    fn step_mode() -> InputStepMode;
+    /// The fields in order, generated by the derive.
+    fn fields() -> Vec<VertexField>;
+
+    /// Locations count up from 0, an explicit location restarts the count. Fails when the
+    /// formats of a field type do not take exactly its size, a wrong `VertexAttr`.
+    fn try_attributes() -> Result<Vec<VertexAttribute>,ReflectError>
+    {
+        let mut res = Vec::new();
+        let mut location = 0;
+        for (name,explicit,offset,formats,size) in Self::fields().into_iter() {
+            let location_of_field = explicit.unwrap_or(location);
+            let total = formats.iter().map(|f| f.size()).sum::<u64>();
+            if total != size {
+                return Err(ReflectError::AttributeSize{ location: location_of_field, name: name.to_string(), formats: total, field: size });
+            }
+            let mut offset = offset;
+            for (i,f) in formats.iter().enumerate() {
+                res.push(VertexAttribute{
+                    format: *f,
+                    offset,
+                    shader_location: location_of_field + i as u32
+                });
+                offset += f.size();
+            }
+            location = location_of_field + formats.len() as u32;
+        }
+        Ok(res)
+    }
+
+    /// Panics where `try_attributes` fails.
+    fn attributes() -> Vec<VertexAttribute>
+    {
+        Self::try_attributes().unwrap_or_else(|e| panic!("{}",e))
+    }
+
+    fn layout() -> VertexLayoutDesc
+    {
+        VertexLayoutDesc{
+            array_stride: size_of::<Self>() as _,
+            step_mode: Self::step_mode(),
+            attributes: Self::attributes()
+        }
+    }
+
+    fn try_layout() -> Result<VertexLayoutDesc,ReflectError>
+    {
+        Ok(VertexLayoutDesc{
+            array_stride: size_of::<Self>() as _,
+            step_mode: Self::step_mode(),
+            attributes: Self::try_attributes()?
+        })
+    }
+
+    /// The formats and size of a field type, for the derive.
+    fn attr_of<A:VertexAttr>() -> (&'static [VertexFormat],u64)
+    {
+        (A::FORMATS,size_of::<A>() as u64)
+    }
+}
+
+mod test_vertex{
+    use crate::vertex::{VertexLayout, VertexAttr};
+    use crate::reflect::ReflectError;
+    use wgpu::{InputStepMode, VertexFormat};
+    use cgmath::{Matrix4, Vector3};
+
+    #[repr(C)]
+    #[derive(VertexLayout)]
+    struct Vertex{
+        position: [f32;3],
+        color: [f32;4],
+        tex_coords: [f32;2]
+    }
+
+    #[repr(C)]
+    #[derive(VertexLayout)]
+    #[step(instance)]
+    struct Instance{
+        #[location(5)]
+        model: Matrix4<f32>,
+        offset: Vector3<f32>,
+        id: u32
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Half(u16);
+
+    impl VertexAttr for Half {
+        const FORMATS: &'static [VertexFormat] = &[VertexFormat::Float32];
+    }
+
+    #[repr(C)]
+    #[derive(VertexLayout)]
+    struct Bad{
+        position: [f32;3],
+        weight: Half
+    }
+
+    #[test]
+    fn vertex()
+    {
+        let l = Vertex::layout();
+        assert_eq!(l.array_stride,36);
+        assert_eq!(l.step_mode,InputStepMode::Vertex);
+        assert_eq!(l.attributes.as_slice(),&wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4, 2 => Float32x2]);
+    }
+
+    #[test]
+    fn instance()
+    {
+        let l = Instance::layout();
+        assert_eq!(l.array_stride,80);
+        assert_eq!(l.step_mode,InputStepMode::Instance);
+        let locations:Vec<_> = l.attributes.iter().map(|a| (a.shader_location,a.offset,a.format)).collect();
+        assert_eq!(locations,vec![
+            (5,0,VertexFormat::Float32x4),
+            (6,16,VertexFormat::Float32x4),
+            (7,32,VertexFormat::Float32x4),
+            (8,48,VertexFormat::Float32x4),
+            (9,64,VertexFormat::Float32x3),
+            (10,76,VertexFormat::Uint32)
+        ]);
+    }
+
+    #[test]
+    fn wrong_size()
+    {
+        let e = Bad::try_layout().unwrap_err();
+        assert_eq!(e,ReflectError::AttributeSize{ location: 1, name: "weight".to_string(), formats: 4, field: 2 });
+        assert_eq!(e.to_string(),"vertex field \"weight\" at location 1 is 2 bytes but its formats take 4");
+    }
+}